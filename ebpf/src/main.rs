@@ -2,15 +2,16 @@
 #![no_main]
 
 use aya_ebpf::{
-    bindings::{TC_ACT_OK, TC_ACT_SHOT},
+    bindings::{BPF_F_MARK_MANGLED_0, BPF_F_PSEUDO_HDR, TC_ACT_OK, TC_ACT_SHOT},
+    helpers::bpf_redirect_neigh,
     macros::{classifier, map},
-    maps::{HashMap, RingBuf},
+    maps::{HashMap, LruHashMap, PerCpuHashMap, RingBuf},
     programs::TcContext,
 };
-use core::mem;
+use core::{mem, ptr};
 use network_types::{
     eth::{EthHdr, EtherType},
-    ip::{IpProto, Ipv4Hdr},
+    ip::{IpProto, Ipv4Hdr, Ipv6Hdr},
     tcp::TcpHdr,
     udp::UdpHdr,
 };
@@ -21,9 +22,54 @@ static WATCH_PORTS: HashMap<u16, u8> = HashMap::with_max_entries(1024, 0);
 #[map]
 static EVENTS: RingBuf = RingBuf::with_byte_size(4096, 0);
 
+/// Redirect mode: trigger port -> overlay target, written by userspace when a chain is active.
+/// Pinned so that the ingress and egress programs share it.
+#[map]
+static REDIRECTS: HashMap<u16, RedirectTarget> = HashMap::pinned(1024, 0);
+
+/// Redirect mode: redirected flows -> original destination, used to translate replies back.
+#[map]
+static FLOWS: LruHashMap<FlowKey, [u8; 4]> = LruHashMap::pinned(65536, 0);
+
+/// Egress traffic on the uplinks of active backend-triggered chains, by interface index,
+/// read periodically by userspace. Pinned so that every attached instance shares it.
+#[map]
+static LINK_STATS: PerCpuHashMap<u32, PortStats> = PerCpuHashMap::pinned(1024, 0);
+
 #[unsafe(no_mangle)]
 static IS_EGRESS: u8 = 0;
 
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PortStats {
+    packets: u64,
+    bytes: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RedirectTarget {
+    ip: [u8; 4],
+    ifindex: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FlowKey {
+    overlay_ip: [u8; 4],
+    overlay_port: u16,
+    local_port: u16,
+    proto: u8,
+    _pad: [u8; 3],
+}
+
+const IPV4_CSUM_OFFSET: usize = EthHdr::LEN + 10;
+const IPV4_SRC_OFFSET: usize = EthHdr::LEN + 12;
+const IPV4_DST_OFFSET: usize = EthHdr::LEN + 16;
+const TCP_CSUM_OFFSET: usize = 16;
+const UDP_CSUM_OFFSET: usize = 6;
+const VXLAN_PORT: u16 = 4789;
+
 // #[map]
 // static DATA: RingBuf = RingBuf::with_byte_size(4096 * RawFrame::LEN as u32, 0);
 
@@ -52,6 +98,15 @@ pub fn nullnet_filter_ports(ctx: TcContext) -> i32 {
     }
 }
 
+/// Attached on egress to the uplink of the entry network of a backend-triggered chain
+/// (its VXLAN tunnel, or the veth pair towards the other bridge when both ends are on
+/// this host), to account the chain's traffic to `LINK_STATS`.
+#[classifier]
+pub fn nullnet_count_link(ctx: TcContext) -> i32 {
+    count_link_traffic(&ctx);
+    TC_ACT_OK
+}
+
 // #[classifier]
 // pub fn nullnet_redirect_ingress(ctx: TcContext) -> i32 {
 //     match redirect_ingress(ctx) {
@@ -101,66 +156,221 @@ fn ptr_at<T>(ctx: &TcContext, offset: usize) -> Result<*const T, ()> {
 // }
 
 #[inline]
-fn filter_ports(ctx: TcContext) -> Result<i32, ()> {
+fn filter_ports(mut ctx: TcContext) -> Result<i32, ()> {
     let eth_header: *const EthHdr = ptr_at(&ctx, 0)?;
     let ether_type = EtherType::try_from(unsafe { (*eth_header).ether_type }).map_err(|_| ())?;
 
-    #[allow(clippy::single_match)]
-    match ether_type {
+    // destination addresses are reported as IPv6; IPv4 ones are v4-mapped (::ffff:a.b.c.d)
+    let (proto, l4_offset, dst_ip) = match ether_type {
         EtherType::Ipv4 => {
             let ipv4_header: *const Ipv4Hdr = ptr_at(&ctx, EthHdr::LEN)?;
-
-            match unsafe { (*ipv4_header).proto } {
-                IpProto::Udp => {
-                    let udp_header: *const UdpHdr = ptr_at(&ctx, EthHdr::LEN + Ipv4Hdr::LEN)?;
-                    // let src_port = u16::from_be_bytes(unsafe { (*udp_header).src });
-                    let dst_port = u16::from_be_bytes(unsafe { (*udp_header).dst });
-                    let dst_ip = unsafe { (*ipv4_header).dst_addr };
-
-                    if emit_if_watched(dst_port, dst_ip) {
-                        return Ok(TC_ACT_SHOT);
-                    }
-
-                    // if src_port == 9999 && dst_port == 9999 {
-                    //     return Ok(TC_ACT_OK);
-                    // }
-                }
-                IpProto::Tcp => {
-                    let tcp_header: *const TcpHdr = ptr_at(&ctx, EthHdr::LEN + Ipv4Hdr::LEN)?;
-                    // let src_port = u16::from_be_bytes(unsafe { (*tcp_header).source });
-                    let dst_port = u16::from_be_bytes(unsafe { (*tcp_header).dest });
-                    let dst_ip = unsafe { (*ipv4_header).dst_addr };
-
-                    if emit_if_watched(dst_port, dst_ip) {
-                        return Ok(TC_ACT_SHOT);
-                    }
-
-                    // if src_port == 50051 || dst_port == 50051 {
-                    //     return Ok(TC_ACT_OK);
-                    // }
-                }
-                _ => {}
+            if let Some(ret) = redirect_ipv4(&mut ctx, ipv4_header)? {
+                return Ok(ret);
             }
+            let [a, b, c, d] = unsafe { (*ipv4_header).dst_addr };
+            let dst_ip = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d];
+            (
+                unsafe { (*ipv4_header).proto },
+                EthHdr::LEN + Ipv4Hdr::LEN,
+                dst_ip,
+            )
+        }
+        EtherType::Ipv6 => {
+            let ipv6_header: *const Ipv6Hdr = ptr_at(&ctx, EthHdr::LEN)?;
+            (
+                unsafe { (*ipv6_header).next_hdr },
+                EthHdr::LEN + Ipv6Hdr::LEN,
+                unsafe { (*ipv6_header).dst_addr },
+            )
         }
         // EtherType::Arp => {
         //     return Ok(TC_ACT_OK);
         // }
-        _ => {}
+        _ => return Ok(TC_ACT_OK),
     };
 
+    match proto {
+        IpProto::Udp => {
+            let udp_header: *const UdpHdr = ptr_at(&ctx, l4_offset)?;
+            // let src_port = u16::from_be_bytes(unsafe { (*udp_header).src });
+            let dst_port = u16::from_be_bytes(unsafe { (*udp_header).dst });
+
+            // the overlay tunnels themselves
+            if dst_port == VXLAN_PORT && is_egress() {
+                return Ok(TC_ACT_OK);
+            }
+
+            if emit_if_watched(dst_port, dst_ip) {
+                return Ok(TC_ACT_SHOT);
+            }
+
+            // if src_port == 9999 && dst_port == 9999 {
+            //     return Ok(TC_ACT_OK);
+            // }
+        }
+        IpProto::Tcp => {
+            let tcp_header: *const TcpHdr = ptr_at(&ctx, l4_offset)?;
+            // let src_port = u16::from_be_bytes(unsafe { (*tcp_header).source });
+            let dst_port = u16::from_be_bytes(unsafe { (*tcp_header).dest });
+
+            if emit_if_watched(dst_port, dst_ip) {
+                return Ok(TC_ACT_SHOT);
+            }
+
+            // if src_port == 50051 || dst_port == 50051 {
+            //     return Ok(TC_ACT_OK);
+            // }
+        }
+        _ => {}
+    }
+
     // Ok(TC_ACT_SHOT)
     Ok(TC_ACT_OK)
 }
 
+/// Redirect mode (alternative to Netfilter DNAT, IPv4 only).
+///
+/// On egress, traffic to a port with a `REDIRECTS` entry gets its destination rewritten
+/// to the overlay address and is sent out of the overlay bridge with `bpf_redirect_neigh`;
+/// the original destination is remembered in `FLOWS`.
+/// On ingress, replies from the overlay address get their original source back.
+///
+/// Returns `None` if the packet is not subject to redirection.
+#[inline]
+fn redirect_ipv4(ctx: &mut TcContext, ipv4_header: *const Ipv4Hdr) -> Result<Option<i32>, ()> {
+    let proto = unsafe { (*ipv4_header).proto };
+    let l4_offset = EthHdr::LEN + Ipv4Hdr::LEN;
+    let (src_port, dst_port, csum_offset, csum_flags) = match proto {
+        IpProto::Tcp => {
+            let tcp_header: *const TcpHdr = ptr_at(ctx, l4_offset)?;
+            (
+                u16::from_be_bytes(unsafe { (*tcp_header).source }),
+                u16::from_be_bytes(unsafe { (*tcp_header).dest }),
+                l4_offset + TCP_CSUM_OFFSET,
+                0,
+            )
+        }
+        IpProto::Udp => {
+            let udp_header: *const UdpHdr = ptr_at(ctx, l4_offset)?;
+            (
+                u16::from_be_bytes(unsafe { (*udp_header).src }),
+                u16::from_be_bytes(unsafe { (*udp_header).dst }),
+                l4_offset + UDP_CSUM_OFFSET,
+                u64::from(BPF_F_MARK_MANGLED_0),
+            )
+        }
+        _ => return Ok(None),
+    };
+
+    if is_egress() {
+        let Some(target) = (unsafe { REDIRECTS.get(&dst_port) }).copied() else {
+            return Ok(None);
+        };
+        let orig_dst = unsafe { (*ipv4_header).dst_addr };
+        let key = FlowKey {
+            overlay_ip: target.ip,
+            overlay_port: dst_port,
+            local_port: src_port,
+            proto: proto as u8,
+            _pad: [0; 3],
+        };
+        FLOWS.insert(&key, &orig_dst, 0).map_err(|_| ())?;
+        rewrite_ipv4_addr(
+            ctx,
+            IPV4_DST_OFFSET,
+            orig_dst,
+            target.ip,
+            csum_offset,
+            csum_flags,
+        )?;
+        let ret = unsafe { bpf_redirect_neigh(target.ifindex, ptr::null_mut(), 0, 0) };
+        Ok(Some(ret as i32))
+    } else {
+        let src_addr = unsafe { (*ipv4_header).src_addr };
+        let key = FlowKey {
+            overlay_ip: src_addr,
+            overlay_port: src_port,
+            local_port: dst_port,
+            proto: proto as u8,
+            _pad: [0; 3],
+        };
+        let Some(orig_dst) = (unsafe { FLOWS.get(&key) }).copied() else {
+            return Ok(None);
+        };
+        rewrite_ipv4_addr(
+            ctx,
+            IPV4_SRC_OFFSET,
+            src_addr,
+            orig_dst,
+            csum_offset,
+            csum_flags,
+        )?;
+        Ok(Some(TC_ACT_OK))
+    }
+}
+
+/// Replaces the IPv4 address at `addr_offset`, fixing up the L3 and L4 checksums.
+#[inline]
+fn rewrite_ipv4_addr(
+    ctx: &mut TcContext,
+    addr_offset: usize,
+    from: [u8; 4],
+    to: [u8; 4],
+    csum_offset: usize,
+    csum_flags: u64,
+) -> Result<(), ()> {
+    // checksum helpers work on the raw (network order) values
+    let from = u64::from(u32::from_ne_bytes(from));
+    let to_raw = u32::from_ne_bytes(to);
+    let l4_flags = csum_flags | u64::from(BPF_F_PSEUDO_HDR) | 4;
+    ctx.l4_csum_replace(csum_offset, from, u64::from(to_raw), l4_flags)
+        .map_err(|_| ())?;
+    ctx.l3_csum_replace(IPV4_CSUM_OFFSET, from, u64::from(to_raw), 4)
+        .map_err(|_| ())?;
+    ctx.store(addr_offset, &to, 0).map_err(|_| ())
+}
+
+#[inline]
+fn is_egress() -> bool {
+    let is_egress = unsafe { core::ptr::read_volatile(&IS_EGRESS) };
+    is_egress != 0
+}
+
+/// Accounts an IP packet leaving through the interface to `LINK_STATS`.
+#[inline]
+fn count_link_traffic(ctx: &TcContext) {
+    let Ok(eth_header) = ptr_at::<EthHdr>(ctx, 0) else {
+        return;
+    };
+    if !matches!(
+        EtherType::try_from(unsafe { (*eth_header).ether_type }),
+        Ok(EtherType::Ipv4 | EtherType::Ipv6)
+    ) {
+        return;
+    }
+    let ifindex = unsafe { (*ctx.skb.skb).ifindex };
+    let bytes = u64::from(ctx.len());
+    // per-CPU values: no concurrent writers
+    if let Some(stats) = LINK_STATS.get_ptr_mut(&ifindex) {
+        unsafe {
+            (*stats).packets += 1;
+            (*stats).bytes += bytes;
+        }
+    } else {
+        let stats = PortStats { packets: 1, bytes };
+        let _ = LINK_STATS.insert(&ifindex, &stats, 0);
+    }
+}
+
 #[repr(C)]
 struct PortEvent {
     port: u16,
-    dst_ip: [u8; 4],
+    dst_ip: [u8; 16],
 }
 
 #[inline]
-fn emit_if_watched(dst_port: u16, dst_ip: [u8; 4]) -> bool {
-    if unsafe { core::ptr::read_volatile(&IS_EGRESS) } == 0 {
+fn emit_if_watched(dst_port: u16, dst_ip: [u8; 16]) -> bool {
+    if !is_egress() {
         return false;
     }
     if unsafe { WATCH_PORTS.get(&dst_port) }.is_none() {
@@ -0,0 +1,72 @@
+use super::{PROTOS, sudo};
+use std::net::IpAddr;
+
+const CHAIN: &str = "NULLNET_DNAT";
+const IPTABLES: [&str; 2] = ["iptables", "ip6tables"];
+const HOOK_CHAINS: [&str; 2] = ["OUTPUT", "PREROUTING"];
+
+/// Creates (or flushes) the private DNAT chain and hooks it from
+/// OUTPUT and PREROUTING, for both address families. Idempotent.
+pub(super) fn init() {
+    for iptables in IPTABLES {
+        // create our chain (no-op if it already exists)
+        let _ = sudo(&[iptables, "-t", "nat", "-N", CHAIN]);
+        // flush any rules left over from a previous run
+        let _ = sudo(&[iptables, "-t", "nat", "-F", CHAIN]);
+        // hook the chain from OUTPUT and PREROUTING (idempotent via -C check)
+        for chain in HOOK_CHAINS {
+            let already = sudo(&[iptables, "-t", "nat", "-C", chain, "-j", CHAIN])
+                .map(|s| s.success())
+                .unwrap_or(false);
+            if !already {
+                let _ = sudo(&[iptables, "-t", "nat", "-A", chain, "-j", CHAIN]);
+            }
+        }
+    }
+}
+
+pub(super) fn install(port: u16, overlay_ip: IpAddr) {
+    for proto in PROTOS {
+        run_iptables("-A", proto, port, overlay_ip);
+    }
+}
+
+pub(super) fn remove(port: u16, overlay_ip: IpAddr) {
+    for proto in PROTOS {
+        run_iptables("-D", proto, port, overlay_ip);
+    }
+}
+
+fn run_iptables(action: &str, proto: &str, port: u16, overlay_ip: IpAddr) {
+    let port_s = port.to_string();
+    let (iptables, target) = match overlay_ip {
+        IpAddr::V4(ip) => ("iptables", format!("{ip}:{port}")),
+        IpAddr::V6(ip) => ("ip6tables", format!("[{ip}]:{port}")),
+    };
+    let status = sudo(&[
+        iptables,
+        "-t",
+        "nat",
+        action,
+        CHAIN,
+        "-p",
+        proto,
+        "--dport",
+        &port_s,
+        "-j",
+        "DNAT",
+        "--to-destination",
+        &target,
+    ]);
+    match status {
+        Ok(s) if s.success() => {
+            println!("[dnat] {iptables} {action} {CHAIN} {proto}/{port} -> {target}");
+        }
+        Ok(s) => {
+            eprintln!("[dnat] {iptables} {action} {CHAIN} {proto}/{port} -> {target} exited {s}");
+        }
+        Err(e) => {
+            eprintln!("[dnat] {iptables} {action} {CHAIN} {proto}/{port} -> {target}: {e}");
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! DNAT steering the initiator's traffic on a trigger port into the overlay.
+//!
+//! The rules are managed by one of two backends, selected at startup with `DNAT_BACKEND`:
+//! - `iptables` (default): a private `NULLNET_DNAT` chain in the `nat` tables
+//! - `nftables`: a dedicated `nullnet` table with `port -> overlay_ip:port` maps
+
+use crate::env::DNAT_BACKEND;
+use std::net::IpAddr;
+use std::process::Command;
+
+mod iptables;
+mod nftables;
+
+const PROTOS: [&str; 2] = ["tcp", "udp"];
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum DnatBackend {
+    Iptables,
+    Nftables,
+}
+
+/// Resets the backend's DNAT state and conntrack so a fresh process start
+/// inherits no stale state from a previous run. Idempotent.
+pub(crate) fn init() {
+    match *DNAT_BACKEND {
+        DnatBackend::Iptables => iptables::init(),
+        DnatBackend::Nftables => nftables::init(),
+    }
+    // drop any conntrack flows that may have been NAT'd through stale rules
+    let _ = sudo(&["conntrack", "-F"]);
+    let _ = sudo(&["conntrack", "-F", "-f", "ipv6"]);
+    println!(
+        "[dnat] init: {:?} backend ready, conntrack flushed",
+        *DNAT_BACKEND
+    );
+}
+
+/// Redirects TCP and UDP traffic for `port` to the same port on `overlay_ip`.
+pub(crate) fn install(port: u16, overlay_ip: IpAddr) {
+    match *DNAT_BACKEND {
+        DnatBackend::Iptables => iptables::install(port, overlay_ip),
+        DnatBackend::Nftables => nftables::install(port, overlay_ip),
+    }
+    flush_conntrack(port, overlay_ip);
+}
+
+/// Removes the redirection installed by [`install`].
+pub(crate) fn remove(port: u16, overlay_ip: IpAddr) {
+    match *DNAT_BACKEND {
+        DnatBackend::Iptables => iptables::remove(port, overlay_ip),
+        DnatBackend::Nftables => nftables::remove(port, overlay_ip),
+    }
+    flush_conntrack(port, overlay_ip);
+}
+
+fn flush_conntrack(port: u16, overlay_ip: IpAddr) {
+    let port_s = port.to_string();
+    let family = if overlay_ip.is_ipv4() { "ipv4" } else { "ipv6" };
+    for proto in PROTOS {
+        let _ = sudo(&[
+            "conntrack",
+            "-D",
+            "-f",
+            family,
+            "-p",
+            proto,
+            "--dport",
+            &port_s,
+        ]);
+    }
+}
+
+fn sudo(args: &[&str]) -> std::io::Result<std::process::ExitStatus> {
+    Command::new("sudo").args(args).status()
+}
@@ -0,0 +1,110 @@
+use std::io::Write;
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+
+const TABLE: &str = "inet nullnet";
+/// Named maps `port -> overlay_ip . port`, one per address family.
+const MAP4: &str = "dnat4";
+const MAP6: &str = "dnat6";
+
+/// Recreates the `nullnet` table, with both maps empty, in a single transaction.
+/// Idempotent.
+pub(super) fn init() {
+    // declaring the table first makes the delete succeed even if it doesn't exist yet
+    let ruleset = format!(
+        "table {TABLE}
+delete table {TABLE}
+table {TABLE} {{
+    map {MAP4} {{
+        type inet_service : ipv4_addr . inet_service
+    }}
+    map {MAP6} {{
+        type inet_service : ipv6_addr . inet_service
+    }}
+    chain prerouting {{
+        type nat hook prerouting priority -100; policy accept;
+        meta nfproto ipv4 meta l4proto {{ tcp, udp }} dnat ip to th dport map @{MAP4}
+        meta nfproto ipv6 meta l4proto {{ tcp, udp }} dnat ip6 to th dport map @{MAP6}
+    }}
+    chain output {{
+        type nat hook output priority -100; policy accept;
+        meta nfproto ipv4 meta l4proto {{ tcp, udp }} dnat ip to th dport map @{MAP4}
+        meta nfproto ipv6 meta l4proto {{ tcp, udp }} dnat ip6 to th dport map @{MAP6}
+    }}
+}}
+"
+    );
+    run_nft(&ruleset, &format!("init table {TABLE}"));
+}
+
+/// Maps `port` to `overlay_ip`, replacing any previous mapping of `port` in a single
+/// transaction (a bare `add element` would fail on it, leaving the stale mapping in place).
+pub(super) fn install(port: u16, overlay_ip: IpAddr) {
+    let map = map_for(overlay_ip);
+    let mut ruleset = String::new();
+    match mapped_ip(map, port) {
+        Some(ip) if ip == overlay_ip => return,
+        Some(_) => ruleset.push_str(&format!("delete element {TABLE} {map} {{ {port} }}\n")),
+        None => {}
+    }
+    ruleset.push_str(&format!(
+        "add element {TABLE} {map} {{ {port} : {overlay_ip} . {port} }}\n"
+    ));
+    run_nft(
+        &ruleset,
+        &format!("add {map} {port} -> {overlay_ip} . {port}"),
+    );
+}
+
+/// Unmaps `port`, unless it has been mapped to another overlay IP in the meantime.
+pub(super) fn remove(port: u16, overlay_ip: IpAddr) {
+    let map = map_for(overlay_ip);
+    if mapped_ip(map, port) != Some(overlay_ip) {
+        return;
+    }
+    run_nft(
+        &format!("delete element {TABLE} {map} {{ {port} }}\n"),
+        &format!("delete {map} {port} -> {overlay_ip} . {port}"),
+    );
+}
+
+/// The overlay IP `port` is currently mapped to in `map`, if any.
+fn mapped_ip(map: &str, port: u16) -> Option<IpAddr> {
+    // e.g., `elements = { 8080 : 10.0.0.2 . 8080 }`
+    let output = Command::new("sudo")
+        .args(["nft", "get", "element"])
+        .args(TABLE.split(' '))
+        .arg(map)
+        .arg(format!("{{ {port} }}"))
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (_, elements) = stdout.split_once("elements = {")?;
+    let (_, target) = elements.split_once(':')?;
+    let (ip, _) = target.split_once(" . ")?;
+    ip.trim().parse().ok()
+}
+
+fn map_for(overlay_ip: IpAddr) -> &'static str {
+    if overlay_ip.is_ipv4() { MAP4 } else { MAP6 }
+}
+
+/// Applies `ruleset` atomically with `nft -f -`, logging the outcome as `what`.
+fn run_nft(ruleset: &str, what: &str) {
+    let res = Command::new("sudo")
+        .args(["nft", "-f", "-"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(ruleset.as_bytes())?;
+            }
+            child.wait()
+        });
+    match res {
+        Ok(s) if s.success() => println!("[dnat] nft {what}"),
+        Ok(s) => eprintln!("[dnat] nft {what} exited {s}"),
+        Err(e) => eprintln!("[dnat] nft {what}: {e}"),
+    }
+}
@@ -1,9 +1,10 @@
-use ipnetwork::Ipv4Network;
-use netlink::NetLinkCommand;
+use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use netlink::{NetLinkCommand, NetNs};
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
 use ovs::OvsCommand;
 use rtnetlink::{Handle, new_connection};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Once;
 
 pub(crate) mod dnat;
 mod netlink;
@@ -14,10 +15,10 @@ pub(crate) async fn setup_br0(rtnetlink_handle: &RtNetLinkHandle) {
     OvsCommand::AddBridge.execute();
 
     // set the bridge up and ovs-system up
-    rtnetlink_handle
+    let _ = rtnetlink_handle
         .execute(NetLinkCommand::SetInterfaceUp("br0"))
         .await;
-    rtnetlink_handle
+    let _ = rtnetlink_handle
         .execute(NetLinkCommand::SetInterfaceUp("ovs-system"))
         .await;
 
@@ -35,7 +36,7 @@ pub(crate) async fn configure_access_port(
     rtnetlink_handle: &RtNetLinkHandle,
     vlan_id: u16,
     net: Ipv4Network,
-) {
+) -> Result<(), Error> {
     let veth_name = format!("veth-{vlan_id}");
     let veth_peer_name = format!("{veth_name}p");
 
@@ -46,19 +47,258 @@ pub(crate) async fn configure_access_port(
             &veth_name,
             &veth_peer_name,
         ))
-        .await;
+        .await?;
 
     // add the peer interface to the bridge as an access port
     OvsCommand::AddAccessPort(&veth_peer_name, vlan_id).execute();
+
+    Ok(())
 }
 
 pub(crate) async fn remove_vlan(rtnetlink_handle: &RtNetLinkHandle, vlan_id: u16) {
     // delete the veth pair
-    rtnetlink_handle
+    let _ = rtnetlink_handle
         .execute(NetLinkCommand::DeleteVeth(vlan_id))
         .await;
 }
 
+/// Endpoint of a VXLAN on this machine.
+pub(crate) struct VxlanEndpoint<'a> {
+    pub(crate) vxlan_id: u32,
+    pub(crate) ns_name: &'a str,
+    pub(crate) ns_net: Ipv4Network,
+    pub(crate) br_name: &'a str,
+    pub(crate) br_net: Ipv4Network,
+    /// Set only when the server assigns IPv6 overlay addresses too
+    pub(crate) ns_net6: Option<Ipv6Network>,
+    pub(crate) br_net6: Option<Ipv6Network>,
+    pub(crate) local_ip: IpAddr,
+    pub(crate) remote_ip: IpAddr,
+    pub(crate) docker_container: Option<&'a str>,
+}
+
+impl VxlanEndpoint<'_> {
+    /// Link attaching the bridge to the other endpoint: the VXLAN tunnel, or the
+    /// veth pair towards the other bridge when both endpoints are on this machine.
+    pub(crate) fn uplink(&self) -> String {
+        let vxlan_id = self.vxlan_id;
+        if self.local_ip != self.remote_ip {
+            format!("vxlan-{}", self.ns_name)
+        } else if self.br_name.ends_with("_s") {
+            format!("veth-{vxlan_id}-s")
+        } else {
+            format!("veth-{vxlan_id}-c")
+        }
+    }
+}
+
+/// Set up a VXLAN endpoint: a namespace (or the given Docker container's one) connected
+/// through a veth pair to a bridge, which is in turn attached to the VXLAN tunnel
+/// (or to a veth pair towards the other bridge, when both endpoints are on this machine).
+pub(crate) async fn setup_vxlan(
+    rtnetlink_handle: &RtNetLinkHandle,
+    endpoint: &VxlanEndpoint<'_>,
+) -> Result<(), Error> {
+    let ns_name = endpoint.ns_name;
+    let br_name = endpoint.br_name;
+    let ns_in = format!("{ns_name}-in");
+    let ns_out = format!("{ns_name}-out");
+
+    let netns = if let Some(container) = endpoint.docker_container {
+        // Docker mode: move into the container's namespace
+        NetNs::Pid(docker_pid(container).await?)
+    } else {
+        // standalone mode: create a new namespace
+        rtnetlink_handle
+            .execute(NetLinkCommand::AddNetNs(ns_name))
+            .await?;
+        NetNs::Named(ns_name)
+    };
+
+    // create a veth pair and move one end into the namespace;
+    // in standalone mode, the default routes go through the bridge
+    let is_standalone = endpoint.docker_container.is_none();
+    let mut ns_nets = vec![(
+        IpNetwork::V4(endpoint.ns_net),
+        is_standalone.then_some(IpAddr::V4(endpoint.br_net.ip())),
+    )];
+    let mut br_nets = vec![IpNetwork::V4(endpoint.br_net)];
+    if let (Some(ns_net6), Some(br_net6)) = (endpoint.ns_net6, endpoint.br_net6) {
+        ns_nets.push((
+            IpNetwork::V6(ns_net6),
+            is_standalone.then_some(IpAddr::V6(br_net6.ip())),
+        ));
+        br_nets.push(IpNetwork::V6(br_net6));
+    }
+    rtnetlink_handle
+        .execute(NetLinkCommand::AddVethPair(&ns_in, &ns_out))
+        .await?;
+    rtnetlink_handle
+        .execute(NetLinkCommand::MoveToNetNs(&ns_in, netns))
+        .await?;
+    rtnetlink_handle
+        .execute(NetLinkCommand::ConfigureInNetNs(netns, &ns_in, &ns_nets))
+        .await?;
+
+    // create the bridge and attach the other end to it
+    rtnetlink_handle
+        .execute(NetLinkCommand::AddBridge(br_name, &br_nets))
+        .await?;
+    rtnetlink_handle
+        .execute(NetLinkCommand::AttachToBridge(&ns_out, br_name))
+        .await?;
+
+    let uplink = endpoint.uplink();
+    if endpoint.local_ip == endpoint.remote_ip {
+        // same host: connect the bridges with a veth pair instead of a VXLAN tunnel
+        let vxlan_id = endpoint.vxlan_id;
+        let veth_s = format!("veth-{vxlan_id}-s");
+        let veth_c = format!("veth-{vxlan_id}-c");
+        rtnetlink_handle
+            .execute(NetLinkCommand::AddVethPair(&veth_s, &veth_c))
+            .await?;
+    } else {
+        rtnetlink_handle
+            .execute(NetLinkCommand::AddVxlan(
+                &uplink,
+                endpoint.vxlan_id,
+                endpoint.local_ip,
+                endpoint.remote_ip,
+            ))
+            .await?;
+    }
+    rtnetlink_handle
+        .execute(NetLinkCommand::AttachToBridge(&uplink, br_name))
+        .await?;
+
+    Ok(())
+}
+
+/// Tear down a VXLAN endpoint set up with `setup_vxlan`.
+///
+/// Every step is attempted even if a previous one failed (e.g. the namespace of a
+/// setup that failed early was never created), so that no link is left behind;
+/// the first error (already logged) is returned at the end.
+pub(crate) async fn teardown_vxlan(
+    rtnetlink_handle: &RtNetLinkHandle,
+    vxlan_id: u32,
+    ns_name: &str,
+    br_name: &str,
+    docker_container: Option<&str>,
+) -> Result<(), Error> {
+    let vxlan_name = format!("vxlan-{ns_name}");
+    let veth_s = format!("veth-{vxlan_id}-s");
+    let ns_out = format!("{ns_name}-out");
+
+    let mut commands = vec![
+        // remove the VXLAN tunnel or same-host veth pair
+        NetLinkCommand::DeleteInterface(&vxlan_name),
+        NetLinkCommand::DeleteInterface(&veth_s),
+        // remove the namespace veth pair
+        NetLinkCommand::DeleteInterface(&ns_out),
+    ];
+    // standalone mode: delete the namespace we created
+    // (Docker mode: nothing to do, Docker manages its own namespace)
+    if docker_container.is_none() {
+        commands.push(NetLinkCommand::DeleteNetNs(ns_name));
+    }
+    // remove the bridge
+    commands.push(NetLinkCommand::DeleteInterface(br_name));
+
+    let mut first_err = None;
+    for command in commands {
+        if let Err(e) = rtnetlink_handle.execute(command).await {
+            first_err.get_or_insert(e);
+        }
+    }
+
+    first_err.map_or(Ok(()), Err)
+}
+
+/// Enable IP forwarding and allow forwarded traffic (Docker sets the FORWARD policy to DROP).
+/// Failing to set the FORWARD policy (e.g. on hosts without iptables) is logged only,
+/// since there's no such policy to lift there.
+pub(crate) fn enable_forwarding() -> Result<(), Error> {
+    std::fs::write("/proc/sys/net/ipv4/ip_forward", "1").handle_err(location!())?;
+    if let Err(e) = accept_forwarded("iptables") {
+        eprintln!(
+            "Could not accept forwarded traffic, overlay traffic may be dropped: {}",
+            e.to_str()
+        );
+    }
+    Ok(())
+}
+
+/// Guards the IPv6 forwarding setup, done at most once.
+static IPV6_FORWARDING: Once = Once::new();
+
+/// Enable IPv6 forwarding and allow forwarded IPv6 traffic, the first time a network with
+/// IPv6 overlay addresses is set up. Failures (e.g. IPv6 disabled on the host, or no
+/// ip6tables) are logged only, since the IPv4 side of the networks works regardless.
+pub(crate) fn enable_ipv6_forwarding() {
+    IPV6_FORWARDING.call_once(|| {
+        keep_accepting_router_advertisements();
+        let res = std::fs::write("/proc/sys/net/ipv6/conf/all/forwarding", "1")
+            .handle_err(location!())
+            .and_then(|()| accept_forwarded("ip6tables"));
+        if let Err(e) = res {
+            eprintln!(
+                "Could not enable IPv6 forwarding, IPv6 overlay traffic may not flow: {}",
+                e.to_str()
+            );
+        }
+    });
+}
+
+/// Router advertisements are ignored once forwarding is enabled, unless `accept_ra` is 2:
+/// raise it on the interfaces accepting them, so the host doesn't lose its IPv6 default route.
+fn keep_accepting_router_advertisements() {
+    let Ok(interfaces) = std::fs::read_dir("/proc/sys/net/ipv6/conf") else {
+        return;
+    };
+    for interface in interfaces.flatten() {
+        let accept_ra = interface.path().join("accept_ra");
+        if std::fs::read_to_string(&accept_ra).is_ok_and(|v| v.trim() == "1") {
+            let _ = std::fs::write(&accept_ra, "2").handle_err(location!());
+        }
+    }
+}
+
+/// Set the FORWARD policy of `iptables` (or `ip6tables`) to ACCEPT.
+fn accept_forwarded(iptables: &str) -> Result<(), Error> {
+    let status = std::process::Command::new("sudo")
+        .args([iptables, "-P", "FORWARD", "ACCEPT"])
+        .status()
+        .handle_err(location!())?;
+    if !status.success() {
+        return Err(format!("{iptables} -P FORWARD ACCEPT exited {status}"))
+            .handle_err(location!());
+    }
+
+    Ok(())
+}
+
+/// PID of the given Docker container, used to reach its network namespace.
+async fn docker_pid(container: &str) -> Result<u32, Error> {
+    let output = tokio::process::Command::new("docker")
+        .args(["inspect", "-f", "{{.State.Pid}}", container])
+        .output()
+        .await
+        .handle_err(location!())?;
+    if !output.status.success() {
+        return Err(format!(
+            "docker inspect {container} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .handle_err(location!());
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .handle_err(location!())
+}
+
 pub(crate) async fn find_ethernet_ip(rtnetlink_handle: &RtNetLinkHandle) -> Option<Ipv4Addr> {
     netlink::find_ethernet_ip(&rtnetlink_handle.handle).await
 }
@@ -77,20 +317,20 @@ impl RtNetLinkHandle {
         })
     }
 
-    async fn execute(&self, command: NetLinkCommand<'_>) {
-        command.execute(self).await;
+    async fn execute(&self, command: NetLinkCommand<'_>) -> Result<(), Error> {
+        command.execute(self).await
     }
 }
 
 pub(crate) async fn cleanup_network(rtnetlink_handle: &RtNetLinkHandle) {
     dnat::init();
-    vxlan_cleanup_network();
+    crate::ebpf::redirect::init();
+    vxlan_cleanup_network(rtnetlink_handle).await;
     vlan_cleanup_network(rtnetlink_handle).await;
 }
 
 /// Cleanup existing namespaces, VXLANs and bridges
-fn vxlan_cleanup_network() {
-    // TODO: do this using rtnetlink
+async fn vxlan_cleanup_network(rtnetlink_handle: &RtNetLinkHandle) {
     use network_interface::{NetworkInterface, NetworkInterfaceConfig};
 
     // first clean up existing namespaces, VXLAN interfaces, and same-host veth pairs
@@ -98,28 +338,18 @@ fn vxlan_cleanup_network() {
         for device in devices {
             if let Some(ns_name) = device.name.strip_prefix("vxlan-") {
                 println!("Cleaning up existing namespace: {ns_name}");
-                let _ = std::process::Command::new("./vxlan_scripts/ns-teardown.sh")
-                    .arg(ns_name)
-                    .spawn()
-                    .map(|mut c| c.wait())
-                    .handle_err(location!());
+                ns_teardown(rtnetlink_handle, ns_name).await;
             } else if device.name.starts_with("ns_") {
                 if let Some(ns_name) = device.name.strip_suffix("-out") {
                     // same-host case: no vxlan- interface, discover namespaces via their veth-out
                     println!("Cleaning up existing namespace: {ns_name}");
-                    let _ = std::process::Command::new("./vxlan_scripts/ns-teardown.sh")
-                        .arg(ns_name)
-                        .spawn()
-                        .map(|mut c| c.wait())
-                        .handle_err(location!());
+                    ns_teardown(rtnetlink_handle, ns_name).await;
                 }
             } else if device.name.starts_with("veth-") {
                 println!("Cleaning up existing same-host veth pair: {}", device.name);
-                let _ = std::process::Command::new("sudo")
-                    .args(["ip", "link", "del", &device.name])
-                    .spawn()
-                    .map(|mut c| c.wait())
-                    .handle_err(location!());
+                let _ = rtnetlink_handle
+                    .execute(NetLinkCommand::DeleteInterface(&device.name))
+                    .await;
             }
         }
     }
@@ -130,20 +360,31 @@ fn vxlan_cleanup_network() {
             if device.name.starts_with("br_") {
                 let br_name = device.name;
                 println!("Cleaning up existing bridge: {br_name}");
-                let _ = std::process::Command::new("./vxlan_scripts/br-teardown.sh")
-                    .arg(br_name)
-                    .spawn()
-                    .map(|mut c| c.wait())
-                    .handle_err(location!());
+                let _ = rtnetlink_handle
+                    .execute(NetLinkCommand::DeleteInterface(&br_name))
+                    .await;
             }
         }
     }
 }
 
+async fn ns_teardown(rtnetlink_handle: &RtNetLinkHandle, ns_name: &str) {
+    let _ = rtnetlink_handle
+        .execute(NetLinkCommand::DeleteInterface(&format!("vxlan-{ns_name}")))
+        .await;
+    let _ = rtnetlink_handle
+        .execute(NetLinkCommand::DeleteInterface(&format!("{ns_name}-out")))
+        .await;
+    // in Docker mode there's no namespace to delete, so this simply fails
+    let _ = rtnetlink_handle
+        .execute(NetLinkCommand::DeleteNetNs(ns_name))
+        .await;
+}
+
 /// Cleanup existing veth and VLANs
 async fn vlan_cleanup_network(rtnetlink_handle: &RtNetLinkHandle) {
     // clean up existing veth interfaces
-    rtnetlink_handle
+    let _ = rtnetlink_handle
         .execute(NetLinkCommand::DeleteAllVeths)
         .await;
 
@@ -1,11 +1,18 @@
 use crate::commands::RtNetLinkHandle;
 use futures::StreamExt;
-use ipnetwork::Ipv4Network;
+use ipnetwork::{IpNetwork, Ipv4Network};
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
 use rtnetlink::packet_route::address::AddressAttribute;
 use rtnetlink::packet_route::link::{LinkAttribute, LinkMessage};
-use rtnetlink::{Handle, LinkUnspec, LinkVeth};
-use std::net::{IpAddr, Ipv4Addr};
+use rtnetlink::{
+    Handle, LinkBridge, LinkUnspec, LinkVeth, LinkVxlan, NetworkNamespace, RouteMessageBuilder,
+    new_connection,
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::AsRawFd;
+
+/// Standard IANA port for VXLAN.
+const VXLAN_PORT: u16 = 4789;
 
 #[derive(Debug)]
 pub(super) enum NetLinkCommand<'a> {
@@ -13,31 +20,95 @@ pub(super) enum NetLinkCommand<'a> {
     DeleteAllVeths,
     DeleteVeth(u16),
     SetInterfaceUp(&'a str),
+    AddNetNs(&'a str),
+    DeleteNetNs(&'a str),
+    /// Create a veth pair, tolerating a peer task having already created it.
+    AddVethPair(&'a str, &'a str),
+    /// Create a bridge with the given addresses and set it up.
+    AddBridge(&'a str, &'a [IpNetwork]),
+    /// Create a VXLAN link with the given name, id, local and remote IPs (same family).
+    AddVxlan(&'a str, u32, IpAddr, IpAddr),
+    /// Enslave an interface to a bridge and set it up.
+    AttachToBridge(&'a str, &'a str),
+    MoveToNetNs(&'a str, NetNs<'a>),
+    /// Assign addresses to an interface inside a namespace, set it (and loopback) up,
+    /// and add a default route via the gateway paired with each address, if any.
+    ConfigureInNetNs(NetNs<'a>, &'a str, &'a [(IpNetwork, Option<IpAddr>)]),
+    /// Delete an interface, if it exists.
+    DeleteInterface(&'a str),
+}
+
+/// A network namespace, either created by us (named) or owned by a process (e.g., a Docker container).
+#[derive(Debug, Clone, Copy)]
+pub(super) enum NetNs<'a> {
+    Named(&'a str),
+    Pid(u32),
+}
+
+impl NetNs<'_> {
+    fn path(self) -> String {
+        match self {
+            NetNs::Named(name) => format!("/var/run/netns/{name}"),
+            NetNs::Pid(pid) => format!("/proc/{pid}/ns/net"),
+        }
+    }
 }
 
 impl NetLinkCommand<'_> {
-    pub(super) async fn execute(&self, rtnetlink_handle: &RtNetLinkHandle) {
+    pub(super) async fn execute(&self, rtnetlink_handle: &RtNetLinkHandle) -> Result<(), Error> {
         let handle = &rtnetlink_handle.handle;
         let init_t = std::time::Instant::now();
-        match self {
+        let res = match self {
             NetLinkCommand::HandleVethPairCreation(addr, veth_name, veth_peer_name) => {
-                let _ = handle_veth_pair_creation(handle, *addr, veth_name, veth_peer_name).await;
+                handle_veth_pair_creation(handle, *addr, veth_name, veth_peer_name).await
             }
             NetLinkCommand::DeleteAllVeths => {
                 delete_all_veths(handle).await;
+                Ok(())
             }
             NetLinkCommand::DeleteVeth(vlan_id) => {
                 delete_veth(handle, *vlan_id).await;
+                Ok(())
             }
             NetLinkCommand::SetInterfaceUp(interface) => {
                 set_interface_up(handle, interface).await;
+                Ok(())
             }
-        }
+            NetLinkCommand::AddNetNs(ns_name) => NetworkNamespace::add((*ns_name).to_string())
+                .await
+                .handle_err(location!()),
+            NetLinkCommand::DeleteNetNs(ns_name) => NetworkNamespace::del((*ns_name).to_string())
+                .await
+                .handle_err(location!()),
+            NetLinkCommand::AddVethPair(name, peer_name) => {
+                add_veth_pair(handle, name, peer_name).await
+            }
+            NetLinkCommand::AddBridge(name, nets) => add_bridge(handle, name, nets).await,
+            NetLinkCommand::AddVxlan(name, vxlan_id, local_ip, remote_ip) => {
+                add_vxlan(handle, name, *vxlan_id, *local_ip, *remote_ip).await
+            }
+            NetLinkCommand::AttachToBridge(interface, br_name) => {
+                attach_to_bridge(handle, interface, br_name).await
+            }
+            NetLinkCommand::MoveToNetNs(interface, netns) => {
+                move_to_netns(handle, interface, *netns).await
+            }
+            NetLinkCommand::ConfigureInNetNs(netns, interface, nets) => {
+                configure_in_netns(*netns, interface, nets).await
+            }
+            NetLinkCommand::DeleteInterface(interface) => {
+                match get_link_by_name(handle, interface).await {
+                    Ok(link) => delete_link(handle, link).await,
+                    Err(_) => Ok(()),
+                }
+            }
+        };
         println!(
             "Executed command {:?} in {} ms",
             self,
             init_t.elapsed().as_millis()
         );
+        res
     }
 }
 
@@ -127,6 +198,191 @@ async fn set_interface_up(handle: &Handle, interface: &str) {
     }
 }
 
+async fn add_veth_pair(handle: &Handle, name: &str, peer_name: &str) -> Result<(), Error> {
+    let res = handle
+        .link()
+        .add(LinkVeth::new(name, peer_name).build())
+        .execute()
+        .await;
+
+    // both ends are created at once: if another task won the race, the pair is already there
+    if res.is_err() && get_link_by_name(handle, name).await.is_ok() {
+        return Ok(());
+    }
+
+    res.handle_err(location!())
+}
+
+async fn add_bridge(handle: &Handle, name: &str, nets: &[IpNetwork]) -> Result<(), Error> {
+    handle
+        .link()
+        .add(LinkBridge::new(name).build())
+        .execute()
+        .await
+        .handle_err(location!())?;
+
+    let bridge = get_link_by_name(handle, name).await?;
+
+    for net in nets {
+        handle
+            .address()
+            .add(bridge.header.index, net.ip(), net.prefix())
+            .execute()
+            .await
+            .handle_err(location!())?;
+    }
+
+    set_link_up(handle, &bridge).await
+}
+
+async fn add_vxlan(
+    handle: &Handle,
+    name: &str,
+    vxlan_id: u32,
+    local_ip: IpAddr,
+    remote_ip: IpAddr,
+) -> Result<(), Error> {
+    let builder = LinkVxlan::new(name, vxlan_id).port(VXLAN_PORT);
+    let req = match (local_ip, remote_ip) {
+        (IpAddr::V4(local_ip), IpAddr::V4(remote_ip)) => {
+            builder.local(local_ip).remote(remote_ip).build()
+        }
+        (IpAddr::V6(local_ip), IpAddr::V6(remote_ip)) => {
+            builder.local6(local_ip).remote6(remote_ip).build()
+        }
+        _ => {
+            return Err(format!(
+                "VXLAN underlay addresses of different families: {local_ip} and {remote_ip}"
+            ))
+            .handle_err(location!());
+        }
+    };
+    handle
+        .link()
+        .add(req)
+        .execute()
+        .await
+        .handle_err(location!())?;
+
+    Ok(())
+}
+
+async fn attach_to_bridge(handle: &Handle, interface: &str, br_name: &str) -> Result<(), Error> {
+    let link = get_link_by_name(handle, interface).await?;
+    let bridge = get_link_by_name(handle, br_name).await?;
+
+    let req = LinkUnspec::new_with_index(link.header.index)
+        .controller(bridge.header.index)
+        .up()
+        .build();
+    handle
+        .link()
+        .set(req)
+        .execute()
+        .await
+        .handle_err(location!())?;
+
+    Ok(())
+}
+
+async fn move_to_netns(handle: &Handle, interface: &str, netns: NetNs<'_>) -> Result<(), Error> {
+    let link = get_link_by_name(handle, interface).await?;
+
+    // a named namespace is referenced by file descriptor, which must stay open until the request is executed
+    let ns_file;
+    let builder = LinkUnspec::new_with_index(link.header.index);
+    let req = match netns {
+        NetNs::Named(_) => {
+            ns_file = std::fs::File::open(netns.path()).handle_err(location!())?;
+            builder.setns_by_fd(ns_file.as_raw_fd()).build()
+        }
+        NetNs::Pid(pid) => builder.setns_by_pid(pid).build(),
+    };
+    handle
+        .link()
+        .set(req)
+        .execute()
+        .await
+        .handle_err(location!())?;
+
+    Ok(())
+}
+
+async fn configure_in_netns(
+    netns: NetNs<'_>,
+    interface: &str,
+    nets: &[(IpNetwork, Option<IpAddr>)],
+) -> Result<(), Error> {
+    let handle = netns_handle(netns).await?;
+
+    let link = get_link_by_name(&handle, interface).await?;
+    let index = link.header.index;
+    for (net, _) in nets {
+        handle
+            .address()
+            .add(index, net.ip(), net.prefix())
+            .execute()
+            .await
+            .handle_err(location!())?;
+    }
+    set_link_up(&handle, &link).await?;
+
+    if let Ok(lo) = get_link_by_name(&handle, "lo").await {
+        set_link_up(&handle, &lo).await?;
+    }
+
+    for gateway in nets.iter().filter_map(|(_, gateway)| *gateway) {
+        let route = match gateway {
+            IpAddr::V4(gateway) => RouteMessageBuilder::<Ipv4Addr>::new()
+                .output_interface(index)
+                .gateway(gateway)
+                .build(),
+            IpAddr::V6(gateway) => RouteMessageBuilder::<Ipv6Addr>::new()
+                .output_interface(index)
+                .gateway(gateway)
+                .build(),
+        };
+        handle
+            .route()
+            .add(route)
+            .execute()
+            .await
+            .handle_err(location!())?;
+    }
+
+    Ok(())
+}
+
+/// Open a netlink connection bound to the given namespace.
+///
+/// The socket is created from a dedicated thread that joins the namespace,
+/// so that the runtime threads stay in the host namespace; once created,
+/// the socket keeps operating in the namespace it was opened in.
+async fn netns_handle(netns: NetNs<'_>) -> Result<Handle, Error> {
+    let path = netns.path();
+    let runtime = tokio::runtime::Handle::current();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+
+    std::thread::spawn(move || {
+        let _ = tx.send(netns_connection(&path, &runtime));
+    });
+
+    rx.await.handle_err(location!())?
+}
+
+fn netns_connection(path: &str, runtime: &tokio::runtime::Handle) -> Result<Handle, Error> {
+    let ns_file = std::fs::File::open(path).handle_err(location!())?;
+    if unsafe { libc::setns(ns_file.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+        return Err(std::io::Error::last_os_error()).handle_err(location!());
+    }
+
+    let _guard = runtime.enter();
+    let (conn, handle, _) = new_connection().handle_err(location!())?;
+    runtime.spawn(conn);
+
+    Ok(handle)
+}
+
 pub(super) async fn find_ethernet_ip(handle: &Handle) -> Option<Ipv4Addr> {
     let mut links = handle.address().get().execute();
     while let Some(msg_res) = links.next().await {
@@ -1,36 +1,146 @@
-use crate::commands::{RtNetLinkHandle, configure_access_port, dnat, remove_vlan};
+use crate::commands::{
+    RtNetLinkHandle, VxlanEndpoint, configure_access_port, enable_ipv6_forwarding, remove_vlan,
+    setup_vxlan, teardown_vxlan,
+};
 use crate::ebpf::triggers::TriggersState;
+use crate::held_nets::HeldNetsState;
 use crate::host_mappings::HostMappingsState;
 use crate::peers::peer::{Peers, VethKey};
-use ipnetwork::Ipv4Network;
+use ipnetwork::{Ipv4Network, Ipv6Network};
 use nullnet_grpc_lib::NullnetGrpcInterface;
 use nullnet_grpc_lib::nullnet_grpc::{
-    HostMapping, MsgId, VlanSetup, VlanTeardown, VxlanSetup, VxlanTeardown, net_message,
+    ClientMessage, HeldNet, HostMapping, MsgId, Nack, NackCode, NodeState, PortActivity,
+    ServiceHealth, VlanSetup, VlanTeardown, VxlanSetup, VxlanTeardown, client_message, net_message,
 };
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
-use tokio::sync::mpsc::Sender;
-use tokio::sync::{RwLock, mpsc};
-
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{Sender, UnboundedReceiver};
+use tokio::sync::{RwLock, mpsc, watch};
+
+/// Delay before the first reconnection attempt, doubled on each failure.
+pub(crate) const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper bound for the reconnection delay.
+pub(crate) const MAX_BACKOFF: Duration = Duration::from_mins(1);
+
+/// Keep the control channel up, reconnecting with exponential backoff
+/// whenever it fails or the server closes it.
+///
+/// The trigger port activity received on `activity_rx` and the health reports
+/// published on `health_rx` are forwarded to the server.
 pub(crate) async fn control_channel(
     server: NullnetGrpcInterface,
     peers: Arc<RwLock<Peers>>,
     rtnetlink_handle: RtNetLinkHandle,
     triggers_state: Arc<TriggersState>,
     host_mappings_state: Arc<HostMappingsState>,
+    held_nets_state: Arc<HeldNetsState>,
+    mut activity_rx: UnboundedReceiver<PortActivity>,
+    mut health_rx: watch::Receiver<ServiceHealth>,
+) {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let connected_at = Instant::now();
+        let _ = run_control_channel(
+            &server,
+            &peers,
+            &rtnetlink_handle,
+            &triggers_state,
+            &host_mappings_state,
+            &held_nets_state,
+            &mut activity_rx,
+            &mut health_rx,
+        )
+        .await;
+
+        // a channel that stayed up for a while starts over with a short delay
+        if connected_at.elapsed() >= MAX_BACKOFF {
+            backoff = INITIAL_BACKOFF;
+        }
+        println!(
+            "Control channel closed; reconnecting in {} seconds...",
+            backoff.as_secs()
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn run_control_channel(
+    server: &NullnetGrpcInterface,
+    peers: &Arc<RwLock<Peers>>,
+    rtnetlink_handle: &RtNetLinkHandle,
+    triggers_state: &Arc<TriggersState>,
+    host_mappings_state: &Arc<HostMappingsState>,
+    held_nets_state: &Arc<HeldNetsState>,
+    activity_rx: &mut UnboundedReceiver<PortActivity>,
+    health_rx: &mut watch::Receiver<ServiceHealth>,
 ) -> Result<(), Error> {
     let (outbound, grpc_rx) = mpsc::channel(64);
+
+    // report the networks held by this host before anything else,
+    // so the server can re-adopt them or tear them down
+    let node_state = NodeState {
+        nets: held_nets(held_nets_state, triggers_state, host_mappings_state),
+    };
+    println!(
+        "Reporting {} held network(s) on control channel connection",
+        node_state.nets.len()
+    );
+    outbound
+        .send(ClientMessage {
+            message: Some(client_message::Message::NodeState(node_state)),
+        })
+        .await
+        .handle_err(location!())?;
+
+    // re-sync the health of the replicas hosted here, e.g. after a server restart
+    let health = health_rx.borrow_and_update().clone();
+    outbound
+        .send(ClientMessage {
+            message: Some(client_message::Message::ServiceHealth(health)),
+        })
+        .await
+        .handle_err(location!())?;
+
     let mut inbound = server
         .control_channel(grpc_rx)
         .await
         .handle_err(location!())?;
 
-    while let Ok(Some(message)) = inbound.message().await {
+    loop {
+        let message = tokio::select! {
+            message = inbound.message() => match message {
+                Ok(Some(message)) => message,
+                Ok(None) => return Ok(()),
+                Err(status) => return Err(status.to_string()).handle_err(location!()),
+            },
+            Some(activity) = activity_rx.recv() => {
+                outbound
+                    .send(ClientMessage {
+                        message: Some(client_message::Message::PortActivity(activity)),
+                    })
+                    .await
+                    .handle_err(location!())?;
+                continue;
+            }
+            Ok(()) = health_rx.changed() => {
+                let health = health_rx.borrow_and_update().clone();
+                outbound
+                    .send(ClientMessage {
+                        message: Some(client_message::Message::ServiceHealth(health)),
+                    })
+                    .await
+                    .handle_err(location!())?;
+                continue;
+            }
+        };
         let rtnetlink_handle = rtnetlink_handle.clone();
         let peers = peers.clone();
         let outbound = outbound.clone();
         let host_mappings_state = host_mappings_state.clone();
+        let held_nets_state = held_nets_state.clone();
         match message.message {
             Some(net_message::Message::VlanSetup(vlan_setup)) => {
                 tokio::spawn(async move {
@@ -40,6 +150,7 @@ pub(crate) async fn control_channel(
                         peers,
                         outbound,
                         host_mappings_state,
+                        held_nets_state,
                     )
                     .await;
                 });
@@ -51,6 +162,7 @@ pub(crate) async fn control_channel(
                         rtnetlink_handle,
                         peers,
                         host_mappings_state,
+                        held_nets_state,
                     )
                     .await;
                 });
@@ -60,9 +172,11 @@ pub(crate) async fn control_channel(
                 tokio::spawn(async move {
                     let _ = handle_vxlan_setup(
                         vxlan_setup,
+                        rtnetlink_handle,
                         outbound,
                         triggers_state,
                         host_mappings_state,
+                        held_nets_state,
                     )
                     .await;
                 });
@@ -70,51 +184,106 @@ pub(crate) async fn control_channel(
             Some(net_message::Message::VxlanTeardown(vxlan_teardown)) => {
                 let triggers_state = triggers_state.clone();
                 tokio::spawn(async move {
-                    handle_vxlan_teardown(vxlan_teardown, triggers_state, host_mappings_state);
+                    let _ = handle_vxlan_teardown(
+                        vxlan_teardown,
+                        rtnetlink_handle,
+                        triggers_state,
+                        host_mappings_state,
+                        held_nets_state,
+                    )
+                    .await;
                 });
             }
             None => {}
         }
     }
+}
 
-    Ok(())
+/// The networks held by this host, with the host mapping and DNAT port installed for each.
+fn held_nets(
+    held_nets_state: &HeldNetsState,
+    triggers_state: &TriggersState,
+    host_mappings_state: &HostMappingsState,
+) -> Vec<HeldNet> {
+    held_nets_state
+        .held()
+        .into_iter()
+        .map(|mut net| {
+            net.host_mapping = host_mappings_state.get(net.net_id);
+            net.dnat_port = triggers_state.active_port(net.net_id).map(u32::from);
+            net
+        })
+        .collect()
+}
+
+fn ack(msg_id: &MsgId) -> ClientMessage {
+    ClientMessage {
+        message: Some(client_message::Message::Ack(msg_id.clone())),
+    }
+}
+
+/// Reject the setup identified by `msg_id` if `res` is an error, so the server
+/// can roll back right away instead of waiting for an acknowledgement.
+async fn nack_on_err<T>(
+    res: Result<T, Error>,
+    outbound: &Sender<ClientMessage>,
+    msg_id: &MsgId,
+    code: NackCode,
+) -> Result<T, Error> {
+    if let Err(e) = &res {
+        let nack = Nack {
+            msg_id: Some(msg_id.clone()),
+            code: code.into(),
+            reason: e.to_str().to_string(),
+        };
+        let _ = outbound
+            .send(ClientMessage {
+                message: Some(client_message::Message::Nack(nack)),
+            })
+            .await;
+    }
+    res
 }
 
 async fn handle_vlan_setup(
     message: VlanSetup,
     rtnetlink_handle: RtNetLinkHandle,
     peers: Arc<RwLock<Peers>>,
-    outbound: Sender<MsgId>,
+    outbound: Sender<ClientMessage>,
     host_mappings_state: Arc<HostMappingsState>,
+    held_nets_state: Arc<HeldNetsState>,
 ) -> Result<(), Error> {
     let msg_id = &message
         .msg_id
         .ok_or("Missing message ID in VXLAN setup message")
         .handle_err(location!())?;
-    let local_veth = message
-        .local_veth
-        .parse::<Ipv4Addr>()
-        .handle_err(location!())?;
-    let remote_ip = message
-        .remote_ip
-        .parse::<Ipv4Addr>()
-        .handle_err(location!())?;
-    let remote_veth = message
-        .remote_veth
-        .parse::<Ipv4Addr>()
-        .handle_err(location!())?;
-    let vlan_id = u16::try_from(message.vlan_id).handle_err(location!())?;
+    let parsed = (|| {
+        let local_veth = message
+            .local_veth
+            .parse::<Ipv4Addr>()
+            .handle_err(location!())?;
+        let remote_ip = message
+            .remote_ip
+            .parse::<Ipv4Addr>()
+            .handle_err(location!())?;
+        let remote_veth = message
+            .remote_veth
+            .parse::<Ipv4Addr>()
+            .handle_err(location!())?;
+        let vlan_id = u16::try_from(message.vlan_id).handle_err(location!())?;
+        let net = Ipv4Network::new(local_veth, 30).handle_err(location!())?;
+        Ok::<_, Error>((net, remote_ip, remote_veth, vlan_id))
+    })();
+    let (net, remote_ip, remote_veth, vlan_id) =
+        nack_on_err(parsed, &outbound, msg_id, NackCode::InvalidMessage).await?;
 
     // setup VLAN on this machine
     let init_t = std::time::Instant::now();
-    configure_access_port(
-        &rtnetlink_handle,
-        vlan_id,
-        Ipv4Network::new(local_veth, 30).unwrap(),
-    )
-    .await;
+    let res = configure_access_port(&rtnetlink_handle, vlan_id, net).await;
+    nack_on_err(res, &outbound, msg_id, NackCode::SetupFailed).await?;
     println!(
-        "veth {local_veth} setup completed in {} ms",
+        "veth {} setup completed in {} ms",
+        net.ip(),
         init_t.elapsed().as_millis()
     );
 
@@ -123,6 +292,7 @@ async fn handle_vlan_setup(
         .write()
         .await
         .insert(VethKey::new(remote_veth, vlan_id), remote_ip);
+    held_nets_state.record_vlan(vlan_id);
 
     // add host mapping if needed
     if let Some(host_mapping) = &message.host_mapping {
@@ -131,7 +301,7 @@ async fn handle_vlan_setup(
     }
 
     // acknowledge message
-    let _ = outbound.send(msg_id.clone()).await;
+    let _ = outbound.send(ack(msg_id)).await;
 
     Ok(())
 }
@@ -141,6 +311,7 @@ async fn handle_vlan_teardown(
     rtnetlink_handle: RtNetLinkHandle,
     peers: Arc<RwLock<Peers>>,
     host_mappings_state: Arc<HostMappingsState>,
+    held_nets_state: Arc<HeldNetsState>,
 ) -> Result<(), Error> {
     let vlan_id = u16::try_from(message.vlan_id).handle_err(location!())?;
 
@@ -156,6 +327,7 @@ async fn handle_vlan_teardown(
 
     // remove peer
     peers.write().await.remove(vlan_id);
+    held_nets_state.forget(u32::from(vlan_id));
 
     // remove host mapping if one was installed at setup
     if let Some(host_mapping) = host_mappings_state.take_vlan(vlan_id) {
@@ -167,53 +339,93 @@ async fn handle_vlan_teardown(
 
 async fn handle_vxlan_setup(
     message: VxlanSetup,
-    outbound: Sender<MsgId>,
+    rtnetlink_handle: RtNetLinkHandle,
+    outbound: Sender<ClientMessage>,
     triggers_state: Arc<TriggersState>,
     host_mappings_state: Arc<HostMappingsState>,
+    held_nets_state: Arc<HeldNetsState>,
 ) -> Result<(), Error> {
     let msg_id = &message
         .msg_id
         .ok_or("Missing message ID in VXLAN setup message")
         .handle_err(location!())?;
     let vxlan_id = message.vxlan_id;
+    let parsed = (|| {
+        let ns_net = message
+            .ns_net
+            .parse::<Ipv4Network>()
+            .handle_err(location!())?;
+        let br_net = message
+            .br_net
+            .parse::<Ipv4Network>()
+            .handle_err(location!())?;
+        let ns_net6 = message
+            .ns_net6
+            .as_deref()
+            .map(str::parse::<Ipv6Network>)
+            .transpose()
+            .handle_err(location!())?;
+        let br_net6 = message
+            .br_net6
+            .as_deref()
+            .map(str::parse::<Ipv6Network>)
+            .transpose()
+            .handle_err(location!())?;
+        let local_ip = message.local_ip.parse::<IpAddr>().handle_err(location!())?;
+        let remote_ip = message
+            .remote_ip
+            .parse::<IpAddr>()
+            .handle_err(location!())?;
+        Ok::<_, Error>((ns_net, br_net, ns_net6, br_net6, local_ip, remote_ip))
+    })();
+    let (ns_net, br_net, ns_net6, br_net6, local_ip, remote_ip) =
+        nack_on_err(parsed, &outbound, msg_id, NackCode::InvalidMessage).await?;
     let ns_name = message.ns_name;
-    let ns_net = message
-        .ns_net
-        .parse::<Ipv4Network>()
-        .handle_err(location!())?;
     let br_name = message.br_name;
-    let br_net = message
-        .br_net
-        .parse::<Ipv4Network>()
-        .handle_err(location!())?;
-    let local_ip = message
-        .local_ip
-        .parse::<Ipv4Addr>()
-        .handle_err(location!())?;
-    let remote_ip = message
-        .remote_ip
-        .parse::<Ipv4Addr>()
-        .handle_err(location!())?;
+
+    // IPv6 overlay addresses are only assigned when the server has an IPv6 plan
+    if ns_net6.is_some() {
+        enable_ipv6_forwarding();
+    }
 
     // setup VXLAN on this machine (optionally attaching a Docker container)
     let init_t = std::time::Instant::now();
-    let mut cmd = std::process::Command::new("./vxlan_scripts/vxlan-setup.sh");
-    cmd.arg(vxlan_id.to_string())
-        .arg(&ns_name)
-        .arg(ns_net.to_string())
-        .arg(br_name)
-        .arg(br_net.to_string())
-        .arg(local_ip.to_string())
-        .arg(remote_ip.to_string());
-    if let Some(container) = &message.docker_container {
-        cmd.arg(container);
+    let endpoint = VxlanEndpoint {
+        vxlan_id,
+        ns_name: &ns_name,
+        ns_net,
+        ns_net6,
+        br_name: &br_name,
+        br_net,
+        br_net6,
+        local_ip,
+        remote_ip,
+        docker_container: message.docker_container.as_deref(),
+    };
+    if let Err(e) = setup_vxlan(&rtnetlink_handle, &endpoint).await {
+        // roll back whatever was set up before the failure; the setup is not acknowledged
+        let _ = teardown_vxlan(
+            &rtnetlink_handle,
+            vxlan_id,
+            &ns_name,
+            &br_name,
+            endpoint.docker_container,
+        )
+        .await;
+        return nack_on_err(Err(e), &outbound, msg_id, NackCode::SetupFailed).await;
     }
-    let _ = cmd.spawn().map(|mut c| c.wait()).handle_err(location!());
+    let uplink = endpoint.uplink();
     println!(
         "VXLAN {vxlan_id} setup completed in {} ms (docker: {})",
         init_t.elapsed().as_millis(),
         message.docker_container.as_deref().unwrap_or("none"),
     );
+    held_nets_state.record_vxlan(
+        vxlan_id,
+        ns_name,
+        br_name.clone(),
+        message.docker_container.clone(),
+    );
 
     // add host mapping if needed
     if let Some(host_mapping) = &message.host_mapping {
@@ -224,32 +436,43 @@ async fn handle_vxlan_setup(
             message.docker_container.clone(),
         );
 
-        // backend-entry edge: install DNAT(dnat_port -> overlay_ip) so the
-        // initiator's traffic on that local port is steered into the new VXLAN
+        // backend-entry edge: redirect dnat_port -> overlay_ip (for each address
+        // family) so the initiator's traffic on that local port is steered into
+        // the new VXLAN
+        let overlay_ips: Vec<IpAddr> = std::iter::once(host_mapping.ip.as_str())
+            .chain(host_mapping.ip6.as_deref())
+            .filter_map(|ip| ip.parse().ok())
+            .collect();
         if let Some(dnat_port) = message.dnat_port
             && let Ok(dnat_port) = u16::try_from(dnat_port)
-            && let Ok(overlay_ip) = host_mapping.ip.parse::<Ipv4Addr>()
+            && !overlay_ips.is_empty()
         {
-            dnat::install(dnat_port, overlay_ip);
-            triggers_state.mark_active(dnat_port, vxlan_id, overlay_ip);
+            triggers_state.activate(
+                dnat_port,
+                vxlan_id,
+                overlay_ips,
+                &br_name,
+                &uplink,
+                message.docker_container.as_deref(),
+            );
         }
     }
 
     // acknowledge message
-    let _ = outbound.send(msg_id.clone()).await;
+    let _ = outbound.send(ack(msg_id)).await;
 
     Ok(())
 }
 
-fn handle_vxlan_teardown(
+async fn handle_vxlan_teardown(
     message: VxlanTeardown,
+    rtnetlink_handle: RtNetLinkHandle,
     triggers_state: Arc<TriggersState>,
     host_mappings_state: Arc<HostMappingsState>,
-) {
-    // remove DNAT before tearing the tunnel down so existing flows reset cleanly
-    if let Some((port, overlay_ip)) = triggers_state.remove_by_vxlan(message.vxlan_id) {
-        dnat::remove(port, overlay_ip);
-    }
+    held_nets_state: Arc<HeldNetsState>,
+) -> Result<(), Error> {
+    // remove the redirection before tearing the tunnel down so existing flows reset cleanly
+    triggers_state.deactivate_vxlan(message.vxlan_id);
 
     // remove host mapping if one was installed at setup
     if let Some((host_mapping, docker_container)) = host_mappings_state.take_vxlan(message.vxlan_id)
@@ -257,29 +480,34 @@ fn handle_vxlan_teardown(
         let _ = remove_host_mapping(&host_mapping, docker_container.as_deref());
     }
 
+    held_nets_state.forget(message.vxlan_id);
+
     // teardown VXLAN on this machine
     let init_t = std::time::Instant::now();
 
-    let vxlan_id = message.vxlan_id.to_string();
-    let ns_name = message.ns_name;
-    let br_name = message.br_name;
-
-    let mut cmd = std::process::Command::new("./vxlan_scripts/vxlan-teardown.sh");
-    cmd.arg(&vxlan_id).arg(&ns_name).arg(&br_name);
-    if let Some(container) = &message.docker_container {
-        cmd.arg(container);
-    }
-    let _ = cmd.spawn().map(|mut c| c.wait()).handle_err(location!());
+    teardown_vxlan(
+        &rtnetlink_handle,
+        message.vxlan_id,
+        &message.ns_name,
+        &message.br_name,
+        message.docker_container.as_deref(),
+    )
+    .await?;
 
     println!(
         "VXLAN teardown completed in {} ms",
         init_t.elapsed().as_millis()
     );
+
+    Ok(())
 }
 
 fn add_host_mapping(hm: &HostMapping, docker_container: Option<&str>) -> Result<(), Error> {
     let path = "/etc/hosts";
-    let entry = format!("{} {}", hm.ip, hm.name);
+    let entries: Vec<String> = std::iter::once(&hm.ip)
+        .chain(hm.ip6.as_ref())
+        .map(|ip| format!("{ip} {}", hm.name))
+        .collect();
 
     if let Some(container) = docker_container {
         // container-targeted: the resolver that needs this name lives inside
@@ -288,7 +516,7 @@ fn add_host_mapping(hm: &HostMapping, docker_container: Option<&str>) -> Result<
             .args(["exec", container, "cat", path])
             .output()
             .handle_err(location!())?;
-        let content = upsert_hosts_entry(&String::from_utf8_lossy(&cat.stdout), &hm.name, &entry);
+        let content = upsert_hosts_entry(&String::from_utf8_lossy(&cat.stdout), &hm.name, &entries);
         let mut child = std::process::Command::new("docker")
             .args([
                 "exec",
@@ -311,25 +539,22 @@ fn add_host_mapping(hm: &HostMapping, docker_container: Option<&str>) -> Result<
     } else {
         // host-targeted: upsert into the host's /etc/hosts
         let content = std::fs::read_to_string(path).handle_err(location!())?;
-        std::fs::write(path, upsert_hosts_entry(&content, &hm.name, &entry))
+        std::fs::write(path, upsert_hosts_entry(&content, &hm.name, &entries))
             .handle_err(location!())?;
     }
 
     Ok(())
 }
 
-fn upsert_hosts_entry(content: &str, name: &str, entry: &str) -> String {
-    let mut lines: Vec<String> = content.lines().map(ToString::to_string).collect();
-    let mut found = false;
-    for line in &mut lines {
-        if line.split_whitespace().skip(1).any(|tok| tok == name) {
-            *line = entry.to_string();
-            found = true;
-        }
-    }
-    if !found {
-        lines.push(entry.to_string());
-    }
+fn upsert_hosts_entry(content: &str, name: &str, entries: &[String]) -> String {
+    // drop stale lines for this name (possibly of another address family),
+    // then append one line per current address
+    let mut lines: Vec<String> = content
+        .lines()
+        .filter(|line| !line.split_whitespace().skip(1).any(|tok| tok == name))
+        .map(ToString::to_string)
+        .collect();
+    lines.extend(entries.iter().cloned());
     lines.join("\n") + "\n"
 }
 
@@ -0,0 +1,123 @@
+//! Traffic of the active backend-triggered chains, reported to the server so that
+//! it can tear down the idle ones.
+//!
+//! Each chain is accounted on the uplink of its entry network (the VXLAN tunnel, or
+//! the veth pair towards the other bridge when both ends are on this machine), so
+//! that chains are counted separately whatever their trigger port, and same-host
+//! chains are counted too.
+
+use crate::ebpf::load::attach;
+use crate::ebpf::redirect::{PIN_PATH, if_index};
+use aya::maps::{Map, MapData, PerCpuHashMap};
+use aya::programs::TcAttachType;
+use aya::{Ebpf, Pod};
+use nullnet_grpc_lib::nullnet_grpc::{PortActivity, PortCounters};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+/// Value of the `LINK_STATS` map, mirrors the eBPF-side definition.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct LinkStats {
+    packets: u64,
+    bytes: u64,
+}
+
+unsafe impl Pod for LinkStats {}
+
+/// The uplink of an active backend-triggered chain.
+struct WatchedLink {
+    ifindex: u32,
+    port: u16,
+    docker_container: Option<String>,
+    /// Totals as of the previous report.
+    reported: LinkStats,
+    /// Counting program attached to the uplink (detached when dropped).
+    _bpf: Ebpf,
+}
+
+/// Watched uplinks, by VXLAN ID.
+static LINKS: LazyLock<Mutex<HashMap<u32, WatchedLink>>> = LazyLock::new(Mutex::default);
+
+/// Start counting the traffic of the chain triggered on `port` (by `docker_container`,
+/// if any), leaving through `uplink`.
+pub fn watch(vxlan_id: u32, port: u16, uplink: &str, docker_container: Option<&str>) {
+    let res = if_index(uplink).and_then(|ifindex| {
+        // a previous interface with the same index may have left its counts behind
+        if let Ok(mut stats) = link_stats() {
+            let _ = stats.remove(&ifindex);
+        }
+        let bpf = attach(uplink, TcAttachType::Egress, "nullnet_count_link")?;
+        Ok(WatchedLink {
+            ifindex,
+            port,
+            docker_container: docker_container.map(String::from),
+            reported: LinkStats::default(),
+            _bpf: bpf,
+        })
+    });
+    match res {
+        Ok(link) => {
+            LINKS.lock().unwrap().insert(vxlan_id, link);
+            println!("[activity] {port}: counting traffic on {uplink}");
+        }
+        Err(e) => eprintln!("[activity] {port}: counting traffic on {uplink}: {e}"),
+    }
+}
+
+/// Stop counting the traffic of the chain through VXLAN `vxlan_id`.
+pub fn unwatch(vxlan_id: u32) {
+    let Some(link) = LINKS.lock().unwrap().remove(&vxlan_id) else {
+        return;
+    };
+    if let Ok(mut stats) = link_stats() {
+        let _ = stats.remove(&link.ifindex);
+    }
+    println!("[activity] {}: not counting traffic anymore", link.port);
+}
+
+/// Traffic of each watched chain since the previous call.
+/// Chains without new traffic are left out.
+pub(super) fn take_activity() -> PortActivity {
+    let mut activity = PortActivity::default();
+    let mut links = LINKS.lock().unwrap();
+    if links.is_empty() {
+        return activity;
+    }
+    let stats = match link_stats() {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("[activity] {e}; skipping report");
+            return activity;
+        }
+    };
+    for link in links.values_mut() {
+        let Ok(per_cpu) = stats.get(&link.ifindex, 0) else {
+            continue;
+        };
+        let total = per_cpu
+            .iter()
+            .fold(LinkStats::default(), |acc, s| LinkStats {
+                packets: acc.packets + s.packets,
+                bytes: acc.bytes + s.bytes,
+            });
+        let last = std::mem::replace(&mut link.reported, total);
+        let packets = total.packets.saturating_sub(last.packets);
+        if packets > 0 {
+            activity.ports.push(PortCounters {
+                port: u32::from(link.port),
+                packets,
+                bytes: total.bytes.saturating_sub(last.bytes),
+                docker_container: link.docker_container.clone(),
+            });
+        }
+    }
+    activity
+}
+
+fn link_stats() -> Result<PerCpuHashMap<MapData, u32, LinkStats>, String> {
+    let path = format!("{PIN_PATH}/LINK_STATS");
+    let map_data = MapData::from_pin(&path).map_err(|e| format!("open '{path}': {e}"))?;
+    PerCpuHashMap::try_from(Map::PerCpuHashMap(map_data))
+        .map_err(|e| format!("LINK_STATS is not a PerCpuHashMap: {e}"))
+}
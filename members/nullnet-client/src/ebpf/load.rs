@@ -1,58 +1,56 @@
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::time::Duration;
 
+use crate::ebpf::activity;
+use crate::ebpf::redirect::PIN_PATH;
 use aya::{
     Ebpf, EbpfLoader, include_bytes_aligned,
     maps::{HashMap as AyaHashMap, RingBuf},
     programs::{SchedClassifier, TcAttachType, tc},
 };
+use nullnet_grpc_lib::nullnet_grpc::PortActivity;
 use tokio::io::Interest;
 use tokio::io::unix::AsyncFd;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
+/// How often the traffic of the active backend-triggered chains is reported to the server.
+const ACTIVITY_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
 /// Spin up the eBPF programs and the egress observer task.
 ///
 /// `config_rx` carries port → service-name updates pushed by the
 /// services-list loop in `main`. The observer applies the diff to the
 /// kernel-side `WATCH_PORTS` map and emits `(service_name, port)` tuples on
 /// `trigger_tx` whenever the kernel reports outgoing traffic on a watched
-/// port.
+/// port. The traffic of the active backend-triggered chains (see `activity`)
+/// is periodically reported on `activity_tx`.
 pub fn load_ebpf(
     eth_name: &str,
     config_rx: UnboundedReceiver<HashMap<u16, String>>,
     trigger_tx: UnboundedSender<(String, u16)>,
+    activity_tx: UnboundedSender<PortActivity>,
 ) {
     crate::ebpf::log::init();
     raise_memlock_rlimit();
 
     println!("[load_ebpf] eth={eth_name}");
 
-    // Ingress: attach a passive program kept alive for future use.
-    {
-        let eth_name = eth_name.to_string();
-        tokio::spawn(async move {
-            match attach(&eth_name, TcAttachType::Ingress) {
-                Ok(_bpf) => {
-                    println!("[Ingress] attached and idle");
-                    std::future::pending::<()>().await
-                }
-                Err(e) => eprintln!("[Ingress] {e}"),
-            }
-        });
-    }
+    // (the ingress program translating replies to redirected flows back is attached
+    // to the overlay bridges as redirections are installed, see `redirect::install`)
 
-    // Egress: attach, then poll EVENTS and apply config updates.
+    // Egress: attach, then poll EVENTS, apply config updates and report activity.
     {
         let eth_name = eth_name.to_string();
         tokio::spawn(async move {
-            let mut bpf = match attach(&eth_name, TcAttachType::Egress) {
+            let mut bpf = match attach(&eth_name, TcAttachType::Egress, "nullnet_filter_ports") {
                 Ok(b) => b,
                 Err(e) => {
                     eprintln!("[Egress] {e}");
                     return;
                 }
             };
-            if let Err(e) = run_observer(&mut bpf, config_rx, trigger_tx).await {
+            if let Err(e) = run_observer(&mut bpf, config_rx, trigger_tx, activity_tx).await {
                 eprintln!("[Egress] {e}");
             }
         });
@@ -71,8 +69,18 @@ fn raise_memlock_rlimit() {
     }
 }
 
-fn attach(eth_name: &str, direction: TcAttachType) -> Result<Ebpf, String> {
+/// Load the TC program `program` and attach it to `eth_name` in the given direction.
+/// It stays attached until the returned `Ebpf` is dropped.
+pub(super) fn attach(
+    eth_name: &str,
+    direction: TcAttachType,
+    program: &str,
+) -> Result<Ebpf, String> {
+    // redirect-mode maps are pinned, so both directions (and userspace) share them
+    std::fs::create_dir_all(PIN_PATH)
+        .map_err(|e| format!("[{direction:?}] create pin path '{PIN_PATH}': {e}"))?;
     let mut loader = EbpfLoader::new();
+    loader.map_pin_path(PIN_PATH);
     if direction == TcAttachType::Egress {
         loader.set_global("IS_EGRESS", &1u8, true);
     }
@@ -93,10 +101,8 @@ fn attach(eth_name: &str, direction: TcAttachType) -> Result<Ebpf, String> {
     }
 
     let program: &mut SchedClassifier = bpf
-        .program_mut("nullnet_filter_ports")
-        .ok_or_else(|| {
-            format!("[{direction:?}] program 'nullnet_filter_ports' not found in bytecode")
-        })?
+        .program_mut(program)
+        .ok_or_else(|| format!("[{direction:?}] program '{program}' not found in bytecode"))?
         .try_into()
         .map_err(|e| format!("[{direction:?}] program is not a SchedClassifier: {e}"))?;
 
@@ -117,6 +123,7 @@ async fn run_observer(
     bpf: &mut Ebpf,
     mut config_rx: UnboundedReceiver<HashMap<u16, String>>,
     trigger_tx: UnboundedSender<(String, u16)>,
+    activity_tx: UnboundedSender<PortActivity>,
 ) -> Result<(), String> {
     let events: RingBuf<_> = bpf
         .take_map("EVENTS")
@@ -130,8 +137,16 @@ async fn run_observer(
 
     let mut port_to_service: HashMap<u16, String> = HashMap::new();
 
+    let mut report_interval = tokio::time::interval(ACTIVITY_REPORT_INTERVAL);
+
     loop {
         tokio::select! {
+            _ = report_interval.tick() => {
+                let activity = activity::take_activity();
+                if !activity.ports.is_empty() && activity_tx.send(activity).is_err() {
+                    eprintln!("[observer] activity receiver dropped");
+                }
+            }
             maybe_config = config_rx.recv() => {
                 let Some(new_config) = maybe_config else {
                     return Ok(());
@@ -144,11 +159,13 @@ async fn run_observer(
                 let events = guard.get_inner_mut();
                 while let Some(item) = events.next() {
                     let bytes: &[u8] = &item;
-                    if bytes.len() < 6 {
+                    // PortEvent { port: u16, dst_ip: [u8; 16] } (IPv4 is v4-mapped)
+                    let Some(ip_bytes) = bytes.get(2..18).and_then(|b| <[u8; 16]>::try_from(b).ok())
+                    else {
                         continue;
-                    }
+                    };
                     let port = u16::from_le_bytes([bytes[0], bytes[1]]);
-                    let dst_ip = Ipv4Addr::new(bytes[2], bytes[3], bytes[4], bytes[5]);
+                    let dst_ip = Ipv6Addr::from(ip_bytes).to_canonical();
                     if let Some(service_name) = port_to_service.get(&port) {
                         if let Err(e) = trigger_tx.send((service_name.clone(), port)) {
                             eprintln!(
@@ -1,3 +1,5 @@
+pub mod activity;
 pub mod load;
 mod log;
+pub mod redirect;
 pub mod triggers;
@@ -0,0 +1,163 @@
+//! Pure-eBPF redirect mode, an alternative to Netfilter DNAT (see `commands::dnat`).
+//!
+//! Userspace maintains the pinned `REDIRECTS` map (`port -> overlay target`):
+//! the TC programs rewrite matching egress traffic towards the overlay and send it out
+//! of the overlay bridge. The replies come back in through that bridge, so the ingress
+//! program translating them back is attached to each bridge with an active redirection.
+
+use crate::ebpf::load::attach;
+use aya::maps::{HashMap as AyaHashMap, Map, MapData};
+use aya::programs::TcAttachType;
+use aya::{Ebpf, Pod};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::net::Ipv4Addr;
+use std::sync::{LazyLock, Mutex};
+
+/// Where the maps shared by the TC programs and userspace are pinned.
+pub const PIN_PATH: &str = "/sys/fs/bpf/nullnet";
+
+/// How traffic on a trigger port is steered into the overlay once its chain is active.
+#[derive(Clone, Copy, Debug)]
+pub enum RedirectMode {
+    /// Netfilter DNAT (iptables or nftables, see `DNAT_BACKEND`).
+    Dnat,
+    /// TC eBPF rewrite + `bpf_redirect_neigh`, no conntrack or Netfilter required (IPv4 only).
+    Ebpf,
+}
+
+/// Value of the `REDIRECTS` map, mirrors the eBPF-side definition.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RedirectTarget {
+    ip: [u8; 4],
+    ifindex: u32,
+}
+
+unsafe impl Pod for RedirectTarget {}
+
+/// Key of the `FLOWS` map, mirrors the eBPF-side definition.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FlowKey {
+    overlay_ip: [u8; 4],
+    overlay_port: u16,
+    local_port: u16,
+    proto: u8,
+    _pad: [u8; 3],
+}
+
+unsafe impl Pod for FlowKey {}
+
+/// Ingress programs attached to the overlay bridges, with the ports redirected through each.
+#[derive(Default)]
+struct Bridges {
+    /// Bridge name -> ingress program attached to it (detached when dropped).
+    programs: HashMap<String, Ebpf>,
+    /// Redirected port -> bridge it goes through.
+    ports: HashMap<u16, String>,
+}
+
+static BRIDGES: LazyLock<Mutex<Bridges>> = LazyLock::new(Mutex::default);
+
+/// Clears redirections and flows left in the pinned maps by a previous run. Idempotent.
+pub fn init() {
+    // the maps don't exist until the programs are loaded for the first time
+    if let Ok(mut redirects) = redirects() {
+        let ports: Vec<u16> = redirects.keys().filter_map(Result::ok).collect();
+        for port in ports {
+            let _ = redirects.remove(&port);
+        }
+    }
+    if let Ok(mut flows) = flows() {
+        let keys: Vec<FlowKey> = flows.keys().filter_map(Result::ok).collect();
+        for key in keys {
+            let _ = flows.remove(&key);
+        }
+    }
+    println!("[redirect] init: stale redirections and flows cleared");
+}
+
+/// Redirects traffic for `port` to the same port on `overlay_ip`, through the bridge `br_name`.
+pub fn install(port: u16, overlay_ip: Ipv4Addr, br_name: &str) {
+    let res = if_index(br_name).and_then(|ifindex| {
+        // replies must be translated back before the redirected traffic starts flowing
+        attach_to_bridge(port, br_name)?;
+        let target = RedirectTarget {
+            ip: overlay_ip.octets(),
+            ifindex,
+        };
+        redirects()?
+            .insert(port, target, 0)
+            .map_err(|e| format!("insert into REDIRECTS: {e}"))
+    });
+    match res {
+        Ok(()) => println!("[redirect] {port} -> {overlay_ip}:{port} via {br_name}"),
+        Err(e) => eprintln!("[redirect] {port} -> {overlay_ip}:{port} via {br_name}: {e}"),
+    }
+}
+
+/// Removes the redirection installed by [`install`], detaching the ingress program
+/// from its bridge if no other port is redirected through it.
+pub fn remove(port: u16) {
+    let res = redirects().and_then(|mut redirects| {
+        redirects
+            .remove(&port)
+            .map_err(|e| format!("remove from REDIRECTS: {e}"))
+    });
+    match res {
+        Ok(()) => println!("[redirect] {port} removed"),
+        Err(e) => eprintln!("[redirect] {port} removal: {e}"),
+    }
+    detach_from_bridge(port);
+}
+
+/// Attach the ingress program to `br_name` (once per bridge), recording `port` as using it.
+fn attach_to_bridge(port: u16, br_name: &str) -> Result<(), String> {
+    let mut bridges = BRIDGES.lock().unwrap();
+    if !bridges.programs.contains_key(br_name) {
+        let bpf = attach(br_name, TcAttachType::Ingress, "nullnet_filter_ports")?;
+        bridges.programs.insert(br_name.to_string(), bpf);
+    }
+    bridges.ports.insert(port, br_name.to_string());
+    Ok(())
+}
+
+/// Forget that `port` goes through its bridge, detaching the ingress program
+/// from the bridge once no port uses it anymore.
+fn detach_from_bridge(port: u16) {
+    let mut bridges = BRIDGES.lock().unwrap();
+    let Some(br_name) = bridges.ports.remove(&port) else {
+        return;
+    };
+    if !bridges.ports.values().any(|b| *b == br_name) {
+        // dropping the program detaches it
+        bridges.programs.remove(&br_name);
+        println!("[redirect] ingress program detached from {br_name}");
+    }
+}
+
+fn redirects() -> Result<AyaHashMap<MapData, u16, RedirectTarget>, String> {
+    let path = format!("{PIN_PATH}/REDIRECTS");
+    let map_data = MapData::from_pin(&path).map_err(|e| format!("open '{path}': {e}"))?;
+    AyaHashMap::try_from(Map::HashMap(map_data))
+        .map_err(|e| format!("REDIRECTS is not a HashMap: {e}"))
+}
+
+fn flows() -> Result<AyaHashMap<MapData, FlowKey, [u8; 4]>, String> {
+    let path = format!("{PIN_PATH}/FLOWS");
+    let map_data = MapData::from_pin(&path).map_err(|e| format!("open '{path}': {e}"))?;
+    AyaHashMap::try_from(Map::LruHashMap(map_data))
+        .map_err(|e| format!("FLOWS is not an LruHashMap: {e}"))
+}
+
+pub(super) fn if_index(if_name: &str) -> Result<u32, String> {
+    let name = CString::new(if_name).map_err(|e| e.to_string())?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(format!(
+            "no interface '{if_name}': {}",
+            std::io::Error::last_os_error()
+        )),
+        ifindex => Ok(ifindex),
+    }
+}
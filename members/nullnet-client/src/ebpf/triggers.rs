@@ -1,5 +1,9 @@
+use crate::commands::dnat;
+use crate::ebpf::activity;
+use crate::ebpf::redirect::{self, RedirectMode};
+use crate::env::REDIRECT_MODE;
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
@@ -11,11 +15,17 @@ const PENDING_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Per-trigger-port lifecycle:
 /// - `Pending`: backend_trigger fired, waiting for the server to set up the chain.
-/// - `Active`: VXLAN is up and DNAT is installed. Stores the bookkeeping
-///   needed to tear DNAT down when the matching VxlanTeardown arrives.
+/// - `Active`: VXLAN is up and the redirection (DNAT or eBPF, see `REDIRECT_MODE`)
+///   is installed. Stores the bookkeeping needed to remove it (one DNAT rule set per
+///   address family) when the matching VxlanTeardown arrives.
 pub enum Lifecycle {
-    Pending { since: Instant },
-    Active { vxlan_id: u32, overlay_ip: Ipv4Addr },
+    Pending {
+        since: Instant,
+    },
+    Active {
+        vxlan_id: u32,
+        overlay_ips: Vec<IpAddr>,
+    },
 }
 
 #[derive(Default)]
@@ -44,12 +54,43 @@ impl TriggersState {
         true
     }
 
-    pub fn mark_active(&self, port: u16, vxlan_id: u32, overlay_ip: Ipv4Addr) {
+    /// Steer traffic on `port` into the VXLAN through `br_name`, using the
+    /// configured redirect mode, and mark the trigger as active.
+    /// The chain's traffic is counted on `uplink` from now on (see `activity`).
+    pub fn activate(
+        &self,
+        port: u16,
+        vxlan_id: u32,
+        overlay_ips: Vec<IpAddr>,
+        br_name: &str,
+        uplink: &str,
+        docker_container: Option<&str>,
+    ) {
+        match *REDIRECT_MODE {
+            RedirectMode::Dnat => {
+                for overlay_ip in &overlay_ips {
+                    dnat::install(port, *overlay_ip);
+                }
+            }
+            RedirectMode::Ebpf => {
+                // the TC programs only rewrite IPv4 traffic
+                match overlay_ips.iter().find_map(|ip| match ip {
+                    IpAddr::V4(ip) => Some(*ip),
+                    IpAddr::V6(_) => None,
+                }) {
+                    Some(overlay_ip) => redirect::install(port, overlay_ip, br_name),
+                    None => eprintln!("[redirect] {port}: no IPv4 overlay address"),
+                }
+            }
+        }
+
+        activity::watch(vxlan_id, port, uplink, docker_container);
+
         self.by_port.lock().unwrap().insert(
             port,
             Lifecycle::Active {
                 vxlan_id,
-                overlay_ip,
+                overlay_ips,
             },
         );
     }
@@ -59,17 +100,42 @@ impl TriggersState {
         self.by_port.lock().unwrap().remove(&port);
     }
 
-    /// Find the Active entry for `vxlan_id` and remove it. Returns the
-    /// `(port, overlay_ip)` so the caller can tear DNAT down.
-    pub fn remove_by_vxlan(&self, vxlan_id: u32) -> Option<(u16, Ipv4Addr)> {
-        let mut by_port = self.by_port.lock().unwrap();
-        let port = by_port.iter().find_map(|(p, lc)| match lc {
-            Lifecycle::Active { vxlan_id: v, .. } if *v == vxlan_id => Some(*p),
-            _ => None,
-        })?;
-        match by_port.remove(&port)? {
-            Lifecycle::Active { overlay_ip, .. } => Some((port, overlay_ip)),
-            Lifecycle::Pending { .. } => None,
+    /// The trigger port with DNAT installed towards `vxlan_id`, if any.
+    pub fn active_port(&self, vxlan_id: u32) -> Option<u16> {
+        self.by_port
+            .lock()
+            .unwrap()
+            .iter()
+            .find_map(|(p, lc)| match lc {
+                Lifecycle::Active { vxlan_id: v, .. } if *v == vxlan_id => Some(*p),
+                _ => None,
+            })
+    }
+
+    /// Find the Active entry for `vxlan_id`, remove it and its redirection.
+    pub fn deactivate_vxlan(&self, vxlan_id: u32) {
+        activity::unwatch(vxlan_id);
+        let removed = {
+            let mut by_port = self.by_port.lock().unwrap();
+            by_port
+                .iter()
+                .find_map(|(p, lc)| match lc {
+                    Lifecycle::Active { vxlan_id: v, .. } if *v == vxlan_id => Some(*p),
+                    _ => None,
+                })
+                .and_then(|port| by_port.remove(&port).map(|lc| (port, lc)))
+        };
+        let Some((port, Lifecycle::Active { overlay_ips, .. })) = removed else {
+            return;
+        };
+
+        match *REDIRECT_MODE {
+            RedirectMode::Dnat => {
+                for overlay_ip in overlay_ips {
+                    dnat::remove(port, overlay_ip);
+                }
+            }
+            RedirectMode::Ebpf => redirect::remove(port),
         }
     }
 }
@@ -1,3 +1,6 @@
+use crate::commands::dnat::DnatBackend;
+use crate::ebpf::redirect::RedirectMode;
+
 pub static CONTROL_SERVICE_ADDR: std::sync::LazyLock<String> = std::sync::LazyLock::new(|| {
     std::env::var("CONTROL_SERVICE_ADDR").unwrap_or_else(|_| {
         println!("'CONTROL_SERVICE_ADDR' environment variable not set");
@@ -20,3 +23,42 @@ pub static ETH_NAME: std::sync::LazyLock<String> = std::sync::LazyLock::new(|| {
         "ens18".to_string()
     })
 });
+
+pub static DNAT_BACKEND: std::sync::LazyLock<DnatBackend> = std::sync::LazyLock::new(|| {
+    let str = std::env::var("DNAT_BACKEND").unwrap_or_default();
+
+    match str.to_lowercase().as_str() {
+        "nftables" | "nft" => DnatBackend::Nftables,
+        "iptables" | "" => DnatBackend::Iptables,
+        _ => {
+            println!("Invalid 'DNAT_BACKEND' ({str}): using iptables");
+            DnatBackend::Iptables
+        }
+    }
+});
+
+pub static REDIRECT_MODE: std::sync::LazyLock<RedirectMode> = std::sync::LazyLock::new(|| {
+    let str = std::env::var("REDIRECT_MODE").unwrap_or_default();
+
+    match str.to_lowercase().as_str() {
+        "ebpf" => RedirectMode::Ebpf,
+        "dnat" | "" => RedirectMode::Dnat,
+        _ => {
+            println!("Invalid 'REDIRECT_MODE' ({str}): using dnat");
+            RedirectMode::Dnat
+        }
+    }
+});
+
+/// Client certificate (PEM) presented to the server; mutual TLS is used
+/// when `TLS_CERT`, `TLS_KEY` and `TLS_CA` are all set.
+pub static TLS_CERT: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| std::env::var("TLS_CERT").ok());
+
+/// Private key (PEM) of the client certificate.
+pub static TLS_KEY: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| std::env::var("TLS_KEY").ok());
+
+/// CA (PEM) the server certificate is verified against.
+pub static TLS_CA: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| std::env::var("TLS_CA").ok());
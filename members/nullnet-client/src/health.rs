@@ -0,0 +1,296 @@
+//! Active health checks of the services declared in `services.toml`.
+//!
+//! Each check (TCP connect, HTTP GET, or a command run inside the Docker container)
+//! runs on its own interval; the outcome of all the checks is published as a
+//! [`ServiceHealth`] report, forwarded to the server over the control channel.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::watch;
+use tokio::task::{Id, JoinSet};
+
+use nullnet_grpc_lib::nullnet_grpc::{ReplicaHealth, ServiceHealth};
+
+/// How often due checks are looked for.
+const TICK: Duration = Duration::from_secs(1);
+/// The report is re-published at least this often, even when nothing changed,
+/// so that replicas registered after the last change get their health too.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Health checks as declared in `services.toml`, alongside the fields parsed into `Services`.
+#[derive(Deserialize)]
+pub(crate) struct HealthChecksToml {
+    #[serde(default)]
+    services: Vec<ServiceHealthToml>,
+}
+
+#[derive(Deserialize)]
+struct ServiceHealthToml {
+    name: String,
+    docker_container: Option<String>,
+    health_check: Option<HealthCheck>,
+}
+
+impl HealthChecksToml {
+    /// Health checks keyed by `(service name, docker container)` as written in the file.
+    pub(crate) fn into_map(self) -> HashMap<(String, Option<String>), HealthCheck> {
+        self.services
+            .into_iter()
+            .filter_map(|s| Some(((s.name, s.docker_container), s.health_check?)))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct HealthCheck {
+    #[serde(flatten)]
+    probe: Probe,
+    /// Seconds between two checks.
+    #[serde(default = "default_interval")]
+    interval: u64,
+    /// Seconds after which a check is failed.
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+    /// Consecutive failures after which the replica is unhealthy.
+    #[serde(default = "default_failures")]
+    failures: u32,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+fn default_timeout() -> u64 {
+    2
+}
+
+fn default_failures() -> u32 {
+    3
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Probe {
+    /// Connect to the service port.
+    Tcp,
+    /// `GET path` on the service port, healthy on a 2xx or 3xx status.
+    Http {
+        #[serde(default = "default_path")]
+        path: String,
+    },
+    /// Run `command` inside the Docker container (or on the host), healthy on exit status 0.
+    Exec { command: Vec<String> },
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+/// A declared replica with a health check.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct HealthTarget {
+    pub(crate) service_name: String,
+    pub(crate) port: u16,
+    /// Real container name (one target per Swarm replica).
+    pub(crate) docker_container: Option<String>,
+    pub(crate) check: HealthCheck,
+}
+
+type TargetKey = (String, Option<String>);
+
+struct TargetState {
+    last_run: Instant,
+    failures: u32,
+    healthy: bool,
+}
+
+/// Run the checks of the targets currently published on `targets_rx`,
+/// publishing the outcome on `report_tx`.
+///
+/// Each check runs in its own task as soon as it's due, so a slow check doesn't
+/// hold back the others; a target is never checked twice at the same time.
+pub(crate) async fn run_health_checks(
+    targets_rx: watch::Receiver<Vec<HealthTarget>>,
+    report_tx: watch::Sender<ServiceHealth>,
+) {
+    let mut states: HashMap<TargetKey, TargetState> = HashMap::new();
+    let mut checks = JoinSet::new();
+    let mut running: HashMap<Id, TargetKey> = HashMap::new();
+    let mut last_report = Instant::now();
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let targets = targets_rx.borrow().clone();
+
+                // forget the targets no longer declared
+                states.retain(|key, _| is_declared(&targets, key));
+
+                for target in targets {
+                    let key = (target.service_name.clone(), target.docker_container.clone());
+                    let due = states.get(&key).is_none_or(|state| {
+                        state.last_run.elapsed() >= Duration::from_secs(target.check.interval)
+                    });
+                    if !due || running.values().any(|k| *k == key) {
+                        continue;
+                    }
+                    let state = states.entry(key.clone()).or_insert(TargetState {
+                        last_run: Instant::now(),
+                        failures: 0,
+                        healthy: true,
+                    });
+                    state.last_run = Instant::now();
+                    let handle = checks.spawn(async move {
+                        let passed = run_check(&target).await;
+                        (target.check.failures, passed)
+                    });
+                    running.insert(handle.id(), key);
+                }
+            }
+            Some(res) = checks.join_next_with_id(), if !checks.is_empty() => {
+                let (key, max_failures, passed) = match res {
+                    Ok((id, (max_failures, passed))) => {
+                        let Some(key) = running.remove(&id) else {
+                            continue;
+                        };
+                        (key, max_failures, passed)
+                    }
+                    Err(e) => {
+                        if let Some((service_name, docker_container)) = running.remove(&e.id()) {
+                            eprintln!(
+                                "[health] check of '{service_name}' ({docker_container:?}) did not complete: {e}"
+                            );
+                        }
+                        continue;
+                    }
+                };
+                // the target may have been removed while being checked
+                let Some(state) = states.get_mut(&key) else {
+                    continue;
+                };
+                if passed {
+                    state.failures = 0;
+                    state.healthy = true;
+                } else {
+                    state.failures += 1;
+                    if state.healthy && state.failures >= max_failures {
+                        println!("[health] '{}' ({:?}) is unhealthy", key.0, key.1);
+                        state.healthy = false;
+                    }
+                }
+
+                let report = health_report(&states);
+                if *report_tx.borrow() != report || last_report.elapsed() >= REPORT_INTERVAL {
+                    last_report = Instant::now();
+                    report_tx.send_replace(report);
+                }
+            }
+        }
+    }
+}
+
+fn is_declared(targets: &[HealthTarget], key: &TargetKey) -> bool {
+    targets
+        .iter()
+        .any(|t| (&t.service_name, &t.docker_container) == (&key.0, &key.1))
+}
+
+fn health_report(states: &HashMap<TargetKey, TargetState>) -> ServiceHealth {
+    let mut replicas: Vec<ReplicaHealth> = states
+        .iter()
+        .map(|((service_name, docker_container), state)| ReplicaHealth {
+            service_name: service_name.clone(),
+            docker_container: docker_container.clone(),
+            healthy: state.healthy,
+        })
+        .collect();
+    replicas.sort_by(|a, b| {
+        (&a.service_name, &a.docker_container).cmp(&(&b.service_name, &b.docker_container))
+    });
+    ServiceHealth { replicas }
+}
+
+async fn run_check(target: &HealthTarget) -> bool {
+    let timeout = Duration::from_secs(target.check.timeout);
+    let res = tokio::time::timeout(timeout, async {
+        match &target.check.probe {
+            Probe::Tcp => {
+                let ip = target_ip(target.docker_container.as_deref()).await?;
+                TcpStream::connect((ip, target.port)).await.ok().map(|_| ())
+            }
+            Probe::Http { path } => {
+                let ip = target_ip(target.docker_container.as_deref()).await?;
+                http_get(ip, target.port, path).await
+            }
+            Probe::Exec { command } => exec(target.docker_container.as_deref(), command).await,
+        }
+    })
+    .await;
+    matches!(res, Ok(Some(())))
+}
+
+/// Address the service is reachable at from the host.
+async fn target_ip(docker_container: Option<&str>) -> Option<IpAddr> {
+    let Some(container) = docker_container else {
+        return Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    };
+    let output = Command::new("docker")
+        .args([
+            "inspect",
+            "-f",
+            "{{range .NetworkSettings.Networks}}{{.IPAddress}} {{end}}",
+            container,
+        ])
+        .output()
+        .await
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .find_map(|ip| ip.parse().ok())
+}
+
+async fn http_get(ip: IpAddr, port: u16, path: &str) -> Option<()> {
+    let mut stream = TcpStream::connect((ip, port)).await.ok()?;
+    let request = format!("GET {path} HTTP/1.0\r\nHost: {ip}\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.ok()?;
+    let mut buf = [0u8; 32];
+    let n = stream.read(&mut buf).await.ok()?;
+    // e.g. "HTTP/1.1 200 OK"
+    let status: u16 = std::str::from_utf8(&buf[..n])
+        .ok()?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()?;
+    (200..400).contains(&status).then_some(())
+}
+
+async fn exec(docker_container: Option<&str>, command: &[String]) -> Option<()> {
+    let mut cmd = match docker_container {
+        Some(container) => {
+            let mut cmd = Command::new("docker");
+            cmd.args(["exec", container]).args(command);
+            cmd
+        }
+        None => {
+            let (program, args) = command.split_first()?;
+            let mut cmd = Command::new(program);
+            cmd.args(args);
+            cmd
+        }
+    };
+    let status = cmd
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await
+        .ok()?;
+    status.success().then_some(())
+}
@@ -0,0 +1,51 @@
+use nullnet_grpc_lib::nullnet_grpc::HeldNet;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Tracks the networks set up on this host, so they can be reported to the
+/// server whenever the control channel (re)connects: the server re-adopts the
+/// ones it knows about and sends explicit teardowns for the others.
+#[derive(Default)]
+pub struct HeldNetsState {
+    by_id: Mutex<HashMap<u32, HeldNet>>,
+}
+
+impl HeldNetsState {
+    pub fn record_vlan(&self, vlan_id: u16) {
+        let net_id = u32::from(vlan_id);
+        self.by_id.lock().unwrap().insert(
+            net_id,
+            HeldNet {
+                net_id,
+                ..Default::default()
+            },
+        );
+    }
+
+    pub fn record_vxlan(
+        &self,
+        vxlan_id: u32,
+        ns_name: String,
+        br_name: String,
+        docker_container: Option<String>,
+    ) {
+        self.by_id.lock().unwrap().insert(
+            vxlan_id,
+            HeldNet {
+                net_id: vxlan_id,
+                ns_name,
+                br_name,
+                docker_container,
+                ..Default::default()
+            },
+        );
+    }
+
+    pub fn forget(&self, net_id: u32) {
+        self.by_id.lock().unwrap().remove(&net_id);
+    }
+
+    pub fn held(&self) -> Vec<HeldNet> {
+        self.by_id.lock().unwrap().values().cloned().collect()
+    }
+}
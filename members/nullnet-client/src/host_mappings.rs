@@ -27,6 +27,20 @@ impl HostMappingsState {
             .insert(vxlan_id, (hm, docker_container));
     }
 
+    /// The mapping installed for a VLAN or VXLAN, if any.
+    pub fn get(&self, net_id: u32) -> Option<HostMapping> {
+        let by_vlan = u16::try_from(net_id)
+            .ok()
+            .and_then(|vlan_id| self.by_vlan.lock().unwrap().get(&vlan_id).cloned());
+        by_vlan.or_else(|| {
+            self.by_vxlan
+                .lock()
+                .unwrap()
+                .get(&net_id)
+                .map(|(hm, _)| hm.clone())
+        })
+    }
+
     pub fn take_vxlan(&self, vxlan_id: u32) -> Option<(HostMapping, Option<String>)> {
         self.by_vxlan.lock().unwrap().remove(&vxlan_id)
     }
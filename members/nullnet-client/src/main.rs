@@ -1,20 +1,24 @@
 #![allow(clippy::used_underscore_binding)]
 
 use crate::cli::Args;
-use crate::commands::{RtNetLinkHandle, cleanup_network, setup_br0};
-use crate::control_channel::control_channel;
+use crate::commands::{RtNetLinkHandle, cleanup_network, enable_forwarding, setup_br0};
+use crate::control_channel::{INITIAL_BACKOFF, MAX_BACKOFF, control_channel};
 use crate::ebpf::triggers::TriggersState;
-use crate::env::{CONTROL_SERVICE_ADDR, CONTROL_SERVICE_PORT, ETH_NAME};
+use crate::env::{CONTROL_SERVICE_ADDR, CONTROL_SERVICE_PORT, ETH_NAME, TLS_CA, TLS_CERT, TLS_KEY};
 use crate::forward::receive::receive;
 use crate::forward::send::send;
+use crate::health::{HealthCheck, HealthChecksToml, HealthTarget, run_health_checks};
+use crate::held_nets::HeldNetsState;
 use crate::host_mappings::HostMappingsState;
 use crate::local_endpoints::LocalEndpoints;
 use crate::peers::peer::Peers;
 use clap::Parser;
 use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use nullnet_firewall::{DataLink, Firewall, FirewallError, LogLevel};
-use nullnet_grpc_lib::NullnetGrpcInterface;
-use nullnet_grpc_lib::nullnet_grpc::{Net, Services, ServicesListResponse};
+use nullnet_grpc_lib::nullnet_grpc::{
+    Net, PortActivity, Service, ServiceHealth, Services, ServicesListResponse,
+};
+use nullnet_grpc_lib::{MtlsConfig, NullnetGrpcInterface};
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
 use std::collections::HashMap;
 use std::ops::Sub;
@@ -22,8 +26,8 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::{panic, process};
-use tokio::sync::RwLock;
 use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{RwLock, watch};
 use tun_rs::{DeviceBuilder, Layer};
 
 mod cli;
@@ -33,6 +37,8 @@ mod craft;
 mod ebpf;
 mod env;
 mod forward;
+mod health;
+mod held_nets;
 mod host_mappings;
 mod local_endpoints;
 mod peers;
@@ -93,6 +99,8 @@ async fn main() -> Result<(), Error> {
     if net_type.net() == Net::Vlan {
         setup_tap(num_tasks, peers, &firewall_shared, &rtnetlink_handle).await?;
         setup_br0(&rtnetlink_handle).await;
+    } else {
+        enable_forwarding()?;
     }
 
     print_info(net_type.net());
@@ -105,6 +113,17 @@ async fn main() -> Result<(), Error> {
     // remember /etc/hosts entries installed at setup so teardown can undo them
     let host_mappings_state = Arc::new(HostMappingsState::default());
 
+    // remember the networks set up here to report them when the control channel reconnects
+    let held_nets_state = Arc::new(HeldNetsState::default());
+
+    // traffic seen on the trigger ports, reported to the server over the control channel
+    let (activity_tx, activity_rx) = tokio::sync::mpsc::unbounded_channel::<PortActivity>();
+
+    // health checks of the declared services, reported to the server over the control channel
+    let (targets_tx, targets_rx) = watch::channel(Vec::<HealthTarget>::new());
+    let (health_tx, health_rx) = watch::channel(ServiceHealth::default());
+    tokio::spawn(run_health_checks(targets_rx, health_tx));
+
     // listen on the gRPC control channel
     tokio::spawn(async move {
         control_channel(
@@ -113,23 +132,21 @@ async fn main() -> Result<(), Error> {
             rtnetlink_handle,
             triggers_state_cc,
             host_mappings_state,
+            held_nets_state,
+            activity_rx,
+            health_rx,
         )
-        .await
-        .expect("Control channel failed");
+        .await;
     });
 
     // observe outgoing dependency-port traffic via eBPF; the observer's
     // watch-port set is driven by the services-list response from the server.
     let (config_tx, config_rx) = tokio::sync::mpsc::unbounded_channel::<HashMap<u16, String>>();
     let (trigger_tx, mut trigger_rx) = tokio::sync::mpsc::unbounded_channel::<(String, u16)>();
-    ebpf::load::load_ebpf(&ETH_NAME, config_rx, trigger_tx);
+    ebpf::load::load_ebpf(&ETH_NAME, config_rx, trigger_tx, activity_tx);
 
     // declare services + push trigger config to the eBPF observer on each refresh
-    tokio::spawn(async move {
-        declare_services(grpc_server, config_tx)
-            .await
-            .expect("Failed to declare services");
-    });
+    tokio::spawn(declare_services(grpc_server, config_tx, targets_tx));
 
     // forward observed triggers to the gRPC server
     tokio::spawn(async move {
@@ -231,80 +248,136 @@ async fn grpc_init() -> Result<NullnetGrpcInterface, Error> {
     let host = CONTROL_SERVICE_ADDR.to_string();
     let port = *CONTROL_SERVICE_PORT;
 
-    let server = NullnetGrpcInterface::new(&host, port, false)
-        .await
-        .handle_err(location!())?;
+    let server = match (&*TLS_CA, &*TLS_CERT, &*TLS_KEY) {
+        (Some(ca), Some(cert), Some(key)) => {
+            let mtls = MtlsConfig::from_files(ca, cert, key).handle_err(location!())?;
+            NullnetGrpcInterface::with_mtls(&host, port, &mtls).await
+        }
+        (None, None, None) => NullnetGrpcInterface::new(&host, port, false).await,
+        _ => Err("'TLS_CERT', 'TLS_KEY' and 'TLS_CA' must be set together".to_string()),
+    }
+    .handle_err(location!())?;
 
     Ok(server)
 }
 
+/// Declare the services running here every 10 seconds, pushing the trigger ports
+/// returned by the server to the eBPF observer; failed attempts (e.g., while the server
+/// restarts) are retried with the same backoff as the control channel.
 async fn declare_services(
     grpc_server: NullnetGrpcInterface,
     config_tx: UnboundedSender<HashMap<u16, String>>,
-) -> Result<(), Error> {
+    targets_tx: watch::Sender<Vec<HealthTarget>>,
+) {
+    let mut backoff = INITIAL_BACKOFF;
     loop {
-        // read services from file
-        let services_toml = tokio::fs::read_to_string("services.toml")
-            .await
-            .handle_err(location!())?;
-        let mut services: Services = toml::from_str(&services_toml).handle_err(location!())?;
-
-        // get the map of logical name -> real container name (supports both standalone and Swarm)
-        let running_containers = get_running_docker_containers().await;
-        // get the list of actively listening ports on the host
-        let listeners = listeners::get_all().handle_err(location!())?;
-
-        // only declare services that are actually running
-        // For Swarm, a single service name may map to multiple containers (replicas),
-        // so we expand each service entry into one entry per running container.
-        let file_services = services.services;
-        services.services = Vec::new();
-        for service in file_services {
-            if let Some(container) = &service.docker_container {
-                if let Some(real_names) = running_containers.get(container.as_str()) {
-                    for real_name in real_names {
-                        let mut s = service.clone();
-                        s.docker_container = Some(real_name.clone());
-                        services.services.push(s);
+        let Ok(port_to_service) = declare_services_once(&grpc_server, &targets_tx).await else {
+            println!(
+                "Failed to declare services; retrying in {} seconds...",
+                backoff.as_secs()
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            continue;
+        };
+        backoff = INITIAL_BACKOFF;
+
+        if config_tx.send(port_to_service).is_err() {
+            // observer task gone; nothing more to do here
+            return;
+        }
+
+        // wait before re-declaring services
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+/// Declare the services running here, returning the trigger ports attached to them.
+async fn declare_services_once(
+    grpc_server: &NullnetGrpcInterface,
+    targets_tx: &watch::Sender<Vec<HealthTarget>>,
+) -> Result<HashMap<u16, String>, Error> {
+    // read services from file
+    let services_toml = tokio::fs::read_to_string("services.toml")
+        .await
+        .handle_err(location!())?;
+    let mut services: Services = toml::from_str(&services_toml).handle_err(location!())?;
+    let health_checks = toml::from_str::<HealthChecksToml>(&services_toml)
+        .handle_err(location!())?
+        .into_map();
+    let mut health_targets = Vec::new();
+
+    // get the map of logical name -> real container name (supports both standalone and Swarm)
+    let running_containers = get_running_docker_containers().await;
+    // get the list of actively listening ports on the host
+    let listeners = listeners::get_all().handle_err(location!())?;
+
+    // only declare services that are actually running
+    // For Swarm, a single service name may map to multiple containers (replicas),
+    // so we expand each service entry into one entry per running container.
+    let file_services = services.services;
+    services.services = Vec::new();
+    for service in file_services {
+        let health_check =
+            health_checks.get(&(service.name.clone(), service.docker_container.clone()));
+        if let Some(container) = &service.docker_container {
+            if let Some(real_names) = running_containers.get(container.as_str()) {
+                for real_name in real_names {
+                    let mut s = service.clone();
+                    s.docker_container = Some(real_name.clone());
+                    if let Some(check) = health_check {
+                        health_targets.push(health_target(&s, check));
                     }
+                    services.services.push(s);
                 }
-            } else {
-                // Host services: only declare if the port is actively listening
-                if listeners
-                    .iter()
-                    .any(|listener| u32::from(listener.socket.port()) == service.port)
-                {
-                    services.services.push(service);
+            }
+        } else {
+            // Host services: only declare if the port is actively listening
+            if listeners
+                .iter()
+                .any(|listener| u32::from(listener.socket.port()) == service.port)
+            {
+                if let Some(check) = health_check {
+                    health_targets.push(health_target(&service, check));
                 }
+                services.services.push(service);
             }
         }
+    }
 
-        println!("Declaring services to gRPC server: {services:?}");
-
-        // send services to gRPC server; response carries the trigger ports
-        // attached to the services we just declared as hosting.
-        let response: ServicesListResponse = grpc_server
-            .services_list(services)
-            .await
-            .handle_err(location!())?;
-
-        let mut port_to_service: HashMap<u16, String> = HashMap::new();
-        for st in response.service_triggers {
-            for port in st.ports {
-                let Ok(port) = u16::try_from(port) else {
-                    eprintln!("server returned invalid trigger port {port}; skipping");
-                    continue;
-                };
-                port_to_service.insert(port, st.service_name.clone());
-            }
-        }
-        if config_tx.send(port_to_service).is_err() {
-            // observer task gone; nothing more to do here
-            return Ok(());
+    println!("Declaring services to gRPC server: {services:?}");
+    targets_tx.send_if_modified(|targets| {
+        let changed = *targets != health_targets;
+        *targets = health_targets;
+        changed
+    });
+
+    // send services to gRPC server; response carries the trigger ports
+    // attached to the services we just declared as hosting.
+    let response: ServicesListResponse = grpc_server
+        .services_list(services)
+        .await
+        .handle_err(location!())?;
+
+    let mut port_to_service: HashMap<u16, String> = HashMap::new();
+    for st in response.service_triggers {
+        for port in st.ports {
+            let Ok(port) = u16::try_from(port) else {
+                eprintln!("server returned invalid trigger port {port}; skipping");
+                continue;
+            };
+            port_to_service.insert(port, st.service_name.clone());
         }
+    }
+    Ok(port_to_service)
+}
 
-        // wait before re-declaring services
-        tokio::time::sleep(Duration::from_secs(10)).await;
+fn health_target(service: &Service, check: &HealthCheck) -> HealthTarget {
+    HealthTarget {
+        service_name: service.name.clone(),
+        port: u16::try_from(service.port).unwrap_or_default(),
+        docker_container: service.docker_container.clone(),
+        check: check.clone(),
     }
 }
 
@@ -6,6 +6,7 @@ fn main() {
         .out_dir("./src/proto")
         .type_attribute("nullnet_grpc.Services", "#[derive(serde::Deserialize)]")
         .type_attribute("nullnet_grpc.Service", "#[derive(serde::Deserialize)]")
+        .field_attribute("nullnet_grpc.Services.labels", "#[serde(default)]")
         .compile_protos(&[NULLNET_GRPC_PATH], &[PROTOBUF_DIR_PATH])
         .expect("Protobuf files generation failed");
 }
@@ -2,7 +2,7 @@ mod proto;
 
 use crate::nullnet_grpc::nullnet_grpc_client::NullnetGrpcClient;
 use crate::nullnet_grpc::{
-    BackendTriggerRequest, Empty, MsgId, NetMessage, NetType, ProxyRequest, Services,
+    BackendTriggerRequest, ClientMessage, Empty, NetMessage, NetType, ProxyRequest, Services,
     ServicesListResponse, Upstream,
 };
 pub use proto::*;
@@ -10,21 +10,43 @@ use tokio::sync::mpsc;
 use tonic::Request;
 pub use tonic::Streaming;
 use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
-use tonic::transport::{Channel, ClientTlsConfig};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
 
 #[derive(Clone)]
 pub struct NullnetGrpcInterface {
     client: NullnetGrpcClient<Channel>,
 }
 
+/// PEM-encoded material for mutual TLS with the server.
+#[derive(Clone)]
+pub struct MtlsConfig {
+    /// CA the server certificate is verified against.
+    pub ca: Vec<u8>,
+    /// Client certificate, carrying the node's IP address in its SAN.
+    pub cert: Vec<u8>,
+    /// Private key of the client certificate.
+    pub key: Vec<u8>,
+}
+
+impl MtlsConfig {
+    /// Read the CA, certificate and key from PEM files.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn from_files(ca_path: &str, cert_path: &str, key_path: &str) -> Result<Self, String> {
+        let read = |path: &str| std::fs::read(path).map_err(|e| format!("read '{path}': {e}"));
+        Ok(Self {
+            ca: read(ca_path)?,
+            cert: read(cert_path)?,
+            key: read(key_path)?,
+        })
+    }
+}
+
 impl NullnetGrpcInterface {
     #[allow(clippy::missing_errors_doc)]
     pub async fn new(host: &str, port: u16, tls: bool) -> Result<Self, String> {
         let protocol = if tls { "https" } else { "http" };
 
-        let mut endpoint = Channel::from_shared(format!("{protocol}://{host}:{port}"))
-            .map_err(|e| e.to_string())?
-            .connect_timeout(std::time::Duration::from_secs(10));
+        let mut endpoint = Self::endpoint(protocol, host, port)?;
 
         if tls {
             endpoint = endpoint
@@ -32,6 +54,31 @@ impl NullnetGrpcInterface {
                 .map_err(|e| e.to_string())?;
         }
 
+        Self::connect(&endpoint, host, port).await
+    }
+
+    /// Connect with mutual TLS: the server is verified against `mtls.ca`
+    /// and this node authenticates with `mtls.cert`.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn with_mtls(host: &str, port: u16, mtls: &MtlsConfig) -> Result<Self, String> {
+        let tls_config = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(&mtls.ca))
+            .identity(Identity::from_pem(&mtls.cert, &mtls.key));
+
+        let endpoint = Self::endpoint("https", host, port)?
+            .tls_config(tls_config)
+            .map_err(|e| e.to_string())?;
+
+        Self::connect(&endpoint, host, port).await
+    }
+
+    fn endpoint(protocol: &str, host: &str, port: u16) -> Result<Endpoint, String> {
+        Ok(Channel::from_shared(format!("{protocol}://{host}:{port}"))
+            .map_err(|e| e.to_string())?
+            .connect_timeout(std::time::Duration::from_secs(10)))
+    }
+
+    async fn connect(endpoint: &Endpoint, host: &str, port: u16) -> Result<Self, String> {
         loop {
             if let Ok(channel) = endpoint.connect().await {
                 return Ok(Self {
@@ -60,7 +107,7 @@ impl NullnetGrpcInterface {
     #[allow(clippy::missing_errors_doc)]
     pub async fn control_channel(
         &self,
-        receiver: mpsc::Receiver<MsgId>,
+        receiver: mpsc::Receiver<ClientMessage>,
     ) -> Result<Streaming<NetMessage>, String> {
         let receiver = ReceiverStream::new(receiver);
 
@@ -74,6 +74,11 @@ pub struct VxlanSetup {
     /// initiator's traffic on that local port is steered into the new VXLAN.
     #[prost(uint32, optional, tag = "11")]
     pub dnat_port: ::core::option::Option<u32>,
+    /// Set only when an IPv6 overlay prefix is configured on the server
+    #[prost(string, optional, tag = "12")]
+    pub ns_net6: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(string, optional, tag = "13")]
+    pub br_net6: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct VxlanTeardown {
@@ -91,11 +96,115 @@ pub struct MsgId {
     #[prost(string, tag = "1")]
     pub id: ::prost::alloc::string::String,
 }
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct ClientMessage {
+    #[prost(oneof = "client_message::Message", tags = "1, 2, 3, 4, 5")]
+    pub message: ::core::option::Option<client_message::Message>,
+}
+/// Nested message and enum types in `ClientMessage`.
+pub mod client_message {
+    #[derive(Clone, PartialEq, Eq, Hash, ::prost::Oneof)]
+    pub enum Message {
+        /// Acknowledges a VLAN/VXLAN setup
+        #[prost(message, tag = "1")]
+        Ack(super::MsgId),
+        /// Networks currently held by the client, sent first on every (re)connection
+        #[prost(message, tag = "2")]
+        NodeState(super::NodeState),
+        /// Rejects a VLAN/VXLAN setup that could not be carried out
+        #[prost(message, tag = "3")]
+        Nack(super::Nack),
+        /// Traffic observed on the trigger ports since the previous report
+        #[prost(message, tag = "4")]
+        PortActivity(super::PortActivity),
+        /// Outcome of the health checks of the services hosted by the client
+        #[prost(message, tag = "5")]
+        ServiceHealth(super::ServiceHealth),
+    }
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Nack {
+    #[prost(message, optional, tag = "1")]
+    pub msg_id: ::core::option::Option<MsgId>,
+    #[prost(enumeration = "NackCode", tag = "2")]
+    pub code: i32,
+    /// Human-readable failure reason
+    #[prost(string, tag = "3")]
+    pub reason: ::prost::alloc::string::String,
+}
+/// Lets the server re-adopt the networks it knows about and tear down the others.
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct NodeState {
+    #[prost(message, repeated, tag = "1")]
+    pub nets: ::prost::alloc::vec::Vec<HeldNet>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct HeldNet {
+    /// VLAN or VXLAN ID
+    #[prost(uint32, tag = "1")]
+    pub net_id: u32,
+    /// VXLAN only
+    #[prost(string, tag = "2")]
+    pub ns_name: ::prost::alloc::string::String,
+    /// VXLAN only
+    #[prost(string, tag = "3")]
+    pub br_name: ::prost::alloc::string::String,
+    #[prost(string, optional, tag = "4")]
+    pub docker_container: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(message, optional, tag = "5")]
+    pub host_mapping: ::core::option::Option<HostMapping>,
+    /// Trigger port with DNAT installed towards this network
+    #[prost(uint32, optional, tag = "6")]
+    pub dnat_port: ::core::option::Option<u32>,
+}
+/// Periodic report of the traffic of the backend-triggered chains initiated on the client,
+/// by trigger port, used by the server to tear down the idle ones.
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct PortActivity {
+    #[prost(message, repeated, tag = "1")]
+    pub ports: ::prost::alloc::vec::Vec<PortCounters>,
+}
+/// Health of the service replicas hosted by the client that have a health check;
+/// replicas not listed are considered healthy.
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct ServiceHealth {
+    #[prost(message, repeated, tag = "1")]
+    pub replicas: ::prost::alloc::vec::Vec<ReplicaHealth>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct ReplicaHealth {
+    #[prost(string, tag = "1")]
+    pub service_name: ::prost::alloc::string::String,
+    #[prost(string, optional, tag = "2")]
+    pub docker_container: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(bool, tag = "3")]
+    pub healthy: bool,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct PortCounters {
+    #[prost(uint32, tag = "1")]
+    pub port: u32,
+    /// Packets and bytes since the previous report
+    #[prost(uint64, tag = "2")]
+    pub packets: u64,
+    #[prost(uint64, tag = "3")]
+    pub bytes: u64,
+    /// Docker container initiating the chain, if any
+    #[prost(string, optional, tag = "4")]
+    pub docker_container: ::core::option::Option<::prost::alloc::string::String>,
+}
 #[derive(serde::Deserialize)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Services {
     #[prost(message, repeated, tag = "1")]
     pub services: ::prost::alloc::vec::Vec<Service>,
+    /// Labels of the declaring node (e.g., zone, rack), used for locality-aware placement
+    #[prost(map = "string, string", tag = "2")]
+    #[serde(default)]
+    pub labels: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
 }
 #[derive(serde::Deserialize)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
@@ -128,6 +237,9 @@ pub struct HostMapping {
     pub ip: ::prost::alloc::string::String,
     #[prost(string, tag = "2")]
     pub name: ::prost::alloc::string::String,
+    /// Set only when an IPv6 overlay prefix is configured on the server
+    #[prost(string, optional, tag = "3")]
+    pub ip6: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct ProxyRequest {
@@ -180,6 +292,38 @@ impl Net {
         }
     }
 }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum NackCode {
+    /// Not set, or not known to this version
+    Unspecified = 0,
+    /// The setup message could not be parsed (e.g., malformed network addresses)
+    InvalidMessage = 1,
+    /// The network could not be built on the client
+    SetupFailed = 2,
+}
+impl NackCode {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Unspecified => "NACK_CODE_UNSPECIFIED",
+            Self::InvalidMessage => "INVALID_MESSAGE",
+            Self::SetupFailed => "SETUP_FAILED",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "NACK_CODE_UNSPECIFIED" => Some(Self::Unspecified),
+            "INVALID_MESSAGE" => Some(Self::InvalidMessage),
+            "SETUP_FAILED" => Some(Self::SetupFailed),
+            _ => None,
+        }
+    }
+}
 /// Generated client implementations.
 pub mod nullnet_grpc_client {
     #![allow(
@@ -322,7 +466,7 @@ pub mod nullnet_grpc_client {
         /// Control channel
         pub async fn control_channel(
             &mut self,
-            request: impl tonic::IntoStreamingRequest<Message = super::MsgId>,
+            request: impl tonic::IntoStreamingRequest<Message = super::ClientMessage>,
         ) -> std::result::Result<
             tonic::Response<tonic::codec::Streaming<super::NetMessage>>,
             tonic::Status,
@@ -426,7 +570,7 @@ pub mod nullnet_grpc_server {
         /// Control channel
         async fn control_channel(
             &self,
-            request: tonic::Request<tonic::Streaming<super::MsgId>>,
+            request: tonic::Request<tonic::Streaming<super::ClientMessage>>,
         ) -> std::result::Result<
             tonic::Response<Self::ControlChannelStream>,
             tonic::Status,
@@ -607,7 +751,7 @@ pub mod nullnet_grpc_server {
                 "/nullnet_grpc.NullnetGrpc/ControlChannel" => {
                     #[allow(non_camel_case_types)]
                     struct ControlChannelSvc<T: NullnetGrpc>(pub Arc<T>);
-                    impl<T: NullnetGrpc> tonic::server::StreamingService<super::MsgId>
+                    impl<T: NullnetGrpc> tonic::server::StreamingService<super::ClientMessage>
                     for ControlChannelSvc<T> {
                         type Response = super::NetMessage;
                         type ResponseStream = T::ControlChannelStream;
@@ -617,7 +761,7 @@ pub mod nullnet_grpc_server {
                         >;
                         fn call(
                             &mut self,
-                            request: tonic::Request<tonic::Streaming<super::MsgId>>,
+                            request: tonic::Request<tonic::Streaming<super::ClientMessage>>,
                         ) -> Self::Future {
                             let inner = Arc::clone(&self.0);
                             let fut = async move {
@@ -13,3 +13,16 @@ pub static CONTROL_SERVICE_PORT: std::sync::LazyLock<u16> = std::sync::LazyLock:
 
     str.parse().unwrap_or(50051)
 });
+
+/// Client certificate (PEM) presented to the server; mutual TLS is used
+/// when `TLS_CERT`, `TLS_KEY` and `TLS_CA` are all set.
+pub static TLS_CERT: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| std::env::var("TLS_CERT").ok());
+
+/// Private key (PEM) of the client certificate.
+pub static TLS_KEY: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| std::env::var("TLS_KEY").ok());
+
+/// CA (PEM) the server certificate is verified against.
+pub static TLS_CA: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| std::env::var("TLS_CA").ok());
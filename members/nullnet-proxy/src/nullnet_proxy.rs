@@ -1,6 +1,6 @@
-use crate::env::{CONTROL_SERVICE_ADDR, CONTROL_SERVICE_PORT};
-use nullnet_grpc_lib::NullnetGrpcInterface;
+use crate::env::{CONTROL_SERVICE_ADDR, CONTROL_SERVICE_PORT, TLS_CA, TLS_CERT, TLS_KEY};
 use nullnet_grpc_lib::nullnet_grpc::ProxyRequest;
+use nullnet_grpc_lib::{MtlsConfig, NullnetGrpcInterface};
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
 use std::net::{IpAddr, SocketAddr};
 
@@ -14,9 +14,15 @@ impl NullnetProxy {
         let host = CONTROL_SERVICE_ADDR.to_string();
         let port = *CONTROL_SERVICE_PORT;
 
-        let server = NullnetGrpcInterface::new(&host, port, false)
-            .await
-            .handle_err(location!())?;
+        let server = match (&*TLS_CA, &*TLS_CERT, &*TLS_KEY) {
+            (Some(ca), Some(cert), Some(key)) => {
+                let mtls = MtlsConfig::from_files(ca, cert, key).handle_err(location!())?;
+                NullnetGrpcInterface::with_mtls(&host, port, &mtls).await
+            }
+            (None, None, None) => NullnetGrpcInterface::new(&host, port, false).await,
+            _ => Err("'TLS_CERT', 'TLS_KEY' and 'TLS_CA' must be set together".to_string()),
+        }
+        .handle_err(location!())?;
 
         Ok(Self { server })
     }
@@ -0,0 +1,202 @@
+//! Persistent log of the topology events, one JSON object per line, for post-mortems.
+//!
+//! The log is rotated once it grows past `MAX_FILE_SIZE`: `audit.log` becomes `audit.log.1`,
+//! `audit.log.1` becomes `audit.log.2`, and so on up to `ROTATED_FILES`.
+
+use crate::events::Event;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+pub(crate) const AUDIT_PATH: &str = "./audit.log";
+
+const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+const ROTATED_FILES: u32 = 5;
+
+/// How many entries a query returns by default (the most recent ones).
+const DEFAULT_LIMIT: usize = 1000;
+
+pub(crate) struct AuditLog {
+    path: PathBuf,
+}
+
+/// Filters of a query of the audit log.
+#[derive(Default, Deserialize)]
+pub(crate) struct AuditQuery {
+    /// Only entries from this RFC 3339 timestamp on.
+    pub(crate) since: Option<String>,
+    pub(crate) service: Option<String>,
+    /// Only entries involving this node, on either end.
+    pub(crate) node: Option<IpAddr>,
+    pub(crate) limit: Option<usize>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Append the events to the log until the orchestrator goes away.
+    pub(crate) async fn record(self, mut events: broadcast::Receiver<Event>) {
+        loop {
+            let line = match events.recv().await {
+                Ok(event) => serde_json::to_string(&event).ok(),
+                Err(RecvError::Lagged(missed)) => Some(
+                    serde_json::json!({
+                        "timestamp": Utc::now().to_rfc3339(),
+                        "type": "lagged",
+                        "missed": missed,
+                    })
+                    .to_string(),
+                ),
+                Err(RecvError::Closed) => break,
+            };
+            if let Some(line) = line
+                && let Err(e) = self.append(&line).await
+            {
+                eprintln!("failed to write to the audit log: {e}");
+            }
+        }
+    }
+
+    async fn append(&self, line: &str) -> std::io::Result<()> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(format!("{line}\n").as_bytes()).await?;
+        if file.metadata().await?.len() > MAX_FILE_SIZE {
+            self.rotate().await?;
+        }
+        Ok(())
+    }
+
+    async fn rotate(&self) -> std::io::Result<()> {
+        for n in (1..ROTATED_FILES).rev() {
+            let from = self.rotated_path(n);
+            if tokio::fs::try_exists(&from).await? {
+                tokio::fs::rename(&from, self.rotated_path(n + 1)).await?;
+            }
+        }
+        tokio::fs::rename(&self.path, self.rotated_path(1)).await
+    }
+
+    fn rotated_path(&self, n: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    /// The most recent entries matching `query`, oldest first.
+    pub(crate) async fn query(&self, query: &AuditQuery) -> Result<Vec<Value>, String> {
+        let since = query
+            .since
+            .as_deref()
+            .map(DateTime::parse_from_rfc3339)
+            .transpose()
+            .map_err(|e| format!("invalid 'since': {e}"))?;
+        let node = query.node.map(|node| node.to_string());
+
+        let mut entries = Vec::new();
+        let files = (1..=ROTATED_FILES)
+            .rev()
+            .map(|n| self.rotated_path(n))
+            .chain([self.path.clone()]);
+        for file in files {
+            let Ok(content) = tokio::fs::read_to_string(&file).await else {
+                continue;
+            };
+            for line in content.lines() {
+                let Ok(entry) = serde_json::from_str::<Value>(line) else {
+                    continue;
+                };
+                if let Some(since) = since {
+                    let timestamp = entry["timestamp"]
+                        .as_str()
+                        .and_then(|t| DateTime::parse_from_rfc3339(t).ok());
+                    if timestamp.is_none_or(|t| t < since) {
+                        continue;
+                    }
+                }
+                if let Some(service) = &query.service
+                    && entry["service"].as_str() != Some(service)
+                {
+                    continue;
+                }
+                if let Some(node) = &node
+                    && !["ip", "node", "client_ip", "server_ip"]
+                        .iter()
+                        .any(|key| entry[key].as_str() == Some(node))
+                {
+                    continue;
+                }
+                entries.push(entry);
+            }
+        }
+
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        Ok(entries.split_off(entries.len().saturating_sub(limit)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_query_spans_rotated_files() {
+        let dir = std::env::temp_dir().join(format!("nullnet-audit-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let log = AuditLog::new(dir.join("audit.log"));
+
+        let entry = |n: u32, service: &str| {
+            format!(
+                "{{\"timestamp\":\"2026-01-01T00:00:0{n}+00:00\",\"type\":\"change_applied\",\
+                 \"change\":\"Removed\",\"service\":\"{service}\"}}\n"
+            )
+        };
+        let rotated = entry(1, "A") + &entry(2, "B");
+        tokio::fs::write(log.rotated_path(1), rotated)
+            .await
+            .unwrap();
+        tokio::fs::write(&log.path, entry(3, "A") + "not json\n" + &entry(4, "A"))
+            .await
+            .unwrap();
+
+        let services = |entries: Vec<Value>| -> Vec<String> {
+            entries
+                .iter()
+                .map(|e| {
+                    format!(
+                        "{}{}",
+                        e["service"].as_str().unwrap(),
+                        &e["timestamp"].as_str().unwrap()[18..19]
+                    )
+                })
+                .collect()
+        };
+        let all = log.query(&AuditQuery::default()).await.unwrap();
+        assert_eq!(services(all), ["A1", "B2", "A3", "A4"]);
+
+        let query = AuditQuery {
+            since: Some("2026-01-01T00:00:02Z".to_string()),
+            service: Some("A".to_string()),
+            limit: Some(1),
+            ..AuditQuery::default()
+        };
+        assert_eq!(services(log.query(&query).await.unwrap()), ["A4"]);
+
+        let query = AuditQuery {
+            since: Some("yesterday".to_string()),
+            ..AuditQuery::default()
+        };
+        assert!(log.query(&query).await.is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use ipnetwork::{Ipv4Network, Ipv6Network};
 use nullnet_grpc_lib::nullnet_grpc::Net;
 
 pub static NET_TYPE: std::sync::LazyLock<Net> = std::sync::LazyLock::new(|| {
@@ -21,3 +22,61 @@ pub static TIMEOUT: std::sync::LazyLock<u64> = std::sync::LazyLock::new(|| {
 
     str.parse().unwrap_or(60)
 });
+
+/// IPv4 ranges the overlay addresses are carved from, as a comma-separated list of CIDRs
+/// (e.g. `172.20.0.0/16,172.21.0.0/16`); defaults to `10.0.0.0/8`.
+pub static OVERLAY_CIDRS: std::sync::LazyLock<Vec<Ipv4Network>> = std::sync::LazyLock::new(|| {
+    let default = vec![Ipv4Network::new(std::net::Ipv4Addr::new(10, 0, 0, 0), 8).unwrap()];
+
+    let Ok(str) = std::env::var("OVERLAY_CIDRS") else {
+        return default;
+    };
+
+    let cidrs: Result<Vec<Ipv4Network>, _> =
+        str.split(',').map(|cidr| cidr.trim().parse()).collect();
+    match cidrs {
+        Ok(cidrs) if !cidrs.is_empty() => cidrs,
+        Ok(_) => default,
+        Err(e) => {
+            println!("Invalid 'OVERLAY_CIDRS' ({e}): using 10.0.0.0/8");
+            default
+        }
+    }
+});
+
+/// Optional IPv6 prefix for the VXLAN overlay, assigned alongside the IPv4 addresses.
+/// Each NET ID takes a block of 8 addresses, so the prefix must be /125 or shorter
+/// (a shorter prefix can also limit the number of NET IDs, see `OverlayPlan`).
+pub static OVERLAY_V6_PREFIX: std::sync::LazyLock<Option<Ipv6Network>> =
+    std::sync::LazyLock::new(|| {
+        let str = std::env::var("OVERLAY_V6_PREFIX").ok()?;
+
+        match str.parse::<Ipv6Network>() {
+            Ok(prefix) if prefix.prefix() <= 125 => Some(prefix),
+            Ok(_) => {
+                println!("'OVERLAY_V6_PREFIX' must be /125 or shorter: IPv6 overlay disabled");
+                None
+            }
+            Err(e) => {
+                println!("Invalid 'OVERLAY_V6_PREFIX' ({e}): IPv6 overlay disabled");
+                None
+            }
+        }
+    });
+
+/// Server certificate (PEM) of the gRPC endpoint; mutual TLS is enabled
+/// when `TLS_CERT`, `TLS_KEY` and `TLS_CA` are all set.
+pub static TLS_CERT: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| std::env::var("TLS_CERT").ok());
+
+/// Private key (PEM) of the server certificate.
+pub static TLS_KEY: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| std::env::var("TLS_KEY").ok());
+
+/// CA (PEM) the client certificates of nullnet-client and nullnet-proxy nodes are verified against.
+pub static TLS_CA: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| std::env::var("TLS_CA").ok());
+
+/// Bearer token required by the admin endpoints of the HTTP API, which are disabled when it's not set.
+pub static ADMIN_TOKEN: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()));
@@ -0,0 +1,111 @@
+//! Topology events, published by the orchestrator, streamed at `/api/events`
+//! and recorded in the audit log.
+
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// How many events are buffered for each subscriber before it starts missing them.
+pub(crate) const EVENTS_CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Event {
+    pub(crate) timestamp: String,
+    #[serde(flatten)]
+    pub(crate) kind: TopologyEvent,
+}
+
+impl Event {
+    pub(crate) fn new(kind: TopologyEvent) -> Self {
+        Self {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            kind,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum TopologyEvent {
+    ReplicaAdded {
+        service: String,
+        ip: IpAddr,
+        port: u16,
+        docker_container: Option<String>,
+    },
+    ReplicaRemoved {
+        service: String,
+        ip: IpAddr,
+        docker_container: Option<String>,
+        /// The `ServiceChange` that removed it.
+        reason: &'static str,
+    },
+    EdgeSetUp {
+        #[serde(flatten)]
+        edge: EdgeEvent,
+        time_ms: u128,
+    },
+    /// The setup of an edge failed on one of its ends, and was undone on both.
+    EdgeRolledBack {
+        #[serde(flatten)]
+        edge: EdgeEvent,
+        reason: String,
+    },
+    EdgeTornDown {
+        #[serde(flatten)]
+        edge: EdgeEvent,
+        /// The `ServiceChange` that tore it down.
+        reason: &'static str,
+    },
+    /// A `ServiceChange` is being applied, tearing down the chains it affects.
+    ChangeApplied {
+        change: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        service: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        node: Option<IpAddr>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        docker_container: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        net_id: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        port: Option<u16>,
+    },
+    NodeConnected {
+        ip: IpAddr,
+    },
+    NodeDisconnected {
+        ip: IpAddr,
+    },
+    ConfigReloaded {
+        services: Vec<String>,
+    },
+}
+
+/// The network between a client and a replica of a service.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct EdgeEvent {
+    pub(crate) net_id: u32,
+    pub(crate) service: String,
+    pub(crate) client: String,
+    pub(crate) client_ip: IpAddr,
+    pub(crate) server_ip: IpAddr,
+    pub(crate) client_docker: Option<String>,
+    pub(crate) server_docker: Option<String>,
+}
+
+impl TopologyEvent {
+    /// Name of the event, as in its `type` field.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            TopologyEvent::ReplicaAdded { .. } => "replica_added",
+            TopologyEvent::ReplicaRemoved { .. } => "replica_removed",
+            TopologyEvent::EdgeSetUp { .. } => "edge_set_up",
+            TopologyEvent::EdgeRolledBack { .. } => "edge_rolled_back",
+            TopologyEvent::EdgeTornDown { .. } => "edge_torn_down",
+            TopologyEvent::ChangeApplied { .. } => "change_applied",
+            TopologyEvent::NodeConnected { .. } => "node_connected",
+            TopologyEvent::NodeDisconnected { .. } => "node_disconnected",
+            TopologyEvent::ConfigReloaded { .. } => "config_reloaded",
+        }
+    }
+}
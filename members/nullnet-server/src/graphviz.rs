@@ -1,14 +1,14 @@
 use crate::env::NET_TYPE;
 use crate::services::clients::ClientInfo;
 use crate::services::service_info::ServiceInfo;
+use crate::services::service_map::{ServiceMap, Services};
 use nullnet_liberror::{ErrorHandler, Location, location};
 use serde::Serialize;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use std::fmt::Write;
 use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::RwLock;
 
 // ---------------------------------------------------------------------------
 // Shared helpers
@@ -17,9 +17,7 @@ use tokio::sync::RwLock;
 /// Set of `(service_name, replica_ip, docker_container)` triples that are
 /// acting as a client in at least one dependency edge. Used to mark replicas
 /// as "active" even when their own client map is empty.
-fn initiators(
-    services: &HashMap<String, ServiceInfo>,
-) -> HashSet<(String, IpAddr, Option<String>)> {
+fn initiators(services: &Services) -> HashSet<(String, IpAddr, Option<String>)> {
     services
         .values()
         .filter_map(|info| {
@@ -41,7 +39,7 @@ fn initiators(
 // Graphviz dot output
 // ---------------------------------------------------------------------------
 
-pub(crate) fn render_graphviz(services: &HashMap<String, ServiceInfo>) -> String {
+pub(crate) fn render_graphviz(services: &Services) -> String {
     let mut entries: Vec<_> = services.iter().collect();
     entries.sort_by_key(|(name, _)| *name);
 
@@ -79,9 +77,9 @@ pub(crate) fn render_graphviz(services: &HashMap<String, ServiceInfo>) -> String
     graphviz
 }
 
-pub(crate) async fn generate_graphviz(services: Arc<RwLock<HashMap<String, ServiceInfo>>>) {
+pub(crate) async fn generate_graphviz(services: Arc<ServiceMap>) {
     loop {
-        let services = services.read().await.clone();
+        let services = services.snapshot().await;
         let graphviz = render_graphviz(&services);
         let _ = tokio::fs::write("graph.dot", graphviz)
             .await
@@ -175,7 +173,7 @@ struct GraphEdgeJson {
     setup_ms: u128,
 }
 
-pub(crate) fn render_graph_json(services: &HashMap<String, ServiceInfo>) -> GraphJson {
+pub(crate) fn render_graph_json(services: &Services) -> GraphJson {
     let initiators = initiators(services);
 
     let mut nodes: Vec<GraphNodeJson> = services
@@ -0,0 +1,160 @@
+use super::AppState;
+use crate::env::ADMIN_TOKEN;
+use crate::services::changes::{
+    ServiceChange, apply_changes, detect_client_eviction, detect_net_eviction,
+    detect_replica_draining,
+};
+use crate::services::clients::Client;
+use crate::services::service_info::ServiceInfo;
+use axum::Json;
+use axum::extract::{Path, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use std::net::IpAddr;
+
+/// Reject requests without `Authorization: Bearer <ADMIN_TOKEN>`, and all of them
+/// when no token is set. Guards the admin API and the writes to `services.toml`.
+pub(super) async fn require_admin_token(request: Request, next: Next) -> Response {
+    let Some(token) = ADMIN_TOKEN.as_deref() else {
+        return (
+            StatusCode::FORBIDDEN,
+            "this endpoint is disabled: set ADMIN_TOKEN to enable it",
+        )
+            .into_response();
+    };
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|provided| constant_time_eq(provided.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Compare without bailing out at the first difference, not to leak the token through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Deserialize)]
+pub(super) struct ProxyClientJson {
+    service: String,
+    /// IP of the client, as seen by the proxy.
+    client: String,
+    proxy: IpAddr,
+}
+
+#[derive(Deserialize)]
+pub(super) struct ReplicaJson {
+    service: String,
+    ip: IpAddr,
+    docker_container: Option<String>,
+}
+
+/// Tear down the chains of a proxy client.
+pub(super) async fn evict_client_handler(
+    State(state): State<AppState>,
+    Json(body): Json<ProxyClientJson>,
+) -> Response {
+    let client = Client::new(body.client, Some(body.proxy));
+    let mut services = state.services.lock_chains_of(&[&body.service]).await;
+    let Some(change) = detect_client_eviction(&services, &body.service, client) else {
+        return (StatusCode::NOT_FOUND, "no such proxy client on the service").into_response();
+    };
+    apply_changes(vec![change], &mut services, &state.orchestrator).await;
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Tear down a network, with the chains using it.
+pub(super) async fn evict_net_handler(
+    State(state): State<AppState>,
+    Path(net_id): Path<u32>,
+) -> Response {
+    let names = state.services.services_of_net(net_id);
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let mut services = state.services.lock_chains_of(&names).await;
+    let Some(change) = detect_net_eviction(&services, net_id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("network {net_id} is not established"),
+        )
+            .into_response();
+    };
+    apply_changes(vec![change], &mut services, &state.orchestrator).await;
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub(super) async fn drain_handler(
+    State(state): State<AppState>,
+    Json(body): Json<ReplicaJson>,
+) -> Response {
+    set_draining(&state, body, true).await
+}
+
+pub(super) async fn undrain_handler(
+    State(state): State<AppState>,
+    Json(body): Json<ReplicaJson>,
+) -> Response {
+    set_draining(&state, body, false).await
+}
+
+async fn set_draining(state: &AppState, body: ReplicaJson, draining: bool) -> Response {
+    let mut services = state.services.lock(&[&body.service]).await;
+    let Some(change) = detect_replica_draining(
+        &services,
+        &body.service,
+        body.ip,
+        body.docker_container,
+        draining,
+    ) else {
+        return (StatusCode::NOT_FOUND, "no such replica of the service").into_response();
+    };
+    apply_changes(vec![change], &mut services, &state.orchestrator).await;
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub(super) async fn cordon_handler(
+    State(state): State<AppState>,
+    Path(ip): Path<IpAddr>,
+) -> Response {
+    set_cordoned(&state, ip, true).await
+}
+
+pub(super) async fn uncordon_handler(
+    State(state): State<AppState>,
+    Path(ip): Path<IpAddr>,
+) -> Response {
+    set_cordoned(&state, ip, false).await
+}
+
+async fn set_cordoned(state: &AppState, ip: IpAddr, cordoned: bool) -> Response {
+    let change = ServiceChange::NodeCordoned { ip, cordoned };
+    let mut services = state
+        .services
+        .lock_where(|_, si| matches!(si, ServiceInfo::Registered(reg) if reg.has_replica_on_ip(ip)))
+        .await;
+    apply_changes(vec![change], &mut services, &state.orchestrator).await;
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
@@ -1,11 +1,18 @@
-use axum::http::{StatusCode, header};
-use axum::response::Response;
+use super::AppState;
+use crate::services::config_store::{ConfigWrite, ConfigWriteError};
+use crate::services::input::{SERVICES_PATH, ServicesToml, preview_config_update};
+use crate::services::validation::{Issue, Rejection, validate};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
 
-pub(super) async fn config_handler() -> Response {
-    match tokio::fs::read_to_string("./services/services.toml").await {
-        Ok(content) => Response::builder()
+pub(super) async fn config_handler(State(state): State<AppState>) -> Response {
+    match state.config_store.read().await {
+        Ok((content, etag)) => Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header(header::ETAG, etag)
             .body(axum::body::Body::from(content))
             .unwrap(),
         Err(_) => Response::builder()
@@ -14,3 +21,138 @@ pub(super) async fn config_handler() -> Response {
             .unwrap(),
     }
 }
+
+#[derive(Serialize)]
+struct ValidationJson {
+    /// Issues of the file currently on disk (`None` if it can't be read).
+    current: Option<Vec<Issue>>,
+    /// The last file rejected by the watcher, if any.
+    last_rejected: Option<Rejection>,
+}
+
+pub(super) async fn validation_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let current = tokio::fs::read_to_string(SERVICES_PATH)
+        .await
+        .ok()
+        .map(|content| validate(&content));
+    let last_rejected = state.last_rejection.lock().await.clone();
+    axum::Json(ValidationJson {
+        current,
+        last_rejected,
+    })
+}
+
+/// What replacing `services.toml` with the body would tear down: the issues
+/// of the body instead, if it would be rejected.
+pub(super) async fn preview_handler(State(state): State<AppState>, body: String) -> Response {
+    let loaded = match ServicesToml::parse(&body) {
+        Ok(loaded) => loaded,
+        Err(issues) => {
+            return (StatusCode::UNPROCESSABLE_ENTITY, axum::Json(issues)).into_response();
+        }
+    };
+    let services = state.services.snapshot().await;
+    axum::Json(preview_config_update(&services, &loaded).await).into_response()
+}
+
+/// Replace `services.toml` with the body.
+pub(super) async fn replace_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let Some(if_match) = if_match(&headers) else {
+        return missing_if_match();
+    };
+    let result = state
+        .config_store
+        .replace(if_match, body, &state.services, &state.orchestrator)
+        .await;
+    write_response(result)
+}
+
+/// Apply the body (`remove = [...]` and `[[services]]` to upsert) to `services.toml`.
+pub(super) async fn patch_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let Some(if_match) = if_match(&headers) else {
+        return missing_if_match();
+    };
+    let result = state
+        .config_store
+        .patch(if_match, &body, &state.services, &state.orchestrator)
+        .await;
+    write_response(result)
+}
+
+/// Restore a version of `services.toml` from the history.
+pub(super) async fn rollback_handler(
+    State(state): State<AppState>,
+    Path(version): Path<u32>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(if_match) = if_match(&headers) else {
+        return missing_if_match();
+    };
+    let result = state
+        .config_store
+        .rollback(if_match, version, &state.services, &state.orchestrator)
+        .await;
+    write_response(result)
+}
+
+pub(super) async fn history_handler(State(state): State<AppState>) -> impl IntoResponse {
+    axum::Json(state.config_store.history().await)
+}
+
+pub(super) async fn history_version_handler(
+    State(state): State<AppState>,
+    Path(version): Path<u32>,
+) -> Response {
+    match state.config_store.version(version).await {
+        Some(content) => (
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            content,
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn if_match(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::IF_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+}
+
+fn missing_if_match() -> Response {
+    (
+        StatusCode::PRECONDITION_REQUIRED,
+        "If-Match header with the ETag of the current config (or *) is required",
+    )
+        .into_response()
+}
+
+fn write_response(result: Result<ConfigWrite, ConfigWriteError>) -> Response {
+    match result {
+        Ok(write) => ([(header::ETAG, write.etag.clone())], axum::Json(write)).into_response(),
+        Err(ConfigWriteError::VersionMismatch { current }) => (
+            StatusCode::PRECONDITION_FAILED,
+            [(header::ETAG, current)],
+            "services.toml changed since it was read",
+        )
+            .into_response(),
+        Err(ConfigWriteError::Invalid(issues)) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, axum::Json(issues)).into_response()
+        }
+        Err(ConfigWriteError::UnknownVersion(version)) => (
+            StatusCode::NOT_FOUND,
+            format!("no version {version} in the history"),
+        )
+            .into_response(),
+        Err(ConfigWriteError::Io(e)) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
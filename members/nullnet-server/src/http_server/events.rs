@@ -0,0 +1,59 @@
+use super::AppState;
+use crate::audit::AuditQuery;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
+
+/// Live topology events for clients accepting `text/event-stream`,
+/// the entries of the audit log matching the query otherwise.
+pub(super) async fn events_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+) -> Response {
+    let wants_stream = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+    if wants_stream {
+        return event_stream(&state).into_response();
+    }
+
+    match state.audit_log.query(&query).await {
+        Ok(entries) => axum::Json(entries).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+/// Topology events as Server-Sent Events, named after their `type`.
+///
+/// Subscribers falling behind get a `lagged` event with the number of events they missed,
+/// after which they should refresh their view (e.g. from `/api/graph`).
+fn event_stream(state: &AppState) -> impl IntoResponse + use<> {
+    let mut events = state.orchestrator.subscribe();
+    let (tx, rx) = mpsc::channel::<Result<Event, Infallible>>(16);
+    tokio::spawn(async move {
+        loop {
+            let sse_event = match events.recv().await {
+                Ok(event) => match Event::default().event(event.kind.name()).json_data(&event) {
+                    Ok(sse_event) => sse_event,
+                    Err(_) => continue,
+                },
+                Err(RecvError::Lagged(missed)) => {
+                    Event::default().event("lagged").data(missed.to_string())
+                }
+                Err(RecvError::Closed) => break,
+            };
+            // the client went away
+            if tx.send(Ok(sse_event)).await.is_err() {
+                break;
+            }
+        }
+    });
+    Sse::new(ReceiverStream::new(rx)).keep_alive(KeepAlive::default())
+}
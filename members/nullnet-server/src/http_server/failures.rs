@@ -0,0 +1,7 @@
+use super::AppState;
+use axum::extract::State;
+use axum::response::IntoResponse;
+
+pub(super) async fn failures_handler(State(state): State<AppState>) -> impl IntoResponse {
+    axum::Json(state.orchestrator.setup_failures().await)
+}
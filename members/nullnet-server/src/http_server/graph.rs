@@ -4,6 +4,6 @@ use axum::extract::State;
 use axum::response::IntoResponse;
 
 pub(super) async fn graph_handler(State(state): State<AppState>) -> impl IntoResponse {
-    let services = state.services.read().await;
+    let services = state.services.snapshot().await;
     axum::Json(render_graph_json(&services))
 }
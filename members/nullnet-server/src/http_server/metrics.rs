@@ -0,0 +1,15 @@
+use super::AppState;
+use crate::metrics::render;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+
+pub(super) async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        render(&state.orchestrator).await,
+    )
+}
@@ -1,15 +1,22 @@
+use crate::audit::AuditLog;
 use crate::orchestrator::Orchestrator;
-use crate::services::service_info::ServiceInfo;
+use crate::services::config_store::ConfigStore;
+use crate::services::service_map::ServiceMap;
+use crate::services::validation::Rejection;
 use axum::Router;
-use axum::routing::get;
-use std::collections::HashMap;
+use axum::middleware;
+use axum::routing::{get, post, put};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::Mutex;
 
+mod admin;
 mod config;
+mod events;
+mod failures;
 mod graph;
 mod health;
+mod metrics;
 mod nodes;
 mod pool;
 mod services;
@@ -19,18 +26,53 @@ const HTTP_PORT: u16 = 8080;
 
 #[derive(Clone)]
 pub(crate) struct AppState {
-    pub(crate) services: Arc<RwLock<HashMap<String, ServiceInfo>>>,
+    pub(crate) services: Arc<ServiceMap>,
     pub(crate) orchestrator: Orchestrator,
+    pub(crate) last_rejection: Arc<Mutex<Option<Rejection>>>,
+    pub(crate) config_store: Arc<ConfigStore>,
+    pub(crate) audit_log: Arc<AuditLog>,
 }
 
 pub async fn serve(state: AppState) {
+    let admin = Router::new()
+        .route("/clients/evict", post(admin::evict_client_handler))
+        .route("/nets/{net_id}/teardown", post(admin::evict_net_handler))
+        .route("/replicas/drain", post(admin::drain_handler))
+        .route("/replicas/undrain", post(admin::undrain_handler))
+        .route("/nodes/{ip}/cordon", post(admin::cordon_handler))
+        .route("/nodes/{ip}/uncordon", post(admin::uncordon_handler))
+        .route_layer(middleware::from_fn(admin::require_admin_token));
+
+    let config_writes = Router::new()
+        .route(
+            "/api/config",
+            put(config::replace_handler).patch(config::patch_handler),
+        )
+        .route(
+            "/api/config/rollback/{version}",
+            post(config::rollback_handler),
+        )
+        .route_layer(middleware::from_fn(admin::require_admin_token));
+
     let app = Router::new()
         .route("/api/health", get(health::health))
+        .route("/metrics", get(metrics::metrics_handler))
         .route("/api/services", get(services::services_handler))
         .route("/api/nodes", get(nodes::nodes_handler))
         .route("/api/pool", get(pool::pool_handler))
         .route("/api/config", get(config::config_handler))
+        .route("/api/config/history", get(config::history_handler))
+        .route(
+            "/api/config/history/{version}",
+            get(config::history_version_handler),
+        )
+        .route("/api/config/validation", get(config::validation_handler))
+        .route("/api/config/preview", post(config::preview_handler))
         .route("/api/graph", get(graph::graph_handler))
+        .route("/api/events", get(events::events_handler))
+        .route("/api/failures", get(failures::failures_handler))
+        .merge(config_writes)
+        .nest("/api/admin", admin)
         .fallback(get(static_files::static_handler))
         .with_state(state);
 
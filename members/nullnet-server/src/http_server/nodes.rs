@@ -14,7 +14,7 @@ struct NodeJson {
 
 pub(super) async fn nodes_handler(State(state): State<AppState>) -> impl IntoResponse {
     let connected_ips = state.orchestrator.connected_node_ips().await;
-    let services = state.services.read().await;
+    let services = state.services.snapshot().await;
 
     let mut ip_services: HashMap<IpAddr, Vec<String>> =
         connected_ips.iter().map(|ip| (*ip, vec![])).collect();
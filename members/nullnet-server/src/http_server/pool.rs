@@ -1,4 +1,5 @@
 use super::AppState;
+use crate::overlay_plan::OVERLAY_PLAN;
 use axum::extract::State;
 use axum::response::IntoResponse;
 use serde::Serialize;
@@ -8,6 +9,16 @@ struct PoolJson {
     total: u32,
     in_use: u32,
     free: u32,
+    plan: PlanJson,
+}
+
+#[derive(Serialize)]
+struct PlanJson {
+    cidrs: Vec<String>,
+    v6_prefix: Option<String>,
+    block_prefix: u8,
+    min_net_id: u32,
+    max_net_id: u32,
 }
 
 pub(super) async fn pool_handler(State(state): State<AppState>) -> impl IntoResponse {
@@ -16,5 +27,16 @@ pub(super) async fn pool_handler(State(state): State<AppState>) -> impl IntoResp
         total,
         in_use,
         free,
+        plan: PlanJson {
+            cidrs: OVERLAY_PLAN
+                .cidrs()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            v6_prefix: OVERLAY_PLAN.v6_prefix().map(|prefix| prefix.to_string()),
+            block_prefix: OVERLAY_PLAN.block_prefix(),
+            min_net_id: OVERLAY_PLAN.min_net_id(),
+            max_net_id: OVERLAY_PLAN.max_net_id(),
+        },
     })
 }
@@ -1,4 +1,5 @@
 use super::AppState;
+use crate::services::dep_graph::DepGraph;
 use crate::services::service_info::ServiceInfo;
 use axum::extract::State;
 use axum::response::IntoResponse;
@@ -12,6 +13,8 @@ struct ReplicaJson {
     #[serde(skip_serializing_if = "Option::is_none")]
     docker_container: Option<String>,
     active_sessions: usize,
+    healthy: bool,
+    draining: bool,
 }
 
 #[derive(Serialize)]
@@ -25,10 +28,12 @@ struct ServiceJson {
     timeout_secs: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     max_networks: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warm_networks: Option<u32>,
 }
 
 pub(super) async fn services_handler(State(state): State<AppState>) -> impl IntoResponse {
-    let services = state.services.read().await;
+    let services = state.services.snapshot().await;
     let mut response: Vec<ServiceJson> = services
         .iter()
         .map(|(name, info)| {
@@ -41,6 +46,8 @@ pub(super) async fn services_handler(State(state): State<AppState>) -> impl Into
                         port: r.port(),
                         docker_container: r.docker_container().map(String::from),
                         active_sessions: r.clients().len(),
+                        healthy: r.is_healthy(),
+                        draining: r.is_draining(),
                     })
                     .collect()
             } else {
@@ -49,19 +56,24 @@ pub(super) async fn services_handler(State(state): State<AppState>) -> impl Into
             let triggers = info
                 .triggers()
                 .iter()
-                .map(|(port, chain)| (port.to_string(), chain.clone()))
+                .map(|(port, graph)| (port.to_string(), graph_services(graph)))
                 .collect();
             ServiceJson {
                 name: name.clone(),
                 registered,
                 replicas,
-                proxy_dependencies: info.proxy_deps().to_vec(),
+                proxy_dependencies: graph_services(info.proxy_deps()),
                 triggers,
                 timeout_secs: info.timeout(),
                 max_networks: info.max_networks(),
+                warm_networks: info.warm_networks(),
             }
         })
         .collect();
     response.sort_by(|a, b| a.name.cmp(&b.name));
     axum::Json(response)
 }
+
+fn graph_services(graph: &DepGraph) -> Vec<String> {
+    graph.services().into_iter().map(String::from).collect()
+}
@@ -1,19 +1,29 @@
+mod audit;
 mod env;
+mod events;
 mod graphviz;
 mod http_server;
+mod metrics;
 mod net;
 mod net_id_pool;
 mod nullnet_grpc_impl;
 mod orchestrator;
+mod overlay_plan;
 mod services;
+mod state;
 #[cfg(test)]
 mod tests;
 mod timeout;
+mod tls;
+mod warm;
 
+use crate::audit::{AUDIT_PATH, AuditLog};
 use crate::nullnet_grpc_impl::NullnetGrpcImpl;
+use crate::state::persist_on_exit;
 use nullnet_grpc_lib::nullnet_grpc::nullnet_grpc_server::NullnetGrpcServer;
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
 use std::{panic, process};
 use tonic::transport::Server;
 
@@ -21,6 +31,17 @@ const PORT: u16 = 50051;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
+    // `nullnet-server validate <path>`: check a services.toml without starting the server
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("validate") {
+        let Some(path) = args.get(2) else {
+            eprintln!("usage: nullnet-server validate <path>");
+            process::exit(2);
+        };
+        let valid = services::validation::validate_file(path).await;
+        process::exit(i32::from(!valid));
+    }
+
     // let _gag1: gag::Redirect<std::fs::File>;
     // let _gag2: gag::Redirect<std::fs::File>;
     // if let Some((gag1, gag2)) = redirect_stdout_stderr_to_file() {
@@ -33,11 +54,17 @@ async fn main() -> Result<(), Error> {
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), PORT);
 
     let mut server = Server::builder();
+    if let Some(tls_config) = tls::server_tls_config()? {
+        server = server.tls_config(tls_config).handle_err(location!())?;
+    }
 
     let nullnet = init_nullnet().await?;
     let app_state = http_server::AppState {
         services: nullnet.services().clone(),
         orchestrator: nullnet.orchestrator().clone(),
+        last_rejection: nullnet.last_rejection().clone(),
+        config_store: nullnet.config_store().clone(),
+        audit_log: Arc::new(AuditLog::new(AUDIT_PATH)),
     };
 
     tokio::select! {
@@ -66,13 +93,18 @@ async fn init_nullnet() -> Result<NullnetGrpcImpl, Error> {
         }));
     }
 
-    // handle termination signals: SIGINT, SIGTERM, SIGHUP
+    let nullnet = NullnetGrpcImpl::new().await?;
+
+    // handle termination signals: SIGINT, SIGTERM, SIGHUP, writing out the latest state first
+    let services = nullnet.services().clone();
+    let runtime = tokio::runtime::Handle::current();
     ctrlc::set_handler(move || {
+        runtime.block_on(persist_on_exit(&services));
         process::exit(1);
     })
     .handle_err(location!())?;
 
-    NullnetGrpcImpl::new().await
+    Ok(nullnet)
 }
 
 // fn redirect_stdout_stderr_to_file()
@@ -0,0 +1,249 @@
+//! Control plane metrics, exposed at `/metrics` in the Prometheus text format.
+
+use crate::orchestrator::Orchestrator;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+pub(crate) static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Upper bounds (in seconds) of the latency histograms; network setups time out after 30 s.
+const BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+    /// Requests by (rpc, outcome).
+    rpc_requests: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+    rpc_duration: Mutex<BTreeMap<&'static str, Histogram>>,
+    /// Setup time of the edges by (server service, client kind).
+    edge_setup: Mutex<BTreeMap<(String, &'static str), Histogram>>,
+    rollbacks: AtomicU64,
+    /// Applied `ServiceChange`s by variant.
+    service_changes: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (count, bound) in self.counts.iter_mut().zip(BUCKETS) {
+            if value <= bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn encode(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (count, bound) in self.counts.iter().zip(BUCKETS) {
+            let _ = writeln!(out, "{name}_bucket{{{labels}{sep}le=\"{bound}\"}} {count}");
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{sep}le=\"+Inf\"}} {}",
+            self.count
+        );
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
+
+impl Metrics {
+    /// Record a handled RPC, `rpc` being its name in the proto.
+    pub(crate) fn record_rpc(&self, rpc: &'static str, ok: bool, elapsed: Duration) {
+        let outcome = if ok { "ok" } else { "error" };
+        *lock(&self.rpc_requests).entry((rpc, outcome)).or_default() += 1;
+        lock(&self.rpc_duration)
+            .entry(rpc)
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Record the time taken to set up the network of an edge towards `service`.
+    pub(crate) fn record_edge_setup(&self, service: &str, proxy_client: bool, elapsed: Duration) {
+        let client_kind = if proxy_client { "proxy" } else { "service" };
+        lock(&self.edge_setup)
+            .entry((service.to_string(), client_kind))
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Record an edge setup rolled back after one of its ends failed.
+    pub(crate) fn record_rollback(&self) {
+        self.rollbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record an applied `ServiceChange`, by the name of its variant.
+    pub(crate) fn record_service_change(&self, change: &'static str) {
+        *lock(&self.service_changes).entry(change).or_default() += 1;
+    }
+
+    fn encode(&self, out: &mut String) {
+        header(
+            out,
+            "nullnet_rpc_requests_total",
+            "counter",
+            "Handled RPCs.",
+        );
+        for ((rpc, outcome), count) in lock(&self.rpc_requests).iter() {
+            let _ = writeln!(
+                out,
+                "nullnet_rpc_requests_total{{rpc=\"{rpc}\",outcome=\"{outcome}\"}} {count}"
+            );
+        }
+
+        header(
+            out,
+            "nullnet_rpc_duration_seconds",
+            "histogram",
+            "Time taken to handle RPCs.",
+        );
+        for (rpc, histogram) in lock(&self.rpc_duration).iter() {
+            histogram.encode(
+                out,
+                "nullnet_rpc_duration_seconds",
+                &format!("rpc=\"{rpc}\""),
+            );
+        }
+
+        header(
+            out,
+            "nullnet_edge_setup_seconds",
+            "histogram",
+            "Time taken to set up the network of a chain edge, by server service and client kind.",
+        );
+        for ((service, client_kind), histogram) in lock(&self.edge_setup).iter() {
+            histogram.encode(
+                out,
+                "nullnet_edge_setup_seconds",
+                &format!("service=\"{}\",client=\"{client_kind}\"", escape(service)),
+            );
+        }
+
+        header(
+            out,
+            "nullnet_chain_setup_rollbacks_total",
+            "counter",
+            "Edge setups rolled back after one of their ends failed.",
+        );
+        let _ = writeln!(
+            out,
+            "nullnet_chain_setup_rollbacks_total {}",
+            self.rollbacks.load(Ordering::Relaxed)
+        );
+
+        header(
+            out,
+            "nullnet_service_changes_total",
+            "counter",
+            "Applied service changes (tearing down the chains they affect), by kind.",
+        );
+        for (change, count) in lock(&self.service_changes).iter() {
+            let _ = writeln!(
+                out,
+                "nullnet_service_changes_total{{change=\"{change}\"}} {count}"
+            );
+        }
+    }
+}
+
+/// The metrics, followed by the current state of `orchestrator`.
+pub(crate) async fn render(orchestrator: &Orchestrator) -> String {
+    let mut out = String::new();
+    METRICS.encode(&mut out);
+
+    let (total, in_use, _) = orchestrator.pool_stats().await;
+    header(&mut out, "nullnet_net_ids", "gauge", "NET IDs of the pool.");
+    let _ = writeln!(out, "nullnet_net_ids{{state=\"in_use\"}} {in_use}");
+    let _ = writeln!(out, "nullnet_net_ids{{state=\"total\"}} {total}");
+
+    header(
+        &mut out,
+        "nullnet_pending_acks",
+        "gauge",
+        "Network setups waiting to be acknowledged by a node.",
+    );
+    let _ = writeln!(
+        out,
+        "nullnet_pending_acks {}",
+        orchestrator.pending_acks().await
+    );
+
+    header(
+        &mut out,
+        "nullnet_connected_nodes",
+        "gauge",
+        "Nodes with an open control channel.",
+    );
+    let _ = writeln!(
+        out,
+        "nullnet_connected_nodes {}",
+        orchestrator.connected_node_ips().await.len()
+    );
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Metrics are only updated in short critical sections, so a poisoned lock still holds them.
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let metrics = Metrics::default();
+        metrics.record_rpc("Proxy", true, Duration::from_millis(20));
+        metrics.record_rpc("Proxy", false, Duration::from_millis(200));
+        metrics.record_edge_setup("A", true, Duration::from_millis(7));
+        metrics.record_rollback();
+        metrics.record_service_change("ReplicaRemoved");
+        metrics.record_service_change("ReplicaRemoved");
+
+        let mut out = String::new();
+        metrics.encode(&mut out);
+        let lines: Vec<&str> = out.lines().collect();
+        for expected in [
+            "nullnet_rpc_requests_total{rpc=\"Proxy\",outcome=\"error\"} 1",
+            "nullnet_rpc_requests_total{rpc=\"Proxy\",outcome=\"ok\"} 1",
+            "nullnet_rpc_duration_seconds_bucket{rpc=\"Proxy\",le=\"0.01\"} 0",
+            "nullnet_rpc_duration_seconds_bucket{rpc=\"Proxy\",le=\"0.025\"} 1",
+            "nullnet_rpc_duration_seconds_bucket{rpc=\"Proxy\",le=\"0.25\"} 2",
+            "nullnet_rpc_duration_seconds_bucket{rpc=\"Proxy\",le=\"+Inf\"} 2",
+            "nullnet_rpc_duration_seconds_count{rpc=\"Proxy\"} 2",
+            "nullnet_edge_setup_seconds_bucket{service=\"A\",client=\"proxy\",le=\"0.01\"} 1",
+            "nullnet_chain_setup_rollbacks_total 1",
+            "nullnet_service_changes_total{change=\"ReplicaRemoved\"} 2",
+        ] {
+            assert!(lines.contains(&expected), "missing '{expected}' in:\n{out}");
+        }
+    }
+}
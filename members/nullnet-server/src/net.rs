@@ -1,10 +1,11 @@
-use ipnetwork::Ipv4Network;
+use crate::overlay_plan::OVERLAY_PLAN;
+use ipnetwork::{Ipv4Network, Ipv6Network};
 use nullnet_grpc_lib::nullnet_grpc::{
-    HostMapping, MsgId, Net, NetMessage, VlanSetup, VlanTeardown, VxlanSetup, VxlanTeardown,
-    net_message,
+    HeldNet, HostMapping, MsgId, Net, NetMessage, VlanSetup, VlanTeardown, VxlanSetup,
+    VxlanTeardown, net_message,
 };
 use nullnet_liberror::{ErrorHandler, Location, location};
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 pub(crate) trait NetExt {
     #[allow(clippy::too_many_arguments)]
@@ -20,6 +21,9 @@ pub(crate) trait NetExt {
     ) -> Option<(Ipv4Addr, NetMessage)>;
 
     fn teardown(self, net_id: u32, side: &str, docker_container: Option<String>) -> NetMessage;
+
+    /// Teardown for a network reported by a client, built from what the client holds.
+    fn teardown_held(self, net: HeldNet) -> NetMessage;
 }
 
 impl NetExt for Net {
@@ -64,9 +68,26 @@ impl NetExt for Net {
             },
         }
     }
+
+    fn teardown_held(self, net: HeldNet) -> NetMessage {
+        match self {
+            Net::Vlan => NetMessage {
+                message: Some(net_message::Message::VlanTeardown(VlanTeardown {
+                    vlan_id: net.net_id,
+                })),
+            },
+            Net::Vxlan => NetMessage {
+                message: Some(net_message::Message::VxlanTeardown(VxlanTeardown {
+                    vxlan_id: net.net_id,
+                    ns_name: net.ns_name,
+                    br_name: net.br_name,
+                    docker_container: net.docker_container,
+                })),
+            },
+        }
+    }
 }
 
-#[allow(clippy::unnecessary_wraps)]
 fn vlan_setup(
     msg_id: String,
     dest: IpAddr,
@@ -74,13 +95,12 @@ fn vlan_setup(
     vlan_id: u32,
     remote: IpAddr,
 ) -> Option<(Ipv4Addr, NetMessage)> {
-    // Map vlan_id to a /30 block within 10.0.0.0/8.
+    // Map vlan_id to a /30 block of the overlay address plan.
     // Each ID gets 4 IPs (2 usable), with 2 IPs used for server/client veth.
-    let offset = vlan_id * 4;
-    let [_, a, b, c] = offset.to_be_bytes();
+    let block = u32::from(OVERLAY_PLAN.block(vlan_id)?);
 
-    let server_veth = Ipv4Addr::new(10, a, b, c + 1);
-    let client_veth = Ipv4Addr::new(10, a, b, c + 2);
+    let server_veth = Ipv4Addr::from(block + 1);
+    let client_veth = Ipv4Addr::from(block + 2);
 
     let (local_veth, remote_veth) = if remote_server_name.is_some() {
         // this is for client
@@ -93,6 +113,7 @@ fn vlan_setup(
     let host_mapping = remote_server_name.map(|name| HostMapping {
         ip: server_veth.to_string(),
         name,
+        ip6: None,
     });
 
     Some((
@@ -120,28 +141,27 @@ fn vxlan_setup(
     docker_containers: (Option<String>, Option<String>),
     dnat_port: Option<u32>,
 ) -> Option<(Ipv4Addr, NetMessage)> {
-    // Map vxlan_id to a /29 block within 10.0.0.0/8.
+    // Map vxlan_id to a /29 block of the overlay address plan.
     // Each ID gets 8 IPs (6 usable), with 4 IPs used for ns/br server/client.
-    let offset = vxlan_id * 8;
-    let [_, a, b, c] = offset.to_be_bytes();
+    let block = u32::from(OVERLAY_PLAN.block(vxlan_id)?);
 
     let client_docker = docker_containers.0;
     let server_docker = docker_containers.1;
     let is_server_docker = server_docker.is_some();
 
-    let ns_net_server = Ipv4Network::new(Ipv4Addr::new(10, a, b, c + 1), 29)
+    let ns_net_server = Ipv4Network::new(Ipv4Addr::from(block + 1), 29)
         .handle_err(location!())
         .ok()?;
-    let br_net_server = Ipv4Network::new(Ipv4Addr::new(10, a, b, c + 2), 29)
+    let br_net_server = Ipv4Network::new(Ipv4Addr::from(block + 2), 29)
         .handle_err(location!())
         .ok()?;
 
     let (ns_net, br_net, docker_container, side) = if remote_server_name.is_some() {
         // this is for client
-        let ns_net_client = Ipv4Network::new(Ipv4Addr::new(10, a, b, c + 3), 29)
+        let ns_net_client = Ipv4Network::new(Ipv4Addr::from(block + 3), 29)
             .handle_err(location!())
             .ok()?;
-        let br_net_client = Ipv4Network::new(Ipv4Addr::new(10, a, b, c + 4), 29)
+        let br_net_client = Ipv4Network::new(Ipv4Addr::from(block + 4), 29)
             .handle_err(location!())
             .ok()?;
         (ns_net_client, br_net_client, client_docker, "c")
@@ -156,9 +176,20 @@ fn vxlan_setup(
         br_net_server.ip()
     };
 
+    // same layout in the IPv6 overlay, if configured
+    let v6_nets = match OVERLAY_PLAN.block6(vxlan_id) {
+        Some(block6) => Some(vxlan_nets6(
+            block6,
+            remote_server_name.is_some(),
+            is_server_docker,
+        )?),
+        None => None,
+    };
+
     let host_mapping = remote_server_name.map(|name| HostMapping {
         ip: server_net_ip.to_string(),
         name,
+        ip6: v6_nets.map(|(_, _, server_net_ip6)| server_net_ip6.to_string()),
     });
 
     Some((
@@ -176,7 +207,50 @@ fn vxlan_setup(
                 host_mapping,
                 docker_container,
                 dnat_port,
+                ns_net6: v6_nets.map(|(ns_net6, _, _)| ns_net6.to_string()),
+                br_net6: v6_nets.map(|(_, br_net6, _)| br_net6.to_string()),
             })),
         },
     ))
 }
+
+/// IPv6 counterpart of the /29 blocks used by `vxlan_setup`: lays out the /125
+/// `block6` with the same host offsets (1-2 for the server side, 3-4 for the
+/// client side). Returns `(ns_net, br_net, server_net_ip)`.
+fn vxlan_nets6(
+    block6: Ipv6Addr,
+    is_client: bool,
+    is_server_docker: bool,
+) -> Option<(Ipv6Network, Ipv6Network, Ipv6Addr)> {
+    let block = u128::from(block6);
+    let net = |host: u128| {
+        Ipv6Network::new(Ipv6Addr::from(block + host), 125)
+            .handle_err(location!())
+            .ok()
+    };
+
+    let (ns_host, br_host) = if is_client { (3, 4) } else { (1, 2) };
+    let server_host = if is_server_docker { 1 } else { 2 };
+
+    Some((net(ns_host)?, net(br_host)?, net(server_host)?.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vxlan_nets6_layout() {
+        let block6: Ipv6Addr = "fd00:6e6e::328".parse().unwrap();
+
+        let (ns_net, br_net, server_ip) = vxlan_nets6(block6, false, false).unwrap();
+        assert_eq!(ns_net.to_string(), "fd00:6e6e::329/125");
+        assert_eq!(br_net.to_string(), "fd00:6e6e::32a/125");
+        assert_eq!(server_ip, "fd00:6e6e::32a".parse::<Ipv6Addr>().unwrap());
+
+        let (ns_net, br_net, server_ip) = vxlan_nets6(block6, true, true).unwrap();
+        assert_eq!(ns_net.to_string(), "fd00:6e6e::32b/125");
+        assert_eq!(br_net.to_string(), "fd00:6e6e::32c/125");
+        assert_eq!(server_ip, "fd00:6e6e::329".parse::<Ipv6Addr>().unwrap());
+    }
+}
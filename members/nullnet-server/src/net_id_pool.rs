@@ -1,19 +1,14 @@
 use std::collections::BTreeSet;
 use std::sync::LazyLock;
 
-use crate::env::NET_TYPE;
-use nullnet_grpc_lib::nullnet_grpc::Net;
+use crate::overlay_plan::OVERLAY_PLAN;
 
 /// Minimum allocatable NET ID (same for both VLAN and VXLAN).
-const MIN_NET_ID: u32 = 101;
+pub(crate) const MIN_NET_ID: u32 = 101;
 
-/// Maximum allocatable NET ID, depends on `NET_TYPE`:
-/// - VLAN: 4094 (802.1Q is 12-bit; 0 and 4095 are reserved)
-/// - VXLAN: 2,097,151 (subnet mapping uses /29 blocks in 10.0.0.0/8)
-static MAX_NET_ID: LazyLock<u32> = LazyLock::new(|| match *NET_TYPE {
-    Net::Vlan => 4094,
-    Net::Vxlan => 2_097_151,
-});
+/// Maximum allocatable NET ID: the last one whose overlay block fits the address plan,
+/// capped to what `NET_TYPE` can carry (4094 for VLAN, 24-bit VNIs for VXLAN).
+static MAX_NET_ID: LazyLock<u32> = LazyLock::new(|| OVERLAY_PLAN.max_net_id());
 
 /// Pool for VLAN/VXLAN network IDs.
 ///
@@ -60,6 +55,23 @@ impl NetIdPool {
         }
     }
 
+    /// Mark a specific network ID as in use (e.g. when restoring persisted state).
+    /// Returns `false` if the ID is out of range or already in use.
+    pub(crate) fn reserve(&mut self, id: u32) -> bool {
+        if id < MIN_NET_ID || id > *MAX_NET_ID {
+            return false;
+        }
+
+        if id >= self.next_fresh {
+            // every skipped fresh ID becomes reusable
+            self.freed.extend(self.next_fresh..id);
+            self.next_fresh = id + 1;
+            true
+        } else {
+            self.freed.remove(&id)
+        }
+    }
+
     /// Returns (total_capacity, in_use, free).
     pub(crate) fn stats(&self) -> (u32, u32, u32) {
         let capacity = *MAX_NET_ID - MIN_NET_ID + 1;
@@ -131,6 +143,23 @@ mod tests {
         assert!(pool.freed.is_empty());
     }
 
+    #[test]
+    fn test_reserve_net_ids() {
+        let mut pool = NetIdPool::new();
+        assert!(pool.reserve(104));
+        assert!(pool.reserve(102));
+        // already in use or out of range
+        assert!(!pool.reserve(104));
+        assert!(!pool.reserve(100));
+        assert!(!pool.reserve(*MAX_NET_ID + 1));
+        assert_eq!(pool.in_use(), 2);
+
+        // skipped IDs are handed out first, then fresh ones
+        assert_eq!(pool.allocate(), Some(101));
+        assert_eq!(pool.allocate(), Some(103));
+        assert_eq!(pool.allocate(), Some(105));
+    }
+
     #[test]
     fn test_stats_fresh_pool() {
         let pool = NetIdPool::new();
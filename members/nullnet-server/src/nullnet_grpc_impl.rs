@@ -1,38 +1,59 @@
+use crate::audit::{AUDIT_PATH, AuditLog};
 use crate::env::NET_TYPE;
+use crate::events::{EdgeEvent, TopologyEvent};
 use crate::graphviz::generate_graphviz;
+use crate::metrics::METRICS;
 use crate::orchestrator::Orchestrator;
-use crate::services::changes::{
-    apply_changes, collect_dep_chain_edges, detect_services_list_changes,
-};
-use crate::services::clients::{Client, ClientInfo};
+use crate::services::changes::{apply_changes, detect_services_list_changes};
+use crate::services::clients::{ChainContext, Client, ClientInfo};
+use crate::services::config_store::ConfigStore;
 use crate::services::edge::{Edge, RegisteredEdge};
-use crate::services::input::ServicesToml;
+use crate::services::input::{SERVICES_PATH, ServicesToml};
 use crate::services::service_info::ServiceInfo;
+use crate::services::service_map::ServiceMap;
+use crate::services::validation::Rejection;
+use crate::state::{persist_state, recover_state};
 use crate::timeout::check_timeouts;
+use crate::tls::node_ip;
+use crate::warm::{WarmBackoff, keep_networks_warm, plan_warm_networks};
 use nullnet_grpc_lib::nullnet_grpc::nullnet_grpc_server::NullnetGrpc;
 use nullnet_grpc_lib::nullnet_grpc::{
-    BackendTriggerRequest, Empty, MsgId, NetMessage, NetType, ProxyRequest, ServiceTrigger,
+    BackendTriggerRequest, ClientMessage, Empty, NetMessage, NetType, ProxyRequest, ServiceTrigger,
     Services, ServicesListResponse, Upstream,
 };
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
 use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
-use tokio::sync::{Notify, RwLock, mpsc};
+use tokio::sync::{Mutex, Notify, OnceCell, mpsc};
 use tokio::task::JoinSet;
 use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status, Streaming};
 
+#[derive(Clone)]
 pub(crate) struct NullnetGrpcImpl {
     /// The available services
-    services: Arc<RwLock<HashMap<String, ServiceInfo>>>,
+    services: Arc<ServiceMap>,
     /// Orchestrator to manage TAP-based clients and NET setups
     orchestrator: Orchestrator,
+    /// The last services.toml rejected by the watcher
+    last_rejection: Arc<Mutex<Option<Rejection>>>,
+    /// Writes of services.toml through the HTTP API
+    config_store: Arc<ConfigStore>,
+    /// Proxy requests being served, by service and proxy client
+    proxy_requests: Arc<Mutex<HashMap<(String, Client), InFlightProxyRequest>>>,
+    /// Notified when warm networks need to be replenished
+    warm_networks_needed: Arc<Notify>,
+    /// Warm networks failing to be set up, retried with a backoff
+    warm_backoff: Arc<Mutex<WarmBackoff>>,
 }
 
+/// Outcome of a proxy request, shared with the identical requests arriving while it's served.
+type InFlightProxyRequest = Arc<OnceCell<Result<Upstream, String>>>;
+
 impl NullnetGrpcImpl {
     pub async fn new() -> Result<Self, Error> {
-        let services = Arc::new(RwLock::new(ServicesToml::load().await?));
+        let services = Arc::new(ServiceMap::new(ServicesToml::load().await?));
 
         // regenerate the service graphviz periodically for debugging
         let services_2 = services.clone();
@@ -41,14 +62,40 @@ impl NullnetGrpcImpl {
         });
 
         let orchestrator = Orchestrator::new();
+
+        // keep a persistent record of the topology events, starting with the recovered chains
+        let events = orchestrator.subscribe();
+        tokio::spawn(async move {
+            AuditLog::new(AUDIT_PATH).record(events).await;
+        });
+
+        // rebuild the chains that were live before a restart, then keep them persisted
+        recover_state(&services, &orchestrator).await;
+        let services_2 = services.clone();
+        let orchestrator_2 = orchestrator.clone();
+        tokio::spawn(async move {
+            persist_state(services_2, orchestrator_2).await;
+        });
+
         let config_changed = Arc::new(Notify::new());
+        let last_rejection = Arc::new(Mutex::new(None));
+        let config_store = Arc::new(ConfigStore::new(SERVICES_PATH, config_changed.clone()));
 
         // keep services up to date with the services.toml file
         let services_2 = services.clone();
         let orchestrator_2 = orchestrator.clone();
         let config_changed_2 = config_changed.clone();
+        let last_rejection_2 = last_rejection.clone();
+        let config_store_2 = config_store.clone();
         tokio::spawn(async move {
-            if let Err(e) = ServicesToml::watch(&services_2, orchestrator_2, config_changed_2).await
+            if let Err(e) = ServicesToml::watch(
+                &services_2,
+                orchestrator_2,
+                config_changed_2,
+                last_rejection_2,
+                config_store_2,
+            )
+            .await
             {
                 eprintln!("failed to watch services.toml for changes: {e:?}");
             }
@@ -61,15 +108,29 @@ impl NullnetGrpcImpl {
             check_timeouts(services_2, orchestrator_2, config_changed).await;
         });
 
-        Ok(NullnetGrpcImpl {
+        let server = NullnetGrpcImpl {
             services,
             orchestrator,
-        })
+            last_rejection,
+            config_store,
+            proxy_requests: Arc::new(Mutex::new(HashMap::new())),
+            warm_networks_needed: Arc::new(Notify::new()),
+            warm_backoff: Arc::new(Mutex::new(WarmBackoff::default())),
+        };
+
+        // keep networks set up ahead of the proxy requests
+        let server_2 = server.clone();
+        let warm_networks_needed = server.warm_networks_needed.clone();
+        tokio::spawn(async move {
+            keep_networks_warm(server_2, warm_networks_needed).await;
+        });
+
+        Ok(server)
     }
 
     async fn control_channel_impl(
         &self,
-        request: Request<Streaming<MsgId>>,
+        request: Request<Streaming<ClientMessage>>,
     ) -> Result<Response<<NullnetGrpcImpl as NullnetGrpc>::ControlChannelStream>, Error> {
         let (outbound, receiver) = mpsc::channel(64);
 
@@ -80,16 +141,11 @@ impl NullnetGrpcImpl {
         Ok(Response::new(ReceiverStream::new(receiver)))
     }
 
-    // TODO: avoid race conditions when multiple proxy requests are made concurrently
     async fn proxy_impl(
         &self,
         request: Request<ProxyRequest>,
     ) -> Result<Response<Upstream>, Error> {
-        let proxy_ip = request
-            .remote_addr()
-            .ok_or("Could not get remote address for proxy request")
-            .handle_err(location!())?
-            .ip();
+        let proxy_ip = node_ip(&request)?;
 
         let req = request.into_inner();
 
@@ -102,20 +158,67 @@ impl NullnetGrpcImpl {
         Ok(Response::new(upstream))
     }
 
+    /// Serve a proxy request, coalescing it with the identical ones already in flight:
+    /// only the first of them sets up the chain, and all get the same upstream.
     pub(crate) async fn handle_proxy_request(
         &self,
         service_name: &str,
         proxy_ip: IpAddr,
         client_ip: &str,
+    ) -> Result<Upstream, Error> {
+        let key = (
+            service_name.to_string(),
+            Client::new(client_ip.to_string(), Some(proxy_ip)),
+        );
+        let in_flight = self
+            .proxy_requests
+            .lock()
+            .await
+            .entry(key.clone())
+            .or_default()
+            .clone();
+
+        // if the request serving it is cancelled, one of the waiting ones takes over
+        let result = in_flight
+            .get_or_init(|| async {
+                self.serve_proxy_request(service_name, proxy_ip, client_ip)
+                    .await
+                    .map_err(|e| e.to_str().to_string())
+            })
+            .await
+            .clone();
+
+        // later requests are served anew (e.g. after the chain timed out)
+        let mut proxy_requests = self.proxy_requests.lock().await;
+        if proxy_requests
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, &in_flight))
+        {
+            proxy_requests.remove(&key);
+        }
+        drop(proxy_requests);
+
+        result.handle_err(location!())
+    }
+
+    async fn serve_proxy_request(
+        &self,
+        service_name: &str,
+        proxy_ip: IpAddr,
+        client_ip: &str,
     ) -> Result<Upstream, Error> {
         println!("Received proxy request for '{service_name}'");
 
-        let service_info = self
-            .services
-            .read()
-            .await
-            .get(service_name)
-            .cloned()
+        // warm networks are kept for every proxy making requests
+        if self.orchestrator.add_proxy_node(proxy_ip).await {
+            self.warm_networks_needed.notify_one();
+        }
+
+        let proxy_client = Client::new(client_ip.to_string(), Some(proxy_ip));
+
+        let mut services_mut = self.services.lock_chains_of(&[service_name]).await;
+        let service_info = services_mut
+            .get_mut(service_name)
             .ok_or("Service not found")
             .handle_err(location!())?;
 
@@ -127,18 +230,21 @@ impl NullnetGrpcImpl {
             Err("Service is not registered").handle_err(location!())?
         };
 
-        let proxy_client = Client::new(client_ip.to_string(), Some(proxy_ip));
-
         // Sticky session: check if this client is already connected to a replica
         if let Some(upstream) = registered.is_client_setup(&proxy_client) {
             println!("'{client_ip}' ---> '{service_name}' is already set up");
 
             // update the latest timestamp for this client since it's being used again
-            let mut services_mut = self.services.write().await;
-            if let Some(ServiceInfo::Registered(reg)) = services_mut.get_mut(service_name) {
-                reg.set_latest_now(&proxy_client);
-            }
+            registered.set_latest_now(&proxy_client);
+
+            return Ok(upstream);
+        }
 
+        // Warm network: hand over one set up ahead on this proxy, and replace it
+        if let Some(upstream) = registered.take_warm_network(&proxy_client) {
+            println!("'{client_ip}' ---> '{service_name}' took over a warm network");
+            self.warm_networks_needed.notify_one();
+            self.orchestrator.state_changed();
             return Ok(upstream);
         }
 
@@ -153,32 +259,26 @@ impl NullnetGrpcImpl {
                 "Max networks ({max}) reached for '{service_name}', \
                  reusing network on proxy {proxy_ip}"
             );
-            let mut services_mut = self.services.write().await;
-            if let Some(ServiceInfo::Registered(reg)) = services_mut.get_mut(service_name) {
-                // Create a new Client entry sharing the existing network
-                let new_ci = ClientInfo::new(proxy_ip, client_net, server_net, net_id, 0, None);
-                reg.add_client_to_replica(
-                    replica_ip,
-                    replica_docker.as_deref(),
-                    proxy_client.clone(),
-                    new_ci,
-                );
-                reg.add_chain(&proxy_client);
-            }
-            // Increment chains on each dependency edge
-            let dep_edges = collect_dep_chain_edges(
-                service_name,
+            let chain = ChainContext::proxy(service_name, replica_ip, replica_docker.as_deref());
+            // Create a new Client entry sharing the existing network
+            let new_ci = ClientInfo::new(proxy_ip, client_net, server_net, net_id, 0, None);
+            registered.add_client_to_replica(
                 replica_ip,
                 replica_docker.as_deref(),
-                &services_mut,
+                proxy_client.clone(),
+                new_ci,
             );
-            for (dep_client, dep_name) in dep_edges {
+            registered.add_chain(&proxy_client, &chain);
+            // Increment chains on each dependency edge
+            for (dep_client, dep_name) in services_mut.chain_edges(&chain) {
                 if let Some(ServiceInfo::Registered(dep_reg)) = services_mut.get_mut(&dep_name) {
-                    dep_reg.add_chain(&dep_client);
+                    dep_reg.add_chain(&dep_client, &chain);
                 }
             }
+            self.orchestrator.state_changed();
             return Ok(upstream);
         }
+        drop(services_mut);
 
         let response = self
             .new_proxy_chain(service_name, proxy_ip, client_ip)
@@ -190,17 +290,13 @@ impl NullnetGrpcImpl {
         &self,
         request: Request<Services>,
     ) -> Result<Response<ServicesListResponse>, Error> {
-        let sender_ip = request
-            .remote_addr()
-            .ok_or("Could not get remote address for services list request")
-            .handle_err(location!())?
-            .ip();
+        let sender_ip = node_ip(&request)?;
 
         let req = request.into_inner();
 
         println!(
-            "Received services list from '{}': {:?}",
-            sender_ip, req.services
+            "Received services list from '{}' (labels {:?}): {:?}",
+            sender_ip, req.labels, req.services
         );
 
         let service_list: Vec<(String, u16, Option<String>)> = req
@@ -216,14 +312,18 @@ impl NullnetGrpcImpl {
             .collect::<Result<_, Error>>()?;
 
         self.apply_services_list(sender_ip, &service_list).await?;
+        self.set_node_labels(sender_ip, &req.labels).await;
 
         // Build the trigger config to send back: only the triggers attached
         // to the services this caller declared as hosting.
-        let guard = self.services.read().await;
-        let service_triggers: Vec<ServiceTrigger> = service_list
+        let names: Vec<&str> = service_list
             .iter()
-            .map(|(name, _, _)| name)
+            .map(|(name, _, _)| name.as_str())
             .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let guard = self.services.lock(&names).await;
+        let service_triggers: Vec<ServiceTrigger> = names
             .into_iter()
             .filter_map(|name| {
                 let triggers = guard.get(name)?.triggers();
@@ -233,7 +333,7 @@ impl NullnetGrpcImpl {
                 let mut ports: Vec<u32> = triggers.keys().map(|p| u32::from(*p)).collect();
                 ports.sort_unstable();
                 Some(ServiceTrigger {
-                    service_name: name.clone(),
+                    service_name: name.to_string(),
                     ports,
                 })
             })
@@ -248,14 +348,14 @@ impl NullnetGrpcImpl {
         proxy_ip: IpAddr,
         client_ip: &str,
     ) -> Result<Response<Upstream>, Error> {
-        let guard = self.services.read().await;
+        let guard = self.services.lock(&[service_name]).await;
         let reg = match guard.get(service_name) {
             Some(ServiceInfo::Registered(reg)) => reg,
             _ => Err("Service is not registered").handle_err(location!())?,
         };
         let replica = reg
-            .pick_replica_least_clients()
-            .ok_or("Service has no replicas")
+            .pick_replica(client_ip.parse().ok())
+            .ok_or("Service has no eligible replicas")
             .handle_err(location!())?;
         let service_ip = replica.ip();
         let service_port = replica.port();
@@ -283,8 +383,9 @@ impl NullnetGrpcImpl {
         service_name: &str,
         service_ip: IpAddr,
         service_docker: Option<&str>,
+        client_ip: &str,
     ) -> Result<Vec<RegisteredEdge>, Error> {
-        let guard = self.services.read().await;
+        let guard = self.services.lock_chains_of(&[service_name]).await;
         let service_info = guard
             .get(service_name)
             .ok_or("Service not found")
@@ -293,9 +394,9 @@ impl NullnetGrpcImpl {
             Err("Service is not registered").handle_err(location!())?
         };
         let dep_chain = registered.proxy_dependency_chain(
-            service_name.to_string(),
             service_ip,
             service_docker,
+            client_ip.parse().ok(),
             &guard,
         );
         drop(guard);
@@ -319,7 +420,7 @@ impl NullnetGrpcImpl {
         service_docker: Option<&str>,
         port: u16,
     ) -> Result<Option<Vec<RegisteredEdge>>, Error> {
-        let guard = self.services.read().await;
+        let guard = self.services.lock_chains_of(&[service_name]).await;
         let service_info = guard
             .get(service_name)
             .ok_or("Service not found")
@@ -327,13 +428,9 @@ impl NullnetGrpcImpl {
         let ServiceInfo::Registered(registered) = service_info else {
             Err("Service is not registered").handle_err(location!())?
         };
-        let Some(raw_chain) = registered.backend_dependency_chain(
-            service_name,
-            service_ip,
-            service_docker,
-            port,
-            &guard,
-        ) else {
+        let Some(raw_chain) =
+            registered.backend_dependency_chain(service_ip, service_docker, port, &guard)
+        else {
             return Ok(None);
         };
         drop(guard);
@@ -352,7 +449,7 @@ impl NullnetGrpcImpl {
         service_docker: Option<&str>,
     ) -> Result<Ipv4Addr, Error> {
         let mut dep_chain = self
-            .build_proxy_dep_chain(service_name, service_ip, service_docker)
+            .build_proxy_dep_chain(service_name, service_ip, service_docker, client_ip)
             .await?;
 
         dep_chain.push(RegisteredEdge::new(
@@ -362,6 +459,7 @@ impl NullnetGrpcImpl {
             service_ip,
             Client::new(service_name.to_string(), None),
             service_docker.map(String::from),
+            ChainContext::proxy(service_name, service_ip, service_docker),
         ));
 
         self.net_chain_setup(dep_chain)
@@ -374,11 +472,7 @@ impl NullnetGrpcImpl {
         &self,
         request: Request<BackendTriggerRequest>,
     ) -> Result<Response<Empty>, Error> {
-        let sender_ip = request
-            .remote_addr()
-            .ok_or("Could not get remote address for backend trigger")
-            .handle_err(location!())?
-            .ip();
+        let sender_ip = node_ip(&request)?;
 
         let req = request.into_inner();
         let port = u16::try_from(req.port).handle_err(location!())?;
@@ -399,7 +493,7 @@ impl NullnetGrpcImpl {
         // on the first-dep edge if already set up, and decides whether the
         // chain for this trigger port needs rebuilding.
         let (initiator_ip, initiator_docker, needs_rebuild) = {
-            let guard = self.services.write().await;
+            let guard = self.services.lock_chains_of(&[initiator_name]).await;
             let si = guard
                 .get(initiator_name)
                 .ok_or("Initiator service not found")
@@ -421,7 +515,7 @@ impl NullnetGrpcImpl {
             let first_dep = reg
                 .triggers()
                 .get(&port)
-                .and_then(|chain| chain.first())
+                .and_then(|graph| graph.deps_of(initiator_name).first())
                 .cloned();
             println!(
                 "[trigger] triggers map for '{initiator_name}': {:?}; first_dep for port {port}: {first_dep:?}",
@@ -492,10 +586,76 @@ impl NullnetGrpcImpl {
         println!("[trigger] dispatching net_chain_setup for '{initiator_name}' port {port}");
         self.net_chain_setup(chain).await?;
         println!("[trigger] net_chain_setup completed for '{initiator_name}' port {port}");
+
+        // the chain's idle period starts now
+        if let Some(ServiceInfo::Registered(reg)) = self
+            .services
+            .lock(&[initiator_name])
+            .await
+            .get_mut(initiator_name)
+        {
+            reg.mark_backend_chain_active(initiator_ip, initiator_docker, port);
+        }
         Ok(())
     }
 
-    pub(crate) fn services(&self) -> &Arc<RwLock<HashMap<String, ServiceInfo>>> {
+    /// Set up the warm networks missing on the connected proxies, and release the ones in excess.
+    pub(crate) async fn replenish_warm_networks(&self) {
+        let proxies = self.orchestrator.connected_proxy_nodes().await;
+        let mut missing = Vec::new();
+        let mut excess = Vec::new();
+        let mut in_excess = Vec::new();
+        self.services
+            .for_each(|name, si| {
+                let (service_missing, service_excess) = plan_warm_networks(name, si, &proxies);
+                if !service_excess.is_empty() {
+                    in_excess.push(name.to_string());
+                }
+                missing.extend(service_missing);
+                excess.extend(service_excess);
+            })
+            .await;
+
+        if !excess.is_empty() {
+            let names: Vec<&str> = in_excess.iter().map(String::as_str).collect();
+            let mut services_mut = self.services.lock_chains_of(&names).await;
+            apply_changes(excess, &mut services_mut, &self.orchestrator).await;
+        }
+
+        self.warm_backoff.lock().await.hold(&mut missing);
+
+        let mut join_set = JoinSet::new();
+        for (service_name, proxy_ip) in missing {
+            let server = self.clone();
+            join_set.spawn(async move {
+                let client = Client::warm(proxy_ip);
+                let res = server
+                    .new_proxy_chain(&service_name, proxy_ip, client.name())
+                    .await;
+                if let Err(e) = &res {
+                    eprintln!("failed to set up a warm network for '{service_name}': {e:?}");
+                }
+                ((service_name, proxy_ip), res.is_ok())
+            });
+        }
+        let mut outcomes: HashMap<(String, IpAddr), bool> = HashMap::new();
+        while let Some(res) = join_set.join_next().await {
+            if let Ok((key, succeeded)) = res {
+                *outcomes.entry(key).or_insert(true) &= succeeded;
+            }
+        }
+
+        let mut backoff = self.warm_backoff.lock().await;
+        for ((service_name, proxy_ip), succeeded) in outcomes {
+            if let Some(delay) = backoff.record(service_name.clone(), proxy_ip, succeeded) {
+                println!(
+                    "warm networks for '{service_name}' on proxy {proxy_ip} will be retried in {delay:?}"
+                );
+            }
+        }
+    }
+
+    pub(crate) fn services(&self) -> &Arc<ServiceMap> {
         &self.services
     }
 
@@ -503,26 +663,73 @@ impl NullnetGrpcImpl {
         &self.orchestrator
     }
 
+    pub(crate) fn last_rejection(&self) -> &Arc<Mutex<Option<Rejection>>> {
+        &self.last_rejection
+    }
+
+    pub(crate) fn config_store(&self) -> &Arc<ConfigStore> {
+        &self.config_store
+    }
+
     pub(crate) async fn apply_services_list(
         &self,
         sender_ip: IpAddr,
         service_list: &[(String, u16, Option<String>)],
     ) -> Result<(), Error> {
-        let mut services_mut = self.services.write().await;
+        // the services listed by the sender or with replicas on it, and the ones their chains go through
+        let mut services_mut = self
+            .services
+            .lock_chains_where(|name, si| {
+                service_list.iter().any(|(listed, _, _)| listed == name)
+                    || matches!(si, ServiceInfo::Registered(reg) if reg.has_replica_on_ip(sender_ip))
+            })
+            .await;
 
         let changes = detect_services_list_changes(&services_mut, sender_ip, service_list);
-        apply_changes(changes, &mut services_mut, None, &self.orchestrator).await;
+        apply_changes(changes, &mut services_mut, &self.orchestrator).await;
 
         // add/update replicas for services that are present
         for (name, port, docker_container) in service_list {
-            services_mut.entry(name.clone()).and_modify(|si| {
-                si.add_replica(sender_ip, *port, docker_container.clone());
-            });
+            let Some(si) = services_mut.get_mut(name) else {
+                continue;
+            };
+            let is_new = !matches!(si, ServiceInfo::Registered(reg)
+                if reg.replicas().iter().any(|r| r.matches_identity(sender_ip, docker_container.as_deref())));
+            si.add_replica(sender_ip, *port, docker_container.clone());
+            if is_new {
+                self.orchestrator.publish(TopologyEvent::ReplicaAdded {
+                    service: name.clone(),
+                    ip: sender_ip,
+                    port: *port,
+                    docker_container: docker_container.clone(),
+                });
+            }
+        }
+        self.orchestrator.state_changed();
+
+        // replicas joining a cordoned node don't take new clients either
+        if self.orchestrator.is_cordoned(sender_ip).await {
+            for si in services_mut.values_mut() {
+                si.set_node_draining(sender_ip, true);
+            }
         }
 
         Ok(())
     }
 
+    /// Record the labels declared by the node at `ip` on all the replicas it hosts.
+    pub(crate) async fn set_node_labels(&self, ip: IpAddr, labels: &HashMap<String, String>) {
+        let mut services_mut = self
+            .services
+            .lock_where(
+                |_, si| matches!(si, ServiceInfo::Registered(reg) if reg.has_replica_on_ip(ip)),
+            )
+            .await;
+        for si in services_mut.values_mut() {
+            si.set_node_labels(ip, labels);
+        }
+    }
+
     #[allow(clippy::too_many_lines)]
     pub(crate) async fn net_chain_setup(
         &self,
@@ -535,13 +742,14 @@ impl NullnetGrpcImpl {
             let client_docker = edge.client_docker;
             let server_docker = edge.server_docker;
             let backend_entry_port = edge.backend_entry_port;
+            let chain = edge.chain;
 
             let services = self.services.clone();
             let orchestrator = self.orchestrator.clone();
             join_set_outer.spawn(async move {
                 let init_time = std::time::Instant::now();
 
-                let mut services_guard = services.write().await;
+                let mut services_guard = services.lock(&[server.name()]).await;
                 let Some(ServiceInfo::Registered(reg)) = services_guard.get_mut(server.name())
                 else {
                     return EdgeOutcome::Failed;
@@ -555,12 +763,13 @@ impl NullnetGrpcImpl {
                     reg.is_client_on_replica(&client, server_ethernet, server_docker.as_deref())
                 };
                 if already_setup {
-                    reg.add_chain(&client);
-                    return EdgeOutcome::Success {
+                    reg.add_chain(&client, &chain);
+                    return EdgeOutcome::Success(Box::new(SuccessfulEdge {
                         client,
+                        chain,
                         server_name: server.name().to_string(),
                         proxy_upstream: None,
-                    };
+                    }));
                 }
                 // reserve the slot so concurrent requests see it as in-progress
                 reg.add_client_to_replica(
@@ -576,7 +785,7 @@ impl NullnetGrpcImpl {
                     eprintln!("NET ID pool exhausted");
                     // remove placeholder
                     if let Some(ServiceInfo::Registered(reg)) =
-                        services.write().await.get_mut(server.name())
+                        services.lock(&[server.name()]).await.get_mut(server.name())
                     {
                         reg.remove_client(&client);
                     }
@@ -606,37 +815,57 @@ impl NullnetGrpcImpl {
                     backend_entry_port,
                 );
 
-                let (server_ok, client_ok) = tokio::join!(server_res, client_res);
-
-                if server_ok.is_none() || client_ok.is_none() {
-                    // rollback
-                    orchestrator
-                        .send_net_teardown(
-                            client_ethernet,
-                            client_docker.clone(),
-                            server_ethernet,
-                            server_docker.clone(),
-                            net_id,
-                        )
-                        .await;
-                    // remove placeholder
-                    if let Some(ServiceInfo::Registered(reg)) =
-                        services.write().await.get_mut(server.name())
-                    {
-                        reg.remove_client(&client);
-                    }
-                    return EdgeOutcome::Failed;
-                }
-
-                let (Some(net_ip_server), Some(net_ip_client)) = (server_ok, client_ok) else {
-                    return EdgeOutcome::Failed;
-                };
+                // the first failure (e.g., a rejection) aborts the other setup and rolls back
+                let (net_ip_server, net_ip_client) =
+                    match tokio::try_join!(server_res, client_res) {
+                        Ok(net_ips) => net_ips,
+                        Err(reason) => {
+                            eprintln!(
+                                "Network {net_id} between {client_ethernet} and {server_ethernet} failed: {reason}"
+                            );
+                            // rollback
+                            METRICS.record_rollback();
+                            orchestrator.publish(TopologyEvent::EdgeRolledBack {
+                                edge: EdgeEvent {
+                                    net_id,
+                                    service: server.name().to_string(),
+                                    client: client.display_name(),
+                                    client_ip: client_ethernet,
+                                    server_ip: server_ethernet,
+                                    client_docker: client_docker.clone(),
+                                    server_docker: server_docker.clone(),
+                                },
+                                reason,
+                            });
+                            orchestrator
+                                .send_net_teardown(
+                                    client_ethernet,
+                                    client_docker.clone(),
+                                    server_ethernet,
+                                    server_docker.clone(),
+                                    net_id,
+                                )
+                                .await;
+                            // remove placeholder
+                            if let Some(ServiceInfo::Registered(reg)) =
+                                services.lock(&[server.name()]).await.get_mut(server.name())
+                            {
+                                reg.remove_client(&client);
+                            }
+                            return EdgeOutcome::Failed;
+                        }
+                    };
 
                 println!("{server_ethernet} acknowledged");
                 println!("{client_ethernet} acknowledged");
+                METRICS.record_edge_setup(
+                    server.name(),
+                    client.is_proxy().is_some(),
+                    init_time.elapsed(),
+                );
 
                 // register the link between the two services
-                let mut guard = services.write().await;
+                let mut guard = services.lock(&[server.name()]).await;
                 if let Some(ServiceInfo::Registered(reg)) = guard.get_mut(server.name()) {
                     let time_ms = init_time.elapsed().as_millis();
                     let ci = ClientInfo::new(
@@ -653,7 +882,19 @@ impl NullnetGrpcImpl {
                         client.clone(),
                         ci,
                     );
-                    reg.add_chain(&client);
+                    reg.add_chain(&client, &chain);
+                    orchestrator.publish(TopologyEvent::EdgeSetUp {
+                        edge: EdgeEvent {
+                            net_id,
+                            service: server.name().to_string(),
+                            client: client.display_name(),
+                            client_ip: client_ethernet,
+                            server_ip: server_ethernet,
+                            client_docker: client_docker.clone(),
+                            server_docker: server_docker.clone(),
+                        },
+                        time_ms,
+                    });
                 } else {
                     // service was unregistered during setup — teardown NETs
                     drop(guard);
@@ -675,11 +916,12 @@ impl NullnetGrpcImpl {
                     None
                 };
 
-                EdgeOutcome::Success {
+                EdgeOutcome::Success(Box::new(SuccessfulEdge {
                     client,
+                    chain,
                     server_name: server.name().to_string(),
                     proxy_upstream,
-                }
+                }))
             });
         }
 
@@ -687,16 +929,8 @@ impl NullnetGrpcImpl {
         let mut any_failure = false;
         while let Some(res) = join_set_outer.join_next().await {
             match res {
-                Ok(EdgeOutcome::Success {
-                    client,
-                    server_name,
-                    proxy_upstream,
-                }) => {
-                    successful.push(SuccessfulEdge {
-                        client,
-                        server_name,
-                        proxy_upstream,
-                    });
+                Ok(EdgeOutcome::Success(edge)) => {
+                    successful.push(*edge);
                 }
                 Ok(EdgeOutcome::Failed) | Err(_) => {
                     any_failure = true;
@@ -705,15 +939,18 @@ impl NullnetGrpcImpl {
         }
 
         if any_failure {
-            let mut services_mut = self.services.write().await;
             for edge in &successful {
+                let mut services_mut = self.services.lock(&[&edge.server_name]).await;
                 if let Some(ServiceInfo::Registered(reg)) = services_mut.get_mut(&edge.server_name)
                 {
-                    reg.decrement_chain(&edge.client, &self.orchestrator).await;
+                    reg.decrement_chain(&edge.client, &edge.chain, &self.orchestrator)
+                        .await;
                 }
             }
+            self.orchestrator.state_changed();
             Err("NET chain setup failed").handle_err(location!())?;
         }
+        self.orchestrator.state_changed();
 
         let upstream = successful.iter().find_map(|e| e.proxy_upstream);
         Ok(upstream)
@@ -721,16 +958,13 @@ impl NullnetGrpcImpl {
 }
 
 enum EdgeOutcome {
-    Success {
-        client: Client,
-        server_name: String,
-        proxy_upstream: Option<Ipv4Addr>,
-    },
+    Success(Box<SuccessfulEdge>),
     Failed,
 }
 
 struct SuccessfulEdge {
     client: Client,
+    chain: ChainContext,
     server_name: String,
     proxy_upstream: Option<Ipv4Addr>,
 }
@@ -739,8 +973,13 @@ struct SuccessfulEdge {
 impl NullnetGrpcImpl {
     pub(crate) fn new_for_test(services: HashMap<String, ServiceInfo>) -> Self {
         NullnetGrpcImpl {
-            services: Arc::new(RwLock::new(services)),
+            services: Arc::new(ServiceMap::new(services)),
             orchestrator: Orchestrator::new(),
+            last_rejection: Arc::new(Mutex::new(None)),
+            config_store: Arc::new(ConfigStore::new(SERVICES_PATH, Arc::default())),
+            proxy_requests: Arc::new(Mutex::new(HashMap::new())),
+            warm_networks_needed: Arc::new(Notify::new()),
+            warm_backoff: Arc::new(Mutex::new(WarmBackoff::default())),
         }
     }
 }
@@ -757,22 +996,21 @@ impl NullnetGrpc for NullnetGrpcImpl {
         &self,
         req: Request<Services>,
     ) -> Result<Response<ServicesListResponse>, Status> {
-        self.services_list_impl(req)
-            .await
-            .map_err(|err| Status::internal(err.to_str()))
+        let start = std::time::Instant::now();
+        let res = self.services_list_impl(req).await;
+        METRICS.record_rpc("ServicesList", res.is_ok(), start.elapsed());
+        res.map_err(|err| Status::internal(err.to_str()))
     }
 
     type ControlChannelStream = ReceiverStream<Result<NetMessage, Status>>;
 
     async fn control_channel(
         &self,
-        request: Request<Streaming<MsgId>>,
+        request: Request<Streaming<ClientMessage>>,
     ) -> Result<Response<Self::ControlChannelStream>, Status> {
         println!(
             "Nullnet control channel requested from '{}'",
-            request
-                .remote_addr()
-                .map_or("unknown".into(), |addr| addr.ip().to_string())
+            node_ip(&request).map_or("unknown".into(), |ip| ip.to_string())
         );
 
         self.control_channel_impl(request)
@@ -781,17 +1019,19 @@ impl NullnetGrpc for NullnetGrpcImpl {
     }
 
     async fn proxy(&self, req: Request<ProxyRequest>) -> Result<Response<Upstream>, Status> {
-        self.proxy_impl(req)
-            .await
-            .map_err(|err| Status::internal(err.to_str()))
+        let start = std::time::Instant::now();
+        let res = self.proxy_impl(req).await;
+        METRICS.record_rpc("Proxy", res.is_ok(), start.elapsed());
+        res.map_err(|err| Status::internal(err.to_str()))
     }
 
     async fn backend_trigger(
         &self,
         req: Request<BackendTriggerRequest>,
     ) -> Result<Response<Empty>, Status> {
-        self.backend_trigger_impl(req)
-            .await
-            .map_err(|err| Status::internal(err.to_str()))
+        let start = std::time::Instant::now();
+        let res = self.backend_trigger_impl(req).await;
+        METRICS.record_rpc("BackendTrigger", res.is_ok(), start.elapsed());
+        res.map_err(|err| Status::internal(err.to_str()))
     }
 }
@@ -1,25 +1,60 @@
 use crate::env::NET_TYPE;
+use crate::events::{EVENTS_CAPACITY, Event, TopologyEvent};
 use crate::net::NetExt;
 use crate::net_id_pool::NetIdPool;
-use crate::services::changes::{apply_changes, detect_node_disconnect_changes};
+use crate::services::changes::{
+    apply_changes, apply_node_health, detect_health_changes, detect_node_disconnect_changes,
+    detect_node_state_changes, net_ids_on_node,
+};
 use crate::services::service_info::ServiceInfo;
-use nullnet_grpc_lib::nullnet_grpc::{MsgId, NetMessage};
+use crate::services::service_map::ServiceMap;
+use crate::tls::node_ip;
+use nullnet_grpc_lib::nullnet_grpc::{
+    ClientMessage, Nack, NetMessage, NodeState, PortActivity, ServiceHealth, client_message,
+};
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
-use std::collections::HashMap;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{Mutex, RwLock, mpsc, oneshot};
+use tokio::sync::{Mutex, Notify, RwLock, broadcast, mpsc, oneshot};
 use tonic::{Request, Status, Streaming};
 use uuid::Uuid;
 
 type OutboundStream = mpsc::Sender<Result<NetMessage, Status>>;
 
+/// Outcome of a network setup as reported by the client: the failure reason on error.
+type SetupResult = Result<(), String>;
+
+/// How many network setup failures are kept for inspection.
+const MAX_SETUP_FAILURES: usize = 100;
+
+/// How long to wait for a client to acknowledge a network setup.
+const SETUP_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Debug, Clone)]
 pub struct Orchestrator {
     clients: Arc<RwLock<HashMap<IpAddr, OutboundStream>>>,
-    pending: Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>,
+    pending: Arc<Mutex<HashMap<String, oneshot::Sender<SetupResult>>>>,
     net_id_pool: Arc<Mutex<NetIdPool>>,
+    setup_failures: Arc<Mutex<VecDeque<SetupFailure>>>,
+    events: broadcast::Sender<Event>,
+    /// Nodes cordoned through the admin API, whose replicas don't take new clients.
+    cordoned: Arc<RwLock<HashSet<IpAddr>>>,
+    /// Nodes that made proxy requests, for which warm networks are kept.
+    proxy_nodes: Arc<RwLock<HashSet<IpAddr>>>,
+    /// Notified whenever the persisted state may have changed.
+    state_changed: Arc<Notify>,
+}
+
+/// A network setup that was rejected by a client or never acknowledged.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct SetupFailure {
+    pub(crate) timestamp: String,
+    pub(crate) node: IpAddr,
+    pub(crate) net_id: u32,
+    pub(crate) reason: String,
 }
 
 impl Orchestrator {
@@ -28,57 +63,204 @@ impl Orchestrator {
             clients: Arc::new(RwLock::new(HashMap::new())),
             pending: Arc::new(Mutex::new(HashMap::new())),
             net_id_pool: Arc::new(Mutex::new(NetIdPool::new())),
+            setup_failures: Arc::new(Mutex::new(VecDeque::new())),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            cordoned: Arc::new(RwLock::new(HashSet::new())),
+            proxy_nodes: Arc::new(RwLock::new(HashSet::new())),
+            state_changed: Arc::new(Notify::new()),
         }
     }
 
     pub(crate) async fn add_client(
         &self,
-        request: Request<Streaming<MsgId>>,
+        request: Request<Streaming<ClientMessage>>,
         outbound: OutboundStream,
-        services: Arc<RwLock<HashMap<String, ServiceInfo>>>,
+        services: Arc<ServiceMap>,
     ) -> Result<(), Error> {
-        let client_ip = request
-            .remote_addr()
-            .ok_or("Could not get remote address for control channel request")
-            .handle_err(location!())?
-            .ip();
+        let client_ip = node_ip(&request)?;
 
-        self.clients.write().await.insert(client_ip, outbound);
+        self.clients
+            .write()
+            .await
+            .insert(client_ip, outbound.clone());
+        self.publish(TopologyEvent::NodeConnected { ip: client_ip });
 
         let mut inbound = request.into_inner();
         let orchestrator = self.clone();
         tokio::spawn(async move {
-            while let Ok(Some(msg_id)) = inbound.message().await {
-                if let Some(tx) = orchestrator.pending.lock().await.remove(&msg_id.id) {
-                    let _ = tx.send(());
+            while let Ok(Some(msg)) = inbound.message().await {
+                match msg.message {
+                    Some(client_message::Message::Ack(msg_id)) => {
+                        orchestrator.resolve_pending(&msg_id.id, Ok(())).await;
+                    }
+                    Some(client_message::Message::Nack(nack)) => {
+                        orchestrator.handle_nack(nack).await;
+                    }
+                    Some(client_message::Message::NodeState(node_state)) => {
+                        orchestrator
+                            .reconcile_node_state(client_ip, node_state, &services)
+                            .await;
+                    }
+                    Some(client_message::Message::PortActivity(activity)) => {
+                        orchestrator
+                            .record_port_activity(client_ip, &activity, &services)
+                            .await;
+                    }
+                    Some(client_message::Message::ServiceHealth(health)) => {
+                        orchestrator
+                            .handle_service_health(client_ip, &health, &services)
+                            .await;
+                    }
+                    None => {}
                 }
             }
 
             println!("Control channel from '{client_ip}' closed");
-            orchestrator
-                .handle_node_disconnect(client_ip, &services)
-                .await;
+
+            // the node already reconnected: its new channel reports what it holds
+            let superseded = orchestrator
+                .clients
+                .read()
+                .await
+                .get(&client_ip)
+                .is_some_and(|current| !current.same_channel(&outbound));
+            if !superseded {
+                orchestrator
+                    .handle_node_disconnect(client_ip, &services)
+                    .await;
+            }
         });
 
         Ok(())
     }
 
-    pub(crate) async fn remove_client(&self, ip: &IpAddr) {
-        self.clients.write().await.remove(ip);
+    /// Reconcile the networks a (re)connected node reports with the ones tracked
+    /// for it: known networks are re-adopted, unknown ones are torn down on the
+    /// node, and tracked networks the node no longer holds have their chains
+    /// torn down.
+    pub(crate) async fn reconcile_node_state(
+        &self,
+        node_ip: IpAddr,
+        node_state: NodeState,
+        services: &ServiceMap,
+    ) {
+        // the services with networks or replicas on the node, and the ones their chains go through
+        let mut services_guard = services
+            .lock_chains_where(|_, si| {
+                matches!(si, ServiceInfo::Registered(reg)
+                    if reg.has_replica_on_ip(node_ip) || reg.net_ids_on_node(node_ip).next().is_some())
+            })
+            .await;
+        let known = net_ids_on_node(&services_guard, node_ip);
+        let held: HashSet<u32> = node_state.nets.iter().map(|net| net.net_id).collect();
+
+        let outbound = self.clients.read().await.get(&node_ip).cloned();
+        for net in node_state.nets {
+            if known.contains(&net.net_id) {
+                println!("Re-adopting network {} held by '{node_ip}'", net.net_id);
+                // backend chains entering this network are idle-tracked again
+                if let Some(port) = net.dnat_port.and_then(|p| u16::try_from(p).ok()) {
+                    for si in services_guard.values_mut() {
+                        if let ServiceInfo::Registered(reg) = si {
+                            reg.adopt_backend_chain(node_ip, port);
+                        }
+                    }
+                }
+            } else if let Some(outbound) = &outbound {
+                println!(
+                    "Tearing down unknown network {} held by '{node_ip}'",
+                    net.net_id
+                );
+                let message = NET_TYPE.teardown_held(net);
+                let _ = outbound.send(Ok(message)).await.handle_err(location!());
+            }
+        }
+
+        let changes = detect_node_state_changes(&services_guard, node_ip, &held);
+        apply_changes(changes, &mut services_guard, self).await;
     }
 
-    pub(crate) async fn handle_node_disconnect(
+    /// Refresh the idle period of the backend chains initiated from `node_ip`
+    /// (by the reported Docker containers, if any) that saw traffic.
+    pub(crate) async fn record_port_activity(
         &self,
-        client_ip: IpAddr,
-        services: &Arc<RwLock<HashMap<String, ServiceInfo>>>,
+        node_ip: IpAddr,
+        activity: &PortActivity,
+        services: &ServiceMap,
     ) {
+        let mut services_guard = services.lock_where(|_, si| hosted_on(si, node_ip)).await;
+        for counters in activity.ports.iter().filter(|c| c.packets > 0) {
+            let Ok(port) = u16::try_from(counters.port) else {
+                continue;
+            };
+            for si in services_guard.values_mut() {
+                if let ServiceInfo::Registered(reg) = si {
+                    reg.record_backend_activity(
+                        node_ip,
+                        counters.docker_container.as_deref(),
+                        port,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Record the health of the replicas hosted by `node_ip`, and tear down
+    /// the chains pointing at the ones that became unhealthy.
+    pub(crate) async fn handle_service_health(
+        &self,
+        node_ip: IpAddr,
+        health: &ServiceHealth,
+        services: &ServiceMap,
+    ) {
+        let mut services_guard = services
+            .lock_chains_where(|_, si| hosted_on(si, node_ip))
+            .await;
+        let changes = detect_health_changes(&services_guard, node_ip, health);
+        apply_node_health(&mut services_guard, node_ip, health);
+        apply_changes(changes, &mut services_guard, self).await;
+    }
+
+    /// Complete the pending setup identified by `msg_id`, if still awaited.
+    async fn resolve_pending(&self, msg_id: &str, result: SetupResult) {
+        if let Some(tx) = self.pending.lock().await.remove(msg_id) {
+            let _ = tx.send(result);
+        }
+    }
+
+    async fn handle_nack(&self, nack: Nack) {
+        let Some(msg_id) = &nack.msg_id else {
+            return;
+        };
+        let reason = format!(
+            "rejected by client ({}): {}",
+            nack.code().as_str_name(),
+            nack.reason
+        );
+        self.resolve_pending(&msg_id.id, Err(reason)).await;
+    }
+
+    pub(crate) async fn remove_client(&self, ip: &IpAddr) {
+        self.clients.write().await.remove(ip);
+    }
+
+    pub(crate) async fn handle_node_disconnect(&self, client_ip: IpAddr, services: &ServiceMap) {
         self.remove_client(&client_ip).await;
+        self.publish(TopologyEvent::NodeDisconnected { ip: client_ip });
 
-        let mut services_guard = services.write().await;
+        let mut services_guard = services
+            .lock_chains_where(|_, si| {
+                matches!(si, ServiceInfo::Registered(reg)
+                    if reg.has_replica_on_ip(client_ip) || reg.has_proxy_clients_of(client_ip))
+            })
+            .await;
         let changes = detect_node_disconnect_changes(&services_guard, client_ip);
-        apply_changes(changes, &mut services_guard, None, self).await;
+        apply_changes(changes, &mut services_guard, self).await;
     }
 
+    /// Send a network setup to `dest` and wait for its acknowledgement.
+    /// Failures (including rejections by the client) are logged and recorded
+    /// for inspection, and their reason is returned.
     pub(crate) async fn send_net_setup(
         &self,
         dest: IpAddr,
@@ -87,47 +269,175 @@ impl Orchestrator {
         remote: IpAddr,
         docker_containers: (Option<String>, Option<String>),
         dnat_port: Option<u32>,
-    ) -> Option<Ipv4Addr> {
-        let outbound = self.clients.read().await.get(&dest).cloned();
-        if let Some(outbound) = outbound {
-            let (tx, rx) = oneshot::channel();
-            let msg_id = Uuid::new_v4().to_string();
-            self.pending.lock().await.insert(msg_id.clone(), tx);
-
-            let (server_net, message) = NET_TYPE.setup(
-                msg_id.clone(),
+    ) -> Result<Ipv4Addr, String> {
+        let res = self
+            .try_net_setup(
                 dest,
                 remote_server_name,
                 net_id,
                 remote,
                 docker_containers,
                 dnat_port,
-            )?;
+            )
+            .await;
 
-            if outbound.send(Ok(message)).await.is_err() {
-                self.pending.lock().await.remove(&msg_id);
-                return None;
+        if let Err(reason) = &res {
+            eprintln!("Network {net_id} setup on '{dest}' failed: {reason}");
+            let mut setup_failures = self.setup_failures.lock().await;
+            if setup_failures.len() == MAX_SETUP_FAILURES {
+                setup_failures.pop_front();
             }
+            setup_failures.push_back(SetupFailure {
+                timestamp: chrono::Utc::now().to_rfc3339(),
+                node: dest,
+                net_id,
+                reason: reason.clone(),
+            });
+        }
 
-            if let Ok(result) = tokio::time::timeout(Duration::from_secs(30), rx).await {
-                result.ok().map(|()| server_net)
-            } else {
-                self.pending.lock().await.remove(&msg_id);
-                None
-            }
-        } else {
-            None
+        res
+    }
+
+    async fn try_net_setup(
+        &self,
+        dest: IpAddr,
+        remote_server_name: Option<String>,
+        net_id: u32,
+        remote: IpAddr,
+        docker_containers: (Option<String>, Option<String>),
+        dnat_port: Option<u32>,
+    ) -> Result<Ipv4Addr, String> {
+        let outbound = self
+            .clients
+            .read()
+            .await
+            .get(&dest)
+            .cloned()
+            .ok_or("node is not connected")?;
+
+        let msg_id = Uuid::new_v4().to_string();
+        let (server_net, message) = NET_TYPE
+            .setup(
+                msg_id.clone(),
+                dest,
+                remote_server_name,
+                net_id,
+                remote,
+                docker_containers,
+                dnat_port,
+            )
+            .ok_or_else(|| format!("NET ID {net_id} does not fit the overlay address plan"))?;
+
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().await.insert(msg_id.clone(), tx);
+        // stop waiting for the acknowledgement if the setup is abandoned (e.g., rolled back)
+        let _pending = PendingGuard {
+            pending: self.pending.clone(),
+            msg_id,
+        };
+
+        outbound
+            .send(Ok(message))
+            .await
+            .map_err(|_| "control channel closed")?;
+
+        match tokio::time::timeout(SETUP_TIMEOUT, rx).await {
+            Ok(Ok(Ok(()))) => Ok(server_net),
+            Ok(Ok(Err(reason))) => Err(reason),
+            Ok(Err(_)) => Err("setup abandoned".to_string()),
+            Err(_) => Err(format!(
+                "not acknowledged within {} s",
+                SETUP_TIMEOUT.as_secs()
+            )),
         }
     }
 
+    /// Most recent network setup failures, newest first.
+    pub(crate) async fn setup_failures(&self) -> Vec<SetupFailure> {
+        self.setup_failures
+            .lock()
+            .await
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+
     pub(crate) async fn allocate_net_id(&self) -> Option<u32> {
         self.net_id_pool.lock().await.allocate()
     }
 
+    pub(crate) async fn reserve_net_id(&self, net_id: u32) -> bool {
+        self.net_id_pool.lock().await.reserve(net_id)
+    }
+
     pub(crate) async fn connected_node_ips(&self) -> Vec<IpAddr> {
         self.clients.read().await.keys().cloned().collect()
     }
 
+    pub(crate) async fn set_cordoned(&self, ip: IpAddr, cordoned: bool) {
+        let mut nodes = self.cordoned.write().await;
+        if cordoned {
+            nodes.insert(ip);
+        } else {
+            nodes.remove(&ip);
+        }
+    }
+
+    pub(crate) async fn is_cordoned(&self, ip: IpAddr) -> bool {
+        self.cordoned.read().await.contains(&ip)
+    }
+
+    /// Record the node at `ip` as a proxy. Returns whether it wasn't known as one yet.
+    pub(crate) async fn add_proxy_node(&self, ip: IpAddr) -> bool {
+        if self.proxy_nodes.read().await.contains(&ip) {
+            return false;
+        }
+        self.proxy_nodes.write().await.insert(ip)
+    }
+
+    /// The known proxy nodes with an open control channel.
+    pub(crate) async fn connected_proxy_nodes(&self) -> Vec<IpAddr> {
+        let clients = self.clients.read().await;
+        self.proxy_nodes
+            .read()
+            .await
+            .iter()
+            .filter(|ip| clients.contains_key(ip))
+            .copied()
+            .collect()
+    }
+
+    /// Signal that replicas or client entries changed, so that the state is persisted again.
+    pub(crate) fn state_changed(&self) {
+        self.state_changed.notify_one();
+    }
+
+    /// Wait for the next `state_changed` signal (or for the pending one, if any).
+    pub(crate) async fn wait_state_changed(&self) {
+        self.state_changed.notified().await;
+    }
+
+    /// Send `kind` to the subscribers of the topology events, if any.
+    pub(crate) fn publish(&self, kind: TopologyEvent) {
+        let _ = self.events.send(Event::new(kind));
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Whether anyone is listening to the topology events,
+    /// to skip collecting them otherwise.
+    pub(crate) fn has_subscribers(&self) -> bool {
+        self.events.receiver_count() > 0
+    }
+
+    /// How many network setups are waiting for an acknowledgement.
+    pub(crate) async fn pending_acks(&self) -> usize {
+        self.pending.lock().await.len()
+    }
+
     pub(crate) async fn pool_stats(&self) -> (u32, u32, u32) {
         self.net_id_pool.lock().await.stats()
     }
@@ -154,6 +464,27 @@ impl Orchestrator {
     }
 }
 
+/// Removes a pending setup when its sender stops waiting for it.
+struct PendingGuard {
+    pending: Arc<Mutex<HashMap<String, oneshot::Sender<SetupResult>>>>,
+    msg_id: String,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        let pending = self.pending.clone();
+        let msg_id = std::mem::take(&mut self.msg_id);
+        tokio::spawn(async move {
+            pending.lock().await.remove(&msg_id);
+        });
+    }
+}
+
+/// Whether `si` has replicas on the node at `ip`.
+fn hosted_on(si: &ServiceInfo, ip: IpAddr) -> bool {
+    matches!(si, ServiceInfo::Registered(reg) if reg.has_replica_on_ip(ip))
+}
+
 #[cfg(test)]
 impl Orchestrator {
     pub(crate) async fn net_ids_in_use(&self) -> u32 {
@@ -161,12 +492,64 @@ impl Orchestrator {
     }
 
     pub(crate) async fn register_fake_client(&self, ip: IpAddr) {
+        self.register_fake_client_recording(ip).await;
+    }
+
+    /// Register a fake client that rejects every network setup with `code`,
+    /// returning the number of setups rejected.
+    pub(crate) async fn register_fake_client_nacking(
+        &self,
+        ip: IpAddr,
+        code: nullnet_grpc_lib::nullnet_grpc::NackCode,
+    ) -> Arc<std::sync::atomic::AtomicUsize> {
         use nullnet_grpc_lib::nullnet_grpc::net_message;
 
         let (tx, mut rx) = mpsc::channel::<Result<NetMessage, Status>>(64);
         self.clients.write().await.insert(ip, tx);
 
-        let pending = self.pending.clone();
+        let orchestrator = self.clone();
+        let rejected = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let rejected_2 = rejected.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(msg)) = rx.recv().await {
+                if let Some(
+                    net_message::Message::VlanSetup(nullnet_grpc_lib::nullnet_grpc::VlanSetup {
+                        msg_id,
+                        ..
+                    })
+                    | net_message::Message::VxlanSetup(nullnet_grpc_lib::nullnet_grpc::VxlanSetup {
+                        msg_id,
+                        ..
+                    }),
+                ) = msg.message
+                {
+                    rejected_2.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    orchestrator
+                        .handle_nack(Nack {
+                            msg_id,
+                            code: code.into(),
+                            reason: "bridge creation failed".to_string(),
+                        })
+                        .await;
+                }
+            }
+        });
+        rejected
+    }
+
+    /// Same as `register_fake_client`, returning the IDs of the networks torn down on `ip`.
+    pub(crate) async fn register_fake_client_recording(
+        &self,
+        ip: IpAddr,
+    ) -> Arc<std::sync::Mutex<Vec<u32>>> {
+        use nullnet_grpc_lib::nullnet_grpc::net_message;
+
+        let (tx, mut rx) = mpsc::channel::<Result<NetMessage, Status>>(64);
+        self.clients.write().await.insert(ip, tx);
+
+        let orchestrator = self.clone();
+        let torn_down = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let torn_down_2 = torn_down.clone();
         tokio::spawn(async move {
             while let Some(Ok(msg)) = rx.recv().await {
                 // auto-ack NetSetup messages
@@ -178,14 +561,52 @@ impl Orchestrator {
                         nullnet_grpc_lib::nullnet_grpc::VxlanSetup { msg_id, .. },
                     )) => {
                         if let Some(msg_id) = msg_id {
-                            if let Some(tx) = pending.lock().await.remove(&msg_id.id) {
-                                let _ = tx.send(());
-                            }
+                            orchestrator.resolve_pending(&msg_id.id, Ok(())).await;
                         }
                     }
+                    Some(net_message::Message::VlanTeardown(
+                        nullnet_grpc_lib::nullnet_grpc::VlanTeardown { vlan_id: net_id },
+                    ))
+                    | Some(net_message::Message::VxlanTeardown(
+                        nullnet_grpc_lib::nullnet_grpc::VxlanTeardown {
+                            vxlan_id: net_id, ..
+                        },
+                    )) => {
+                        torn_down_2.lock().unwrap().push(net_id);
+                    }
                     _ => {}
                 }
             }
         });
+        torn_down
+    }
+
+    /// Register a fake client taking `delay` to handle each message (acknowledging the
+    /// setups), like a node slow to apply them: once its channel is full, senders wait.
+    pub(crate) async fn register_fake_client_slow(&self, ip: IpAddr, delay: Duration) {
+        use nullnet_grpc_lib::nullnet_grpc::net_message;
+
+        let (tx, mut rx) = mpsc::channel::<Result<NetMessage, Status>>(64);
+        self.clients.write().await.insert(ip, tx);
+
+        let orchestrator = self.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(msg)) = rx.recv().await {
+                tokio::time::sleep(delay).await;
+                if let Some(
+                    net_message::Message::VlanSetup(nullnet_grpc_lib::nullnet_grpc::VlanSetup {
+                        msg_id: Some(msg_id),
+                        ..
+                    })
+                    | net_message::Message::VxlanSetup(nullnet_grpc_lib::nullnet_grpc::VxlanSetup {
+                        msg_id: Some(msg_id),
+                        ..
+                    }),
+                ) = msg.message
+                {
+                    orchestrator.resolve_pending(&msg_id.id, Ok(())).await;
+                }
+            }
+        });
     }
 }
@@ -0,0 +1,256 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::LazyLock;
+
+use crate::env::{NET_TYPE, OVERLAY_CIDRS, OVERLAY_V6_PREFIX};
+use crate::net_id_pool::MIN_NET_ID;
+use ipnetwork::{Ipv4Network, Ipv6Network};
+use nullnet_grpc_lib::nullnet_grpc::Net;
+
+/// Overlay address plan in use, built from `NET_TYPE`, `OVERLAY_CIDRS` and `OVERLAY_V6_PREFIX`.
+pub(crate) static OVERLAY_PLAN: LazyLock<OverlayPlan> =
+    LazyLock::new(|| OverlayPlan::new(*NET_TYPE, &OVERLAY_CIDRS, *OVERLAY_V6_PREFIX));
+
+/// Prefix length of the IPv6 per-net blocks (same 8 addresses as the IPv4 VXLAN blocks).
+const BLOCK6_PREFIX: u8 = 125;
+
+/// How overlay addresses are assigned to networks.
+///
+/// Each NET ID gets a fixed-size block (/30 for VLAN, /29 for VXLAN): blocks are
+/// consumed in order from the first CIDR onwards, starting from `MIN_NET_ID`.
+/// NET IDs whose block wouldn't fit in the configured ranges are refused.
+///
+/// The default plan (`10.0.0.0/8` alone) keeps the layout used before plans were
+/// configurable, where NET ID `n` gets the `n`-th block, so that the addresses of
+/// networks persisted by older versions still match the ones live on the clients.
+#[derive(Debug)]
+pub(crate) struct OverlayPlan {
+    /// IPv4 ranges the blocks are carved from, in order.
+    cidrs: Vec<Ipv4Network>,
+    /// Optional IPv6 prefix (VXLAN only), carved into /125 blocks.
+    v6_prefix: Option<Ipv6Network>,
+    /// Prefix length of the IPv4 per-net blocks.
+    block_prefix: u8,
+    /// NET ID getting the first IPv4 block (0 for the default plan, `MIN_NET_ID` otherwise).
+    first_block_net_id: u32,
+    /// Highest NET ID that fits both the plan and the network type.
+    max_net_id: u32,
+}
+
+impl OverlayPlan {
+    /// Build a plan for `net`, skipping (with a message) CIDRs that are smaller
+    /// than a block or that overlap a previous one.
+    pub(crate) fn new(net: Net, cidrs: &[Ipv4Network], v6_prefix: Option<Ipv6Network>) -> Self {
+        let (block_prefix, max_wire_id, v6_prefix) = match net {
+            // 802.1Q is 12-bit; 0 and 4095 are reserved
+            Net::Vlan => (30, 4094, None),
+            // VNIs are 24-bit
+            Net::Vxlan => (29, 16_777_215, v6_prefix),
+        };
+
+        let mut usable: Vec<Ipv4Network> = Vec::new();
+        for cidr in cidrs {
+            let cidr = Ipv4Network::new(cidr.network(), cidr.prefix()).unwrap_or(*cidr);
+            if cidr.prefix() > block_prefix {
+                println!("Overlay CIDR {cidr} is smaller than a /{block_prefix} block: skipped");
+            } else if let Some(other) = usable.iter().find(|other| other.overlaps(cidr)) {
+                println!("Overlay CIDR {cidr} overlaps {other}: skipped");
+            } else {
+                usable.push(cidr);
+            }
+        }
+        if usable.is_empty() {
+            println!("No usable overlay CIDR: using 10.0.0.0/8");
+            usable.push(default_cidr());
+        }
+
+        let first_block_net_id = if usable == [default_cidr()] {
+            0
+        } else {
+            MIN_NET_ID
+        };
+        let capacity: u64 = usable
+            .iter()
+            .map(|cidr| 1u64 << (block_prefix - cidr.prefix()))
+            .sum();
+        let mut max_net_id = u64::from(first_block_net_id)
+            .saturating_add(capacity)
+            .saturating_sub(1)
+            .min(u64::from(max_wire_id));
+        if let Some(prefix) = v6_prefix {
+            let bits = BLOCK6_PREFIX.saturating_sub(prefix.prefix());
+            let capacity6 = 1u64.checked_shl(u32::from(bits)).unwrap_or(u64::MAX);
+            max_net_id = max_net_id.min(
+                u64::from(MIN_NET_ID)
+                    .saturating_add(capacity6)
+                    .saturating_sub(1),
+            );
+        }
+
+        Self {
+            cidrs: usable,
+            v6_prefix,
+            block_prefix,
+            first_block_net_id,
+            max_net_id: u32::try_from(max_net_id).unwrap_or(max_wire_id),
+        }
+    }
+
+    pub(crate) fn cidrs(&self) -> &[Ipv4Network] {
+        &self.cidrs
+    }
+
+    pub(crate) fn v6_prefix(&self) -> Option<Ipv6Network> {
+        self.v6_prefix
+    }
+
+    pub(crate) fn block_prefix(&self) -> u8 {
+        self.block_prefix
+    }
+
+    pub(crate) fn min_net_id(&self) -> u32 {
+        MIN_NET_ID
+    }
+
+    pub(crate) fn max_net_id(&self) -> u32 {
+        self.max_net_id
+    }
+
+    /// Network address of the IPv4 block assigned to `net_id`,
+    /// or `None` if the ID doesn't fit the plan.
+    pub(crate) fn block(&self, net_id: u32) -> Option<Ipv4Addr> {
+        let mut index =
+            u64::from(self.index(net_id)?) + u64::from(MIN_NET_ID - self.first_block_net_id);
+        for cidr in &self.cidrs {
+            let blocks = 1u64 << (self.block_prefix - cidr.prefix());
+            if index < blocks {
+                let block_size = 1u64 << (32 - self.block_prefix);
+                let addr = u64::from(u32::from(cidr.network())) + index * block_size;
+                return u32::try_from(addr).ok().map(Ipv4Addr::from);
+            }
+            index -= blocks;
+        }
+        None
+    }
+
+    /// Network address of the IPv6 /125 block assigned to `net_id`,
+    /// or `None` if no IPv6 prefix is configured or the ID doesn't fit the plan.
+    pub(crate) fn block6(&self, net_id: u32) -> Option<Ipv6Addr> {
+        let prefix = self.v6_prefix?;
+        let index = self.index(net_id)?;
+        let block_size = 1u128 << (128 - BLOCK6_PREFIX);
+        Some(Ipv6Addr::from(
+            u128::from(prefix.network()) + u128::from(index) * block_size,
+        ))
+    }
+
+    /// Position of `net_id` among the allocatable NET IDs.
+    fn index(&self, net_id: u32) -> Option<u32> {
+        (MIN_NET_ID..=self.max_net_id)
+            .contains(&net_id)
+            .then(|| net_id - MIN_NET_ID)
+    }
+}
+
+/// The range used when no `OVERLAY_CIDRS` is set (or none is usable).
+fn default_cidr() -> Ipv4Network {
+    Ipv4Network::new(Ipv4Addr::new(10, 0, 0, 0), 8).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidrs(cidrs: &[&str]) -> Vec<Ipv4Network> {
+        cidrs.iter().map(|c| c.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_blocks_span_cidrs_in_order() {
+        let plan = OverlayPlan::new(
+            Net::Vxlan,
+            &cidrs(&["172.20.0.0/28", "192.168.100.0/29"]),
+            None,
+        );
+        assert_eq!(plan.max_net_id(), MIN_NET_ID + 2);
+
+        assert_eq!(plan.block(MIN_NET_ID), Some(Ipv4Addr::new(172, 20, 0, 0)));
+        assert_eq!(
+            plan.block(MIN_NET_ID + 1),
+            Some(Ipv4Addr::new(172, 20, 0, 8))
+        );
+        assert_eq!(
+            plan.block(MIN_NET_ID + 2),
+            Some(Ipv4Addr::new(192, 168, 100, 0))
+        );
+    }
+
+    #[test]
+    fn test_ids_outside_plan_are_refused() {
+        let plan = OverlayPlan::new(Net::Vlan, &cidrs(&["172.20.0.0/29"]), None);
+        assert_eq!(plan.max_net_id(), MIN_NET_ID + 1);
+
+        assert_eq!(plan.block(MIN_NET_ID - 1), None);
+        assert_eq!(
+            plan.block(MIN_NET_ID + 1),
+            Some(Ipv4Addr::new(172, 20, 0, 4))
+        );
+        assert_eq!(plan.block(MIN_NET_ID + 2), None);
+    }
+
+    #[test]
+    fn test_net_type_limits_max_net_id() {
+        let plan = OverlayPlan::new(Net::Vlan, &cidrs(&["10.0.0.0/8"]), None);
+        assert_eq!(plan.max_net_id(), 4094);
+
+        let plan = OverlayPlan::new(Net::Vxlan, &cidrs(&["10.0.0.0/8"]), None);
+        assert_eq!(plan.max_net_id(), (1 << 21) - 1);
+    }
+
+    #[test]
+    fn test_default_plan_keeps_historical_layout() {
+        let plan = OverlayPlan::new(Net::Vxlan, &cidrs(&["10.0.0.0/8"]), None);
+        assert_eq!(plan.block(MIN_NET_ID), Some(Ipv4Addr::new(10, 0, 3, 40)));
+
+        let plan = OverlayPlan::new(Net::Vlan, &cidrs(&["10.0.0.0/8"]), None);
+        assert_eq!(plan.block(MIN_NET_ID), Some(Ipv4Addr::new(10, 0, 1, 148)));
+
+        // any other plan starts from its first block
+        let plan = OverlayPlan::new(Net::Vxlan, &cidrs(&["10.0.0.0/9"]), None);
+        assert_eq!(plan.block(MIN_NET_ID), Some(Ipv4Addr::new(10, 0, 0, 0)));
+    }
+
+    #[test]
+    fn test_unusable_cidrs_are_skipped() {
+        let plan = OverlayPlan::new(
+            Net::Vxlan,
+            &cidrs(&["172.20.0.0/16", "172.20.1.0/24", "192.168.0.0/30"]),
+            None,
+        );
+        assert_eq!(plan.cidrs(), cidrs(&["172.20.0.0/16"]).as_slice());
+
+        let plan = OverlayPlan::new(Net::Vxlan, &cidrs(&["192.168.0.0/30"]), None);
+        assert_eq!(plan.cidrs(), cidrs(&["10.0.0.0/8"]).as_slice());
+    }
+
+    #[test]
+    fn test_v6_prefix_limits_capacity() {
+        let prefix: Ipv6Network = "fd00:6e6e::/123".parse().unwrap();
+        let plan = OverlayPlan::new(Net::Vxlan, &cidrs(&["10.0.0.0/8"]), Some(prefix));
+        assert_eq!(plan.max_net_id(), MIN_NET_ID + 3);
+
+        assert_eq!(
+            plan.block6(MIN_NET_ID),
+            Some("fd00:6e6e::".parse().unwrap())
+        );
+        assert_eq!(
+            plan.block6(MIN_NET_ID + 3),
+            Some("fd00:6e6e::18".parse().unwrap())
+        );
+        assert_eq!(plan.block6(MIN_NET_ID + 4), None);
+
+        // no IPv6 overlay for VLAN
+        let plan = OverlayPlan::new(Net::Vlan, &cidrs(&["10.0.0.0/8"]), Some(prefix));
+        assert_eq!(plan.v6_prefix(), None);
+        assert_eq!(plan.block6(MIN_NET_ID), None);
+    }
+}
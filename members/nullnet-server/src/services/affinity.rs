@@ -0,0 +1,83 @@
+use crate::services::service_info::Replica;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Per-service `affinity` setting in `services.toml`: how the replica reached
+/// by a chain hop is chosen relative to the node of the previous hop.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct Affinity {
+    #[serde(default)]
+    policy: AffinityPolicy,
+    /// Node label keys shared with the previous hop, from the most to the least specific.
+    #[serde(default = "default_labels")]
+    labels: Vec<String>,
+}
+
+impl Default for Affinity {
+    fn default() -> Self {
+        Self {
+            policy: AffinityPolicy::default(),
+            labels: default_labels(),
+        }
+    }
+}
+
+fn default_labels() -> Vec<String> {
+    vec!["zone".to_string()]
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum AffinityPolicy {
+    /// Locality is ignored.
+    #[default]
+    None,
+    /// The closest replicas are preferred, falling back to any replica.
+    Preferred,
+    /// Only replicas on the same host or sharing one of the labels are eligible.
+    Required,
+}
+
+impl Affinity {
+    /// Replicas eligible for a hop from the node at `prev_ip` with `prev_labels`:
+    /// the ones on the same host if any, otherwise the ones sharing the first
+    /// label (in order) that any replica shares with the previous hop.
+    pub(crate) fn candidates<'a>(
+        &self,
+        replicas: &[&'a Replica],
+        prev_ip: IpAddr,
+        prev_labels: &HashMap<String, String>,
+    ) -> Vec<&'a Replica> {
+        if self.policy == AffinityPolicy::None {
+            return replicas.to_vec();
+        }
+
+        let same_host: Vec<&Replica> = replicas
+            .iter()
+            .copied()
+            .filter(|r| r.ip() == prev_ip)
+            .collect();
+        if !same_host.is_empty() {
+            return same_host;
+        }
+        for key in &self.labels {
+            let Some(value) = prev_labels.get(key) else {
+                continue;
+            };
+            let sharing: Vec<&Replica> = replicas
+                .iter()
+                .copied()
+                .filter(|r| r.labels().get(key) == Some(value))
+                .collect();
+            if !sharing.is_empty() {
+                return sharing;
+            }
+        }
+
+        match self.policy {
+            AffinityPolicy::Required => Vec::new(),
+            AffinityPolicy::None | AffinityPolicy::Preferred => replicas.to_vec(),
+        }
+    }
+}
@@ -1,7 +1,13 @@
+use crate::events::{EdgeEvent, TopologyEvent};
+use crate::metrics::METRICS;
 use crate::orchestrator::Orchestrator;
-use crate::services::clients::Client;
+use crate::services::clients::{ChainContext, Client};
+use crate::services::dep_graph::DepGraph;
 use crate::services::service_info::ServiceInfo;
-use std::collections::{HashMap, HashSet};
+use crate::services::service_map::{AllServices, Services};
+use nullnet_grpc_lib::nullnet_grpc::ServiceHealth;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::net::IpAddr;
 use std::time::Duration;
 
@@ -26,6 +32,133 @@ pub(crate) enum ServiceChange {
     ProxyDisconnected { ip: IpAddr },
     /// A proxy client's timeout expired; tear down its chains.
     ProxyClientTimedOut { name: String, client: Client },
+    /// A network is no longer held by one of its ends; tear down the chains using it.
+    NetLost { net_id: u32 },
+    /// A replica failed its health check; tear down the chains pointing at it.
+    ReplicaUnhealthy {
+        name: String,
+        ip: IpAddr,
+        docker_container: Option<String>,
+    },
+    /// No traffic was seen on a backend-triggered chain for its `idle_timeout`.
+    BackendChainIdle {
+        name: String,
+        ip: IpAddr,
+        docker_container: Option<String>,
+        port: u16,
+    },
+    /// A proxy client was evicted through the admin API; tear down its chains.
+    ProxyClientEvicted { name: String, client: Client },
+    /// A network was torn down through the admin API; tear down the chains using it.
+    NetEvicted { net_id: u32 },
+    /// A replica was drained (or put back in rotation) through the admin API:
+    /// nothing is torn down, its clients are left to time out.
+    ReplicaDraining {
+        name: String,
+        ip: IpAddr,
+        docker_container: Option<String>,
+        draining: bool,
+    },
+    /// A node was cordoned (or uncordoned) through the admin API: all its replicas are drained.
+    NodeCordoned { ip: IpAddr, cordoned: bool },
+    /// A warm network exceeds the service's `warm_networks`; tear down its chain.
+    WarmNetworkReleased { name: String, client: Client },
+}
+
+impl ServiceChange {
+    /// Name of the variant, as reported in the metrics.
+    fn kind(&self) -> &'static str {
+        match self {
+            ServiceChange::Removed { .. } => "Removed",
+            ServiceChange::ProxyDepsChanged { .. } => "ProxyDepsChanged",
+            ServiceChange::TriggersChanged { .. } => "TriggersChanged",
+            ServiceChange::ReachabilityChanged { .. } => "ReachabilityChanged",
+            ServiceChange::ReplicasRemoved { .. } => "ReplicasRemoved",
+            ServiceChange::ReplicaRemoved { .. } => "ReplicaRemoved",
+            ServiceChange::ProxyDisconnected { .. } => "ProxyDisconnected",
+            ServiceChange::ProxyClientTimedOut { .. } => "ProxyClientTimedOut",
+            ServiceChange::NetLost { .. } => "NetLost",
+            ServiceChange::ReplicaUnhealthy { .. } => "ReplicaUnhealthy",
+            ServiceChange::BackendChainIdle { .. } => "BackendChainIdle",
+            ServiceChange::ProxyClientEvicted { .. } => "ProxyClientEvicted",
+            ServiceChange::NetEvicted { .. } => "NetEvicted",
+            ServiceChange::ReplicaDraining { .. } => "ReplicaDraining",
+            ServiceChange::NodeCordoned { .. } => "NodeCordoned",
+            ServiceChange::WarmNetworkReleased { .. } => "WarmNetworkReleased",
+        }
+    }
+}
+
+impl ServiceChange {
+    /// What this change is about, for the audit log.
+    fn applied_event(&self) -> TopologyEvent {
+        let (mut service, mut node, mut docker_container, mut net_id, mut port) =
+            (None, None, None, None, None);
+        match self {
+            ServiceChange::Removed { name }
+            | ServiceChange::ProxyDepsChanged { name }
+            | ServiceChange::TriggersChanged { name }
+            | ServiceChange::ReachabilityChanged { name } => service = Some(name.clone()),
+            ServiceChange::ReplicasRemoved { name, ip } => {
+                service = Some(name.clone());
+                node = Some(*ip);
+            }
+            ServiceChange::ReplicaRemoved {
+                name,
+                ip,
+                docker_container: docker,
+            }
+            | ServiceChange::ReplicaUnhealthy {
+                name,
+                ip,
+                docker_container: docker,
+            } => {
+                service = Some(name.clone());
+                node = Some(*ip);
+                docker_container.clone_from(docker);
+            }
+            ServiceChange::ProxyDisconnected { ip } => node = Some(*ip),
+            ServiceChange::ProxyClientTimedOut { name, client }
+            | ServiceChange::ProxyClientEvicted { name, client }
+            | ServiceChange::WarmNetworkReleased { name, client } => {
+                service = Some(name.clone());
+                node = client.is_proxy();
+            }
+            ServiceChange::NetLost { net_id: id } | ServiceChange::NetEvicted { net_id: id } => {
+                net_id = Some(*id);
+            }
+            ServiceChange::ReplicaDraining {
+                name,
+                ip,
+                docker_container: docker,
+                ..
+            } => {
+                service = Some(name.clone());
+                node = Some(*ip);
+                docker_container.clone_from(docker);
+            }
+            ServiceChange::NodeCordoned { ip, .. } => node = Some(*ip),
+            ServiceChange::BackendChainIdle {
+                name,
+                ip,
+                docker_container: docker,
+                port: p,
+            } => {
+                service = Some(name.clone());
+                node = Some(*ip);
+                docker_container.clone_from(docker);
+                port = Some(*p);
+            }
+        }
+        TopologyEvent::ChangeApplied {
+            change: self.kind(),
+            service,
+            node,
+            docker_container,
+            net_id,
+            port,
+        }
+    }
 }
 
 enum ProxyFilter<'a> {
@@ -57,7 +190,7 @@ impl ProxyFilter<'_> {
 }
 
 pub(crate) fn detect_config_changes(
-    current: &HashMap<String, ServiceInfo>,
+    current: &Services,
     loaded: &HashMap<String, ServiceInfo>,
 ) -> Vec<ServiceChange> {
     let mut changes = Vec::new();
@@ -104,7 +237,7 @@ pub(crate) fn detect_config_changes(
 }
 
 pub(crate) fn detect_services_list_changes(
-    current: &HashMap<String, ServiceInfo>,
+    current: &Services,
     sender_ip: IpAddr,
     service_list: &[(String, u16, Option<String>)],
 ) -> Vec<ServiceChange> {
@@ -137,7 +270,7 @@ pub(crate) fn detect_services_list_changes(
 }
 
 pub(crate) fn detect_node_disconnect_changes(
-    current: &HashMap<String, ServiceInfo>,
+    current: &Services,
     disconnected_ip: IpAddr,
 ) -> Vec<ServiceChange> {
     let mut changes: Vec<ServiceChange> = current
@@ -155,17 +288,8 @@ pub(crate) fn detect_node_disconnect_changes(
         })
         .collect();
 
-    let has_proxy_clients = current.iter().any(|(_, si)| {
-        if let ServiceInfo::Registered(reg) = si {
-            reg.replicas().iter().any(|replica| {
-                replica
-                    .clients()
-                    .keys()
-                    .any(|c| c.is_proxy() == Some(disconnected_ip))
-            })
-        } else {
-            false
-        }
+    let has_proxy_clients = current.values().any(|si| {
+        matches!(si, ServiceInfo::Registered(reg) if reg.has_proxy_clients_of(disconnected_ip))
     });
     if has_proxy_clients {
         changes.push(ServiceChange::ProxyDisconnected {
@@ -176,12 +300,150 @@ pub(crate) fn detect_node_disconnect_changes(
     changes
 }
 
+/// Replicas hosted by `node_ip` that were healthy and are reported unhealthy in `health`.
+pub(crate) fn detect_health_changes(
+    current: &Services,
+    node_ip: IpAddr,
+    health: &ServiceHealth,
+) -> Vec<ServiceChange> {
+    let mut changes = Vec::new();
+    for (name, si) in current {
+        let ServiceInfo::Registered(reg) = si else {
+            continue;
+        };
+        for replica in reg.replicas() {
+            if replica.ip() == node_ip
+                && replica.is_healthy()
+                && !reported_healthy(health, name, replica.docker_container())
+            {
+                changes.push(ServiceChange::ReplicaUnhealthy {
+                    name: name.clone(),
+                    ip: node_ip,
+                    docker_container: replica.docker_container().map(String::from),
+                });
+            }
+        }
+    }
+    changes
+}
+
+/// Record the health reported by `node_ip` on every replica it hosts.
+pub(crate) fn apply_node_health(services: &mut Services, node_ip: IpAddr, health: &ServiceHealth) {
+    for (name, si) in services.iter_mut() {
+        let ServiceInfo::Registered(reg) = si else {
+            continue;
+        };
+        let replicas: Vec<(Option<String>, bool)> = reg
+            .replicas()
+            .iter()
+            .filter(|r| r.ip() == node_ip)
+            .map(|r| {
+                let docker = r.docker_container();
+                (
+                    docker.map(String::from),
+                    reported_healthy(health, name, docker),
+                )
+            })
+            .collect();
+        for (docker, healthy) in replicas {
+            si.set_replica_health(node_ip, docker.as_deref(), healthy);
+        }
+    }
+}
+
+/// Replicas not listed in `health` have no health check, and are healthy.
+fn reported_healthy(health: &ServiceHealth, name: &str, docker_container: Option<&str>) -> bool {
+    !health.replicas.iter().any(|r| {
+        !r.healthy && r.service_name == name && r.docker_container.as_deref() == docker_container
+    })
+}
+
+/// IDs of the established networks with one end on the node at `ip`.
+pub(crate) fn net_ids_on_node(current: &Services, ip: IpAddr) -> HashSet<u32> {
+    current
+        .values()
+        .filter_map(|si| match si {
+            ServiceInfo::Registered(reg) => Some(reg.net_ids_on_node(ip)),
+            ServiceInfo::Unregistered(_) => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Networks tracked for `node_ip` that the node doesn't report holding anymore.
+pub(crate) fn detect_node_state_changes(
+    current: &Services,
+    node_ip: IpAddr,
+    held: &HashSet<u32>,
+) -> Vec<ServiceChange> {
+    let mut lost: Vec<u32> = net_ids_on_node(current, node_ip)
+        .difference(held)
+        .copied()
+        .collect();
+    lost.sort_unstable();
+    lost.into_iter()
+        .map(|net_id| ServiceChange::NetLost { net_id })
+        .collect()
+}
+
+/// Eviction of the proxy client `client` from service `name` through the admin API,
+/// if it's connected to it.
+pub(crate) fn detect_client_eviction(
+    current: &Services,
+    name: &str,
+    client: Client,
+) -> Option<ServiceChange> {
+    let ServiceInfo::Registered(reg) = current.get(name)? else {
+        return None;
+    };
+    reg.is_client_setup(&client)?;
+    Some(ServiceChange::ProxyClientEvicted {
+        name: name.to_string(),
+        client,
+    })
+}
+
+/// Eviction of the network `net_id` through the admin API, if it's established.
+pub(crate) fn detect_net_eviction(current: &Services, net_id: u32) -> Option<ServiceChange> {
+    current
+        .values()
+        .any(|si| match si {
+            ServiceInfo::Registered(reg) => reg
+                .all_clients_owned()
+                .iter()
+                .any(|(_, ci, _, _)| !ci.is_placeholder() && ci.net_id() == net_id),
+            ServiceInfo::Unregistered(_) => false,
+        })
+        .then_some(ServiceChange::NetEvicted { net_id })
+}
+
+/// Draining of a replica of service `name` through the admin API, if it exists.
+pub(crate) fn detect_replica_draining(
+    current: &Services,
+    name: &str,
+    ip: IpAddr,
+    docker_container: Option<String>,
+    draining: bool,
+) -> Option<ServiceChange> {
+    let ServiceInfo::Registered(reg) = current.get(name)? else {
+        return None;
+    };
+    reg.replicas()
+        .iter()
+        .any(|r| r.matches_identity(ip, docker_container.as_deref()))
+        .then(|| ServiceChange::ReplicaDraining {
+            name: name.to_string(),
+            ip,
+            docker_container,
+            draining,
+        })
+}
+
 // --- Teardown helpers ---
 
 async fn teardown_invalidated_service(
     invalidated_service: &str,
-    is_failed: bool,
-    services: &mut HashMap<String, ServiceInfo>,
+    services: &mut Services,
     orchestrator: &Orchestrator,
 ) {
     let services_to_cleanup: Vec<String> = services
@@ -214,26 +476,39 @@ async fn teardown_invalidated_service(
         teardown_all_backend_chains_for(&name, only_through, services, orchestrator).await;
     }
 
-    if is_failed && let Some(si @ ServiceInfo::Registered(_)) = services.get(invalidated_service) {
-        let proxy_deps = si.proxy_deps().to_vec();
+    if let Some(si @ ServiceInfo::Registered(_)) = services.get(invalidated_service) {
+        let proxy_deps = si.proxy_deps().clone();
         let triggers = si.triggers().clone();
+        let idle_timeouts = si.idle_timeouts().clone();
         let timeout = si.timeout();
         let max_nets = si.max_networks();
-        services.insert(
-            invalidated_service.to_string(),
-            ServiceInfo::new(proxy_deps, triggers, timeout, max_nets),
+        let warm_nets = si.warm_networks();
+        let replica_selector = si.replica_selector();
+        let affinity = si.affinity().clone();
+        services.replace(
+            invalidated_service,
+            ServiceInfo::new(
+                proxy_deps,
+                triggers,
+                idle_timeouts,
+                timeout,
+                max_nets,
+                warm_nets,
+                replica_selector,
+                affinity,
+            ),
         );
     }
 }
 
 /// Tear down proxy chains on a service, filtered by `proxy_filter`.
 ///
-/// For each matching proxy client, walks the full dependency chain from the
-/// service replica the proxy is on, decrementing each edge at every level
-/// (A→B→C→…). Then tears down the proxy→service edge itself.
+/// For each matching proxy client, decrements each edge of the chain started
+/// by the service replica the proxy is on.
+/// Then tears down the proxy→service edge itself.
 async fn teardown_chain(
     name: &str,
-    services: &mut HashMap<String, ServiceInfo>,
+    services: &mut Services,
     orchestrator: &Orchestrator,
     proxy_filter: ProxyFilter<'_>,
 ) {
@@ -302,128 +577,67 @@ async fn teardown_chain(
     }
 }
 
-/// Walk the proxy dependency chain starting from a specific service replica
-/// (read-only) and collect the `(client, dep_service_name)` edges. Walks
-/// `proxy_deps` recursively (linear chain, possibly with ghost edges from
-/// ancestor levels).
-pub(crate) fn collect_dep_chain_edges(
-    service_name: &str,
-    replica_ip: IpAddr,
-    replica_docker: Option<&str>,
-    services: &HashMap<String, ServiceInfo>,
-) -> Vec<(Client, String)> {
-    let mut edges = Vec::new();
-    let mut current_name = service_name.to_string();
-    let mut current_ip = replica_ip;
-    let mut current_docker: Option<String> = replica_docker.map(String::from);
-
-    loop {
-        let deps = services
-            .get(&current_name)
-            .map(ServiceInfo::proxy_deps)
-            .unwrap_or_default();
-        if deps.is_empty() {
-            break;
-        }
-
-        let mut next: Option<(String, IpAddr, Option<String>)> = None;
-        for dep_name in deps {
-            let hop = emit_edge_and_probe_hop(
-                &mut edges,
-                &current_name,
-                current_ip,
-                current_docker.as_deref(),
-                dep_name,
-                services,
-            );
-            if let Some((ip, docker)) = hop {
-                next = Some((dep_name.clone(), ip, docker));
-            }
-        }
-
-        match next {
-            Some((name, ip, docker)) => {
-                current_name = name;
-                current_ip = ip;
-                current_docker = docker;
-            }
-            None => break,
-        }
-    }
-
-    edges
-}
-
-/// Walk the backend trigger chains starting from a specific initiator replica
-/// and collect the `(client, dep_service_name)` edges. One chain per trigger
-/// port; each is walked as a linear chain. If `only_through` is `Some(dep)`,
-/// chains that don't reference `dep` are skipped — useful for dep-side teardown
-/// where only chains that go through the affected dep should come down.
-fn collect_backend_chain_edges(
+/// Trigger ports of the initiator's backend chains. If `only_through` is
+/// `Some(dep)`, chains that don't reference `dep` are skipped — useful for
+/// dep-side teardown where only chains that go through the affected dep
+/// should come down.
+fn backend_chain_ports(
     initiator_name: &str,
-    initiator_ip: IpAddr,
-    initiator_docker: Option<&str>,
     only_through: Option<&str>,
-    services: &HashMap<String, ServiceInfo>,
-) -> Vec<(Client, String)> {
-    let mut edges = Vec::new();
+    services: &Services,
+) -> Vec<u16> {
     let Some(triggers) = services.get(initiator_name).map(ServiceInfo::triggers) else {
-        return edges;
+        return Vec::new();
     };
-    for chain in triggers.values() {
-        if let Some(dep) = only_through
-            && !chain.iter().any(|d| d == dep)
-        {
-            continue;
-        }
-        let mut current_name = initiator_name.to_string();
-        let mut current_ip = initiator_ip;
-        let mut current_docker: Option<String> = initiator_docker.map(String::from);
-        for dep_name in chain {
-            let hop = emit_edge_and_probe_hop(
-                &mut edges,
-                &current_name,
-                current_ip,
-                current_docker.as_deref(),
-                dep_name,
-                services,
-            );
-            match hop {
-                Some((ip, docker)) => {
-                    current_name.clone_from(dep_name);
-                    current_ip = ip;
-                    current_docker = docker;
-                }
-                None => break,
-            }
-        }
-    }
-    edges
+    triggers
+        .iter()
+        .filter(|(_, graph)| only_through.is_none_or(|dep| graph.contains(dep)))
+        .map(|(port, _)| *port)
+        .collect()
 }
 
-/// Backend twin of `teardown_dep_chain`: walks the initiator's trigger chains
-/// and decrements each edge. `only_through` filters to chains containing the
+/// Backend twin of `teardown_dep_chain`: tears down the initiator's trigger chains,
+/// decrementing each of their edges. `only_through` filters to chains containing the
 /// given dep name; `None` walks every chain.
 async fn teardown_backend_chain(
     initiator_name: &str,
     initiator_ip: IpAddr,
     initiator_docker: Option<&str>,
     only_through: Option<&str>,
-    services: &mut HashMap<String, ServiceInfo>,
+    services: &mut Services,
     orchestrator: &Orchestrator,
 ) {
-    let edges = collect_backend_chain_edges(
-        initiator_name,
-        initiator_ip,
-        initiator_docker,
-        only_through,
-        services,
-    );
-    for (client, dep_name) in edges {
+    for port in backend_chain_ports(initiator_name, only_through, services) {
+        teardown_backend_chain_at_port(
+            initiator_name,
+            initiator_ip,
+            initiator_docker,
+            port,
+            services,
+            orchestrator,
+        )
+        .await;
+    }
+}
+
+/// Tear down the initiator's trigger chain at `port` only, decrementing each edge.
+async fn teardown_backend_chain_at_port(
+    initiator_name: &str,
+    initiator_ip: IpAddr,
+    initiator_docker: Option<&str>,
+    port: u16,
+    services: &mut Services,
+    orchestrator: &Orchestrator,
+) {
+    let chain = ChainContext::backend(initiator_name, initiator_ip, initiator_docker, port);
+    for (client, dep_name) in services.chain_edges(&chain) {
         if let Some(ServiceInfo::Registered(dep_reg)) = services.get_mut(&dep_name) {
-            dep_reg.decrement_chain(&client, orchestrator).await;
+            dep_reg.decrement_chain(&client, &chain, orchestrator).await;
         }
     }
+    if let Some(ServiceInfo::Registered(reg)) = services.get_mut(initiator_name) {
+        reg.clear_backend_activity(initiator_ip, initiator_docker, port);
+    }
 }
 
 /// Tear down every backend chain initiated by any replica of `initiator_name`.
@@ -431,7 +645,7 @@ async fn teardown_backend_chain(
 async fn teardown_all_backend_chains_for(
     initiator_name: &str,
     only_through: Option<&str>,
-    services: &mut HashMap<String, ServiceInfo>,
+    services: &mut Services,
     orchestrator: &Orchestrator,
 ) {
     let replicas: Vec<(IpAddr, Option<String>)> = match services.get(initiator_name) {
@@ -455,43 +669,20 @@ async fn teardown_all_backend_chains_for(
     }
 }
 
-/// Push a `(current → dep)` edge onto `edges` and return the dep replica's
-/// `(ip, docker)` if a client for `current` is already set up there.
-fn emit_edge_and_probe_hop(
-    edges: &mut Vec<(Client, String)>,
-    current_name: &str,
-    current_ip: IpAddr,
-    current_docker: Option<&str>,
-    dep_name: &str,
-    services: &HashMap<String, ServiceInfo>,
-) -> Option<(IpAddr, Option<String>)> {
-    let client = Client::new_service(
-        current_name.to_string(),
-        current_ip,
-        current_docker.map(String::from),
-    );
-    let hop = match services.get(dep_name) {
-        Some(ServiceInfo::Registered(dep_reg)) => dep_reg.client_replica(&client),
-        _ => None,
-    };
-    edges.push((client, dep_name.to_string()));
-    hop
-}
-
-/// Walk the dependency chain starting from a specific service replica and
-/// decrement `active_chains` at each level. If an edge reaches 0, its VXLAN
-/// is torn down. Handles arbitrary chain lengths (A→B→C→…).
+/// Decrement `active_chains` on each edge of the proxy chain started by a specific
+/// service replica. If an edge reaches 0, its
+/// VXLAN is torn down, so edges shared with other chains outlive this one.
 async fn teardown_dep_chain(
     service_name: &str,
     replica_ip: IpAddr,
     replica_docker: Option<&str>,
-    services: &mut HashMap<String, ServiceInfo>,
+    services: &mut Services,
     orchestrator: &Orchestrator,
 ) {
-    let edges = collect_dep_chain_edges(service_name, replica_ip, replica_docker, services);
-    for (client, dep_name) in edges {
+    let chain = ChainContext::proxy(service_name, replica_ip, replica_docker);
+    for (client, dep_name) in services.chain_edges(&chain) {
         if let Some(ServiceInfo::Registered(dep_reg)) = services.get_mut(&dep_name) {
-            dep_reg.decrement_chain(&client, orchestrator).await;
+            dep_reg.decrement_chain(&client, &chain, orchestrator).await;
         }
     }
 }
@@ -500,199 +691,583 @@ async fn teardown_dep_chain(
 /// then remove those replicas.
 ///
 /// Handles both service-to-service clients ON the removed replicas (by tearing
-/// down the originating service's chains) and proxy clients ON the removed
+/// down the chains using their edges) and proxy clients ON the removed
 /// replicas (by tearing down their full dependency chains).
 async fn teardown_partial_replicas(
     name: &str,
     proxy_filter: ProxyFilter<'_>,
-    services: &mut HashMap<String, ServiceInfo>,
+    services: &mut Services,
     orchestrator: &Orchestrator,
 ) {
-    // Service-to-service clients on the affected replicas.
-    // Each carries its source replica identity, so we tear down only the
-    // proxy chains that route through that specific source replica.
-    let affected: Vec<Client> = {
+    // Service-to-service clients on the affected replicas, with the chains
+    // using their edges (possibly started further upstream).
+    let affected: Vec<(Client, Vec<ChainContext>)> = {
         let Some(ServiceInfo::Registered(reg)) = services.get(name) else {
             return;
         };
-        match &proxy_filter {
+        let clients = match &proxy_filter {
             ProxyFilter::OnIp(ip) => reg.service_clients_on_ip(*ip),
             ProxyFilter::OnReplica(ip, docker) => reg.service_clients_on_replica(*ip, *docker),
             _ => vec![],
-        }
+        };
+        clients
+            .into_iter()
+            .map(|c| {
+                let chains = reg.chains_of(&c);
+                (c, chains)
+            })
+            .collect()
     };
 
-    for client in affected {
-        if let Some((src_ip, src_docker)) = client.replica_identity() {
-            teardown_chain(
-                client.name(),
-                services,
-                orchestrator,
-                ProxyFilter::OnReplica(src_ip, src_docker),
-            )
-            .await;
-            // Backend twin: tear down chains initiated by this upstream replica
-            // that route through the affected dep, so deeper edges keyed by the
-            // gone replica don't leak.
-            teardown_backend_chain(
-                client.name(),
-                src_ip,
-                src_docker,
-                Some(name),
-                services,
-                orchestrator,
-            )
-            .await;
-        }
+    for (client, chains) in affected {
+        teardown_chains_through(name, &client, &chains, services, orchestrator).await;
     }
 
     // Proxy clients on the affected replicas — tear down their chains
     teardown_chain(name, services, orchestrator, proxy_filter).await;
 }
 
+/// Tear down the chains using the edge of the service-to-service `client` to `name`.
+///
+/// Placeholders of setups still in flight don't know their chains yet: the chains
+/// going through `name` on the other edges of `client` are torn down instead.
+async fn teardown_chains_through(
+    name: &str,
+    client: &Client,
+    chains: &[ChainContext],
+    services: &mut Services,
+    orchestrator: &Orchestrator,
+) {
+    let chains: Vec<ChainContext> = if chains.is_empty() {
+        let through_name: BTreeSet<ChainContext> = services
+            .client_edges(client)
+            .into_iter()
+            .flat_map(|(_, chains)| chains)
+            .filter(|chain| chain_graph(services, chain).is_some_and(|g| g.contains(name)))
+            .collect();
+        through_name.into_iter().collect()
+    } else {
+        chains.to_vec()
+    };
+    for chain in &chains {
+        let (ip, docker) = chain.replica();
+        match chain.port() {
+            None => {
+                teardown_chain(
+                    chain.service(),
+                    services,
+                    orchestrator,
+                    ProxyFilter::OnReplica(ip, docker),
+                )
+                .await;
+            }
+            Some(port) => {
+                teardown_backend_chain_at_port(
+                    chain.service(),
+                    ip,
+                    docker,
+                    port,
+                    services,
+                    orchestrator,
+                )
+                .await;
+            }
+        }
+    }
+}
+
+/// The dependency graph `chain` was set up along.
+fn chain_graph<'a>(services: &'a Services, chain: &ChainContext) -> Option<&'a DepGraph> {
+    let si = services.get(chain.service())?;
+    match chain.port() {
+        None => Some(si.proxy_deps()),
+        Some(port) => si.triggers().get(&port),
+    }
+}
+
+/// Tear down every chain routed through the network `net_id`, then drop the
+/// entries still using it (edges no chain walk reached).
+async fn teardown_net(net_id: u32, services: &mut Services, orchestrator: &Orchestrator) {
+    let edges: Vec<(String, Client, Vec<ChainContext>)> = services
+        .net_edges(net_id)
+        .into_iter()
+        .filter_map(|(client, name)| match services.get(&name) {
+            Some(ServiceInfo::Registered(reg)) => {
+                let chains = reg.chains_of(&client);
+                Some((name, client, chains))
+            }
+            _ => None,
+        })
+        .collect();
+
+    for (name, client, chains) in &edges {
+        if client.is_proxy().is_some() {
+            teardown_chain(name, services, orchestrator, ProxyFilter::ByClient(client)).await;
+        } else {
+            teardown_chains_through(name, client, chains, services, orchestrator).await;
+        }
+    }
+
+    let mut leftovers = Vec::new();
+    for (name, client, _) in &edges {
+        if let Some(ServiceInfo::Registered(reg)) = services.get_mut(name)
+            && let Some(entry) = reg.take_client(client)
+        {
+            leftovers.push(entry);
+        }
+    }
+    if let Some((ci, server_ip, server_docker)) = leftovers.into_iter().next() {
+        orchestrator
+            .send_net_teardown(
+                ci.client_ip(),
+                ci.docker_container().cloned(),
+                server_ip,
+                server_docker,
+                net_id,
+            )
+            .await;
+    }
+}
+
 // --- Main apply function ---
 
 pub(crate) async fn apply_changes(
     changes: Vec<ServiceChange>,
-    services: &mut HashMap<String, ServiceInfo>,
-    loaded_services: Option<&HashMap<String, ServiceInfo>>,
+    services: &mut Services,
     orchestrator: &Orchestrator,
 ) {
     for change in changes {
-        match change {
-            ServiceChange::Removed { name } => {
-                teardown_invalidated_service(&name, true, services, orchestrator).await;
-                services.remove(&name);
-            }
-            ServiceChange::ProxyDepsChanged { name } => {
-                teardown_invalidated_service(&name, false, services, orchestrator).await;
-            }
-            ServiceChange::TriggersChanged { name } => {
-                teardown_all_backend_chains_for(&name, None, services, orchestrator).await;
-            }
-            ServiceChange::ReachabilityChanged { name } => {
-                teardown_chain(&name, services, orchestrator, ProxyFilter::All).await;
-                teardown_all_backend_chains_for(&name, None, services, orchestrator).await;
-            }
-            ServiceChange::ReplicasRemoved { name, ip } => {
-                let is_last = if let Some(ServiceInfo::Registered(reg)) = services.get(&name) {
-                    reg.replicas().iter().all(|r| r.ip() == ip)
-                } else {
-                    false
-                };
-
-                if is_last {
-                    // Last replica gone — config-based cascade to transitive dependents
-                    teardown_invalidated_service(&name, true, services, orchestrator).await;
-                } else {
-                    // Backend chains initiated by replicas of `name` at `ip`
-                    let dockers: Vec<Option<String>> =
-                        if let Some(ServiceInfo::Registered(reg)) = services.get(&name) {
-                            reg.replicas()
-                                .iter()
-                                .filter(|r| r.ip() == ip)
-                                .map(|r| r.docker_container().map(String::from))
-                                .collect()
-                        } else {
-                            vec![]
-                        };
-                    for docker in dockers {
-                        teardown_backend_chain(
-                            &name,
-                            ip,
-                            docker.as_deref(),
-                            None,
-                            services,
-                            orchestrator,
-                        )
-                        .await;
-                    }
-                    teardown_partial_replicas(&name, ProxyFilter::OnIp(ip), services, orchestrator)
-                        .await;
-                    if let Some(si) = services.get_mut(&name) {
-                        si.remove_replicas_on_ip(ip);
-                    }
-                }
-            }
-            ServiceChange::ReplicaRemoved {
-                name,
-                ip,
-                docker_container,
-            } => {
-                let is_last = if let Some(ServiceInfo::Registered(reg)) = services.get(&name) {
-                    reg.replicas().len() == 1
-                } else {
-                    false
-                };
-
-                if is_last {
-                    teardown_invalidated_service(&name, true, services, orchestrator).await;
-                } else {
-                    // Backend chains initiated by this specific replica
+        let reason = change.kind();
+        METRICS.record_service_change(reason);
+        orchestrator.publish(change.applied_event());
+        if !orchestrator.has_subscribers() {
+            apply_change(change, services, orchestrator).await;
+            continue;
+        }
+        let before = Topology::of(services);
+        apply_change(change, services, orchestrator).await;
+        for event in before.removed_from(&Topology::of(services), reason) {
+            orchestrator.publish(event);
+        }
+    }
+    orchestrator.state_changed();
+}
+
+/// Apply `changes` without recording them, as done by previews.
+async fn run_changes(
+    changes: Vec<ServiceChange>,
+    services: &mut Services,
+    orchestrator: &Orchestrator,
+) {
+    for change in changes {
+        apply_change(change, services, orchestrator).await;
+    }
+}
+
+async fn apply_change(change: ServiceChange, services: &mut Services, orchestrator: &Orchestrator) {
+    match change {
+        ServiceChange::Removed { name } => {
+            // dropped from the set by `merge_loaded`
+            teardown_invalidated_service(&name, services, orchestrator).await;
+        }
+        ServiceChange::ProxyDepsChanged { name } => {
+            // the chains of other services have deps of their own, even through this one
+            teardown_chain(&name, services, orchestrator, ProxyFilter::All).await;
+        }
+        ServiceChange::TriggersChanged { name } => {
+            teardown_all_backend_chains_for(&name, None, services, orchestrator).await;
+        }
+        ServiceChange::ReachabilityChanged { name } => {
+            teardown_chain(&name, services, orchestrator, ProxyFilter::All).await;
+            teardown_all_backend_chains_for(&name, None, services, orchestrator).await;
+        }
+        ServiceChange::ReplicasRemoved { name, ip } => {
+            let is_last = if let Some(ServiceInfo::Registered(reg)) = services.get(&name) {
+                reg.replicas().iter().all(|r| r.ip() == ip)
+            } else {
+                false
+            };
+
+            if is_last {
+                // Last replica gone — config-based cascade to transitive dependents
+                teardown_invalidated_service(&name, services, orchestrator).await;
+            } else {
+                // Backend chains initiated by replicas of `name` at `ip`
+                let dockers: Vec<Option<String>> =
+                    if let Some(ServiceInfo::Registered(reg)) = services.get(&name) {
+                        reg.replicas()
+                            .iter()
+                            .filter(|r| r.ip() == ip)
+                            .map(|r| r.docker_container().map(String::from))
+                            .collect()
+                    } else {
+                        vec![]
+                    };
+                for docker in dockers {
                     teardown_backend_chain(
                         &name,
                         ip,
-                        docker_container.as_deref(),
+                        docker.as_deref(),
                         None,
                         services,
                         orchestrator,
                     )
                     .await;
-                    teardown_partial_replicas(
-                        &name,
-                        ProxyFilter::OnReplica(ip, docker_container.as_deref()),
-                        services,
-                        orchestrator,
-                    )
-                    .await;
-                    if let Some(si) = services.get_mut(&name) {
-                        si.remove_replica(ip, docker_container.as_deref());
-                    }
                 }
-            }
-            ServiceChange::ProxyDisconnected { ip } => {
-                let proxy_services: Vec<String> = services
-                    .iter()
-                    .filter(|(_, si)| {
-                        if let ServiceInfo::Registered(reg) = si {
-                            reg.replicas().iter().any(|replica| {
-                                replica.clients().keys().any(|c| c.is_proxy() == Some(ip))
-                            })
-                        } else {
-                            false
-                        }
-                    })
-                    .map(|(name, _)| name.clone())
-                    .collect();
-                for name in proxy_services {
-                    teardown_chain(&name, services, orchestrator, ProxyFilter::ByIp(ip)).await;
+                teardown_partial_replicas(&name, ProxyFilter::OnIp(ip), services, orchestrator)
+                    .await;
+                if let Some(si) = services.get_mut(&name) {
+                    si.remove_replicas_on_ip(ip);
                 }
             }
-            ServiceChange::ProxyClientTimedOut { name, client } => {
-                println!(
-                    "Proxy client '{}' timed out on service '{name}'",
-                    client.display_name()
-                );
-                teardown_chain(
+        }
+        ServiceChange::ReplicaRemoved {
+            name,
+            ip,
+            docker_container,
+        } => {
+            let is_last = if let Some(ServiceInfo::Registered(reg)) = services.get(&name) {
+                reg.replicas().len() == 1
+            } else {
+                false
+            };
+
+            if is_last {
+                teardown_invalidated_service(&name, services, orchestrator).await;
+            } else {
+                // Backend chains initiated by this specific replica
+                teardown_backend_chain(
+                    &name,
+                    ip,
+                    docker_container.as_deref(),
+                    None,
+                    services,
+                    orchestrator,
+                )
+                .await;
+                teardown_partial_replicas(
                     &name,
+                    ProxyFilter::OnReplica(ip, docker_container.as_deref()),
                     services,
                     orchestrator,
-                    ProxyFilter::ByClient(&client),
                 )
                 .await;
+                if let Some(si) = services.get_mut(&name) {
+                    si.remove_replica(ip, docker_container.as_deref());
+                }
+            }
+        }
+        ServiceChange::ProxyDisconnected { ip } => {
+            let proxy_services: Vec<String> = services
+                .iter()
+                .filter(|(_, si)| {
+                    matches!(si, ServiceInfo::Registered(reg) if reg.has_proxy_clients_of(ip))
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in proxy_services {
+                teardown_chain(&name, services, orchestrator, ProxyFilter::ByIp(ip)).await;
             }
         }
+        ServiceChange::ProxyClientTimedOut { name, client } => {
+            println!(
+                "Proxy client '{}' timed out on service '{name}'",
+                client.display_name()
+            );
+            teardown_chain(
+                &name,
+                services,
+                orchestrator,
+                ProxyFilter::ByClient(&client),
+            )
+            .await;
+        }
+        ServiceChange::NetLost { net_id } => {
+            println!("Network {net_id} was lost");
+            teardown_net(net_id, services, orchestrator).await;
+        }
+        ServiceChange::ProxyClientEvicted { name, client } => {
+            println!(
+                "Proxy client '{}' evicted from service '{name}'",
+                client.display_name()
+            );
+            teardown_chain(
+                &name,
+                services,
+                orchestrator,
+                ProxyFilter::ByClient(&client),
+            )
+            .await;
+        }
+        ServiceChange::WarmNetworkReleased { name, client } => {
+            println!(
+                "Warm network '{}' released from service '{name}'",
+                client.display_name()
+            );
+            teardown_chain(
+                &name,
+                services,
+                orchestrator,
+                ProxyFilter::ByClient(&client),
+            )
+            .await;
+        }
+        ServiceChange::NetEvicted { net_id } => {
+            println!("Network {net_id} evicted");
+            teardown_net(net_id, services, orchestrator).await;
+        }
+        ServiceChange::ReplicaDraining {
+            name,
+            ip,
+            docker_container,
+            draining,
+        } => {
+            if let Some(si) = services.get_mut(&name) {
+                si.set_replica_draining(ip, docker_container.as_deref(), draining);
+            }
+        }
+        ServiceChange::NodeCordoned { ip, cordoned } => {
+            orchestrator.set_cordoned(ip, cordoned).await;
+            for si in services.values_mut() {
+                si.set_node_draining(ip, cordoned);
+            }
+        }
+        ServiceChange::ReplicaUnhealthy {
+            name,
+            ip,
+            docker_container,
+        } => {
+            println!(
+                "Replica of '{name}' on {ip} (docker: {}) is unhealthy",
+                docker_container.as_deref().unwrap_or("none")
+            );
+            teardown_partial_replicas(
+                &name,
+                ProxyFilter::OnReplica(ip, docker_container.as_deref()),
+                services,
+                orchestrator,
+            )
+            .await;
+        }
+        ServiceChange::BackendChainIdle {
+            name,
+            ip,
+            docker_container,
+            port,
+        } => {
+            println!("Backend chain of '{name}' on {ip} (port {port}) is idle");
+            teardown_backend_chain_at_port(
+                &name,
+                ip,
+                docker_container.as_deref(),
+                port,
+                services,
+                orchestrator,
+            )
+            .await;
+        }
     }
+}
 
-    // for config updates: update existing services and insert new ones
-    if let Some(loaded) = loaded_services {
-        for (name, loaded_info) in loaded {
-            services
-                .entry(name.clone())
-                .and_modify(|existing| {
-                    existing.update_from_file(loaded_info);
-                })
-                .or_insert(loaded_info.clone());
+/// For config updates, once their changes are applied: remove the services no longer
+/// in the config, update existing ones and insert new ones.
+pub(crate) fn merge_loaded(services: &mut AllServices, loaded: &HashMap<String, ServiceInfo>) {
+    let removed: Vec<String> = services
+        .keys()
+        .filter(|name| !loaded.contains_key(*name))
+        .cloned()
+        .collect();
+    for name in removed {
+        services.remove(&name);
+    }
+    for (name, loaded_info) in loaded {
+        match services.get_mut(name) {
+            Some(existing) => existing.update_from_file(loaded_info),
+            None => services.insert(name.clone(), loaded_info.clone()),
+        }
+    }
+}
+
+// --- Preview ---
+
+/// What applying a set of changes would tear down.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct ChangesImpact {
+    /// Networks torn down.
+    pub(crate) net_ids: Vec<u32>,
+    /// Proxy clients losing their chain.
+    pub(crate) proxy_clients: Vec<ProxyClientImpact>,
+    /// Backend-triggered chains torn down (brought up again by their next trigger).
+    pub(crate) backend_chains: Vec<BackendChainImpact>,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub(crate) struct ProxyClientImpact {
+    pub(crate) service: String,
+    pub(crate) client: String,
+    pub(crate) proxy: IpAddr,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub(crate) struct BackendChainImpact {
+    pub(crate) service: String,
+    pub(crate) ip: IpAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) docker_container: Option<String>,
+    pub(crate) port: u16,
+}
+
+/// Plan `changes` like `apply_changes` would, on a copy of `services` and with an
+/// orchestrator not connected to any node, so that nothing is actually torn down.
+pub(crate) async fn preview_changes(
+    changes: Vec<ServiceChange>,
+    services: &Services,
+) -> ChangesImpact {
+    let mut planned = services.copy();
+    run_changes(changes, &mut planned, &Orchestrator::new()).await;
+
+    let before = LiveChains::of(services);
+    let after = LiveChains::of(&planned);
+    let mut net_ids: Vec<u32> = before.net_ids.difference(&after.net_ids).copied().collect();
+    net_ids.sort_unstable();
+    let mut proxy_clients: Vec<ProxyClientImpact> = before
+        .proxy_clients
+        .difference(&after.proxy_clients)
+        .filter_map(|(service, client)| {
+            Some(ProxyClientImpact {
+                service: service.clone(),
+                client: client.name().to_string(),
+                proxy: client.is_proxy()?,
+            })
+        })
+        .collect();
+    proxy_clients.sort();
+    let mut backend_chains: Vec<BackendChainImpact> = before
+        .backend_chains
+        .difference(&after.backend_chains)
+        .filter_map(|chain| {
+            let (ip, docker) = chain.replica();
+            Some(BackendChainImpact {
+                service: chain.service().to_string(),
+                ip,
+                docker_container: docker.map(String::from),
+                port: chain.port()?,
+            })
+        })
+        .collect();
+    backend_chains.sort();
+
+    ChangesImpact {
+        net_ids,
+        proxy_clients,
+        backend_chains,
+    }
+}
+
+/// Networks, proxy clients and backend-triggered chains set up in a services map.
+/// The edges and replicas of the services, to report what a change removed.
+struct Topology {
+    edges: HashMap<u32, EdgeEvent>,
+    replicas: HashSet<(String, IpAddr, Option<String>)>,
+}
+
+impl Topology {
+    fn of(services: &Services) -> Self {
+        let mut topology = Self {
+            edges: HashMap::new(),
+            replicas: HashSet::new(),
+        };
+        for (name, si) in services {
+            let ServiceInfo::Registered(reg) = si else {
+                continue;
+            };
+            for replica in reg.replicas() {
+                topology.replicas.insert((
+                    name.clone(),
+                    replica.ip(),
+                    replica.docker_container().map(String::from),
+                ));
+            }
+            for (client, ci, server_ip, server_docker) in reg.all_clients_owned() {
+                if ci.is_placeholder() {
+                    continue;
+                }
+                topology.edges.insert(
+                    ci.net_id(),
+                    EdgeEvent {
+                        net_id: ci.net_id(),
+                        service: name.clone(),
+                        client: client.display_name(),
+                        client_ip: ci.client_ip(),
+                        server_ip,
+                        client_docker: ci.docker_container().cloned(),
+                        server_docker,
+                    },
+                );
+            }
+        }
+        topology
+    }
+
+    /// Events for the edges and replicas of `self` missing from `after`.
+    fn removed_from(mut self, after: &Topology, reason: &'static str) -> Vec<TopologyEvent> {
+        let mut torn_down: Vec<EdgeEvent> = self
+            .edges
+            .drain()
+            .filter(|(net_id, _)| !after.edges.contains_key(net_id))
+            .map(|(_, edge)| edge)
+            .collect();
+        torn_down.sort_unstable_by_key(|edge| edge.net_id);
+        let mut removed: Vec<_> = self.replicas.difference(&after.replicas).collect();
+        removed.sort_unstable();
+
+        torn_down
+            .into_iter()
+            .map(|edge| TopologyEvent::EdgeTornDown { edge, reason })
+            .chain(removed.into_iter().map(|(service, ip, docker_container)| {
+                TopologyEvent::ReplicaRemoved {
+                    service: service.clone(),
+                    ip: *ip,
+                    docker_container: docker_container.clone(),
+                    reason,
+                }
+            }))
+            .collect()
+    }
+}
+
+struct LiveChains {
+    net_ids: HashSet<u32>,
+    proxy_clients: HashSet<(String, Client)>,
+    backend_chains: HashSet<ChainContext>,
+}
+
+impl LiveChains {
+    fn of(services: &Services) -> Self {
+        let mut live = Self {
+            net_ids: HashSet::new(),
+            proxy_clients: HashSet::new(),
+            backend_chains: HashSet::new(),
+        };
+        for (name, si) in services {
+            let ServiceInfo::Registered(reg) = si else {
+                continue;
+            };
+            for (client, ci, _, _) in reg.all_clients_owned() {
+                if ci.is_placeholder() {
+                    continue;
+                }
+                live.net_ids.insert(ci.net_id());
+                if client.is_proxy().is_some() {
+                    live.proxy_clients.insert((name.clone(), client));
+                }
+                live.backend_chains.extend(
+                    ci.chains()
+                        .into_iter()
+                        .filter(|chain| chain.port().is_some()),
+                );
+            }
         }
+        live
     }
 }
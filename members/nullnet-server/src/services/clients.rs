@@ -1,6 +1,11 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr};
 use std::time::Instant;
+use uuid::Uuid;
+
+/// Name prefix of the clients of warm networks.
+const WARM_CLIENT_PREFIX: &str = "warm-";
 
 #[derive(Clone, Default, Debug)]
 pub(super) struct Clients {
@@ -9,7 +14,14 @@ pub(super) struct Clients {
 }
 
 impl Clients {
-    pub(super) fn add_client(&mut self, client: Client, client_info: ClientInfo) {
+    pub(super) fn add_client(&mut self, client: Client, mut client_info: ClientInfo) {
+        // keep the chains that joined the edge while it was being set up
+        if let Some(placeholder) = self.clients.get(&client).filter(|ci| ci.is_placeholder()) {
+            client_info.active_chains += placeholder.active_chains;
+            client_info
+                .chains
+                .extend(placeholder.chains.iter().cloned());
+        }
         self.clients.insert(client, client_info);
     }
 
@@ -26,7 +38,7 @@ impl Clients {
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
 pub(crate) struct Client {
     name: String,
     proxy: Option<IpAddr>,
@@ -45,6 +57,15 @@ impl Client {
         }
     }
 
+    /// Create the client of a warm network on the proxy at `proxy_ip`,
+    /// waiting to be handed out to a real client.
+    pub(crate) fn warm(proxy_ip: IpAddr) -> Self {
+        Self::new(
+            format!("{WARM_CLIENT_PREFIX}{}", Uuid::new_v4()),
+            Some(proxy_ip),
+        )
+    }
+
     /// Create a service-to-service client identified by its source replica.
     pub(crate) fn new_service(
         name: String,
@@ -74,6 +95,11 @@ impl Client {
         self.proxy
     }
 
+    /// Whether this is the client of a warm network (proxy clients are named after their IP).
+    pub(crate) fn is_warm(&self) -> bool {
+        self.proxy.is_some() && self.name.starts_with(WARM_CLIENT_PREFIX)
+    }
+
     /// The source replica identity for service-to-service clients.
     pub(crate) fn replica_identity(&self) -> Option<(IpAddr, Option<&str>)> {
         self.replica
@@ -82,7 +108,55 @@ impl Client {
     }
 }
 
-#[derive(Clone, Debug)]
+/// The chain an edge was set up for: the replica of the service it starts from,
+/// and the trigger port for backend-triggered chains (`None` for proxy chains).
+///
+/// A dep reached by several chains gets onward edges that depend on each chain's
+/// own graph, so teardowns go through the chains using an edge rather than its upstream.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+pub(crate) struct ChainContext {
+    service: String,
+    replica: (IpAddr, Option<String>),
+    port: Option<u16>,
+}
+
+impl ChainContext {
+    pub(crate) fn proxy(service: &str, replica_ip: IpAddr, replica_docker: Option<&str>) -> Self {
+        Self {
+            service: service.to_string(),
+            replica: (replica_ip, replica_docker.map(String::from)),
+            port: None,
+        }
+    }
+
+    pub(crate) fn backend(
+        service: &str,
+        replica_ip: IpAddr,
+        replica_docker: Option<&str>,
+        port: u16,
+    ) -> Self {
+        Self {
+            service: service.to_string(),
+            replica: (replica_ip, replica_docker.map(String::from)),
+            port: Some(port),
+        }
+    }
+
+    pub(crate) fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub(crate) fn replica(&self) -> (IpAddr, Option<&str>) {
+        (self.replica.0, self.replica.1.as_deref())
+    }
+
+    /// The trigger port of a backend-triggered chain.
+    pub(crate) fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) struct ClientInfo {
     /// Real IP of the client node (used for teardown).
     client_ip: IpAddr,
@@ -91,6 +165,12 @@ pub(crate) struct ClientInfo {
     net_id: u32,
     time_ms: u128,
     active_chains: usize,
+    /// The chains using this edge, one entry per active chain (empty for placeholders,
+    /// and recovered on restore for the entries of snapshots predating them).
+    #[serde(default)]
+    chains: Vec<ChainContext>,
+    /// Not persisted: restored entries start a fresh timeout period.
+    #[serde(skip, default = "Instant::now")]
     latest: Instant,
     docker_container: Option<String>,
 }
@@ -111,6 +191,7 @@ impl ClientInfo {
             net_id,
             time_ms,
             active_chains: 0,
+            chains: Vec::new(),
             latest: Instant::now(),
             docker_container,
         }
@@ -124,6 +205,7 @@ impl ClientInfo {
             net_id: 0,
             time_ms: 0,
             active_chains: 0,
+            chains: Vec::new(),
             latest: Instant::now(),
             docker_container: None,
         }
@@ -149,28 +231,67 @@ impl ClientInfo {
         self.net_id
     }
 
+    /// Placeholders reserve a slot while the NET setup is still in flight.
+    pub(crate) fn is_placeholder(&self) -> bool {
+        self.net_id == 0
+    }
+
     pub(crate) fn time_ms(&self) -> u128 {
         self.time_ms
     }
 
-    pub(super) fn add_active_chain(&mut self) {
+    pub(super) fn add_active_chain(&mut self, chain: &ChainContext) {
         self.active_chains += 1;
+        self.chains.push(chain.clone());
         self.set_latest_now();
     }
 
+    /// Record a chain already counted in `active_chains`.
+    pub(super) fn adopt_chain(&mut self, chain: &ChainContext) {
+        self.chains.push(chain.clone());
+    }
+
     pub(super) fn set_latest_now(&mut self) {
         self.latest = Instant::now();
     }
 
-    pub(super) fn remove_active_chains(&mut self, num_chains: usize) {
-        self.active_chains = self.active_chains.saturating_sub(num_chains);
+    pub(super) fn remove_active_chain(&mut self, chain: &ChainContext) {
+        self.active_chains = self.active_chains.saturating_sub(1);
+        if let Some(pos) = self.chains.iter().position(|c| c == chain) {
+            self.chains.swap_remove(pos);
+        }
     }
 
     pub(super) fn active_chains(&self) -> usize {
         self.active_chains
     }
 
+    /// The distinct chains using this edge.
+    pub(crate) fn chains(&self) -> Vec<ChainContext> {
+        let mut chains = Vec::new();
+        for chain in &self.chains {
+            if !chains.contains(chain) {
+                chains.push(chain.clone());
+            }
+        }
+        chains
+    }
+
     pub(super) fn latest(&self) -> Instant {
         self.latest
     }
 }
+
+impl PartialEq for ClientInfo {
+    fn eq(&self, other: &Self) -> bool {
+        // `latest` is not persisted, so it's not part of the comparison
+        self.client_ip == other.client_ip
+            && self.client_net == other.client_net
+            && self.server_net == other.server_net
+            && self.net_id == other.net_id
+            && self.time_ms == other.time_ms
+            && self.active_chains == other.active_chains
+            && self.chains == other.chains
+            && self.docker_container == other.docker_container
+    }
+}
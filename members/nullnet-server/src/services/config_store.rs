@@ -0,0 +1,311 @@
+//! Versioned writes of `services.toml` through the HTTP API.
+//!
+//! Each write is checked against the version it replaces (its ETag), validated,
+//! written atomically and applied. The versions it replaces are kept, numbered,
+//! in the `history` directory next to the file, so that they can be rolled back to.
+
+use crate::orchestrator::Orchestrator;
+use crate::services::input::{ServicesToml, apply_config_update};
+use crate::services::service_map::ServiceMap;
+use crate::services::validation::Issue;
+use serde::Serialize;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+use toml::{Table, Value};
+
+/// How many replaced versions are kept.
+const MAX_HISTORY: usize = 50;
+
+pub(crate) struct ConfigStore {
+    path: PathBuf,
+    history_dir: PathBuf,
+    /// Serializes writes, so that each one is checked against the version it replaces.
+    write_lock: Mutex<()>,
+    /// ETag of the config last applied, through the API or by the file watcher:
+    /// the watcher doesn't apply it again when the API's own write triggers it.
+    applied_etag: std::sync::Mutex<Option<String>>,
+    /// Notified when a write is applied.
+    config_changed: Arc<Notify>,
+}
+
+#[derive(Debug)]
+pub(crate) enum ConfigWriteError {
+    /// The version the write was based on isn't the current one anymore.
+    VersionMismatch {
+        current: String,
+    },
+    /// The resulting config would be rejected.
+    Invalid(Vec<Issue>),
+    /// No such version in the history.
+    UnknownVersion(u32),
+    Io(String),
+}
+
+/// Outcome of a successful write.
+#[derive(Debug, Serialize)]
+pub(crate) struct ConfigWrite {
+    /// ETag of the new config.
+    pub(crate) etag: String,
+    /// Version of the history the replaced config was saved as.
+    pub(crate) previous_version: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct HistoryEntry {
+    pub(crate) version: u32,
+    pub(crate) saved_at: Option<String>,
+}
+
+impl ConfigStore {
+    pub(crate) fn new(path: impl Into<PathBuf>, config_changed: Arc<Notify>) -> Self {
+        let path = path.into();
+        let history_dir = path.with_file_name("history");
+        Self {
+            path,
+            history_dir,
+            write_lock: Mutex::new(()),
+            applied_etag: std::sync::Mutex::new(None),
+            config_changed,
+        }
+    }
+
+    /// Whether `content` is the config last applied.
+    pub(crate) fn is_applied(&self, content: &str) -> bool {
+        self.applied_etag.lock().unwrap().as_deref() == Some(etag(content).as_str())
+    }
+
+    /// Record `content` as the config last applied.
+    pub(crate) fn set_applied(&self, content: &str) {
+        *self.applied_etag.lock().unwrap() = Some(etag(content));
+    }
+
+    /// The current config, with its ETag.
+    pub(crate) async fn read(&self) -> std::io::Result<(String, String)> {
+        let content = tokio::fs::read_to_string(&self.path).await?;
+        let etag = etag(&content);
+        Ok((content, etag))
+    }
+
+    /// Replace the config with `content`, if `if_match` is the ETag of the current one.
+    pub(crate) async fn replace(
+        &self,
+        if_match: &str,
+        content: String,
+        services: &ServiceMap,
+        orchestrator: &Orchestrator,
+    ) -> Result<ConfigWrite, ConfigWriteError> {
+        self.update(if_match, |_| Ok(content), services, orchestrator)
+            .await
+    }
+
+    /// Apply `patch` to the config, if `if_match` is the ETag of the current one.
+    ///
+    /// The services listed in its `remove` array are dropped, then its `[[services]]`
+    /// replace the declarations with the same name or are added.
+    pub(crate) async fn patch(
+        &self,
+        if_match: &str,
+        patch: &str,
+        services: &ServiceMap,
+        orchestrator: &Orchestrator,
+    ) -> Result<ConfigWrite, ConfigWriteError> {
+        self.update(
+            if_match,
+            |current| merge_patch(current, patch).map_err(ConfigWriteError::Invalid),
+            services,
+            orchestrator,
+        )
+        .await
+    }
+
+    /// Restore `version` of the history, if `if_match` is the ETag of the current config.
+    pub(crate) async fn rollback(
+        &self,
+        if_match: &str,
+        version: u32,
+        services: &ServiceMap,
+        orchestrator: &Orchestrator,
+    ) -> Result<ConfigWrite, ConfigWriteError> {
+        let content = self
+            .version(version)
+            .await
+            .ok_or(ConfigWriteError::UnknownVersion(version))?;
+        self.replace(if_match, content, services, orchestrator)
+            .await
+    }
+
+    /// The saved versions, oldest first.
+    pub(crate) async fn history(&self) -> Vec<HistoryEntry> {
+        let mut entries = Vec::new();
+        for version in self.versions().await {
+            let saved_at = tokio::fs::metadata(self.version_path(version))
+                .await
+                .and_then(|m| m.modified())
+                .ok()
+                .map(|t| chrono::DateTime::<chrono::Utc>::from(t).to_rfc3339());
+            entries.push(HistoryEntry { version, saved_at });
+        }
+        entries
+    }
+
+    /// Content of `version` of the history.
+    pub(crate) async fn version(&self, version: u32) -> Option<String> {
+        tokio::fs::read_to_string(self.version_path(version))
+            .await
+            .ok()
+    }
+
+    async fn update(
+        &self,
+        if_match: &str,
+        new_content: impl FnOnce(&str) -> Result<String, ConfigWriteError>,
+        services: &ServiceMap,
+        orchestrator: &Orchestrator,
+    ) -> Result<ConfigWrite, ConfigWriteError> {
+        let _write_guard = self.write_lock.lock().await;
+
+        let (current, current_etag) = self.read().await.map_err(io_error)?;
+        if if_match != "*" && if_match != current_etag {
+            return Err(ConfigWriteError::VersionMismatch {
+                current: current_etag,
+            });
+        }
+        let content = new_content(&current)?;
+        let loaded = ServicesToml::parse(&content).map_err(ConfigWriteError::Invalid)?;
+
+        let previous_version = self.save_version(&current).await.map_err(io_error)?;
+        // before the file watcher is triggered
+        self.set_applied(&content);
+        self.write_atomically(&content).await.map_err(io_error)?;
+        println!("services.toml replaced through the API (previous version: {previous_version})");
+        apply_config_update(&mut *services.write().await, loaded, orchestrator).await;
+        self.config_changed.notify_one();
+
+        Ok(ConfigWrite {
+            etag: etag(&content),
+            previous_version,
+        })
+    }
+
+    /// Write to a temporary file next to the config, then move it in place.
+    async fn write_atomically(&self, content: &str) -> std::io::Result<()> {
+        let tmp_path = self.path.with_extension("toml.tmp");
+        tokio::fs::write(&tmp_path, content).await?;
+        tokio::fs::File::open(&tmp_path).await?.sync_all().await?;
+        tokio::fs::rename(&tmp_path, &self.path).await
+    }
+
+    /// Save `content` as the next version of the history, dropping the oldest ones.
+    async fn save_version(&self, content: &str) -> std::io::Result<u32> {
+        tokio::fs::create_dir_all(&self.history_dir).await?;
+        let versions = self.versions().await;
+        let version = versions.last().map_or(1, |last| last + 1);
+        tokio::fs::write(self.version_path(version), content).await?;
+        let excess = (versions.len() + 1).saturating_sub(MAX_HISTORY);
+        for old in &versions[..excess] {
+            tokio::fs::remove_file(self.version_path(*old)).await?;
+        }
+        Ok(version)
+    }
+
+    /// The numbers of the saved versions, ascending.
+    async fn versions(&self) -> Vec<u32> {
+        let mut versions = Vec::new();
+        if let Ok(mut dir) = tokio::fs::read_dir(&self.history_dir).await {
+            while let Ok(Some(entry)) = dir.next_entry().await {
+                if let Some(version) = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_suffix(".toml"))
+                    .and_then(|n| n.parse().ok())
+                {
+                    versions.push(version);
+                }
+            }
+        }
+        versions.sort_unstable();
+        versions
+    }
+
+    fn version_path(&self, version: u32) -> PathBuf {
+        self.history_dir.join(format!("{version}.toml"))
+    }
+}
+
+/// ETag of a config content.
+pub(crate) fn etag(content: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+fn io_error(e: std::io::Error) -> ConfigWriteError {
+    ConfigWriteError::Io(e.to_string())
+}
+
+fn merge_patch(current: &str, patch: &str) -> Result<String, Vec<Issue>> {
+    let invalid = |message: String| vec![Issue::error(None, message)];
+    let mut config: Table = current
+        .parse()
+        .map_err(|e: toml::de::Error| invalid(e.message().to_string()))?;
+    let mut patch: Table = patch
+        .parse()
+        .map_err(|e: toml::de::Error| invalid(format!("patch: {}", e.message())))?;
+
+    let mut services = match config.remove("services") {
+        Some(Value::Array(services)) => services,
+        None => Vec::new(),
+        Some(_) => return Err(invalid("'services' must be an array".to_string())),
+    };
+    let name_of = |service: &Value| {
+        service
+            .get("name")
+            .and_then(Value::as_str)
+            .map(String::from)
+    };
+
+    let removed = match patch.remove("remove") {
+        Some(Value::Array(names)) => names,
+        None => Vec::new(),
+        Some(_) => return Err(invalid("patch: 'remove' must list names".to_string())),
+    };
+    let patched = match patch.remove("services") {
+        Some(Value::Array(patched)) => patched,
+        None => Vec::new(),
+        Some(_) => return Err(invalid("patch: 'services' must be an array".to_string())),
+    };
+    if let Some(key) = patch.keys().next() {
+        return Err(invalid(format!("patch: unexpected '{key}'")));
+    }
+
+    // `remove` comes first, so that a service can be removed and declared again
+    for name in removed {
+        let Some(name) = name.as_str() else {
+            return Err(invalid("patch: 'remove' must list names".to_string()));
+        };
+        let len = services.len();
+        services.retain(|s| name_of(s).as_deref() != Some(name));
+        if services.len() == len {
+            return Err(invalid(format!(
+                "patch: cannot remove '{name}', it's not declared"
+            )));
+        }
+    }
+    for service in patched {
+        let Some(name) = name_of(&service) else {
+            return Err(invalid("patch: services must have a name".to_string()));
+        };
+        match services
+            .iter_mut()
+            .find(|s| name_of(s).as_deref() == Some(&name))
+        {
+            Some(existing) => *existing = service,
+            None => services.push(service),
+        }
+    }
+
+    config.insert("services".to_string(), Value::Array(services));
+    toml::to_string(&config).map_err(|e| invalid(e.to_string()))
+}
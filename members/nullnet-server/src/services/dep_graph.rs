@@ -0,0 +1,219 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+/// Dependencies of a chain as written in `services.toml`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum DepsToml {
+    /// `["B", "C"]`: the linear chain root → B → C.
+    Chain(Vec<String>),
+    /// `{ A = ["B", "C"], C = ["D"] }`: the direct dependencies of each service,
+    /// starting from the root (fan-out A → B and A → C, then C → D).
+    Graph(BTreeMap<String, Vec<String>>),
+}
+
+impl DepsToml {
+    /// True iff no dependency is declared.
+    pub(crate) fn is_empty(&self) -> bool {
+        match self {
+            DepsToml::Chain(chain) => chain.is_empty(),
+            DepsToml::Graph(map) => map.values().all(Vec::is_empty),
+        }
+    }
+}
+
+impl Default for DepsToml {
+    fn default() -> Self {
+        DepsToml::Chain(Vec::new())
+    }
+}
+
+/// Acyclic dependency graph of a chain, rooted at the service the chain starts from.
+///
+/// A service reached through several paths is a single node: within a chain,
+/// it's placed on one replica and its own dependencies are brought up once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DepGraph {
+    root: String,
+    /// Direct dependencies of the services having any, in declaration order.
+    deps: BTreeMap<String, Vec<String>>,
+}
+
+impl DepGraph {
+    /// Graph without dependencies.
+    pub(crate) fn empty(root: &str) -> Self {
+        Self {
+            root: root.to_string(),
+            deps: BTreeMap::new(),
+        }
+    }
+
+    /// Build the graph declared by `deps` for `root`, rejecting self-references, cycles
+    /// and services declared with dependencies but not reachable from `root`.
+    pub(crate) fn new(root: &str, deps: DepsToml) -> Result<Self, String> {
+        let mut graph = Self::empty(root);
+        match deps {
+            DepsToml::Chain(chain) => {
+                let mut prev = root;
+                for dep in &chain {
+                    graph.add_edge(prev, dep);
+                    prev = dep;
+                }
+            }
+            DepsToml::Graph(map) => {
+                for (service, deps) in &map {
+                    for dep in deps {
+                        graph.add_edge(service, dep);
+                    }
+                }
+            }
+        }
+
+        if let Some((service, _)) = graph.deps.iter().find(|(s, deps)| deps.contains(s)) {
+            return Err(format!("'{service}' depends on itself"));
+        }
+        if let Some(cycle) = graph.find_cycle() {
+            return Err(format!(
+                "dependencies of '{root}' contain a cycle: {}",
+                cycle.join(" -> ")
+            ));
+        }
+        let reachable: HashSet<&str> = graph.services().into_iter().collect();
+        if let Some(unreachable) = graph
+            .deps
+            .keys()
+            .find(|s| *s != root && !reachable.contains(s.as_str()))
+        {
+            return Err(format!(
+                "'{unreachable}' is not reachable from '{root}' in its dependencies"
+            ));
+        }
+        Ok(graph)
+    }
+
+    fn add_edge(&mut self, from: &str, to: &str) {
+        let deps = self.deps.entry(from.to_string()).or_default();
+        if !deps.iter().any(|d| d == to) {
+            deps.push(to.to_string());
+        }
+    }
+
+    /// A cycle reachable from the root, as the services along it.
+    fn find_cycle(&self) -> Option<Vec<String>> {
+        fn visit<'a>(
+            graph: &'a DepGraph,
+            service: &'a str,
+            path: &mut Vec<&'a str>,
+            done: &mut HashSet<&'a str>,
+        ) -> Option<Vec<String>> {
+            if let Some(start) = path.iter().position(|s| *s == service) {
+                let mut cycle: Vec<String> =
+                    path[start..].iter().map(ToString::to_string).collect();
+                cycle.push(service.to_string());
+                return Some(cycle);
+            }
+            if !done.insert(service) {
+                return None;
+            }
+            path.push(service);
+            for dep in graph.deps_of(service) {
+                if let Some(cycle) = visit(graph, dep, path, done) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            None
+        }
+
+        visit(self, &self.root, &mut Vec::new(), &mut HashSet::new())
+    }
+
+    pub(crate) fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Direct dependencies of `service` in this graph.
+    pub(crate) fn deps_of(&self, service: &str) -> &[String] {
+        self.deps
+            .get(service)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// True iff `service` is one of the (transitive) dependencies of the root.
+    pub(crate) fn contains(&self, service: &str) -> bool {
+        self.services().contains(&service)
+    }
+
+    /// The dependencies of the root, in breadth-first order.
+    pub(crate) fn services(&self) -> Vec<&str> {
+        let mut services = Vec::new();
+        for (_, to) in self.edges() {
+            if to != self.root && !services.contains(&to) {
+                services.push(to);
+            }
+        }
+        services
+    }
+
+    /// The `(service, dependency)` edges, in breadth-first order from the root:
+    /// the edges leaving a service come after the first edge reaching it.
+    pub(crate) fn edges(&self) -> Vec<(&str, &str)> {
+        let mut edges = Vec::new();
+        let mut visited = HashSet::from([self.root.as_str()]);
+        let mut queue = VecDeque::from([self.root.as_str()]);
+        while let Some(service) = queue.pop_front() {
+            for dep in self.deps_of(service) {
+                edges.push((service, dep.as_str()));
+                if visited.insert(dep) {
+                    queue.push_back(dep);
+                }
+            }
+        }
+        edges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(root: &str, toml_str: &str) -> Result<DepGraph, String> {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            deps: DepsToml,
+        }
+        let wrapper: Wrapper = toml::from_str(toml_str).unwrap();
+        DepGraph::new(root, wrapper.deps)
+    }
+
+    #[test]
+    fn test_chain_is_a_linear_graph() {
+        let graph = graph("A", r#"deps = ["B", "C"]"#).unwrap();
+        assert_eq!(graph.edges(), [("A", "B"), ("B", "C")]);
+        assert_eq!(graph.services(), ["B", "C"]);
+    }
+
+    #[test]
+    fn test_shared_dependency_is_a_single_node() {
+        let graph = graph(
+            "A",
+            r#"deps = { A = ["B", "C"], B = ["D"], C = ["D"], D = ["E"] }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            graph.edges(),
+            [("A", "B"), ("A", "C"), ("B", "D"), ("C", "D"), ("D", "E")]
+        );
+        assert_eq!(graph.services(), ["B", "C", "D", "E"]);
+        assert!(graph.contains("E"));
+        assert!(!graph.contains("A"));
+    }
+
+    #[test]
+    fn test_cycles_and_unreachable_services_are_rejected() {
+        assert!(graph("A", r#"deps = ["B", "A"]"#).is_err());
+        assert!(graph("A", r#"deps = { A = ["B"], B = ["C"], C = ["B"] }"#).is_err());
+        assert!(graph("A", r#"deps = { A = ["B"], C = ["D"] }"#).is_err());
+    }
+}
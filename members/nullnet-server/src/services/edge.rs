@@ -1,4 +1,4 @@
-use crate::services::clients::Client;
+use crate::services::clients::{ChainContext, Client};
 use std::net::IpAddr;
 
 pub(crate) struct Edge {
@@ -6,6 +6,7 @@ pub(crate) struct Edge {
     pub(crate) server: (Option<IpAddr>, Client),
     pub(crate) client_docker: Option<String>,
     pub(crate) server_docker: Option<String>,
+    pub(crate) chain: ChainContext,
 }
 
 impl Edge {
@@ -16,12 +17,14 @@ impl Edge {
         server_ip: Option<IpAddr>,
         server: Client,
         server_docker: Option<String>,
+        chain: ChainContext,
     ) -> Self {
         Self {
             client: (client_ip, client),
             server: (server_ip, server),
             client_docker,
             server_docker,
+            chain,
         }
     }
 
@@ -32,6 +35,7 @@ impl Edge {
                 server: (server_ip, self.server.1),
                 client_docker: self.client_docker,
                 server_docker: self.server_docker,
+                chain: self.chain,
                 backend_entry_port: None,
             })
         } else {
@@ -45,6 +49,8 @@ pub(crate) struct RegisteredEdge {
     pub(crate) server: (IpAddr, Client),
     pub(crate) client_docker: Option<String>,
     pub(crate) server_docker: Option<String>,
+    /// The chain this edge is set up for.
+    pub(crate) chain: ChainContext,
     /// `Some(port)` iff this edge is the entry point of a backend-triggered
     /// chain. The port is the trigger port observed by the initiator and is
     /// echoed in the client-side `VxlanSetup.dnat_port` so the receiver can
@@ -60,12 +66,14 @@ impl RegisteredEdge {
         server_ip: IpAddr,
         server: Client,
         server_docker: Option<String>,
+        chain: ChainContext,
     ) -> Self {
         Self {
             client: (client_ip, client),
             server: (server_ip, server),
             client_docker,
             server_docker,
+            chain,
             backend_entry_port: None,
         }
     }
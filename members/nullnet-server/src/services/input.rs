@@ -1,22 +1,34 @@
 use crate::env::TIMEOUT;
+use crate::events::TopologyEvent;
 use crate::orchestrator::Orchestrator;
-use crate::services::changes::{apply_changes, detect_config_changes};
+use crate::services::affinity::Affinity;
+use crate::services::changes::{
+    ChangesImpact, apply_changes, detect_config_changes, merge_loaded, preview_changes,
+};
+use crate::services::config_store::ConfigStore;
+use crate::services::dep_graph::{DepGraph, DepsToml};
+use crate::services::load_balancing::LoadBalancing;
 use crate::services::service_info::ServiceInfo;
+use crate::services::service_map::{AllServices, ServiceMap, Services};
+use crate::services::validation::{Issue, Rejection, validate};
 use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
 use serde::Deserialize;
 use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
 use std::ops::Sub;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc as tokio_mpsc;
-use tokio::sync::{Notify, RwLock};
+use tokio::sync::{Mutex, Notify};
 use tokio::time::Instant;
 
-const SERVICES_PATH: &str = "./services/services.toml";
+pub(crate) const SERVICES_PATH: &str = "./services/services.toml";
+const SERVICES_FILE: &str = "services.toml";
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct ServicesToml {
     services: Vec<ServiceToml>,
 }
@@ -30,17 +42,42 @@ impl ServicesToml {
         let services_toml_str = tokio::fs::read_to_string(path)
             .await
             .handle_err(location!())?;
-        let services_toml: ServicesToml =
-            toml::from_str(&services_toml_str).handle_err(location!())?;
-        let services = services_toml.services_map();
+        let services = Self::parse(&services_toml_str)
+            .map_err(|issues| {
+                let issues: Vec<String> = issues.iter().map(ToString::to_string).collect();
+                issues.join("; ")
+            })
+            .handle_err(location!())?;
         println!("Loaded services: {services:?}");
         Ok(services)
     }
 
+    /// Services declared in `content` if it's valid, or the issues it's rejected for.
+    pub(crate) fn parse(content: &str) -> Result<HashMap<String, ServiceInfo>, Vec<Issue>> {
+        let issues = validate(content);
+        if issues.iter().any(Issue::is_error) {
+            return Err(issues);
+        }
+        for issue in &issues {
+            println!("services.toml {issue}");
+        }
+        let services = toml::from_str::<ServicesToml>(content)
+            .map_err(|e| e.to_string())
+            .and_then(ServicesToml::services_map)
+            .map_err(|e| vec![Issue::error(None, e)])?;
+        Ok(services)
+    }
+
+    pub(super) fn services(&self) -> &[ServiceToml] {
+        &self.services
+    }
+
     pub(crate) async fn watch(
-        services: &Arc<RwLock<HashMap<String, ServiceInfo>>>,
+        services: &ServiceMap,
         orchestrator: Orchestrator,
         config_changed: Arc<Notify>,
+        last_rejection: Arc<Mutex<Option<Rejection>>>,
+        config_store: Arc<ConfigStore>,
     ) -> Result<(), Error> {
         let mut services_directory = PathBuf::from(SERVICES_PATH);
         services_directory.pop();
@@ -65,19 +102,46 @@ impl ServicesToml {
                 println!("File watcher channel closed, stopping watch");
                 break;
             }
+            // history and temporary files of the config API live in the same directory
             if let Some(Ok(Event {
                 kind: EventKind::Modify(_),
+                paths,
                 ..
             })) = event
+                && paths
+                    .iter()
+                    .any(|p| p.file_name() == Some(OsStr::new(SERVICES_FILE)))
             {
                 // debounce duplicated events
                 if last_update_time.elapsed().as_millis() > 100 {
                     // ensure file changes are propagated
                     tokio::time::sleep(Duration::from_millis(100)).await;
-                    if let Ok(loaded_services) = ServicesToml::load().await {
-                        let services_mut = &mut *services.write().await;
-                        apply_config_update(services_mut, loaded_services, &orchestrator).await;
-                        config_changed.notify_one();
+                    if let Ok(content) = tokio::fs::read_to_string(SERVICES_PATH).await
+                        // e.g., written through the API, which applied it already
+                        && !config_store.is_applied(&content)
+                    {
+                        match ServicesToml::parse(&content) {
+                            Ok(loaded_services) => {
+                                println!("Loaded services: {loaded_services:?}");
+                                let services_mut = &mut *services.write().await;
+                                apply_config_update(services_mut, loaded_services, &orchestrator)
+                                    .await;
+                                config_store.set_applied(&content);
+                                config_changed.notify_one();
+                            }
+                            Err(issues) => {
+                                // keep the services loaded so far
+                                eprintln!("Rejected services.toml:");
+                                for issue in &issues {
+                                    eprintln!("  {issue}");
+                                }
+                                *last_rejection.lock().await = Some(Rejection {
+                                    timestamp: chrono::Utc::now().to_rfc3339(),
+                                    content,
+                                    issues,
+                                });
+                            }
+                        }
                     }
                     last_update_time = Instant::now();
                 }
@@ -87,102 +151,158 @@ impl ServicesToml {
         Ok(())
     }
 
-    pub(crate) fn services_map(self) -> HashMap<String, ServiceInfo> {
-        // Proxy: last-write-wins per dep (a name referenced from multiple
-        // services has its tail overwritten by the last processor).
-        let mut proxy_accum: HashMap<String, Vec<String>> = HashMap::new();
-        // Trigger-chain deps: discoverable as (non-entry-point) services so
-        // hosts can register replicas of them.
-        let mut trigger_dep_names: HashSet<String> = HashSet::new();
-
-        for s in &self.services {
-            for d in &s.proxy_dependencies {
-                proxy_accum.insert(d.clone(), tail_after(&s.proxy_dependencies, d));
-            }
-            for t in &s.triggers {
-                for dep in &t.chain {
-                    trigger_dep_names.insert(dep.clone());
-                }
-            }
-        }
+    pub(crate) fn services_map(self) -> Result<HashMap<String, ServiceInfo>, String> {
+        let declared: Vec<(String, ServiceInfo)> = self
+            .services
+            .into_iter()
+            .map(|s| Ok((s.name.clone(), s.into_service_info()?)))
+            .collect::<Result<_, String>>()?;
 
-        let mut ret_val: HashMap<String, ServiceInfo> = HashMap::new();
-        for (name, proxy) in proxy_accum {
-            ret_val.insert(name, ServiceInfo::new(proxy, HashMap::new(), None, None));
-        }
-        for name in trigger_dep_names {
-            ret_val
-                .entry(name)
-                .or_insert_with(|| ServiceInfo::new(Vec::new(), HashMap::new(), None, None));
-        }
+        // Deps: discoverable as (non-entry-point) services so hosts can register
+        // replicas of them. Their onward deps are scoped to the graph of each
+        // entry point reaching them, so they don't have any of their own.
+        let dep_names: HashSet<&str> = declared
+            .iter()
+            .flat_map(|(_, si)| {
+                std::iter::once(si.proxy_deps())
+                    .chain(si.triggers().values())
+                    .flat_map(DepGraph::services)
+            })
+            .collect();
+
+        let mut ret_val: HashMap<String, ServiceInfo> = dep_names
+            .into_iter()
+            .map(|name| {
+                let service_info = ServiceInfo::new(
+                    DepGraph::empty(name),
+                    HashMap::new(),
+                    HashMap::new(),
+                    None,
+                    None,
+                    None,
+                    LoadBalancing::default().selector(),
+                    Affinity::default(),
+                );
+                (name.to_string(), service_info)
+            })
+            .collect();
 
         // Explicit declarations override any implicit entries for the same
         // name and are treated as entry points (`Some(timeout)`). To register a
         // service as a backend dep without making it an entry point, do not
         // declare it explicitly — listing it in a `triggers.chain` is enough.
-        for s in self.services {
-            let triggers = s.triggers.into_iter().map(|t| (t.port, t.chain)).collect();
-            ret_val.insert(
-                s.name,
-                ServiceInfo::new(
-                    s.proxy_dependencies,
-                    triggers,
-                    Some(s.timeout.unwrap_or(*TIMEOUT)),
-                    s.max_networks,
-                ),
-            );
-        }
+        ret_val.extend(declared);
 
-        ret_val
+        Ok(ret_val)
     }
 }
 
-/// Return the elements of `slice` that come after the first occurrence of `elem`.
-fn tail_after(slice: &[String], elem: &str) -> Vec<String> {
-    slice
-        .iter()
-        .position(|d| d == elem)
-        .map(|i| slice[i + 1..].to_vec())
-        .unwrap_or_default()
-}
-
 pub(crate) async fn apply_config_update(
-    services: &mut HashMap<String, ServiceInfo>,
+    services: &mut AllServices,
     loaded_services: HashMap<String, ServiceInfo>,
     orchestrator: &Orchestrator,
 ) {
     let changes = detect_config_changes(services, &loaded_services);
-    apply_changes(changes, services, Some(&loaded_services), orchestrator).await;
+    apply_changes(changes, services, orchestrator).await;
+    merge_loaded(services, &loaded_services);
+
+    let mut names: Vec<String> = loaded_services.into_keys().collect();
+    names.sort_unstable();
+    orchestrator.publish(TopologyEvent::ConfigReloaded { services: names });
 }
 
-#[derive(Deserialize)]
-struct ServiceToml {
-    name: String,
+/// What `apply_config_update` would tear down, without applying anything.
+pub(crate) async fn preview_config_update(
+    services: &Services,
+    loaded_services: &HashMap<String, ServiceInfo>,
+) -> ChangesImpact {
+    let changes = detect_config_changes(services, loaded_services);
+    preview_changes(changes, services).await
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct ServiceToml {
+    pub(super) name: String,
     /// Per-service entry timeout in seconds for proxy clients. If omitted,
     /// defaults to the global `TIMEOUT` env var (or 60s). A value of 0
     /// disables the timeout. Any explicit declaration is treated as an entry
     /// point; backend deps without a proxy-reachable role should be left out
     /// of explicit declarations and picked up implicitly via trigger chains.
-    timeout: Option<u64>,
-    /// Linear dep chain walked on proxy-triggered setup.
+    pub(super) timeout: Option<u64>,
+    /// Deps brought up on proxy-triggered setup: either a linear chain
+    /// (`["B", "C"]`) or the direct deps of each service of a graph
+    /// (`{ A = ["B", "C"], C = ["D"] }`).
     #[serde(default)]
-    proxy_dependencies: Vec<String>,
+    pub(super) proxy_dependencies: DepsToml,
     /// Backend-triggered chains: each entry pairs a port observed by the
-    /// service host with the linear chain to bring up. One chain per port.
+    /// service host with the chain (or graph) to bring up. One per port.
     #[serde(default)]
-    triggers: Vec<TriggerToml>,
+    pub(super) triggers: Vec<TriggerToml>,
     /// Maximum number of networks that can be created for this service.
     /// Applies to proxy chains only (backend chains are unbounded).
     /// When the limit is reached, new proxy clients reuse an existing network
     /// on the same proxy node instead of creating a new one.
-    max_networks: Option<u32>,
+    pub(super) max_networks: Option<u32>,
+    /// Proxy→service networks (with their dep chains) kept set up for each proxy
+    /// node, so that new clients are handed one instead of waiting for a setup.
+    /// Replenished in the background as they're handed out.
+    pub(super) warm_networks: Option<u32>,
+    /// How new chains are spread across the service's replicas, both when it's
+    /// the entry point and when it's a dep. Defaults to least-clients.
+    #[serde(default)]
+    pub(super) load_balancing: LoadBalancing,
+    /// How the replica reached by a chain hop is chosen relative to the node of
+    /// the previous hop (same host first, then shared labels). Ignored by default.
+    #[serde(default)]
+    pub(super) affinity: Affinity,
 }
 
-#[derive(Deserialize)]
-struct TriggerToml {
-    port: u16,
+impl ServiceToml {
+    /// Service info of an explicit declaration, i.e., an entry point.
+    pub(super) fn into_service_info(self) -> Result<ServiceInfo, String> {
+        let proxy_deps = DepGraph::new(&self.name, self.proxy_dependencies)?;
+        let mut triggers = HashMap::new();
+        let mut idle_timeouts = HashMap::new();
+        for t in self.triggers {
+            let graph = DepGraph::new(&self.name, t.chain)?;
+            // the trigger port is redirected to a single dep
+            if graph.deps_of(&self.name).len() > 1 {
+                return Err(format!(
+                    "trigger {} of '{}' must start with a single dependency",
+                    t.port, self.name
+                ));
+            }
+            triggers.insert(t.port, graph);
+            if let Some(idle_timeout) = t.idle_timeout {
+                idle_timeouts.insert(t.port, idle_timeout);
+            }
+        }
+        Ok(ServiceInfo::new(
+            proxy_deps,
+            triggers,
+            idle_timeouts,
+            Some(self.timeout.unwrap_or(*TIMEOUT)),
+            self.max_networks,
+            self.warm_networks,
+            self.load_balancing.selector(),
+            self.affinity,
+        ))
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct TriggerToml {
+    pub(super) port: u16,
+    /// Same format as `proxy_dependencies`; the service reaches a single dep,
+    /// the target of the trigger port.
     #[serde(default)]
-    chain: Vec<String>,
+    pub(super) chain: DepsToml,
+    /// Seconds without traffic on `port` after which the chain is torn down
+    /// (it's brought up again by the next trigger). If omitted or 0, the chain
+    /// stays up until a config change or a disconnection.
+    pub(super) idle_timeout: Option<u64>,
 }
 
 #[cfg(test)]
@@ -200,18 +320,29 @@ proxy_dependencies = ["pre.fs.color.com", "fs.color.com"]
 [[services.triggers]]
 port = 5555
 chain = ["ts.color.com", "deeper.dep"]
+idle_timeout = 300
+
+[[services.triggers]]
+port = 6666
+chain = ["ts.color.com"]
 
 [[services]]
 name = "fs.color.com"
 timeout = 30
 "#;
         let parsed: ServicesToml = toml::from_str(toml_str).unwrap();
-        let map = parsed.services_map();
+        let map = parsed.services_map().unwrap();
 
         // explicit entry points keep their configured timeout
         assert_eq!(map["color.com"].timeout(), Some(0));
         assert_eq!(map["fs.color.com"].timeout(), Some(30));
 
+        // idle timeouts are kept only for the triggers that set one
+        assert_eq!(
+            map["color.com"].idle_timeouts(),
+            &HashMap::from([(5555, 300)])
+        );
+
         // every name referenced in a proxy_dependencies list or trigger chain
         // is implicitly added with timeout=None (registrable as a dep, not an
         // entry point), regardless of its position in the chain
@@ -219,4 +350,24 @@ timeout = 30
         assert_eq!(map["ts.color.com"].timeout(), None);
         assert_eq!(map["deeper.dep"].timeout(), None);
     }
+
+    #[test]
+    fn dep_tails_are_scoped_to_entry_points() {
+        let toml_str = r#"
+[[services]]
+name = "A"
+proxy_dependencies = ["B", "C"]
+
+[[services]]
+name = "F"
+proxy_dependencies = ["B", "D"]
+"#;
+        let parsed: ServicesToml = toml::from_str(toml_str).unwrap();
+        let map = parsed.services_map().unwrap();
+
+        // B leads to C in A's chains and to D in F's
+        assert_eq!(map["A"].proxy_deps().edges(), [("A", "B"), ("B", "C")]);
+        assert_eq!(map["F"].proxy_deps().edges(), [("F", "B"), ("B", "D")]);
+        assert!(map["B"].proxy_deps().edges().is_empty());
+    }
 }
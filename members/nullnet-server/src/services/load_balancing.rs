@@ -0,0 +1,310 @@
+use crate::services::service_info::Replica;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Placement policy picking the replica of a service that a new chain lands on.
+///
+/// Used both for the entry service of proxy chains and for every dep hop of a chain.
+pub(crate) trait ReplicaSelector: Debug + Send + Sync {
+    /// Select one of `replicas` for a new chain, or `None` if none is eligible.
+    ///
+    /// `client_ip` is the proxy client the chain is set up for
+    /// (or the initiator's host, for backend-triggered chains).
+    fn select<'a>(
+        &self,
+        replicas: &[&'a Replica],
+        client_ip: Option<IpAddr>,
+    ) -> Option<&'a Replica>;
+}
+
+/// Per-service `load_balancing` setting in `services.toml`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub(crate) enum LoadBalancing {
+    #[default]
+    LeastClients,
+    RoundRobin,
+    /// Weights keyed by replica IP, or by `ip/container` for a single Docker container.
+    /// Replicas not listed have weight 1; weight 0 excludes a replica.
+    Weighted {
+        #[serde(default)]
+        weights: HashMap<String, u32>,
+    },
+    ConsistentHash,
+    RandomTwoChoices,
+}
+
+impl LoadBalancing {
+    pub(crate) fn selector(&self) -> Arc<dyn ReplicaSelector> {
+        match self {
+            LoadBalancing::LeastClients => Arc::new(LeastClients),
+            LoadBalancing::RoundRobin => Arc::new(RoundRobin::default()),
+            LoadBalancing::Weighted { weights } => Arc::new(Weighted {
+                weights: weights.clone(),
+            }),
+            LoadBalancing::ConsistentHash => Arc::new(ConsistentHash),
+            LoadBalancing::RandomTwoChoices => Arc::new(RandomTwoChoices::new()),
+        }
+    }
+}
+
+/// The replica with the fewest clients (first one on ties).
+#[derive(Debug)]
+pub(crate) struct LeastClients;
+
+impl ReplicaSelector for LeastClients {
+    fn select<'a>(&self, replicas: &[&'a Replica], _: Option<IpAddr>) -> Option<&'a Replica> {
+        replicas.iter().copied().min_by_key(|r| r.clients().len())
+    }
+}
+
+/// Replicas in turn, in registration order.
+#[derive(Debug, Default)]
+pub(crate) struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl ReplicaSelector for RoundRobin {
+    fn select<'a>(&self, replicas: &[&'a Replica], _: Option<IpAddr>) -> Option<&'a Replica> {
+        if replicas.is_empty() {
+            return None;
+        }
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        replicas.get(next % replicas.len()).copied()
+    }
+}
+
+/// The replica whose client count is lowest relative to its weight
+/// (counting the new client), so that replicas fill up proportionally.
+#[derive(Debug)]
+pub(crate) struct Weighted {
+    weights: HashMap<String, u32>,
+}
+
+impl Weighted {
+    fn weight(&self, replica: &Replica) -> u32 {
+        let ip = replica.ip().to_string();
+        replica
+            .docker_container()
+            .and_then(|container| self.weights.get(&format!("{ip}/{container}")))
+            .or_else(|| self.weights.get(&ip))
+            .copied()
+            .unwrap_or(1)
+    }
+}
+
+impl ReplicaSelector for Weighted {
+    fn select<'a>(&self, replicas: &[&'a Replica], _: Option<IpAddr>) -> Option<&'a Replica> {
+        let mut best: Option<(&Replica, u64, u64)> = None;
+        for &replica in replicas {
+            let weight = u64::from(self.weight(replica));
+            if weight == 0 {
+                continue;
+            }
+            let load = replica.clients().len() as u64 + 1;
+            // load / weight < best_load / best_weight, without divisions
+            if best
+                .is_none_or(|(_, best_load, best_weight)| load * best_weight < best_load * weight)
+            {
+                best = Some((replica, load, weight));
+            }
+        }
+        best.map(|(replica, _, _)| replica)
+    }
+}
+
+/// Rendezvous hashing on the client IP: a client keeps landing on the same replica
+/// as long as it's registered, and only the clients of a removed replica move.
+/// Falls back to [`LeastClients`] when there's no client IP.
+#[derive(Debug)]
+pub(crate) struct ConsistentHash;
+
+impl ReplicaSelector for ConsistentHash {
+    fn select<'a>(
+        &self,
+        replicas: &[&'a Replica],
+        client_ip: Option<IpAddr>,
+    ) -> Option<&'a Replica> {
+        let Some(client_ip) = client_ip else {
+            return LeastClients.select(replicas, None);
+        };
+        replicas.iter().copied().max_by_key(|r| {
+            let mut key = ip_bytes(client_ip);
+            key.extend(ip_bytes(r.ip()));
+            key.extend(r.docker_container().unwrap_or_default().bytes());
+            fnv1a(&key)
+        })
+    }
+}
+
+fn ip_bytes(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    }
+}
+
+/// 64-bit FNV-1a: stable across runs and Rust versions, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Power of two random choices: the least loaded of two distinct random replicas.
+#[derive(Debug)]
+pub(crate) struct RandomTwoChoices {
+    /// xorshift64* state.
+    state: AtomicU64,
+}
+
+impl RandomTwoChoices {
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self::with_seed(seed)
+    }
+
+    /// Selector with a fixed seed, for reproducible placements.
+    pub(crate) fn with_seed(seed: u64) -> Self {
+        Self {
+            // the state must never be 0
+            state: AtomicU64::new(seed | 1),
+        }
+    }
+
+    fn next_random(&self) -> u64 {
+        let step = |mut x: u64| {
+            x ^= x >> 12;
+            x ^= x << 25;
+            x ^= x >> 27;
+            Some(x)
+        };
+        let prev = self
+            .state
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, step)
+            .unwrap_or_default();
+        step(prev)
+            .unwrap_or_default()
+            .wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+impl ReplicaSelector for RandomTwoChoices {
+    fn select<'a>(&self, replicas: &[&'a Replica], _: Option<IpAddr>) -> Option<&'a Replica> {
+        let len = replicas.len() as u64;
+        if len < 2 {
+            return replicas.first().copied();
+        }
+        let random = self.next_random();
+        let first = random % len;
+        let second = (first + 1 + (random >> 32) % (len - 1)) % len;
+        let first = replicas[usize::try_from(first).ok()?];
+        let second = replicas[usize::try_from(second).ok()?];
+        if second.clients().len() < first.clients().len() {
+            Some(second)
+        } else {
+            Some(first)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn replicas(ips: &[[u8; 4]]) -> Vec<Replica> {
+        ips.iter()
+            .map(|ip| Replica::new(IpAddr::V4(Ipv4Addr::from(*ip)), 8080, None))
+            .collect()
+    }
+
+    fn refs(replicas: &[Replica]) -> Vec<&Replica> {
+        replicas.iter().collect()
+    }
+
+    #[test]
+    fn test_round_robin_cycles_replicas() {
+        let replicas = replicas(&[[1, 1, 1, 1], [2, 2, 2, 2], [3, 3, 3, 3]]);
+        let rr = RoundRobin::default();
+        let picked: Vec<IpAddr> = (0..4)
+            .map(|_| rr.select(&refs(&replicas), None).unwrap().ip())
+            .collect();
+        assert_eq!(
+            picked,
+            [
+                replicas[0].ip(),
+                replicas[1].ip(),
+                replicas[2].ip(),
+                replicas[0].ip()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_weighted_skips_zero_weights() {
+        let replicas = replicas(&[[1, 1, 1, 1], [2, 2, 2, 2]]);
+        let weighted = Weighted {
+            weights: HashMap::from([("1.1.1.1".to_string(), 0)]),
+        };
+        assert_eq!(
+            weighted.select(&refs(&replicas), None).unwrap().ip(),
+            replicas[1].ip()
+        );
+
+        let weighted = Weighted {
+            weights: HashMap::from([("1.1.1.1".to_string(), 0), ("2.2.2.2".to_string(), 0)]),
+        };
+        assert!(weighted.select(&refs(&replicas), None).is_none());
+    }
+
+    #[test]
+    fn test_consistent_hash_only_moves_clients_of_removed_replica() {
+        let all = replicas(&[[1, 1, 1, 1], [2, 2, 2, 2], [3, 3, 3, 3], [4, 4, 4, 4]]);
+        let clients: Vec<IpAddr> = (1..=50)
+            .map(|i| IpAddr::V4(Ipv4Addr::new(10, 0, 0, i)))
+            .collect();
+        let before: Vec<IpAddr> = clients
+            .iter()
+            .map(|c| ConsistentHash.select(&refs(&all), Some(*c)).unwrap().ip())
+            .collect();
+        // every replica gets some clients
+        for replica in &all {
+            assert!(before.contains(&replica.ip()));
+        }
+
+        let removed = all[1].ip();
+        let remaining: Vec<Replica> = all.into_iter().filter(|r| r.ip() != removed).collect();
+        for (client, before) in clients.iter().zip(before) {
+            let after = ConsistentHash
+                .select(&refs(&remaining), Some(*client))
+                .unwrap()
+                .ip();
+            if before != removed {
+                assert_eq!(after, before, "client {client} moved");
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_two_choices_is_reproducible() {
+        let replicas = replicas(&[[1, 1, 1, 1], [2, 2, 2, 2], [3, 3, 3, 3], [4, 4, 4, 4]]);
+        let pick = |selector: &RandomTwoChoices| -> Vec<IpAddr> {
+            (0..10)
+                .map(|_| selector.select(&refs(&replicas), None).unwrap().ip())
+                .collect()
+        };
+        assert_eq!(
+            pick(&RandomTwoChoices::with_seed(42)),
+            pick(&RandomTwoChoices::with_seed(42))
+        );
+    }
+}
@@ -1,5 +1,11 @@
+pub(crate) mod affinity;
 pub(crate) mod changes;
 pub(crate) mod clients;
+pub(crate) mod config_store;
+pub(crate) mod dep_graph;
 pub(crate) mod edge;
 pub(super) mod input;
+pub(crate) mod load_balancing;
 pub(crate) mod service_info;
+pub(crate) mod service_map;
+pub(crate) mod validation;
@@ -1,9 +1,14 @@
 use crate::orchestrator::Orchestrator;
-use crate::services::clients::{Client, ClientInfo, Clients};
+use crate::services::affinity::Affinity;
+use crate::services::clients::{ChainContext, Client, ClientInfo, Clients};
+use crate::services::dep_graph::DepGraph;
 use crate::services::edge::Edge;
+use crate::services::load_balancing::ReplicaSelector;
+use crate::services::service_map::Services;
 use nullnet_grpc_lib::nullnet_grpc::Upstream;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 #[derive(Clone, Debug)]
@@ -13,17 +18,26 @@ pub(crate) enum ServiceInfo {
 }
 
 impl ServiceInfo {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
-        proxy_deps: Vec<String>,
-        triggers: HashMap<u16, Vec<String>>,
+        proxy_deps: DepGraph,
+        triggers: HashMap<u16, DepGraph>,
+        idle_timeouts: HashMap<u16, u64>,
         timeout: Option<u64>,
         max_networks: Option<u32>,
+        warm_networks: Option<u32>,
+        replica_selector: Arc<dyn ReplicaSelector>,
+        affinity: Affinity,
     ) -> Self {
         ServiceInfo::Unregistered(UnregisteredServiceInfo::new(
             proxy_deps,
             triggers,
+            idle_timeouts,
             timeout,
             max_networks,
+            warm_networks,
+            replica_selector,
+            affinity,
         ))
     }
 
@@ -33,19 +47,24 @@ impl ServiceInfo {
                 *self = ServiceInfo::Registered(RegisteredServiceInfo {
                     proxy_deps: unreg.proxy_deps.clone(),
                     triggers: unreg.triggers.clone(),
+                    idle_timeouts: unreg.idle_timeouts.clone(),
                     timeout: unreg.timeout,
                     max_networks: unreg.max_networks,
+                    warm_networks: unreg.warm_networks,
+                    replica_selector: unreg.replica_selector.clone(),
+                    affinity: unreg.affinity.clone(),
+                    replica_slots: HashMap::from([((ip, docker_container.clone()), 0)]),
+                    client_slots: HashMap::new(),
                     replicas: vec![Replica::new(ip, port, docker_container)],
+                    changed_clients: std::mem::take(&mut unreg.changed_clients),
                 });
             }
             ServiceInfo::Registered(reg) => {
-                if let Some(replica) = reg
-                    .replicas
-                    .iter_mut()
-                    .find(|r| r.matches_identity(ip, docker_container.as_deref()))
-                {
+                if let Some(replica) = reg.replica_mut(ip, docker_container.as_deref()) {
                     replica.port = port;
                 } else {
+                    reg.replica_slots
+                        .insert((ip, docker_container.clone()), reg.replicas.len());
                     reg.replicas.push(Replica::new(ip, port, docker_container));
                 }
             }
@@ -57,13 +76,20 @@ impl ServiceInfo {
     pub(crate) fn remove_replicas_on_ip(&mut self, ip: IpAddr) {
         if let ServiceInfo::Registered(reg) = self {
             reg.replicas.retain(|r| r.ip != ip);
+            reg.reindex();
             if reg.replicas.is_empty() {
-                *self = ServiceInfo::Unregistered(UnregisteredServiceInfo::new(
+                let mut unreg = UnregisteredServiceInfo::new(
                     reg.proxy_deps.clone(),
                     reg.triggers.clone(),
+                    reg.idle_timeouts.clone(),
                     reg.timeout,
                     reg.max_networks,
-                ));
+                    reg.warm_networks,
+                    reg.replica_selector.clone(),
+                    reg.affinity.clone(),
+                );
+                unreg.changed_clients = std::mem::take(&mut reg.changed_clients);
+                *self = ServiceInfo::Unregistered(unreg);
             }
         }
     }
@@ -74,13 +100,20 @@ impl ServiceInfo {
         if let ServiceInfo::Registered(reg) = self {
             reg.replicas
                 .retain(|r| !r.matches_identity(ip, docker_container));
+            reg.reindex();
             if reg.replicas.is_empty() {
-                *self = ServiceInfo::Unregistered(UnregisteredServiceInfo::new(
+                let mut unreg = UnregisteredServiceInfo::new(
                     reg.proxy_deps.clone(),
                     reg.triggers.clone(),
+                    reg.idle_timeouts.clone(),
                     reg.timeout,
                     reg.max_networks,
-                ));
+                    reg.warm_networks,
+                    reg.replica_selector.clone(),
+                    reg.affinity.clone(),
+                );
+                unreg.changed_clients = std::mem::take(&mut reg.changed_clients);
+                *self = ServiceInfo::Unregistered(unreg);
             }
         }
     }
@@ -95,18 +128,27 @@ impl ServiceInfo {
     pub(crate) fn update_from_file(&mut self, loaded: &Self) {
         let loaded_timeout = loaded.timeout();
         let loaded_max_networks = loaded.max_networks();
+        let loaded_warm_networks = loaded.warm_networks();
         match self {
             ServiceInfo::Unregistered(unreg) => {
-                unreg.proxy_deps = loaded.proxy_deps().to_vec();
+                unreg.proxy_deps.clone_from(loaded.proxy_deps());
                 unreg.triggers.clone_from(loaded.triggers());
+                unreg.idle_timeouts.clone_from(loaded.idle_timeouts());
                 unreg.timeout = loaded_timeout;
                 unreg.max_networks = loaded_max_networks;
+                unreg.warm_networks = loaded_warm_networks;
+                unreg.replica_selector = loaded.replica_selector();
+                unreg.affinity = loaded.affinity().clone();
             }
             ServiceInfo::Registered(reg) => {
-                reg.proxy_deps = loaded.proxy_deps().to_vec();
+                reg.proxy_deps.clone_from(loaded.proxy_deps());
                 reg.triggers.clone_from(loaded.triggers());
+                reg.idle_timeouts.clone_from(loaded.idle_timeouts());
                 reg.timeout = loaded_timeout;
                 reg.max_networks = loaded_max_networks;
+                reg.warm_networks = loaded_warm_networks;
+                reg.replica_selector = loaded.replica_selector();
+                reg.affinity = loaded.affinity().clone();
             }
         }
     }
@@ -118,52 +160,172 @@ impl ServiceInfo {
         }
     }
 
-    pub(crate) fn proxy_deps(&self) -> &[String] {
+    pub(crate) fn warm_networks(&self) -> Option<u32> {
+        match self {
+            ServiceInfo::Unregistered(unreg) => unreg.warm_networks,
+            ServiceInfo::Registered(reg) => reg.warm_networks,
+        }
+    }
+
+    pub(crate) fn replica_selector(&self) -> Arc<dyn ReplicaSelector> {
+        match self {
+            ServiceInfo::Unregistered(unreg) => unreg.replica_selector.clone(),
+            ServiceInfo::Registered(reg) => reg.replica_selector.clone(),
+        }
+    }
+
+    pub(crate) fn affinity(&self) -> &Affinity {
+        match self {
+            ServiceInfo::Unregistered(unreg) => &unreg.affinity,
+            ServiceInfo::Registered(reg) => &reg.affinity,
+        }
+    }
+
+    /// Set the labels declared by the node at `ip` on the replicas it hosts.
+    pub(crate) fn set_node_labels(&mut self, ip: IpAddr, labels: &HashMap<String, String>) {
+        if let ServiceInfo::Registered(reg) = self {
+            for replica in reg.replicas.iter_mut().filter(|r| r.ip == ip) {
+                replica.labels.clone_from(labels);
+            }
+        }
+    }
+
+    /// Mark the replica `(ip, docker_container)` as healthy or not.
+    pub(crate) fn set_replica_health(
+        &mut self,
+        ip: IpAddr,
+        docker_container: Option<&str>,
+        healthy: bool,
+    ) {
+        if let ServiceInfo::Registered(reg) = self
+            && let Some(replica) = reg.replica_mut(ip, docker_container)
+        {
+            replica.healthy = healthy;
+        }
+    }
+
+    /// Drain the replica `(ip, docker_container)`, or put it back in rotation.
+    pub(crate) fn set_replica_draining(
+        &mut self,
+        ip: IpAddr,
+        docker_container: Option<&str>,
+        draining: bool,
+    ) {
+        if let ServiceInfo::Registered(reg) = self
+            && let Some(replica) = reg.replica_mut(ip, docker_container)
+        {
+            replica.draining = draining;
+        }
+    }
+
+    /// Drain all the replicas on the node at `ip`, or put them back in rotation.
+    pub(crate) fn set_node_draining(&mut self, ip: IpAddr, draining: bool) {
+        if let ServiceInfo::Registered(reg) = self {
+            for replica in reg.replicas.iter_mut().filter(|r| r.ip == ip) {
+                replica.draining = draining;
+            }
+        }
+    }
+
+    /// Replace the placement policy, e.g. with a seeded one for reproducible placements.
+    #[cfg(test)]
+    pub(crate) fn set_replica_selector(&mut self, replica_selector: Arc<dyn ReplicaSelector>) {
+        match self {
+            ServiceInfo::Unregistered(unreg) => unreg.replica_selector = replica_selector,
+            ServiceInfo::Registered(reg) => reg.replica_selector = replica_selector,
+        }
+    }
+
+    pub(crate) fn proxy_deps(&self) -> &DepGraph {
         match self {
             ServiceInfo::Unregistered(unreg) => &unreg.proxy_deps,
             ServiceInfo::Registered(reg) => &reg.proxy_deps,
         }
     }
 
-    pub(crate) fn triggers(&self) -> &HashMap<u16, Vec<String>> {
+    pub(crate) fn triggers(&self) -> &HashMap<u16, DepGraph> {
         match self {
             ServiceInfo::Unregistered(unreg) => &unreg.triggers,
             ServiceInfo::Registered(reg) => &reg.triggers,
         }
     }
 
-    /// True iff `other` appears in any of this service's dep lists (proxy or backend).
+    pub(crate) fn idle_timeouts(&self) -> &HashMap<u16, u64> {
+        match self {
+            ServiceInfo::Unregistered(unreg) => &unreg.idle_timeouts,
+            ServiceInfo::Registered(reg) => &reg.idle_timeouts,
+        }
+    }
+
+    /// True iff `other` appears in any of this service's dep graphs (proxy or backend).
     pub(crate) fn deps_contain(&self, other: &str) -> bool {
-        self.proxy_deps().iter().any(|d| d == other)
-            || self.triggers().values().flatten().any(|d| d == other)
+        self.proxy_deps().contains(other) || self.triggers().values().any(|g| g.contains(other))
+    }
+
+    /// Clients with an entry on any replica.
+    pub(crate) fn clients(&self) -> Vec<Client> {
+        match self {
+            ServiceInfo::Unregistered(_) => Vec::new(),
+            ServiceInfo::Registered(reg) => reg.client_slots.keys().cloned().collect(),
+        }
+    }
+
+    /// Clients whose entries changed since the last call, for the edge index to catch up with.
+    pub(crate) fn take_changed_clients(&mut self) -> HashSet<Client> {
+        match self {
+            ServiceInfo::Unregistered(unreg) => std::mem::take(&mut unreg.changed_clients),
+            ServiceInfo::Registered(reg) => std::mem::take(&mut reg.changed_clients),
+        }
     }
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct UnregisteredServiceInfo {
-    /// Linear dep chain walked on proxy-triggered setup.
-    proxy_deps: Vec<String>,
+    /// Dep graph brought up on proxy-triggered setup.
+    proxy_deps: DepGraph,
     /// Backend-triggered chains keyed by the trigger port observed on the
-    /// initiator's host. One linear chain per port; no implicit fan-out.
-    triggers: HashMap<u16, Vec<String>>,
+    /// initiator's host. One graph per port, whose root has a single dep
+    /// (the target of the trigger port) that may fan out further.
+    triggers: HashMap<u16, DepGraph>,
+    /// Idle timeout (seconds) of the backend-triggered chains, keyed by trigger port.
+    idle_timeouts: HashMap<u16, u64>,
     /// Whether the proxy is reachable for this service, with the associated timeout.
     timeout: Option<u64>,
     /// Maximum number of networks for this service.
     max_networks: Option<u32>,
+    /// Networks kept set up for each proxy node, ahead of its requests.
+    warm_networks: Option<u32>,
+    /// Placement policy for new chains (`load_balancing` setting).
+    replica_selector: Arc<dyn ReplicaSelector>,
+    /// Locality policy for the chain hops reaching this service.
+    affinity: Affinity,
+    /// Clients whose entries changed since the edge index last caught up with them
+    /// (carried over from the registered service whose last replica was removed).
+    changed_clients: HashSet<Client>,
 }
 
 impl UnregisteredServiceInfo {
+    #[allow(clippy::too_many_arguments)]
     fn new(
-        proxy_deps: Vec<String>,
-        triggers: HashMap<u16, Vec<String>>,
+        proxy_deps: DepGraph,
+        triggers: HashMap<u16, DepGraph>,
+        idle_timeouts: HashMap<u16, u64>,
         timeout: Option<u64>,
         max_networks: Option<u32>,
+        warm_networks: Option<u32>,
+        replica_selector: Arc<dyn ReplicaSelector>,
+        affinity: Affinity,
     ) -> Self {
         Self {
             proxy_deps,
             triggers,
+            idle_timeouts,
             timeout,
             max_networks,
+            warm_networks,
+            replica_selector,
+            affinity,
+            changed_clients: HashSet::new(),
         }
     }
 }
@@ -174,15 +336,30 @@ pub(crate) struct Replica {
     port: u16,
     docker_container: Option<String>,
     clients: Clients,
+    /// Last time traffic was seen on the backend chains initiated by this replica,
+    /// keyed by trigger port. Not persisted: adopted chains start a fresh idle period.
+    backend_activity: HashMap<u16, Instant>,
+    /// Labels of the hosting node (e.g., `zone`), as declared in its services list.
+    labels: HashMap<String, String>,
+    /// Outcome of the latest health check reported by the hosting node
+    /// (replicas without a health check are always healthy).
+    healthy: bool,
+    /// Drained through the admin API: no new clients land on it,
+    /// while the existing ones keep it until they time out.
+    draining: bool,
 }
 
 impl Replica {
-    fn new(ip: IpAddr, port: u16, docker_container: Option<String>) -> Self {
+    pub(super) fn new(ip: IpAddr, port: u16, docker_container: Option<String>) -> Self {
         Self {
             ip,
             port,
             docker_container,
             clients: Clients::default(),
+            backend_activity: HashMap::new(),
+            labels: HashMap::new(),
+            healthy: true,
+            draining: false,
         }
     }
 
@@ -202,6 +379,23 @@ impl Replica {
         self.clients.clients()
     }
 
+    pub(crate) fn labels(&self) -> &HashMap<String, String> {
+        &self.labels
+    }
+
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.healthy
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    /// Whether new chains can land on this replica.
+    fn accepts_new_clients(&self) -> bool {
+        self.healthy && !self.draining
+    }
+
     /// A replica is uniquely identified by its `(ip, docker_container)` pair.
     pub(crate) fn matches_identity(&self, ip: IpAddr, docker_container: Option<&str>) -> bool {
         self.ip == ip && self.docker_container.as_deref() == docker_container
@@ -210,108 +404,217 @@ impl Replica {
 
 #[derive(Clone, Debug)]
 pub(crate) struct RegisteredServiceInfo {
-    /// Linear dep chain walked on proxy-triggered setup.
-    proxy_deps: Vec<String>,
+    /// Dep graph brought up on proxy-triggered setup.
+    proxy_deps: DepGraph,
     /// Backend-triggered chains keyed by the trigger port observed on the
-    /// initiator's host. One linear chain per port; no implicit fan-out.
-    triggers: HashMap<u16, Vec<String>>,
+    /// initiator's host. One graph per port, whose root has a single dep
+    /// (the target of the trigger port) that may fan out further.
+    triggers: HashMap<u16, DepGraph>,
+    /// Idle timeout (seconds) of the backend-triggered chains, keyed by trigger port.
+    idle_timeouts: HashMap<u16, u64>,
     /// Whether the proxy is reachable for this service, with the associated timeout.
     timeout: Option<u64>,
     /// Maximum number of networks for this service.
     max_networks: Option<u32>,
+    /// Networks kept set up for each proxy node, ahead of its requests.
+    warm_networks: Option<u32>,
+    /// Placement policy for new chains (`load_balancing` setting).
+    replica_selector: Arc<dyn ReplicaSelector>,
+    /// Locality policy for the chain hops reaching this service.
+    affinity: Affinity,
     /// Replicas of this service.
     replicas: Vec<Replica>,
+    /// Position in `replicas` of each replica, by `(ip, docker_container)`.
+    replica_slots: HashMap<(IpAddr, Option<String>), usize>,
+    /// Positions in `replicas` of the replicas hosting each client entry.
+    /// Sticky sessions keep a proxy client on a single replica, while a service
+    /// client can reach several replicas of a dep when the one it used turned unhealthy.
+    client_slots: HashMap<Client, BTreeSet<usize>>,
+    /// Clients whose entries changed since the edge index last caught up with them.
+    changed_clients: HashSet<Client>,
 }
 
 impl RegisteredServiceInfo {
-    /// Build the linear chain of edges for a proxy-triggered chain.
+    /// Build the edges of the dep graph for a proxy-triggered chain.
     pub(crate) fn proxy_dependency_chain(
         &self,
-        service_name: String,
         service_ip: IpAddr,
         service_docker: Option<&str>,
-        services: &HashMap<String, ServiceInfo>,
+        client_ip: Option<IpAddr>,
+        services: &Services,
     ) -> Vec<Edge> {
-        build_linear_chain(
+        let chain = ChainContext::proxy(self.proxy_deps.root(), service_ip, service_docker);
+        build_chain(
             &self.proxy_deps,
-            service_name,
+            &chain,
             service_ip,
             service_docker,
+            client_ip,
             services,
         )
     }
 
-    /// Build the chain of edges for the trigger at `port`, if one exists.
-    /// Each chain starts at this service's replica.
+    /// Build the edges of the dep graph for the trigger at `port`, if one exists.
+    /// Each chain starts at this service's replica, and its deps are placed
+    /// as if the initiator's host were the client.
     pub(crate) fn backend_dependency_chain(
         &self,
-        service_name: &str,
         service_ip: IpAddr,
         service_docker: Option<&str>,
         port: u16,
-        services: &HashMap<String, ServiceInfo>,
+        services: &Services,
     ) -> Option<Vec<Edge>> {
-        let chain = self.triggers.get(&port)?;
-        Some(build_linear_chain(
-            chain,
-            service_name.to_string(),
+        let graph = self.triggers.get(&port)?;
+        let chain = ChainContext::backend(graph.root(), service_ip, service_docker, port);
+        Some(build_chain(
+            graph,
+            &chain,
             service_ip,
             service_docker,
+            Some(service_ip),
             services,
         ))
     }
 
-    /// Invariant: a given `Client` exists on exactly one replica (sticky sessions).
-    /// These methods search across replicas and update the first (only) match.
-    pub(crate) fn add_chain(&mut self, client: &Client) {
-        for replica in &mut self.replicas {
-            if let Some(client_info) = replica.clients.clients_mut().get_mut(client) {
-                client_info.add_active_chain();
-                return;
+    fn replica_slot(&self, ip: IpAddr, docker_container: Option<&str>) -> Option<usize> {
+        self.replica_slots
+            .get(&(ip, docker_container.map(String::from)))
+            .copied()
+    }
+
+    fn replica_mut(&mut self, ip: IpAddr, docker_container: Option<&str>) -> Option<&mut Replica> {
+        let slot = self.replica_slot(ip, docker_container)?;
+        self.replicas.get_mut(slot)
+    }
+
+    /// Position of the first replica hosting a given client entry.
+    fn client_slot(&self, client: &Client) -> Option<usize> {
+        self.client_slots.get(client)?.first().copied()
+    }
+
+    /// Remove a client entry from the replica at `slot`.
+    fn remove_client_at(&mut self, slot: usize, client: &Client) -> Option<ClientInfo> {
+        let client_info = self.replicas[slot].clients.clients_mut().remove(client)?;
+        self.changed_clients.insert(client.clone());
+        if let Some(slots) = self.client_slots.get_mut(client) {
+            slots.remove(&slot);
+            if slots.is_empty() {
+                self.client_slots.remove(client);
             }
         }
+        Some(client_info)
     }
 
-    pub(crate) fn set_latest_now(&mut self, client: &Client) {
-        for replica in &mut self.replicas {
-            if let Some(client_info) = replica.clients.clients_mut().get_mut(client) {
-                client_info.set_latest_now();
-                return;
+    /// Rebuild the slots after replicas were removed.
+    fn reindex(&mut self) {
+        self.replica_slots.clear();
+        self.changed_clients
+            .extend(self.client_slots.drain().map(|(client, _)| client));
+        for (slot, replica) in self.replicas.iter().enumerate() {
+            self.replica_slots
+                .insert((replica.ip, replica.docker_container.clone()), slot);
+            for client in replica.clients.clients().keys() {
+                self.client_slots
+                    .entry(client.clone())
+                    .or_default()
+                    .insert(slot);
             }
         }
     }
 
-    /// Decrement `active_chains` for a specific client entry.
+    /// Invariant: a given proxy `Client` exists on exactly one replica (sticky sessions).
+    /// These methods update the first replica hosting the client entry.
+    pub(crate) fn add_chain(&mut self, client: &Client, chain: &ChainContext) {
+        if let Some(slot) = self.client_slot(client)
+            && let Some(client_info) = self.replicas[slot].clients.clients_mut().get_mut(client)
+        {
+            client_info.add_active_chain(chain);
+            self.changed_clients.insert(client.clone());
+        }
+    }
+
+    /// Record that `chain` uses the edge of a client entry restored without its chains,
+    /// leaving `active_chains` untouched.
+    pub(crate) fn adopt_chain(&mut self, client: &Client, chain: &ChainContext) {
+        if let Some(slot) = self.client_slot(client)
+            && let Some(client_info) = self.replicas[slot].clients.clients_mut().get_mut(client)
+        {
+            client_info.adopt_chain(chain);
+            self.changed_clients.insert(client.clone());
+        }
+    }
+
+    pub(crate) fn set_latest_now(&mut self, client: &Client) {
+        if let Some(slot) = self.client_slot(client)
+            && let Some(client_info) = self.replicas[slot].clients.clients_mut().get_mut(client)
+        {
+            client_info.set_latest_now();
+        }
+    }
+
+    /// Decrement `active_chains` for a specific client entry, used by `chain`.
     /// If it reaches 0, the VXLAN is torn down and the entry is removed.
-    pub(crate) async fn decrement_chain(&mut self, client: &Client, orchestrator: &Orchestrator) {
-        for replica in &mut self.replicas {
-            if let Some(ci) = replica.clients.clients_mut().get_mut(client) {
-                ci.remove_active_chains(1);
-                if ci.active_chains() == 0
-                    && let Some(ci) = replica.clients.clients_mut().remove(client)
-                {
-                    orchestrator
-                        .send_net_teardown(
-                            ci.client_ip(),
-                            ci.docker_container().cloned(),
-                            replica.ip,
-                            replica.docker_container.clone(),
-                            ci.net_id(),
-                        )
-                        .await;
-                }
-                return;
-            }
+    pub(crate) async fn decrement_chain(
+        &mut self,
+        client: &Client,
+        chain: &ChainContext,
+        orchestrator: &Orchestrator,
+    ) {
+        let Some(slot) = self.client_slot(client) else {
+            return;
+        };
+        let Some(ci) = self.replicas[slot].clients.clients_mut().get_mut(client) else {
+            return;
+        };
+        ci.remove_active_chain(chain);
+        self.changed_clients.insert(client.clone());
+        if ci.active_chains() == 0
+            && let Some(ci) = self.remove_client_at(slot, client)
+        {
+            let replica = &self.replicas[slot];
+            orchestrator
+                .send_net_teardown(
+                    ci.client_ip(),
+                    ci.docker_container().cloned(),
+                    replica.ip,
+                    replica.docker_container.clone(),
+                    ci.net_id(),
+                )
+                .await;
         }
     }
 
+    /// The healthy replica already hosting a given client entry, if any.
+    fn healthy_replica_of(&self, client: &Client) -> Option<&Replica> {
+        self.client_slots
+            .get(client)?
+            .iter()
+            .map(|slot| &self.replicas[*slot])
+            .find(|r| r.healthy)
+    }
+
     /// Find which server replica hosts a given client entry.
     /// Returns the server replica's `(ip, docker_container)`.
     pub(crate) fn client_replica(&self, client: &Client) -> Option<(IpAddr, Option<String>)> {
-        self.replicas
-            .iter()
-            .find(|r| r.clients.clients().contains_key(client))
-            .map(|r| (r.ip, r.docker_container.clone()))
+        let replica = &self.replicas[self.client_slot(client)?];
+        Some((replica.ip, replica.docker_container.clone()))
+    }
+
+    /// The entries of a given client, on each replica hosting it.
+    pub(crate) fn entries_of(&self, client: &Client) -> impl Iterator<Item = &ClientInfo> {
+        self.client_slots
+            .get(client)
+            .into_iter()
+            .flatten()
+            .filter_map(|slot| self.replicas[*slot].clients.clients().get(client))
+    }
+
+    /// The chains using the edge of a given client entry.
+    pub(crate) fn chains_of(&self, client: &Client) -> Vec<ChainContext> {
+        self.client_slot(client)
+            .and_then(|slot| self.replicas[slot].clients.clients().get(client))
+            .map(ClientInfo::chains)
+            .unwrap_or_default()
     }
 
     /// Count total proxy clients across all replicas.
@@ -323,7 +626,7 @@ impl RegisteredServiceInfo {
             .count()
     }
 
-    /// Find the least-used proxy client on the given proxy IP.
+    /// Find the least-used proxy client on the given proxy IP (on a replica taking new clients).
     /// Returns the upstream, network IPs/ID, and replica identity —
     /// everything the caller needs to create a new Client entry that
     /// shares the same physical network.
@@ -335,6 +638,7 @@ impl RegisteredServiceInfo {
         let best = self
             .replicas
             .iter()
+            .filter(|r| r.accepts_new_clients())
             .flat_map(|r| {
                 r.clients.clients().iter().filter_map(move |(c, ci)| {
                     if c.is_proxy() == Some(proxy_ip) && ci.server_net() != Ipv4Addr::UNSPECIFIED {
@@ -373,15 +677,116 @@ impl RegisteredServiceInfo {
             .any(|r| r.clients.clients().values().any(|ci| ci.net_id() == net_id))
     }
 
+    /// IDs of the established networks with one end on the node at `ip`.
+    pub(crate) fn net_ids_on_node(&self, ip: IpAddr) -> impl Iterator<Item = u32> + '_ {
+        self.replicas.iter().flat_map(move |r| {
+            r.clients
+                .clients()
+                .values()
+                .filter(move |ci| !ci.is_placeholder() && (r.ip == ip || ci.client_ip() == ip))
+                .map(ClientInfo::net_id)
+        })
+    }
+
     pub(crate) fn max_networks(&self) -> Option<u32> {
         self.max_networks
     }
 
-    /// Select the replica with the fewest active clients.
-    pub(crate) fn pick_replica_least_clients(&self) -> Option<&Replica> {
+    /// Clients of the warm networks on the proxy at `proxy_ip`, including the ones being set up,
+    /// on the replicas accepting new clients (the only ones they can be handed out on).
+    pub(crate) fn warm_clients(&self, proxy_ip: IpAddr) -> Vec<(Client, bool)> {
         self.replicas
             .iter()
-            .min_by_key(|r| r.clients.clients().len())
+            .filter(|r| r.accepts_new_clients())
+            .flat_map(|r| r.clients.clients().iter())
+            .filter(|(c, _)| c.is_warm() && c.is_proxy() == Some(proxy_ip))
+            .map(|(c, ci)| (c.clone(), ci.is_placeholder()))
+            .collect()
+    }
+
+    /// Clients of the warm networks set up on the proxy at `proxy_ip`
+    /// stranded on replicas not accepting new clients anymore.
+    pub(crate) fn stranded_warm_clients(&self, proxy_ip: IpAddr) -> Vec<Client> {
+        self.replicas
+            .iter()
+            .filter(|r| !r.accepts_new_clients())
+            .flat_map(|r| r.clients.clients().iter())
+            .filter(|(c, ci)| c.is_warm() && c.is_proxy() == Some(proxy_ip) && !ci.is_placeholder())
+            .map(|(c, _)| c.clone())
+            .collect()
+    }
+
+    /// Hand a warm network on the proxy of `client` over to it, on the replica picked
+    /// by `load_balancing` among the ones accepting new clients.
+    pub(crate) fn take_warm_network(&mut self, client: &Client) -> Option<Upstream> {
+        let proxy_ip = client.is_proxy()?;
+        let is_ready_warm = |c: &Client, ci: &ClientInfo| {
+            c.is_warm() && c.is_proxy() == Some(proxy_ip) && !ci.is_placeholder()
+        };
+        let candidates: Vec<&Replica> = self
+            .replicas
+            .iter()
+            .filter(|r| r.accepts_new_clients())
+            .filter(|r| {
+                r.clients
+                    .clients()
+                    .iter()
+                    .any(|(c, ci)| is_ready_warm(c, ci))
+            })
+            .collect();
+        let client_ip = client.name().parse().ok();
+        let replica = self.replica_selector.select(&candidates, client_ip)?;
+        let slot = self.replica_slot(replica.ip, replica.docker_container.as_deref())?;
+        let warm = replica
+            .clients
+            .clients()
+            .iter()
+            .find(|(c, ci)| is_ready_warm(c, ci))
+            .map(|(c, _)| c.clone())?;
+
+        let mut client_info = self.remove_client_at(slot, &warm)?;
+        client_info.set_latest_now();
+        let upstream = Upstream {
+            ip: client_info.server_net().to_string(),
+            port: u32::from(self.replicas[slot].port),
+        };
+        self.client_slots
+            .entry(client.clone())
+            .or_default()
+            .insert(slot);
+        self.changed_clients.insert(client.clone());
+        self.replicas[slot]
+            .clients
+            .add_client(client.clone(), client_info);
+        Some(upstream)
+    }
+
+    /// Select the healthy replica a new chain for `client_ip` lands on, according to `load_balancing`
+    /// (draining replicas are skipped).
+    pub(crate) fn pick_replica(&self, client_ip: Option<IpAddr>) -> Option<&Replica> {
+        let replicas: Vec<&Replica> = self
+            .replicas
+            .iter()
+            .filter(|r| r.accepts_new_clients())
+            .collect();
+        self.replica_selector.select(&replicas, client_ip)
+    }
+
+    /// Select the healthy replica reached by a chain hop from the node at `prev_ip`,
+    /// restricted to the closest replicas according to `affinity`.
+    pub(crate) fn pick_dep_replica(
+        &self,
+        prev_ip: IpAddr,
+        prev_labels: &HashMap<String, String>,
+        client_ip: Option<IpAddr>,
+    ) -> Option<&Replica> {
+        let healthy: Vec<&Replica> = self
+            .replicas
+            .iter()
+            .filter(|r| r.accepts_new_clients())
+            .collect();
+        let candidates = self.affinity.candidates(&healthy, prev_ip, prev_labels);
+        self.replica_selector.select(&candidates, client_ip)
     }
 
     pub(crate) fn add_client_to_replica(
@@ -391,25 +796,23 @@ impl RegisteredServiceInfo {
         client: Client,
         client_info: ClientInfo,
     ) {
-        if let Some(replica) = self
-            .replicas
-            .iter_mut()
-            .find(|r| r.matches_identity(replica_ip, replica_docker))
-        {
-            replica.clients.add_client(client, client_info);
+        if let Some(slot) = self.replica_slot(replica_ip, replica_docker) {
+            self.client_slots
+                .entry(client.clone())
+                .or_default()
+                .insert(slot);
+            self.changed_clients.insert(client.clone());
+            self.replicas[slot].clients.add_client(client, client_info);
         }
     }
 
     pub(crate) fn is_client_setup(&self, client: &Client) -> Option<Upstream> {
-        for replica in &self.replicas {
-            if let Some(server_net) = replica.clients.is_client_setup(client) {
-                return Some(Upstream {
-                    ip: server_net.to_string(),
-                    port: u32::from(replica.port),
-                });
-            }
-        }
-        None
+        let replica = &self.replicas[self.client_slot(client)?];
+        let server_net = replica.clients.is_client_setup(client)?;
+        Some(Upstream {
+            ip: server_net.to_string(),
+            port: u32::from(replica.port),
+        })
     }
 
     /// Check if a specific replica already has this client.
@@ -419,17 +822,27 @@ impl RegisteredServiceInfo {
         ip: IpAddr,
         docker: Option<&str>,
     ) -> bool {
-        self.replicas
-            .iter()
-            .filter(|r| r.matches_identity(ip, docker))
-            .any(|r| r.clients.is_client_setup(client).is_some())
+        self.replica_slot(ip, docker).is_some_and(|slot| {
+            self.client_slots
+                .get(client)
+                .is_some_and(|slots| slots.contains(&slot))
+        })
+    }
+
+    /// Remove a client entry, returning it with its replica's `(ip, docker_container)`.
+    pub(crate) fn take_client(
+        &mut self,
+        client: &Client,
+    ) -> Option<(ClientInfo, IpAddr, Option<String>)> {
+        let slot = self.client_slot(client)?;
+        let client_info = self.remove_client_at(slot, client)?;
+        let replica = &self.replicas[slot];
+        Some((client_info, replica.ip, replica.docker_container.clone()))
     }
 
     pub(crate) fn remove_client(&mut self, client: &Client) {
-        for replica in &mut self.replicas {
-            if replica.clients.clients_mut().remove(client).is_some() {
-                return;
-            }
+        if let Some(slot) = self.client_slot(client) {
+            self.remove_client_at(slot, client);
         }
     }
 
@@ -437,7 +850,7 @@ impl RegisteredServiceInfo {
         &self.replicas
     }
 
-    pub(crate) fn triggers(&self) -> &HashMap<u16, Vec<String>> {
+    pub(crate) fn triggers(&self) -> &HashMap<u16, DepGraph> {
         &self.triggers
     }
 
@@ -451,7 +864,9 @@ impl RegisteredServiceInfo {
                     .clients()
                     .iter()
                     .filter(|(c, ci)| {
-                        c.is_proxy().is_some() && now.duration_since(ci.latest()) >= timeout
+                        c.is_proxy().is_some()
+                            && !c.is_warm()
+                            && now.duration_since(ci.latest()) >= timeout
                     })
                     .map(|(c, _)| c.clone())
             })
@@ -467,12 +882,108 @@ impl RegisteredServiceInfo {
                     .clients
                     .clients()
                     .iter()
-                    .filter(|(c, _)| c.is_proxy().is_some())
+                    .filter(|(c, _)| c.is_proxy().is_some() && !c.is_warm())
                     .map(|(_, ci)| timeout.saturating_sub(now.duration_since(ci.latest())))
             })
             .min()
     }
 
+    /// Start the idle period of the backend chain at `port` initiated by a specific replica.
+    pub(crate) fn mark_backend_chain_active(
+        &mut self,
+        ip: IpAddr,
+        docker_container: Option<&str>,
+        port: u16,
+    ) {
+        if let Some(replica) = self.replica_mut(ip, docker_container) {
+            replica.backend_activity.insert(port, Instant::now());
+        }
+    }
+
+    /// Adopt the backend chain at `port` held by the node at `ip` (e.g., after a
+    /// server restart), starting its idle period unless it's already tracked.
+    pub(crate) fn adopt_backend_chain(&mut self, ip: IpAddr, port: u16) {
+        if !self.triggers.contains_key(&port) {
+            return;
+        }
+        for replica in self.replicas.iter_mut().filter(|r| r.ip == ip) {
+            replica
+                .backend_activity
+                .entry(port)
+                .or_insert_with(Instant::now);
+        }
+    }
+
+    /// Record traffic on the backend chain at `port` initiated by a specific replica.
+    pub(crate) fn record_backend_activity(
+        &mut self,
+        ip: IpAddr,
+        docker_container: Option<&str>,
+        port: u16,
+    ) {
+        if let Some(last_seen) = self
+            .replica_mut(ip, docker_container)
+            .and_then(|replica| replica.backend_activity.get_mut(&port))
+        {
+            *last_seen = Instant::now();
+        }
+    }
+
+    /// Stop tracking the backend chain at `port` initiated by a specific replica.
+    pub(crate) fn clear_backend_activity(
+        &mut self,
+        ip: IpAddr,
+        docker_container: Option<&str>,
+        port: u16,
+    ) {
+        if let Some(replica) = self.replica_mut(ip, docker_container) {
+            replica.backend_activity.remove(&port);
+        }
+    }
+
+    /// Backend chains idle for longer than their trigger's `idle_timeout`,
+    /// as `(initiator ip, initiator docker, trigger port)`.
+    pub(crate) fn idle_backend_chains(&self) -> Vec<(IpAddr, Option<String>, u16)> {
+        let now = Instant::now();
+        self.replicas
+            .iter()
+            .flat_map(|replica| {
+                replica
+                    .backend_activity
+                    .iter()
+                    .filter(|(port, last_seen)| {
+                        self.idle_timeout(**port)
+                            .is_some_and(|timeout| now.duration_since(**last_seen) >= timeout)
+                    })
+                    .map(|(port, _)| (replica.ip, replica.docker_container.clone(), *port))
+            })
+            .collect()
+    }
+
+    pub(crate) fn nearest_backend_idle_expiry(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.replicas
+            .iter()
+            .flat_map(|replica| {
+                replica
+                    .backend_activity
+                    .iter()
+                    .filter_map(|(port, last_seen)| {
+                        let timeout = self.idle_timeout(*port)?;
+                        Some(timeout.saturating_sub(now.duration_since(*last_seen)))
+                    })
+            })
+            .min()
+    }
+
+    /// Idle timeout of the trigger at `port`, if enabled (0 disables it).
+    fn idle_timeout(&self, port: u16) -> Option<Duration> {
+        self.idle_timeouts
+            .get(&port)
+            .filter(|timeout| **timeout > 0)
+            .map(|timeout| Duration::from_secs(*timeout))
+    }
+
     /// Return service-to-service client entries connected to replicas at the given IP.
     pub(crate) fn service_clients_on_ip(&self, ip: IpAddr) -> Vec<Client> {
         self.replicas
@@ -503,6 +1014,13 @@ impl RegisteredServiceInfo {
         self.replicas.iter().any(|r| r.ip == ip)
     }
 
+    /// Whether any replica has a client of the proxy at `proxy_ip`.
+    pub(crate) fn has_proxy_clients_of(&self, proxy_ip: IpAddr) -> bool {
+        self.client_slots
+            .keys()
+            .any(|c| c.is_proxy() == Some(proxy_ip))
+    }
+
     #[cfg(test)]
     pub(crate) fn client_count(&self) -> usize {
         self.replicas
@@ -536,45 +1054,153 @@ impl RegisteredServiceInfo {
     }
 }
 
-/// Build a linear chain of edges from `start` → deps[0] → deps[1] → … → deps[N-1].
-fn build_linear_chain(
-    deps: &[String],
-    service_name: String,
+/// Replica a service of a chain is placed on: `(ip, docker_container, node labels)`.
+type Placement = (IpAddr, Option<String>, HashMap<String, String>);
+
+/// Build the edges of `graph`, starting from the root's replica at `(service_ip, service_docker)`.
+///
+/// Each dep is placed once per chain, when it's first reached: on the healthy replica
+/// that the service it's reached from is already connected to, if any (sharing that
+/// edge with the chains already using it); otherwise on the replica picked by the dep's
+/// placement policy for `client_ip`, among the replicas closest to the previous hop
+/// according to the dep's affinity.
+fn build_chain(
+    graph: &DepGraph,
+    chain_context: &ChainContext,
     service_ip: IpAddr,
     service_docker: Option<&str>,
-    services: &HashMap<String, ServiceInfo>,
+    client_ip: Option<IpAddr>,
+    services: &Services,
 ) -> Vec<Edge> {
+    let root_labels = match services.get(graph.root()) {
+        Some(ServiceInfo::Registered(reg)) => reg
+            .replica_slot(service_ip, service_docker)
+            .map(|slot| reg.replicas[slot].labels.clone())
+            .unwrap_or_default(),
+        _ => HashMap::new(),
+    };
+    // `None` for the deps without an eligible replica
+    let mut placements: HashMap<&str, Option<Placement>> = HashMap::from([(
+        graph.root(),
+        Some((service_ip, service_docker.map(String::from), root_labels)),
+    )]);
+
     let mut chain = Vec::new();
-    let mut current_ip: Option<IpAddr> = Some(service_ip);
-    let mut current_docker: Option<String> = service_docker.map(String::from);
-    let mut current_name = service_name;
-    for dep in deps {
-        let (dep_ip, dep_docker) = match services.get(dep) {
-            Some(ServiceInfo::Registered(reg)) => {
-                if let Some(r) = reg.pick_replica_least_clients() {
-                    (Some(r.ip()), r.docker_container().map(String::from))
-                } else {
-                    (None, None)
-                }
-            }
-            _ => (None, None),
+    for (from, dep) in graph.edges() {
+        let from_placement = placements.get(from).cloned().flatten();
+        if !placements.contains_key(dep) {
+            let picked = match services.get(dep) {
+                Some(ServiceInfo::Registered(reg)) => match &from_placement {
+                    Some((ip, docker, labels)) => reg
+                        .healthy_replica_of(&Client::new_service(
+                            from.to_string(),
+                            *ip,
+                            docker.clone(),
+                        ))
+                        .or_else(|| reg.pick_dep_replica(*ip, labels, client_ip)),
+                    None => reg.pick_replica(client_ip),
+                },
+                _ => None,
+            };
+            let placement = picked.map(|r| {
+                (
+                    r.ip(),
+                    r.docker_container().map(String::from),
+                    r.labels.clone(),
+                )
+            });
+            placements.insert(dep, placement);
+        }
+        let (dep_ip, dep_docker) = match placements.get(dep).cloned().flatten() {
+            Some((ip, docker, _)) => (Some(ip), docker),
+            None => (None, None),
+        };
+
+        let (from_ip, from_docker) = match from_placement {
+            Some((ip, docker, _)) => (Some(ip), docker),
+            None => (None, None),
         };
-        let client = match current_ip {
-            Some(ip) => Client::new_service(current_name.clone(), ip, current_docker.clone()),
-            None => Client::new(current_name.clone(), None),
+        let client = match from_ip {
+            Some(ip) => Client::new_service(from.to_string(), ip, from_docker.clone()),
+            None => Client::new(from.to_string(), None),
         };
-        let edge = Edge::new(
-            current_ip,
+        chain.push(Edge::new(
+            from_ip,
             client,
-            current_docker,
+            from_docker,
             dep_ip,
-            Client::new(dep.clone(), None),
-            dep_docker.clone(),
-        );
-        chain.push(edge);
-        current_ip = dep_ip;
-        current_docker = dep_docker;
-        current_name.clone_from(dep);
+            Client::new(dep.to_string(), None),
+            dep_docker,
+            chain_context.clone(),
+        ));
     }
     chain
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::dep_graph::DepsToml;
+    use crate::services::load_balancing::LoadBalancing;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn test_client_slots_follow_replica_removals() {
+        let mut si = ServiceInfo::new(
+            DepGraph::new("S", DepsToml::default()).unwrap(),
+            HashMap::new(),
+            HashMap::new(),
+            None,
+            None,
+            None,
+            LoadBalancing::default().selector(),
+            Affinity::default(),
+        );
+        for last in 1..=3 {
+            si.add_replica(ip(last), 8080, None);
+        }
+        let service_client = Client::new_service("A".to_string(), ip(9), None);
+        let proxy_client = Client::new("1.2.3.4".to_string(), Some(ip(8)));
+        let ServiceInfo::Registered(reg) = &mut si else {
+            panic!("service is not registered");
+        };
+        for last in [2, 3] {
+            reg.add_client_to_replica(
+                ip(last),
+                None,
+                service_client.clone(),
+                ClientInfo::placeholder(ip(9)),
+            );
+        }
+        reg.add_client_to_replica(
+            ip(3),
+            None,
+            proxy_client.clone(),
+            ClientInfo::placeholder(ip(8)),
+        );
+
+        si.remove_replica(ip(1), None);
+        let ServiceInfo::Registered(reg) = &mut si else {
+            panic!("service is not registered");
+        };
+        assert_eq!(reg.client_replica(&service_client), Some((ip(2), None)));
+        assert!(reg.is_client_on_replica(&service_client, ip(3), None));
+        assert!(reg.is_client_setup(&proxy_client).is_some());
+
+        let (_, taken_ip, _) = reg.take_client(&service_client).unwrap();
+        assert_eq!(taken_ip, ip(2));
+        assert_eq!(reg.client_replica(&service_client), Some((ip(3), None)));
+        assert!(!reg.is_client_on_replica(&service_client, ip(2), None));
+
+        si.remove_replicas_on_ip(ip(3));
+        let ServiceInfo::Registered(reg) = &si else {
+            panic!("service is not registered");
+        };
+        assert!(reg.client_replica(&service_client).is_none());
+        assert!(reg.is_client_setup(&proxy_client).is_none());
+        assert_eq!(reg.replicas().len(), 1);
+    }
+}
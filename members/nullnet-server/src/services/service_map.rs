@@ -0,0 +1,687 @@
+//! The services and their client entries, locked per service.
+//!
+//! Each service sits behind its own lock, so that the chains of unrelated services are
+//! set up and torn down concurrently, even when a node is slow to take its messages.
+//! An operation locks the services it may change, always in name order: a single service
+//! for the updates confined to it, and whole components (the services the chains through
+//! it can span) for the ones walking chains. Only config reloads and restores, the ones
+//! adding and removing services, lock the whole map.
+//!
+//! The edges are indexed across services ([`EdgeIndex`]), so that the edges of a chain,
+//! a client or a network are found without walking dependency graphs or scanning services.
+
+use crate::services::clients::{ChainContext, Client};
+use crate::services::service_info::ServiceInfo;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, btree_map};
+use std::hash::Hash;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+/// The services of the server, each behind its own lock.
+pub(crate) struct ServiceMap {
+    shards: Arc<RwLock<Shards>>,
+    index: Arc<Mutex<EdgeIndex>>,
+}
+
+#[derive(Default)]
+struct Shards {
+    services: BTreeMap<String, Arc<RwLock<ServiceInfo>>>,
+    /// The services the chains through each service can span: the connected components
+    /// of the dependency graphs and of the chains set up (which may predate the config).
+    /// Recomputed whenever the whole map was locked, the only time they can change.
+    components: HashMap<String, Arc<BTreeSet<String>>>,
+}
+
+impl ServiceMap {
+    pub(crate) fn new(services: HashMap<String, ServiceInfo>) -> Self {
+        let AllServices(services) = AllServices::from(services);
+        let components = components(&services, &services.index.lock().unwrap());
+        let shards = Shards {
+            services: services
+                .slots
+                .into_iter()
+                .map(|(name, slot)| (name, Arc::new(RwLock::new(slot.into_owned()))))
+                .collect(),
+            components,
+        };
+        Self {
+            shards: Arc::new(RwLock::new(shards)),
+            index: services.index,
+        }
+    }
+
+    /// All the services, for reading, as they are at a single point in time.
+    pub(crate) async fn read(&self) -> ServicesRef {
+        let shards = self.shards.clone().read_owned().await;
+        let mut slots = BTreeMap::new();
+        for (name, service) in &shards.services {
+            slots.insert(name.clone(), Slot::Read(service.clone().read_owned().await));
+        }
+        ServicesRef {
+            services: Services::locked(slots, self.index.clone()),
+            _shards: shards,
+        }
+    }
+
+    /// All the services, exclusively: the only lock under which services are added or removed.
+    pub(crate) async fn write(&self) -> AllServicesMut {
+        let shards = self.shards.clone().write_owned().await;
+        let mut slots = BTreeMap::new();
+        for (name, service) in &shards.services {
+            slots.insert(
+                name.clone(),
+                Slot::Write(service.clone().write_owned().await),
+            );
+        }
+        AllServicesMut {
+            services: AllServices(Services::locked(slots, self.index.clone())),
+            shards,
+        }
+    }
+
+    /// The services `names`, exclusively.
+    pub(crate) async fn lock(&self, names: &[&str]) -> ServicesMut {
+        let shards = self.shards.clone().read_owned().await;
+        let names = names.iter().map(ToString::to_string).collect();
+        Self::lock_in(shards, names, self.index.clone()).await
+    }
+
+    /// The services `names`, and the ones their chains can go through, exclusively.
+    pub(crate) async fn lock_chains_of(&self, names: &[&str]) -> ServicesMut {
+        let shards = self.shards.clone().read_owned().await;
+        let names = shards.components_of(names.iter().copied());
+        Self::lock_in(shards, names, self.index.clone()).await
+    }
+
+    /// The services matching `filter` (checked one service at a time), exclusively.
+    pub(crate) async fn lock_where(
+        &self,
+        filter: impl Fn(&str, &ServiceInfo) -> bool,
+    ) -> ServicesMut {
+        let shards = self.shards.clone().read_owned().await;
+        let names = shards.matching(filter).await.into_iter().collect();
+        Self::lock_in(shards, names, self.index.clone()).await
+    }
+
+    /// The services matching `filter` (checked one service at a time),
+    /// and the ones their chains can go through, exclusively.
+    ///
+    /// Services can change between the check and the lock:
+    /// the changes to make are to be found again on the locked services.
+    pub(crate) async fn lock_chains_where(
+        &self,
+        filter: impl Fn(&str, &ServiceInfo) -> bool,
+    ) -> ServicesMut {
+        let shards = self.shards.clone().read_owned().await;
+        let matching = shards.matching(filter).await;
+        let names = shards.components_of(matching.iter().map(String::as_str));
+        Self::lock_in(shards, names, self.index.clone()).await
+    }
+
+    /// Visit the services one at a time, each under its own lock.
+    pub(crate) async fn for_each(&self, mut f: impl FnMut(&str, &ServiceInfo)) {
+        let shards = self.shards.read().await;
+        for (name, service) in &shards.services {
+            f(name, &*service.read().await);
+        }
+    }
+
+    /// A copy of the services, each taken under its own lock: unlike [`Self::read`],
+    /// it doesn't hold the services already copied while waiting for the others.
+    pub(crate) async fn snapshot(&self) -> AllServices {
+        let mut services = HashMap::new();
+        self.for_each(|name, si| {
+            services.insert(name.to_string(), si.clone());
+        })
+        .await;
+        AllServices::from(services)
+    }
+
+    /// Services with an edge over the network `net_id`.
+    pub(crate) fn services_of_net(&self, net_id: u32) -> Vec<String> {
+        let index = self.index.lock().unwrap();
+        let services: BTreeSet<&String> = index
+            .by_net
+            .get(&net_id)
+            .into_iter()
+            .flatten()
+            .map(|(_, service)| service)
+            .collect();
+        services.into_iter().cloned().collect()
+    }
+
+    async fn lock_in(
+        shards: OwnedRwLockReadGuard<Shards>,
+        names: BTreeSet<String>,
+        index: Arc<Mutex<EdgeIndex>>,
+    ) -> ServicesMut {
+        let mut slots = BTreeMap::new();
+        for name in names {
+            if let Some(service) = shards.services.get(&name) {
+                let guard = service.clone().write_owned().await;
+                slots.insert(name, Slot::Write(guard));
+            }
+        }
+        ServicesMut {
+            services: Services::locked(slots, index),
+            _shards: shards,
+        }
+    }
+}
+
+impl Shards {
+    async fn matching(&self, filter: impl Fn(&str, &ServiceInfo) -> bool) -> Vec<String> {
+        let mut matching = Vec::new();
+        for (name, service) in &self.services {
+            if filter(name, &*service.read().await) {
+                matching.push(name.clone());
+            }
+        }
+        matching
+    }
+
+    fn components_of<'a>(&self, names: impl Iterator<Item = &'a str>) -> BTreeSet<String> {
+        let mut services = BTreeSet::new();
+        for name in names {
+            match self.components.get(name) {
+                Some(component) => services.extend(component.iter().cloned()),
+                None => {
+                    services.insert(name.to_string());
+                }
+            }
+        }
+        services
+    }
+}
+
+/// The connected components of the services, linking each service to its deps and
+/// to the services of the chains it starts.
+fn components<'a>(
+    services: &'a Services,
+    index: &'a EdgeIndex,
+) -> HashMap<String, Arc<BTreeSet<String>>> {
+    let mut adjacent: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut link = |a: &'a str, b: &'a str| {
+        adjacent.entry(a).or_default().push(b);
+        adjacent.entry(b).or_default().push(a);
+    };
+    for (name, si) in services {
+        link(name.as_str(), name.as_str());
+        for graph in std::iter::once(si.proxy_deps()).chain(si.triggers().values()) {
+            for (from, dep) in graph.edges() {
+                link(from, dep);
+            }
+        }
+    }
+    for (chain, edges) in &index.by_chain {
+        for (_, service) in edges {
+            link(chain.service(), service);
+        }
+    }
+
+    let mut components = HashMap::new();
+    for start in adjacent.keys() {
+        if components.contains_key(*start) {
+            continue;
+        }
+        let mut component = BTreeSet::from([start.to_string()]);
+        let mut to_visit = vec![*start];
+        while let Some(service) = to_visit.pop() {
+            for next in &adjacent[service] {
+                if component.insert(next.to_string()) {
+                    to_visit.push(*next);
+                }
+            }
+        }
+        let component = Arc::new(component);
+        for service in component.iter() {
+            components.insert(service.clone(), component.clone());
+        }
+    }
+    components
+}
+
+enum Slot {
+    Owned(Box<ServiceInfo>),
+    Read(OwnedRwLockReadGuard<ServiceInfo>),
+    Write(OwnedRwLockWriteGuard<ServiceInfo>),
+}
+
+impl Slot {
+    /// The service, unless it's locked for reading.
+    fn get_mut(&mut self) -> Option<&mut ServiceInfo> {
+        match self {
+            Slot::Owned(si) => Some(si),
+            Slot::Write(guard) => Some(guard),
+            Slot::Read(_) => None,
+        }
+    }
+
+    fn into_owned(self) -> ServiceInfo {
+        match self {
+            Slot::Owned(si) => *si,
+            Slot::Read(guard) => guard.clone(),
+            Slot::Write(guard) => guard.clone(),
+        }
+    }
+}
+
+impl Deref for Slot {
+    type Target = ServiceInfo;
+
+    fn deref(&self) -> &ServiceInfo {
+        match self {
+            Slot::Owned(si) => si,
+            Slot::Read(guard) => guard,
+            Slot::Write(guard) => guard,
+        }
+    }
+}
+
+/// A set of services locked in a [`ServiceMap`], possibly not all of them:
+/// the services in the set can be changed, but none can be added or removed
+/// (see [`AllServices`]).
+pub(crate) struct Services {
+    slots: BTreeMap<String, Slot>,
+    /// Services borrowed mutably since the edge index last caught up with them.
+    changed: BTreeSet<String>,
+    /// Services replaced or removed, whose edges are indexed again from scratch.
+    replaced: BTreeSet<String>,
+    index: Arc<Mutex<EdgeIndex>>,
+}
+
+impl Services {
+    fn locked(slots: BTreeMap<String, Slot>, index: Arc<Mutex<EdgeIndex>>) -> Self {
+        Self {
+            slots,
+            changed: BTreeSet::new(),
+            replaced: BTreeSet::new(),
+            index,
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&ServiceInfo> {
+        self.slots.get(name).map(|slot| &**slot)
+    }
+
+    /// The service `name`, for changing it (`None` if it's only locked for reading).
+    pub(crate) fn get_mut(&mut self, name: &str) -> Option<&mut ServiceInfo> {
+        let si = self.slots.get_mut(name)?.get_mut()?;
+        self.changed.insert(name.to_string());
+        Some(si)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn contains_key(&self, name: &str) -> bool {
+        self.slots.contains_key(name)
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &String> {
+        self.slots.keys()
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &ServiceInfo> {
+        self.slots.values().map(|slot| &**slot)
+    }
+
+    pub(crate) fn values_mut(&mut self) -> impl Iterator<Item = &mut ServiceInfo> {
+        self.changed.extend(self.slots.keys().cloned());
+        self.slots.values_mut().filter_map(Slot::get_mut)
+    }
+
+    pub(crate) fn iter(&self) -> Iter<'_> {
+        Iter(self.slots.iter())
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut ServiceInfo)> {
+        self.changed.extend(self.slots.keys().cloned());
+        self.slots
+            .iter_mut()
+            .filter_map(|(name, slot)| Some((name, slot.get_mut()?)))
+    }
+
+    /// Replace the service `name`, returning the previous one
+    /// (`None`, leaving the set untouched, if it can't be changed).
+    pub(crate) fn replace(&mut self, name: &str, si: ServiceInfo) -> Option<ServiceInfo> {
+        let slot = self.slots.get_mut(name)?.get_mut()?;
+        self.replaced.insert(name.to_string());
+        Some(std::mem::replace(slot, si))
+    }
+
+    /// The dependency edges used by `chain`, as `(client, service)`: the proxy
+    /// clients sharing the chain are left out, as their edges come and go on their own.
+    pub(crate) fn chain_edges(&mut self, chain: &ChainContext) -> Vec<(Client, String)> {
+        self.synced_index()
+            .by_chain
+            .get(chain)
+            .into_iter()
+            .flatten()
+            .filter(|(client, _)| client.is_proxy().is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// The edges over the network `net_id`, as `(client, service)`.
+    pub(crate) fn net_edges(&mut self, net_id: u32) -> Vec<(Client, String)> {
+        self.synced_index()
+            .by_net
+            .get(&net_id)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// The chains using the edges of `client`, by the service each edge reaches.
+    pub(crate) fn client_edges(&mut self, client: &Client) -> Vec<(String, Vec<ChainContext>)> {
+        self.synced_index()
+            .by_client
+            .get(client)
+            .into_iter()
+            .flatten()
+            .map(|(service, edge)| (service.clone(), edge.chains.iter().cloned().collect()))
+            .collect()
+    }
+
+    /// The edge index, once caught up with the changes made to this set.
+    fn synced_index(&mut self) -> MutexGuard<'_, EdgeIndex> {
+        let mut index = self.index.lock().unwrap();
+        for name in std::mem::take(&mut self.replaced) {
+            index.remove_service(&name);
+            if let Some(si) = self.slots.get_mut(&name).and_then(Slot::get_mut) {
+                si.take_changed_clients();
+                for client in si.clients() {
+                    index.update(&name, &client, si);
+                }
+            }
+        }
+        for name in std::mem::take(&mut self.changed) {
+            if let Some(si) = self.slots.get_mut(&name).and_then(Slot::get_mut) {
+                for client in si.take_changed_clients() {
+                    index.update(&name, &client, si);
+                }
+            }
+        }
+        index
+    }
+}
+
+impl Services {
+    /// A standalone copy of the services, with its own edge index.
+    pub(crate) fn copy(&self) -> AllServices {
+        AllServices::from(
+            self.iter()
+                .map(|(name, si)| (name.clone(), si.clone()))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Index<&str> for Services {
+    type Output = ServiceInfo;
+
+    fn index(&self, name: &str) -> &ServiceInfo {
+        self.get(name)
+            .unwrap_or_else(|| panic!("service '{name}' is not in the set"))
+    }
+}
+
+pub(crate) struct Iter<'a>(btree_map::Iter<'a, String, Slot>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a String, &'a ServiceInfo);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(name, slot)| (name, &**slot))
+    }
+}
+
+impl<'a> IntoIterator for &'a Services {
+    type Item = (&'a String, &'a ServiceInfo);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// Services locked for reading by [`ServiceMap::read`].
+pub(crate) struct ServicesRef {
+    services: Services,
+    _shards: OwnedRwLockReadGuard<Shards>,
+}
+
+impl Deref for ServicesRef {
+    type Target = Services;
+
+    fn deref(&self) -> &Services {
+        &self.services
+    }
+}
+
+/// All the services, whether locked in a [`ServiceMap`] or a standalone copy:
+/// the only sets services can be added to and removed from.
+pub(crate) struct AllServices(Services);
+
+impl AllServices {
+    /// Add a service, or replace one.
+    pub(crate) fn insert(&mut self, name: String, si: ServiceInfo) {
+        self.0.replaced.insert(name.clone());
+        self.0.slots.insert(name, Slot::Owned(Box::new(si)));
+    }
+
+    /// Remove a service.
+    pub(crate) fn remove(&mut self, name: &str) {
+        if self.0.slots.remove(name).is_some() {
+            self.0.replaced.insert(name.to_string());
+        }
+    }
+}
+
+impl From<HashMap<String, ServiceInfo>> for AllServices {
+    fn from(services: HashMap<String, ServiceInfo>) -> Self {
+        let mut services = Services {
+            replaced: services.keys().cloned().collect(),
+            slots: services
+                .into_iter()
+                .map(|(name, si)| (name, Slot::Owned(Box::new(si))))
+                .collect(),
+            changed: BTreeSet::new(),
+            index: Arc::default(),
+        };
+        drop(services.synced_index());
+        Self(services)
+    }
+}
+
+impl Deref for AllServices {
+    type Target = Services;
+
+    fn deref(&self) -> &Services {
+        &self.0
+    }
+}
+
+impl DerefMut for AllServices {
+    fn deref_mut(&mut self) -> &mut Services {
+        &mut self.0
+    }
+}
+
+/// Services locked exclusively: the edge index catches up with the changes made
+/// to them when they're released.
+pub(crate) struct ServicesMut {
+    // declared first, so that the services are released before the map
+    services: Services,
+    _shards: OwnedRwLockReadGuard<Shards>,
+}
+
+impl Deref for ServicesMut {
+    type Target = Services;
+
+    fn deref(&self) -> &Services {
+        &self.services
+    }
+}
+
+impl DerefMut for ServicesMut {
+    fn deref_mut(&mut self) -> &mut Services {
+        &mut self.services
+    }
+}
+
+impl Drop for ServicesMut {
+    fn drop(&mut self) {
+        drop(self.services.synced_index());
+    }
+}
+
+/// All the services locked exclusively by [`ServiceMap::write`]: the edge index
+/// and the map catch up with the changes made to them when they're released.
+pub(crate) struct AllServicesMut {
+    services: AllServices,
+    shards: OwnedRwLockWriteGuard<Shards>,
+}
+
+impl Deref for AllServicesMut {
+    type Target = AllServices;
+
+    fn deref(&self) -> &AllServices {
+        &self.services
+    }
+}
+
+impl DerefMut for AllServicesMut {
+    fn deref_mut(&mut self) -> &mut AllServices {
+        &mut self.services
+    }
+}
+
+impl Drop for AllServicesMut {
+    fn drop(&mut self) {
+        let services = &mut self.services.0;
+        drop(services.synced_index());
+        let index = services.index.lock().unwrap();
+        self.shards.components = components(services, &index);
+        drop(index);
+        let slots = std::mem::take(&mut services.slots);
+        self.shards
+            .services
+            .retain(|name, _| slots.contains_key(name));
+        for (name, slot) in slots {
+            if let Slot::Owned(si) = slot {
+                self.shards
+                    .services
+                    .insert(name, Arc::new(RwLock::new(*si)));
+            }
+        }
+    }
+}
+
+/// The edges of the services, each being the entries of a client on the replicas of a service.
+#[derive(Default)]
+struct EdgeIndex {
+    /// The edges of each client, by the service they reach.
+    by_client: HashMap<Client, BTreeMap<String, IndexedEdge>>,
+    /// The edges used by each chain, as `(client, service)`.
+    by_chain: HashMap<ChainContext, BTreeSet<(Client, String)>>,
+    /// The edges established over each network, as `(client, service)`.
+    by_net: HashMap<u32, BTreeSet<(Client, String)>>,
+    /// The clients with an edge to each service.
+    by_service: HashMap<String, BTreeSet<Client>>,
+}
+
+#[derive(Default)]
+struct IndexedEdge {
+    net_ids: BTreeSet<u32>,
+    chains: BTreeSet<ChainContext>,
+}
+
+impl IndexedEdge {
+    fn of(si: &ServiceInfo, client: &Client) -> Option<Self> {
+        let ServiceInfo::Registered(reg) = si else {
+            return None;
+        };
+        let mut edge: Option<Self> = None;
+        for client_info in reg.entries_of(client) {
+            let edge = edge.get_or_insert_default();
+            if !client_info.is_placeholder() {
+                edge.net_ids.insert(client_info.net_id());
+            }
+            edge.chains.extend(client_info.chains());
+        }
+        edge
+    }
+}
+
+impl EdgeIndex {
+    /// Index again the edge of `client` to the service `name`.
+    fn update(&mut self, name: &str, client: &Client, si: &ServiceInfo) {
+        self.set(name, client, IndexedEdge::of(si, client));
+    }
+
+    /// Drop all the edges to the service `name`.
+    fn remove_service(&mut self, name: &str) {
+        for client in self.by_service.get(name).cloned().unwrap_or_default() {
+            self.set(name, &client, None);
+        }
+    }
+
+    fn set(&mut self, name: &str, client: &Client, edge: Option<IndexedEdge>) {
+        let key = (client.clone(), name.to_string());
+        if let Some(old) = self
+            .by_client
+            .get_mut(client)
+            .and_then(|edges| edges.remove(name))
+        {
+            for chain in &old.chains {
+                remove_from(&mut self.by_chain, chain, &key);
+            }
+            for net_id in &old.net_ids {
+                remove_from(&mut self.by_net, net_id, &key);
+            }
+        }
+
+        let Some(edge) = edge else {
+            if self.by_client.get(client).is_some_and(BTreeMap::is_empty) {
+                self.by_client.remove(client);
+            }
+            remove_from(&mut self.by_service, name, client);
+            return;
+        };
+        for chain in &edge.chains {
+            self.by_chain
+                .entry(chain.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        for net_id in &edge.net_ids {
+            self.by_net.entry(*net_id).or_default().insert(key.clone());
+        }
+        self.by_service
+            .entry(name.to_string())
+            .or_default()
+            .insert(client.clone());
+        self.by_client
+            .entry(client.clone())
+            .or_default()
+            .insert(name.to_string(), edge);
+    }
+}
+
+fn remove_from<K, Q, V>(map: &mut HashMap<K, BTreeSet<V>>, key: &Q, value: &V)
+where
+    K: Borrow<Q> + Hash + Eq,
+    Q: Hash + Eq + ?Sized,
+    V: Ord,
+{
+    if let Some(values) = map.get_mut(key) {
+        values.remove(value);
+        if values.is_empty() {
+            map.remove(key);
+        }
+    }
+}
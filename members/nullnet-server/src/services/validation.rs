@@ -0,0 +1,297 @@
+//! Checks of the content of `services.toml`, run before a file is loaded.
+//!
+//! Errors reject the file (the services already loaded are kept), while
+//! warnings point at settings that are accepted but likely unintended.
+
+use crate::services::input::ServicesToml;
+use crate::services::load_balancing::LoadBalancing;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+/// Trigger activity is reported by clients this often (in seconds), so shorter
+/// idle timeouts can tear down chains that are still in use.
+const ACTIVITY_REPORT_INTERVAL: u64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in `services.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct Issue {
+    pub(crate) severity: Severity,
+    /// The service the issue is about, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) service: Option<String>,
+    pub(crate) message: String,
+}
+
+impl Issue {
+    pub(crate) fn error(service: Option<&str>, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            service: service.map(String::from),
+            message,
+        }
+    }
+
+    fn warning(service: &str, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            service: Some(service.to_string()),
+            message,
+        }
+    }
+
+    pub(crate) fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match &self.service {
+            Some(service) => write!(f, "{severity}: '{service}': {}", self.message),
+            None => write!(f, "{severity}: {}", self.message),
+        }
+    }
+}
+
+/// A `services.toml` rejected by the watcher, and why.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Rejection {
+    pub(crate) timestamp: String,
+    pub(crate) content: String,
+    pub(crate) issues: Vec<Issue>,
+}
+
+/// Every issue of the `services.toml` content, errors first.
+pub(crate) fn validate(content: &str) -> Vec<Issue> {
+    let services_toml: ServicesToml = match toml::from_str(content) {
+        Ok(services_toml) => services_toml,
+        Err(e) => return vec![Issue::error(None, e.message().to_string())],
+    };
+
+    let mut issues = Vec::new();
+    let mut names = HashSet::new();
+    for service in services_toml.services() {
+        let name = service.name.as_str();
+        if !names.insert(name) {
+            issues.push(Issue::error(
+                Some(name),
+                "declared more than once".to_string(),
+            ));
+        }
+
+        // cycles, self-references, unreachable services, triggers fanning out at their root
+        if let Err(e) = service.clone().into_service_info() {
+            issues.push(Issue::error(Some(name), e));
+        }
+
+        let mut ports = HashSet::new();
+        for trigger in &service.triggers {
+            if !ports.insert(trigger.port) {
+                issues.push(Issue::error(
+                    Some(name),
+                    format!("trigger {} is declared more than once", trigger.port),
+                ));
+            }
+            if trigger.chain.is_empty() {
+                issues.push(Issue::error(
+                    Some(name),
+                    format!("trigger {} has no dependencies to bring up", trigger.port),
+                ));
+            }
+            if let Some(idle_timeout) = trigger.idle_timeout
+                && idle_timeout > 0
+                && idle_timeout <= ACTIVITY_REPORT_INTERVAL
+            {
+                issues.push(Issue::warning(
+                    name,
+                    format!(
+                        "idle_timeout of trigger {} ({idle_timeout} s) is not above the \
+                         {ACTIVITY_REPORT_INTERVAL} s traffic report interval, \
+                         the chain can be torn down while in use",
+                        trigger.port
+                    ),
+                ));
+            }
+        }
+
+        if service.max_networks == Some(0) {
+            issues.push(Issue::error(
+                Some(name),
+                "max_networks must be at least 1".to_string(),
+            ));
+        }
+
+        if let Some(warm_networks) = service.warm_networks
+            && warm_networks > 0
+        {
+            if let Some(max_networks) = service.max_networks
+                && warm_networks > max_networks
+            {
+                issues.push(Issue::error(
+                    Some(name),
+                    format!(
+                        "warm_networks ({warm_networks}) is above max_networks ({max_networks})"
+                    ),
+                ));
+            }
+            // warm networks are handed out regardless of the client
+            if service.load_balancing == LoadBalancing::ConsistentHash {
+                issues.push(Issue::error(
+                    Some(name),
+                    "warm_networks can't be combined with consistent_hash load balancing"
+                        .to_string(),
+                ));
+            }
+        }
+    }
+
+    issues.sort_by_key(|issue| !issue.is_error());
+    issues
+}
+
+/// Validate the file at `path` and print its issues.
+/// Returns whether the file can be loaded.
+pub(crate) async fn validate_file(path: &str) -> bool {
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("cannot read '{path}': {e}");
+            return false;
+        }
+    };
+    let issues = validate(&content);
+    for issue in &issues {
+        println!("{issue}");
+    }
+    let errors = issues.iter().filter(|issue| issue.is_error()).count();
+    let warnings = issues.len() - errors;
+    if errors == 0 {
+        println!("'{path}' is valid ({warnings} warning(s))");
+    } else {
+        println!("'{path}' is invalid: {errors} error(s), {warnings} warning(s)");
+    }
+    errors == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(content: &str) -> Vec<String> {
+        validate(content).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_valid_file_has_no_issues() {
+        let content = r#"
+[[services]]
+name = "A"
+proxy_dependencies = ["B", "C"]
+
+[[services.triggers]]
+port = 5555
+chain = ["D"]
+idle_timeout = 300
+"#;
+        assert!(validate(content).is_empty());
+    }
+
+    #[test]
+    fn test_dependency_errors() {
+        let content = r#"
+[[services]]
+name = "A"
+proxy_dependencies = ["B", "A"]
+
+[[services]]
+name = "B"
+proxy_dependencies = { B = ["C"], C = ["C"] }
+
+[[services]]
+name = "C"
+proxy_dependencies = { C = ["D"], E = ["F"] }
+
+[[services]]
+name = "A"
+"#;
+        assert_eq!(
+            messages(content),
+            [
+                "error: 'A': dependencies of 'A' contain a cycle: A -> B -> A",
+                "error: 'B': 'C' depends on itself",
+                "error: 'C': 'E' is not reachable from 'C' in its dependencies",
+                "error: 'A': declared more than once",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trigger_and_limit_issues() {
+        let content = r#"
+[[services]]
+name = "A"
+timeout = 0
+
+[[services.triggers]]
+port = 5555
+chain = ["B"]
+idle_timeout = 5
+
+[[services.triggers]]
+port = 5555
+chain = { A = ["B", "C"] }
+
+[[services.triggers]]
+port = 6666
+
+[[services]]
+name = "D"
+max_networks = 0
+
+[[services]]
+name = "E"
+max_networks = 2
+warm_networks = 3
+load_balancing = { strategy = "consistent_hash" }
+"#;
+        assert_eq!(
+            messages(content),
+            [
+                "error: 'A': trigger 5555 of 'A' must start with a single dependency",
+                "error: 'A': trigger 5555 is declared more than once",
+                "error: 'A': trigger 6666 has no dependencies to bring up",
+                "error: 'D': max_networks must be at least 1",
+                "error: 'E': warm_networks (3) is above max_networks (2)",
+                "error: 'E': warm_networks can't be combined with consistent_hash load balancing",
+                "warning: 'A': idle_timeout of trigger 5555 (5 s) is not above the 10 s traffic \
+                 report interval, the chain can be torn down while in use",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let issues = validate(
+            r#"
+[[services]]
+name = "A"
+proxy_dependecies = ["B"]
+"#,
+        );
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].is_error());
+        assert!(issues[0].message.contains("proxy_dependecies"));
+    }
+}
@@ -0,0 +1,346 @@
+use crate::orchestrator::Orchestrator;
+use crate::services::clients::{ChainContext, Client, ClientInfo};
+use crate::services::dep_graph::DepGraph;
+use crate::services::service_info::ServiceInfo;
+use crate::services::service_map::{ServiceMap, Services};
+use nullnet_liberror::{Error, ErrorHandler, Location, location};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Where the state snapshot is persisted, relative to the working directory.
+pub(crate) const STATE_PATH: &str = "./state.json";
+
+/// How long restored nodes have to reconnect before their chains are torn down.
+const RECONNECT_GRACE: Duration = Duration::from_mins(1);
+
+/// How long to wait for in-progress changes to the state before exiting without persisting it.
+const EXIT_PERSIST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Durable view of the orchestrator state: every registered replica with the
+/// client entries (and therefore the NET IDs) attached to it.
+///
+/// Persisted atomically whenever it changes so that a restarted server can rebuild
+/// `ServiceInfo::Registered` entries and reserve the NET IDs still live on the
+/// clients, instead of forgetting them and handing the same IDs out again.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub(crate) struct StateSnapshot {
+    replicas: Vec<ReplicaState>,
+}
+
+/// The replica and the client node (with its address) at the two ends of a network.
+type NetworkEnds = (IpAddr, Option<String>, IpAddr, Ipv4Addr);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ReplicaState {
+    service: String,
+    ip: IpAddr,
+    port: u16,
+    docker_container: Option<String>,
+    clients: Vec<(Client, ClientInfo)>,
+}
+
+impl StateSnapshot {
+    /// Capture the registered replicas and their established client entries.
+    /// Placeholders (setups still in flight) are skipped.
+    pub(crate) fn capture(services: &Services) -> Self {
+        let mut replicas: Vec<ReplicaState> = services
+            .iter()
+            .filter_map(|(name, si)| match si {
+                ServiceInfo::Registered(reg) => Some((name, reg)),
+                ServiceInfo::Unregistered(_) => None,
+            })
+            .flat_map(|(name, reg)| {
+                reg.replicas().iter().map(move |r| {
+                    let mut clients: Vec<(Client, ClientInfo)> = r
+                        .clients()
+                        .iter()
+                        .filter(|(_, ci)| !ci.is_placeholder())
+                        .map(|(c, ci)| (c.clone(), ci.clone()))
+                        .collect();
+                    clients.sort_by_key(|(c, ci)| (ci.net_id(), c.display_name()));
+                    ReplicaState {
+                        service: name.clone(),
+                        ip: r.ip(),
+                        port: r.port(),
+                        docker_container: r.docker_container().map(String::from),
+                        clients,
+                    }
+                })
+            })
+            .collect();
+        replicas.sort_by(|a, b| {
+            (&a.service, a.ip, &a.docker_container).cmp(&(&b.service, b.ip, &b.docker_container))
+        });
+        Self { replicas }
+    }
+
+    /// Rebuild registered replicas and client entries into `services` (as
+    /// loaded from the config file), reserving their NET IDs. Replicas of services
+    /// that are no longer configured are dropped, and so are the client entries whose
+    /// NET ID can't be reserved (outside the pool, or used by another network in the snapshot).
+    /// Returns the NET IDs of the restored entries.
+    pub(crate) async fn restore(
+        self,
+        services: &mut Services,
+        orchestrator: &Orchestrator,
+    ) -> BTreeSet<u32> {
+        // entries sharing a network (see `max_networks`) share its NET ID
+        let mut networks: HashMap<u32, NetworkEnds> = HashMap::new();
+        // entries of snapshots predating chain contexts
+        let mut without_chains = HashSet::new();
+        for replica in self.replicas {
+            let Some(si) = services.get_mut(&replica.service) else {
+                println!(
+                    "Dropping persisted replica of '{}': service no longer configured",
+                    replica.service
+                );
+                continue;
+            };
+            si.add_replica(replica.ip, replica.port, replica.docker_container.clone());
+            let ServiceInfo::Registered(reg) = si else {
+                continue;
+            };
+            for (client, client_info) in replica.clients {
+                let net_id = client_info.net_id();
+                let ends = (
+                    replica.ip,
+                    replica.docker_container.clone(),
+                    client_info.client_ip(),
+                    client_info.server_net(),
+                );
+                let reserved = match networks.get(&net_id) {
+                    Some(network) => *network == ends,
+                    None => orchestrator.reserve_net_id(net_id).await,
+                };
+                if !reserved {
+                    eprintln!(
+                        "Dropping persisted client '{}' of '{}': NET ID {net_id} is out of range or already in use",
+                        client.display_name(),
+                        replica.service
+                    );
+                    continue;
+                }
+                networks.insert(net_id, ends);
+                if client_info.chains().is_empty() {
+                    without_chains.insert((replica.service.clone(), client.clone()));
+                }
+                reg.add_client_to_replica(
+                    replica.ip,
+                    replica.docker_container.as_deref(),
+                    client,
+                    client_info,
+                );
+            }
+        }
+        adopt_chains(services, &without_chains);
+        networks.into_keys().collect()
+    }
+
+    /// IPs of every node referenced by the snapshot (replica hosts and client hosts).
+    pub(crate) fn node_ips(&self) -> HashSet<IpAddr> {
+        self.replicas
+            .iter()
+            .flat_map(|r| {
+                std::iter::once(r.ip).chain(r.clients.iter().map(|(_, ci)| ci.client_ip()))
+            })
+            .collect()
+    }
+
+    /// IPs of the proxy nodes of the proxy clients in the snapshot.
+    pub(crate) fn proxy_ips(&self) -> HashSet<IpAddr> {
+        self.replicas
+            .iter()
+            .flat_map(|r| r.clients.iter().filter_map(|(c, _)| c.is_proxy()))
+            .collect()
+    }
+
+    pub(crate) async fn load(path: &str) -> Result<Option<Self>, Error> {
+        match tokio::fs::read_to_string(path).await {
+            Ok(content) => Ok(Some(
+                serde_json::from_str(&content).handle_err(location!())?,
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).handle_err(location!()),
+        }
+    }
+
+    /// Write the snapshot to a temporary file and rename it over `path`,
+    /// so a crash mid-write never leaves a truncated state file behind.
+    pub(crate) async fn persist(&self, path: &str) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(self).handle_err(location!())?;
+        let tmp_path = format!("{path}.tmp");
+        tokio::fs::write(&tmp_path, content)
+            .await
+            .handle_err(location!())?;
+        tokio::fs::rename(&tmp_path, path)
+            .await
+            .handle_err(location!())?;
+        Ok(())
+    }
+}
+
+/// Give the entries `without_chains`, restored from snapshots predating chain contexts,
+/// the chains using them: one proxy chain per proxy client, and one backend chain per
+/// initiator replica and trigger port whose first edge is set up, found by walking
+/// their graphs the way `build_chain` placed them.
+fn adopt_chains(services: &mut Services, without_chains: &HashSet<(String, Client)>) {
+    if without_chains.is_empty() {
+        return;
+    }
+    let mut adopted: Vec<(String, Client, ChainContext)> = Vec::new();
+    for (name, si) in &*services {
+        let ServiceInfo::Registered(reg) = si else {
+            continue;
+        };
+        for replica in reg.replicas() {
+            let (ip, docker) = (replica.ip(), replica.docker_container());
+            for client in replica.clients().keys() {
+                if client.is_proxy().is_none()
+                    || !without_chains.contains(&(name.clone(), client.clone()))
+                {
+                    continue;
+                }
+                let chain = ChainContext::proxy(name, ip, docker);
+                adopted.push((name.clone(), client.clone(), chain.clone()));
+                for (dep_client, dep) in graph_edges(si.proxy_deps(), ip, docker, services) {
+                    adopted.push((dep, dep_client, chain.clone()));
+                }
+            }
+            for (port, graph) in reg.triggers() {
+                let edges = graph_edges(graph, ip, docker, services);
+                let Some((first_client, first_dep)) = edges.first() else {
+                    continue;
+                };
+                if !without_chains.contains(&(first_dep.clone(), first_client.clone())) {
+                    continue;
+                }
+                let chain = ChainContext::backend(name, ip, docker, *port);
+                for (dep_client, dep) in edges {
+                    adopted.push((dep, dep_client, chain.clone()));
+                }
+            }
+        }
+    }
+
+    for (name, client, chain) in adopted {
+        if without_chains.contains(&(name.clone(), client.clone()))
+            && let Some(ServiceInfo::Registered(reg)) = services.get_mut(&name)
+        {
+            reg.adopt_chain(&client, &chain);
+        }
+    }
+}
+
+/// The `(client, dep_service_name)` edges of the chain set up along `graph`
+/// from the root's replica.
+///
+/// Each dep is followed on the replica it was first reached on, so the edges of a dep
+/// shared by several paths are listed once. Edges leaving a dep that wasn't reached are skipped.
+fn graph_edges(
+    graph: &DepGraph,
+    root_ip: IpAddr,
+    root_docker: Option<&str>,
+    services: &Services,
+) -> Vec<(Client, String)> {
+    let mut edges = Vec::new();
+    let mut reached: HashMap<&str, (IpAddr, Option<String>)> =
+        HashMap::from([(graph.root(), (root_ip, root_docker.map(String::from)))]);
+    for (from, dep) in graph.edges() {
+        let Some((ip, docker)) = reached.get(from).cloned() else {
+            continue;
+        };
+        let client = Client::new_service(from.to_string(), ip, docker);
+        if let Some(ServiceInfo::Registered(dep_reg)) = services.get(dep)
+            && let Some(hop) = dep_reg.client_replica(&client)
+        {
+            reached.entry(dep).or_insert(hop);
+        }
+        edges.push((client, dep.to_string()));
+    }
+    edges
+}
+
+/// Load the snapshot at `path`, if any. A snapshot that can't be loaded (e.g. corrupt
+/// or truncated) is moved aside to `<path>.corrupt`, and the server starts with an empty state.
+pub(crate) async fn load_or_set_aside(path: &str) -> Option<StateSnapshot> {
+    match StateSnapshot::load(path).await {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            let corrupt_path = format!("{path}.corrupt");
+            eprintln!(
+                "Could not load '{path}' ({}), moving it to '{corrupt_path}' and starting with an empty state",
+                e.to_str()
+            );
+            let _ = tokio::fs::rename(path, &corrupt_path)
+                .await
+                .handle_err(location!());
+            None
+        }
+    }
+}
+
+/// Restore the persisted state (if any) into `services`, reserving the NET IDs
+/// in use. Nodes that don't reconnect within `RECONNECT_GRACE` are handled as
+/// disconnected, tearing down the chains restored for them.
+pub(crate) async fn recover_state(services: &Arc<ServiceMap>, orchestrator: &Orchestrator) {
+    let Some(snapshot) = load_or_set_aside(STATE_PATH).await else {
+        return;
+    };
+
+    let node_ips = snapshot.node_ips();
+    for proxy_ip in snapshot.proxy_ips() {
+        orchestrator.add_proxy_node(proxy_ip).await;
+    }
+    let net_ids = snapshot
+        .restore(&mut *services.write().await, orchestrator)
+        .await;
+    println!(
+        "Recovered {} NET ID(s) across {} node(s) from '{STATE_PATH}'",
+        net_ids.len(),
+        node_ips.len()
+    );
+
+    let services = services.clone();
+    let orchestrator = orchestrator.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(RECONNECT_GRACE).await;
+        let connected: HashSet<IpAddr> = orchestrator
+            .connected_node_ips()
+            .await
+            .into_iter()
+            .collect();
+        for ip in node_ips.difference(&connected) {
+            println!("Node '{ip}' did not reconnect after restart");
+            orchestrator.handle_node_disconnect(*ip, &services).await;
+        }
+    });
+}
+
+/// Persist the state whenever replicas or client entries change.
+pub(crate) async fn persist_state(services: Arc<ServiceMap>, orchestrator: Orchestrator) {
+    let mut last_persisted = None;
+    loop {
+        // each service is copied under its own lock, not to hold up the changes to the others
+        let snapshot = StateSnapshot::capture(&*services.snapshot().await);
+        if last_persisted.as_ref() != Some(&snapshot) && snapshot.persist(STATE_PATH).await.is_ok()
+        {
+            last_persisted = Some(snapshot);
+        }
+
+        orchestrator.wait_state_changed().await;
+    }
+}
+
+/// Persist the current state before exiting, unless changes keep it locked for too long.
+pub(crate) async fn persist_on_exit(services: &ServiceMap) {
+    let Ok(services) = tokio::time::timeout(EXIT_PERSIST_TIMEOUT, services.read()).await else {
+        eprintln!(
+            "State still being changed after {EXIT_PERSIST_TIMEOUT:?}, exiting without persisting it"
+        );
+        return;
+    };
+    let _ = StateSnapshot::capture(&services).persist(STATE_PATH).await;
+}
@@ -1,12 +1,28 @@
 #![allow(non_snake_case)]
 
+use crate::audit::{AuditLog, AuditQuery};
+use crate::events::{Event, TopologyEvent};
 use crate::graphviz::render_graphviz;
 use crate::nullnet_grpc_impl::NullnetGrpcImpl;
-use crate::services::input::{ServicesToml, apply_config_update};
-use crate::services::service_info::ServiceInfo;
+use crate::services::changes::{
+    BackendChainImpact, ProxyClientImpact, ServiceChange, apply_changes, detect_client_eviction,
+    detect_net_eviction, detect_replica_draining,
+};
+use crate::services::clients::Client;
+use crate::services::config_store::{self, ConfigStore, ConfigWriteError};
+use crate::services::dep_graph::DepGraph;
+use crate::services::input::{ServicesToml, apply_config_update, preview_config_update};
+use crate::services::load_balancing::RandomTwoChoices;
+use crate::services::service_info::{Replica, ServiceInfo};
+use crate::services::service_map::Services;
+use crate::state::{StateSnapshot, load_or_set_aside};
 use crate::timeout::apply_timeouts;
+use nullnet_grpc_lib::nullnet_grpc::{
+    HeldNet, NackCode, NodeState, PortActivity, PortCounters, ReplicaHealth, ServiceHealth,
+};
 use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
 
 fn ip(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
     IpAddr::V4(Ipv4Addr::new(a, b, c, d))
@@ -74,7 +90,7 @@ async fn register_services(server: &NullnetGrpcImpl, ip_map: &HashMap<&str, IpAd
     }
 }
 
-fn assert_graphviz(services: &HashMap<String, ServiceInfo>, fixture: &str, expected_file: &str) {
+fn assert_graphviz(services: &Services, fixture: &str, expected_file: &str) {
     let actual = render_graphviz(services);
     let expected_path = fixture_path(fixture, expected_file);
 
@@ -213,6 +229,203 @@ async fn service_removed_remove_B() {
     assert!(guard.contains_key("D"));
 }
 
+// ===========================================================================
+// state_restored: service_removed topology persisted and restored into a
+// freshly started server (no nodes reconnected yet).
+// ===========================================================================
+
+/// Snapshot the live state, restart from the config file alone, restore.
+async fn state_restored_setup() -> NullnetGrpcImpl {
+    let server = service_removed_setup().await;
+    let snapshot = StateSnapshot::capture(&*server.services().read().await);
+    let json = serde_json::to_string(&snapshot).expect("failed to serialize state");
+    let snapshot: StateSnapshot = serde_json::from_str(&json).expect("failed to parse state");
+
+    let restarted = NullnetGrpcImpl::new_for_test(load_fixture(SERVICE_REMOVED).await);
+    snapshot
+        .restore(
+            &mut *restarted.services().write().await,
+            restarted.orchestrator(),
+        )
+        .await;
+
+    restarted
+}
+
+/// Restored topology and NET IDs match the ones before the restart.
+#[tokio::test]
+async fn state_restored_matches_snapshot() {
+    let server = state_restored_setup().await;
+
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, SERVICE_REMOVED, "start.dot");
+    drop(guard);
+
+    assert_net_ids_in_use(&server, 6).await;
+    // restored IDs are never handed out again
+    assert_eq!(server.orchestrator().allocate_net_id().await, Some(107));
+}
+
+/// Restored chains are torn down like live ones: removing A frees A's chains.
+#[tokio::test]
+async fn state_restored_remove_A() {
+    let server = state_restored_setup().await;
+    let new_config = load_config(SERVICE_REMOVED, "remove_A.toml").await;
+
+    let mut guard = server.services().write().await;
+    apply_config_update(&mut guard, new_config, server.orchestrator()).await;
+    assert_graphviz(&guard, SERVICE_REMOVED, "after_remove_A.dot");
+    drop(guard);
+
+    assert_net_ids_in_use(&server, 2).await;
+}
+
+/// Snapshots predating the chains of the edges get them back on restore:
+/// removing A still frees A's chains.
+#[tokio::test]
+async fn state_restored_legacy_remove_A() {
+    let server = service_removed_setup().await;
+    let snapshot = StateSnapshot::capture(&*server.services().read().await);
+    let mut json = serde_json::to_value(&snapshot).expect("failed to serialize state");
+    for client_info in json["replicas"]
+        .as_array_mut()
+        .unwrap()
+        .iter_mut()
+        .flat_map(|r| r["clients"].as_array_mut().unwrap().iter_mut())
+        .map(|pair| &mut pair[1])
+    {
+        client_info.as_object_mut().unwrap().remove("chains");
+    }
+    let snapshot: StateSnapshot = serde_json::from_value(json).expect("failed to parse state");
+
+    let restarted = NullnetGrpcImpl::new_for_test(load_fixture(SERVICE_REMOVED).await);
+    snapshot
+        .restore(
+            &mut *restarted.services().write().await,
+            restarted.orchestrator(),
+        )
+        .await;
+    let new_config = load_config(SERVICE_REMOVED, "remove_A.toml").await;
+
+    let mut guard = restarted.services().write().await;
+    apply_config_update(&mut guard, new_config, restarted.orchestrator()).await;
+    assert_graphviz(&guard, SERVICE_REMOVED, "after_remove_A.dot");
+    drop(guard);
+
+    assert_net_ids_in_use(&restarted, 2).await;
+}
+
+/// Entries reusing the NET ID of another network, or one outside the pool,
+/// are dropped instead of being restored.
+#[tokio::test]
+async fn state_restored_conflicting_net_ids() {
+    let server = service_removed_setup().await;
+    let snapshot = StateSnapshot::capture(&*server.services().read().await);
+    let mut json = serde_json::to_value(&snapshot).expect("failed to serialize state");
+    // client entries are persisted as `[client, client_info]` pairs
+    let mut client_infos: Vec<&mut serde_json::Value> = json["replicas"]
+        .as_array_mut()
+        .unwrap()
+        .iter_mut()
+        .flat_map(|r| r["clients"].as_array_mut().unwrap().iter_mut())
+        .map(|pair| &mut pair[1])
+        .collect();
+    let reused_net_id = client_infos[0]["net_id"].clone();
+    client_infos[1]["net_id"] = reused_net_id;
+    client_infos[2]["net_id"] = 1.into();
+    let snapshot: StateSnapshot = serde_json::from_value(json).expect("failed to parse state");
+
+    let restarted = NullnetGrpcImpl::new_for_test(load_fixture(SERVICE_REMOVED).await);
+    let net_ids = snapshot
+        .restore(
+            &mut *restarted.services().write().await,
+            restarted.orchestrator(),
+        )
+        .await;
+
+    assert_eq!(net_ids.len(), 4);
+    assert_net_ids_in_use(&restarted, 4).await;
+}
+
+/// A state file that can't be parsed is moved aside, starting with an empty state.
+#[tokio::test]
+async fn state_corrupt_file_set_aside() {
+    let dir = std::env::temp_dir().join(format!("nullnet-state-{}", std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    let path = dir.join("state.json");
+    let path = path.to_str().unwrap();
+    tokio::fs::write(path, r#"{"replicas": [{"service": "A""#)
+        .await
+        .unwrap();
+
+    assert!(load_or_set_aside(path).await.is_none());
+    assert!(!tokio::fs::try_exists(path).await.unwrap());
+    assert!(
+        tokio::fs::try_exists(format!("{path}.corrupt"))
+            .await
+            .unwrap()
+    );
+    // nothing to set aside the second time
+    assert!(load_or_set_aside(path).await.is_none());
+}
+
+/// NET ID of the proxy edge `proxy_ip` → `service`.
+async fn proxy_net_id(server: &NullnetGrpcImpl, service: &str, proxy_ip: IpAddr) -> u32 {
+    let guard = server.services().read().await;
+    let ServiceInfo::Registered(reg) = &guard[service] else {
+        panic!("{service} should be registered");
+    };
+    reg.all_clients_owned()
+        .into_iter()
+        .find(|(c, _, _, _)| c.is_proxy() == Some(proxy_ip))
+        .map(|(_, ci, _, _)| ci.net_id())
+        .expect("proxy edge not found")
+}
+
+/// proxy1 reconnects after the restart holding proxy1→B and a network the
+/// server never heard of, but not proxy1→A. The unknown network is torn down
+/// on proxy1, proxy1→A's chain is torn down, everything else is re-adopted.
+#[tokio::test]
+async fn state_restored_proxy1_reconciled() {
+    let server = state_restored_setup().await;
+    let proxy1 = ip(5, 5, 5, 5);
+    let torn_down = server
+        .orchestrator()
+        .register_fake_client_recording(proxy1)
+        .await;
+
+    let net_to_A = proxy_net_id(&server, "A", proxy1).await;
+    let net_to_B = proxy_net_id(&server, "B", proxy1).await;
+    let node_state = NodeState {
+        nets: vec![
+            HeldNet {
+                net_id: net_to_B,
+                ..Default::default()
+            },
+            HeldNet {
+                net_id: 999,
+                ..Default::default()
+            },
+        ],
+    };
+    server
+        .orchestrator()
+        .reconcile_node_state(proxy1, node_state, server.services())
+        .await;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, SERVICE_REMOVED, "after_lost_proxy1_A.dot");
+    drop(guard);
+
+    // proxy1→A freed; A→C and C→D still used by proxy2's chain = 5 IDs
+    assert_net_ids_in_use(&server, 5).await;
+    let mut torn_down = torn_down.lock().unwrap().clone();
+    torn_down.sort_unstable();
+    assert_eq!(torn_down, vec![net_to_A, 999]);
+}
+
 // ===========================================================================
 // dep_changed: A→B→C, D→C (C shared). proxy1→A+D, proxy2→A
 // ===========================================================================
@@ -257,10 +470,7 @@ async fn dep_changed_add_E_to_A() {
     apply_config_update(&mut guard, new_config, server.orchestrator()).await;
     assert_graphviz(&guard, DEP_CHANGED, "after_add_E_to_A.dot");
 
-    assert_eq!(
-        guard["A"].proxy_deps(),
-        vec!["B".to_string(), "C".to_string(), "E".to_string()]
-    );
+    assert_eq!(guard["A"].proxy_deps().services(), ["B", "C", "E"]);
     assert!(guard.contains_key("E"));
     assert!(matches!(guard["E"], ServiceInfo::Unregistered(_)));
 }
@@ -277,7 +487,7 @@ async fn dep_changed_drop_C_from_A() {
     assert_graphviz(&guard, DEP_CHANGED, "after_drop_C_from_A.dot");
 
     assert!(guard.contains_key("A"));
-    assert_eq!(guard["A"].proxy_deps(), vec!["B".to_string()]);
+    assert_eq!(guard["A"].proxy_deps().services(), ["B"]);
     assert!(guard.contains_key("C"));
 }
 
@@ -292,7 +502,7 @@ async fn dep_changed_drop_all_from_D() {
     apply_config_update(&mut guard, new_config, server.orchestrator()).await;
     assert_graphviz(&guard, DEP_CHANGED, "after_drop_all_from_D.dot");
 
-    assert!(guard["D"].proxy_deps().is_empty());
+    assert!(guard["D"].proxy_deps().services().is_empty());
     assert!(guard.contains_key("C"));
 }
 
@@ -307,10 +517,7 @@ async fn dep_changed_swap_C_for_E() {
     apply_config_update(&mut guard, new_config, server.orchestrator()).await;
     assert_graphviz(&guard, DEP_CHANGED, "after_swap_C_for_E.dot");
 
-    assert_eq!(
-        guard["A"].proxy_deps(),
-        vec!["B".to_string(), "E".to_string()]
-    );
+    assert_eq!(guard["A"].proxy_deps().services(), ["B", "E"]);
     assert!(guard.contains_key("E"));
     assert!(matches!(guard["E"], ServiceInfo::Unregistered(_)));
 }
@@ -351,8 +558,8 @@ async fn reachability_changed_setup() -> NullnetGrpcImpl {
 }
 
 /// B becomes unreachable (loses its [[services]] entry). B's own proxy chain
-/// (proxy2→B) is torn down, but A's chain survives because B's deps are
-/// correctly inferred from A's dependency list. D→E also survives.
+/// (proxy2→B) is torn down, but A's chain survives because the deps after B
+/// come from A's dependency list. D→E also survives.
 #[tokio::test]
 async fn reachability_changed_unreachable_B() {
     let server = reachability_changed_setup().await;
@@ -1706,6 +1913,40 @@ async fn max_networks_reuse_lifecycle() {
     assert_net_ids_in_use(&server, 0).await;
 }
 
+/// At `max_networks`, new clients don't share the network of a drained replica.
+#[tokio::test]
+async fn max_networks_reuse_skips_drained_replica() {
+    let server = NullnetGrpcImpl::new_for_test(load_fixture(MAX_NETWORKS).await);
+    let ip_map = HashMap::from([("A", ip(1, 1, 1, 1)), ("B", ip(2, 2, 2, 2))]);
+    let proxy1 = ip(5, 5, 5, 5);
+    register_services(&server, &ip_map, 8080).await;
+    server.orchestrator().register_fake_client(proxy1).await;
+    setup_proxy_chain(&server, "A", proxy1, "10.0.0.1").await;
+
+    let mut guard = server.services().write().await;
+    let change = detect_replica_draining(&guard, "A", ip(1, 1, 1, 1), None, true).unwrap();
+    apply_changes(vec![change], &mut guard, server.orchestrator()).await;
+    drop(guard);
+
+    assert!(
+        server
+            .handle_proxy_request("A", proxy1, "10.0.0.2")
+            .await
+            .is_err()
+    );
+    let guard = server.services().read().await;
+    let ServiceInfo::Registered(reg_a) = &guard["A"] else {
+        panic!("A should be registered");
+    };
+    assert_eq!(
+        reg_a.client_count(),
+        1,
+        "A should keep its only proxy client"
+    );
+    drop(guard);
+    assert_net_ids_in_use(&server, 2).await;
+}
+
 /// Proxy disconnect tears down both clients sharing a net_id at once.
 /// The shared network should be torn down exactly once (dedup).
 #[tokio::test]
@@ -1811,6 +2052,54 @@ async fn max_networks_different_proxy_bypasses() {
     }
 }
 
+/// A node rejecting its network setups makes the chain fail right away
+/// (instead of after the acknowledgement timeout), rolls back every NET ID,
+/// and records the rejection reason.
+#[tokio::test]
+async fn max_networks_setup_rejected() {
+    let services = load_fixture(MAX_NETWORKS).await;
+    let server = NullnetGrpcImpl::new_for_test(services);
+
+    let a_ip = ip(1, 1, 1, 1);
+    let ip_map = HashMap::from([("A", a_ip), ("B", ip(2, 2, 2, 2))]);
+    let proxy1 = ip(5, 5, 5, 5);
+    register_services(&server, &ip_map, 8080).await;
+    server.orchestrator().register_fake_client(proxy1).await;
+    server
+        .orchestrator()
+        .register_fake_client_nacking(a_ip, NackCode::SetupFailed)
+        .await;
+
+    let init_t = std::time::Instant::now();
+    let res = server.handle_proxy_request("A", proxy1, "10.0.0.1").await;
+    assert!(res.is_err(), "chain setup should fail");
+    assert!(
+        init_t.elapsed() < std::time::Duration::from_secs(5),
+        "rejection should not wait for the acknowledgement timeout"
+    );
+    assert_net_ids_in_use(&server, 0).await;
+
+    let guard = server.services().read().await;
+    assert!(
+        guard.values().all(|si| match si {
+            ServiceInfo::Registered(reg) => !reg.has_clients(),
+            ServiceInfo::Unregistered(_) => true,
+        }),
+        "no client entry should survive the rollback"
+    );
+    drop(guard);
+
+    let failures = server.orchestrator().setup_failures().await;
+    assert!(!failures.is_empty(), "the rejection should be recorded");
+    assert!(failures.iter().all(|f| f.node == a_ip));
+    assert!(
+        failures
+            .iter()
+            .all(|f| f.reason.contains("SETUP_FAILED")
+                && f.reason.contains("bridge creation failed"))
+    );
+}
+
 // ===========================================================================
 // triggers_changed: A entry-point with proxy_dependencies=["B"] and
 // triggers=[{5555, ["C"]}]; D entry-point with triggers=[{6666, ["C"]}].
@@ -1875,8 +2164,8 @@ async fn triggers_changed_swap_A_trigger() {
     apply_config_update(&mut guard, new_config, server.orchestrator()).await;
     assert_graphviz(&guard, TRIGGERS_CHANGED, "after_swap_A_trigger.dot");
     assert_eq!(
-        guard["A"].triggers().get(&5555),
-        Some(&vec!["D".to_string()])
+        guard["A"].triggers().get(&5555).map(DepGraph::services),
+        Some(vec!["D"])
     );
     drop(guard);
 
@@ -2385,3 +2674,1652 @@ async fn backend_multi_replica_disconnect_swarm_host() {
     // A→B and E→B freed; D→B survives = 1
     assert_net_ids_in_use(&server, 1).await;
 }
+
+// ===========================================================================
+// backend_idle_timeout: A entry-point with triggers=[{5555, ["B","C"],
+// idle_timeout=1}, {6666, ["C"]}]. A→B→C and A→C (backend chains).
+// ===========================================================================
+
+const BACKEND_IDLE_TIMEOUT: &str = "backend_idle_timeout";
+
+async fn backend_idle_timeout_setup() -> NullnetGrpcImpl {
+    let services = load_fixture(BACKEND_IDLE_TIMEOUT).await;
+    let server = NullnetGrpcImpl::new_for_test(services);
+
+    let ip_map = HashMap::from([
+        ("A", ip(1, 1, 1, 1)),
+        ("B", ip(2, 2, 2, 2)),
+        ("C", ip(3, 3, 3, 3)),
+    ]);
+    register_services(&server, &ip_map, 8080).await;
+
+    trigger_backend_chain(&server, "A", ip(1, 1, 1, 1), 5555).await;
+    trigger_backend_chain(&server, "A", ip(1, 1, 1, 1), 6666).await;
+
+    // A→B, B→C, A→C = 3 IDs
+    assert_net_ids_in_use(&server, 3).await;
+
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, BACKEND_IDLE_TIMEOUT, "start.dot");
+    drop(guard);
+
+    server
+}
+
+fn port_activity(port: u32) -> PortActivity {
+    container_activity(port, None)
+}
+
+fn container_activity(port: u32, docker_container: Option<&str>) -> PortActivity {
+    PortActivity {
+        ports: vec![PortCounters {
+            port,
+            packets: 10,
+            bytes: 1500,
+            docker_container: docker_container.map(String::from),
+        }],
+    }
+}
+
+/// No traffic on 5555 for its idle timeout: only that chain comes down.
+/// A→C survives (the 6666 trigger has no idle timeout), and the next
+/// trigger rebuilds the idle chain.
+#[tokio::test]
+async fn backend_idle_timeout_expires() {
+    let server = backend_idle_timeout_setup().await;
+
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+    let mut guard = server.services().write().await;
+    apply_timeouts(&mut guard, server.orchestrator()).await;
+    assert_graphviz(&guard, BACKEND_IDLE_TIMEOUT, "after_idle.dot");
+    drop(guard);
+
+    // A→B, B→C freed; A→C survives = 1 ID
+    assert_net_ids_in_use(&server, 1).await;
+
+    trigger_backend_chain(&server, "A", ip(1, 1, 1, 1), 5555).await;
+    assert_net_ids_in_use(&server, 3).await;
+}
+
+/// Traffic reported by the initiator's host on 5555 keeps the chain up;
+/// reports from other hosts or on other ports don't.
+#[tokio::test]
+async fn backend_idle_timeout_refreshed_by_activity() {
+    let server = backend_idle_timeout_setup().await;
+
+    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+    let orchestrator = server.orchestrator();
+    orchestrator
+        .record_port_activity(ip(1, 1, 1, 1), &port_activity(5555), server.services())
+        .await;
+    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+
+    let mut guard = server.services().write().await;
+    apply_timeouts(&mut guard, orchestrator).await;
+    assert_graphviz(&guard, BACKEND_IDLE_TIMEOUT, "start.dot");
+    drop(guard);
+    assert_net_ids_in_use(&server, 3).await;
+
+    orchestrator
+        .record_port_activity(ip(2, 2, 2, 2), &port_activity(5555), server.services())
+        .await;
+    orchestrator
+        .record_port_activity(ip(1, 1, 1, 1), &port_activity(6666), server.services())
+        .await;
+    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+
+    let mut guard = server.services().write().await;
+    apply_timeouts(&mut guard, orchestrator).await;
+    assert_graphviz(&guard, BACKEND_IDLE_TIMEOUT, "after_idle.dot");
+    drop(guard);
+    assert_net_ids_in_use(&server, 1).await;
+}
+
+/// Two containers of A on the same host each initiate a chain on 5555:
+/// traffic reported for one of them doesn't keep the other alive.
+#[tokio::test]
+async fn backend_idle_timeout_per_container() {
+    let services = load_fixture(BACKEND_IDLE_TIMEOUT).await;
+    let server = NullnetGrpcImpl::new_for_test(services);
+    {
+        let mut services = server.services().write().await;
+        for container in ["a1", "a2"] {
+            services.get_mut("A").unwrap().add_replica(
+                ip(1, 1, 1, 1),
+                8080,
+                Some(container.into()),
+            );
+        }
+    }
+    let ip_map = HashMap::from([("B", ip(2, 2, 2, 2)), ("C", ip(3, 3, 3, 3))]);
+    register_services(&server, &ip_map, 8080).await;
+    server
+        .orchestrator()
+        .register_fake_client(ip(1, 1, 1, 1))
+        .await;
+
+    for container in ["a1", "a2"] {
+        setup_backend_chain_for_replica(&server, "A", ip(1, 1, 1, 1), Some(container), 5555).await;
+    }
+
+    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+    let orchestrator = server.orchestrator();
+    orchestrator
+        .record_port_activity(
+            ip(1, 1, 1, 1),
+            &container_activity(5555, Some("a1")),
+            server.services(),
+        )
+        .await;
+    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+
+    let guard = server.services().read().await;
+    let ServiceInfo::Registered(reg_a) = &guard["A"] else {
+        panic!("A should be registered");
+    };
+    assert_eq!(
+        reg_a.idle_backend_chains(),
+        vec![(ip(1, 1, 1, 1), Some("a2".to_string()), 5555)]
+    );
+}
+
+// ===========================================================================
+// load_balancing: one service per strategy.
+//   A: round_robin, E→B with B weighted (3 on 2.2.2.2, 1 on 4.4.4.4),
+//   C: consistent_hash, D: random_two_choices
+// ===========================================================================
+
+const LOAD_BALANCING: &str = "load_balancing";
+
+async fn load_balancing_setup(replicas: &[(&str, IpAddr)]) -> NullnetGrpcImpl {
+    let services = load_fixture(LOAD_BALANCING).await;
+    let server = NullnetGrpcImpl::new_for_test(services);
+
+    let mut guard = server.services().write().await;
+    for (name, replica_ip) in replicas {
+        guard
+            .get_mut(name)
+            .unwrap()
+            .add_replica(*replica_ip, 8080, None);
+    }
+    drop(guard);
+    let unique_ips: HashSet<_> = replicas.iter().map(|(_, ip)| *ip).collect();
+    for replica_ip in unique_ips {
+        server.orchestrator().register_fake_client(replica_ip).await;
+    }
+
+    server
+}
+
+/// Replica hosting the client entry of `client_ip` (reached through `proxy_ip`) on `service`.
+async fn proxy_client_replica(
+    server: &NullnetGrpcImpl,
+    service: &str,
+    proxy_ip: IpAddr,
+    client_ip: &str,
+) -> IpAddr {
+    let guard = server.services().read().await;
+    let ServiceInfo::Registered(reg) = &guard[service] else {
+        panic!("{service} should be registered");
+    };
+    let client = crate::services::clients::Client::new(client_ip.to_string(), Some(proxy_ip));
+    reg.client_replica(&client)
+        .expect("client should be set up")
+        .0
+}
+
+/// Round-robin: new proxy clients go to A's replicas in turn, regardless of load.
+#[tokio::test]
+async fn load_balancing_round_robin() {
+    let replicas = [ip(1, 1, 1, 1), ip(1, 1, 1, 2), ip(1, 1, 1, 3)];
+    let server = load_balancing_setup(&replicas.map(|r| ("A", r))).await;
+    let proxy1 = ip(9, 9, 9, 9);
+    server.orchestrator().register_fake_client(proxy1).await;
+
+    let clients = ["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.4"];
+    for client in clients {
+        setup_proxy_chain(&server, "A", proxy1, client).await;
+    }
+
+    let mut placed = Vec::new();
+    for client in clients {
+        placed.push(proxy_client_replica(&server, "A", proxy1, client).await);
+    }
+    assert_eq!(placed, [replicas[0], replicas[1], replicas[2], replicas[0]]);
+}
+
+/// Weighted: the dep hop from each of E's replicas lands on B's replicas
+/// proportionally to their weights (3:1).
+#[tokio::test]
+async fn load_balancing_weighted_dep_hop() {
+    let e_replicas = [
+        ip(5, 5, 5, 1),
+        ip(5, 5, 5, 2),
+        ip(5, 5, 5, 3),
+        ip(5, 5, 5, 4),
+    ];
+    let mut replicas = e_replicas.map(|r| ("E", r)).to_vec();
+    replicas.extend([("B", ip(2, 2, 2, 2)), ("B", ip(4, 4, 4, 4))]);
+    let server = load_balancing_setup(&replicas).await;
+    let proxy1 = ip(9, 9, 9, 9);
+    server.orchestrator().register_fake_client(proxy1).await;
+
+    // least-clients on E: each proxy client gets its own E replica
+    for client in ["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.4"] {
+        setup_proxy_chain(&server, "E", proxy1, client).await;
+    }
+
+    let guard = server.services().read().await;
+    let ServiceInfo::Registered(reg_b) = &guard["B"] else {
+        panic!("B should be registered");
+    };
+    let per_replica: HashMap<IpAddr, usize> = reg_b
+        .replicas()
+        .iter()
+        .map(|r| (r.ip(), r.clients().len()))
+        .collect();
+    assert_eq!(
+        per_replica,
+        HashMap::from([(ip(2, 2, 2, 2), 3), (ip(4, 4, 4, 4), 1)])
+    );
+}
+
+/// Consistent hashing: a client IP lands on the same replica whichever proxy
+/// it comes through, and removing another replica doesn't move it.
+#[tokio::test]
+async fn load_balancing_consistent_hash() {
+    let replicas = [ip(3, 3, 3, 1), ip(3, 3, 3, 2), ip(3, 3, 3, 3)];
+    let server = load_balancing_setup(&replicas.map(|r| ("C", r))).await;
+    let proxy1 = ip(9, 9, 9, 9);
+    let proxy2 = ip(8, 8, 8, 8);
+    server.orchestrator().register_fake_client(proxy1).await;
+    server.orchestrator().register_fake_client(proxy2).await;
+
+    let clients = ["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.4", "10.0.0.5"];
+    let mut placed = HashMap::new();
+    for client in clients {
+        setup_proxy_chain(&server, "C", proxy1, client).await;
+        setup_proxy_chain(&server, "C", proxy2, client).await;
+        let via_proxy1 = proxy_client_replica(&server, "C", proxy1, client).await;
+        let via_proxy2 = proxy_client_replica(&server, "C", proxy2, client).await;
+        assert_eq!(via_proxy1, via_proxy2, "{client} placed differently");
+        placed.insert(client, via_proxy1);
+    }
+
+    // drop a replica and set the same clients up again through a third proxy
+    let removed = replicas
+        .into_iter()
+        .find(|r| placed.values().any(|p| p != r))
+        .unwrap();
+    server
+        .services()
+        .write()
+        .await
+        .get_mut("C")
+        .unwrap()
+        .remove_replica(removed, None);
+    let proxy3 = ip(7, 7, 7, 7);
+    server.orchestrator().register_fake_client(proxy3).await;
+    for (client, before) in placed {
+        if before == removed {
+            continue;
+        }
+        setup_proxy_chain(&server, "C", proxy3, client).await;
+        let after = proxy_client_replica(&server, "C", proxy3, client).await;
+        assert_eq!(after, before, "{client} moved");
+    }
+}
+
+/// Random two choices: with a seeded selector, placements are reproducible.
+#[tokio::test]
+async fn load_balancing_random_two_choices_seeded() {
+    let replicas = [
+        ip(6, 6, 6, 1),
+        ip(6, 6, 6, 2),
+        ip(6, 6, 6, 3),
+        ip(6, 6, 6, 4),
+    ];
+    let clients = ["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.4", "10.0.0.5"];
+    let proxy1 = ip(9, 9, 9, 9);
+
+    let mut runs = Vec::new();
+    for _ in 0..2 {
+        let server = load_balancing_setup(&replicas.map(|r| ("D", r))).await;
+        server.orchestrator().register_fake_client(proxy1).await;
+        server
+            .services()
+            .write()
+            .await
+            .get_mut("D")
+            .unwrap()
+            .set_replica_selector(Arc::new(RandomTwoChoices::with_seed(7)));
+
+        let mut placed = Vec::new();
+        for client in clients {
+            setup_proxy_chain(&server, "D", proxy1, client).await;
+            placed.push(proxy_client_replica(&server, "D", proxy1, client).await);
+        }
+        runs.push(placed);
+    }
+    assert_eq!(runs[0], runs[1]);
+}
+
+// ===========================================================================
+// affinity: A→B with B preferring local replicas (zone label),
+//   E→C with C requiring a shared rack or zone
+// ===========================================================================
+
+const AFFINITY: &str = "affinity";
+
+/// Node labels as `(key, value)` pairs.
+type NodeLabels = &'static [(&'static str, &'static str)];
+
+/// Register `replicas` in order, each node declaring `labels`.
+async fn affinity_setup(replicas: &[(&str, IpAddr, NodeLabels)]) -> NullnetGrpcImpl {
+    let services = load_fixture(AFFINITY).await;
+    let server = NullnetGrpcImpl::new_for_test(services);
+
+    for (name, replica_ip, labels) in replicas {
+        server
+            .services()
+            .write()
+            .await
+            .get_mut(name)
+            .unwrap()
+            .add_replica(*replica_ip, 8080, None);
+        let labels: HashMap<String, String> = labels
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect();
+        server.set_node_labels(*replica_ip, &labels).await;
+        server
+            .orchestrator()
+            .register_fake_client(*replica_ip)
+            .await;
+    }
+    server
+        .orchestrator()
+        .register_fake_client(ip(9, 9, 9, 9))
+        .await;
+
+    server
+}
+
+/// IP of the replica of `service` holding the dep edge from `from`.
+async fn dep_replica(
+    server: &NullnetGrpcImpl,
+    service: &str,
+    from: &str,
+    from_ip: IpAddr,
+) -> IpAddr {
+    let guard = server.services().read().await;
+    let ServiceInfo::Registered(reg) = &guard[service] else {
+        panic!("{service} should be registered");
+    };
+    let client = crate::services::clients::Client::new_service(from.to_string(), from_ip, None);
+    reg.client_replica(&client)
+        .expect("dep edge should be set up")
+        .0
+}
+
+/// Preferred affinity: a replica on the previous hop's host wins, then one in
+/// its zone, even if least-clients alone would pick another one.
+#[tokio::test]
+async fn affinity_preferred_same_host_then_zone() {
+    let server = affinity_setup(&[
+        ("A", ip(1, 1, 1, 1), &[("zone", "a")]),
+        ("B", ip(3, 3, 3, 3), &[("zone", "b")]),
+        ("B", ip(2, 2, 2, 2), &[("zone", "a")]),
+        ("B", ip(1, 1, 1, 1), &[("zone", "a")]),
+    ])
+    .await;
+    setup_proxy_chain(&server, "A", ip(9, 9, 9, 9), "10.0.0.1").await;
+    assert_eq!(
+        dep_replica(&server, "B", "A", ip(1, 1, 1, 1)).await,
+        ip(1, 1, 1, 1)
+    );
+
+    let server = affinity_setup(&[
+        ("A", ip(1, 1, 1, 1), &[("zone", "a")]),
+        ("B", ip(3, 3, 3, 3), &[("zone", "b")]),
+        ("B", ip(2, 2, 2, 2), &[("zone", "a")]),
+    ])
+    .await;
+    setup_proxy_chain(&server, "A", ip(9, 9, 9, 9), "10.0.0.1").await;
+    assert_eq!(
+        dep_replica(&server, "B", "A", ip(1, 1, 1, 1)).await,
+        ip(2, 2, 2, 2)
+    );
+
+    // nothing local: falls back to any replica
+    let server = affinity_setup(&[
+        ("A", ip(1, 1, 1, 1), &[("zone", "a")]),
+        ("B", ip(3, 3, 3, 3), &[("zone", "b")]),
+    ])
+    .await;
+    setup_proxy_chain(&server, "A", ip(9, 9, 9, 9), "10.0.0.1").await;
+    assert_eq!(
+        dep_replica(&server, "B", "A", ip(1, 1, 1, 1)).await,
+        ip(3, 3, 3, 3)
+    );
+}
+
+/// Required affinity: labels are tried in order (rack before zone), and a
+/// chain with no close enough replica is refused.
+#[tokio::test]
+async fn affinity_required_refuses_remote_replicas() {
+    let server = affinity_setup(&[
+        ("E", ip(1, 1, 1, 1), &[("rack", "r1"), ("zone", "a")]),
+        ("C", ip(2, 2, 2, 2), &[("rack", "r2"), ("zone", "a")]),
+        ("C", ip(3, 3, 3, 3), &[("rack", "r1"), ("zone", "b")]),
+    ])
+    .await;
+    setup_proxy_chain(&server, "E", ip(9, 9, 9, 9), "10.0.0.1").await;
+    assert_eq!(
+        dep_replica(&server, "C", "E", ip(1, 1, 1, 1)).await,
+        ip(3, 3, 3, 3)
+    );
+
+    let server = affinity_setup(&[
+        ("E", ip(1, 1, 1, 1), &[("rack", "r1"), ("zone", "a")]),
+        ("C", ip(4, 4, 4, 4), &[("rack", "r4"), ("zone", "c")]),
+    ])
+    .await;
+    assert!(
+        server
+            .handle_proxy_request("E", ip(9, 9, 9, 9), "10.0.0.1")
+            .await
+            .is_err()
+    );
+    assert_net_ids_in_use(&server, 0).await;
+}
+
+// ===========================================================================
+// health_checks: A→B, B has replicas on 2.2.2.2 and 4.4.4.4
+// ===========================================================================
+
+const HEALTH_CHECKS: &str = "health_checks";
+
+fn service_health(service_name: &str, healthy: bool) -> ServiceHealth {
+    ServiceHealth {
+        replicas: vec![ReplicaHealth {
+            service_name: service_name.to_string(),
+            docker_container: None,
+            healthy,
+        }],
+    }
+}
+
+/// An unhealthy replica has the chains pointing at it torn down and gets no
+/// new ones until its node reports it healthy again.
+#[tokio::test]
+async fn health_check_unhealthy_replica_excluded() {
+    let services = load_fixture(HEALTH_CHECKS).await;
+    let server = NullnetGrpcImpl::new_for_test(services);
+    let a = ip(1, 1, 1, 1);
+    let (b1, b2) = (ip(2, 2, 2, 2), ip(4, 4, 4, 4));
+    let proxy1 = ip(9, 9, 9, 9);
+    {
+        let mut guard = server.services().write().await;
+        guard.get_mut("A").unwrap().add_replica(a, 8080, None);
+        guard.get_mut("B").unwrap().add_replica(b1, 8080, None);
+        guard.get_mut("B").unwrap().add_replica(b2, 8080, None);
+    }
+    for node in [a, b1, b2, proxy1] {
+        server.orchestrator().register_fake_client(node).await;
+    }
+
+    setup_proxy_chain(&server, "A", proxy1, "10.0.0.1").await;
+    assert_eq!(dep_replica(&server, "B", "A", a).await, b1);
+    assert_net_ids_in_use(&server, 2).await;
+
+    // a report from another node doesn't affect B on 2.2.2.2
+    server
+        .orchestrator()
+        .handle_service_health(b2, &service_health("A", false), server.services())
+        .await;
+    assert_net_ids_in_use(&server, 2).await;
+
+    server
+        .orchestrator()
+        .handle_service_health(b1, &service_health("B", false), server.services())
+        .await;
+    assert_net_ids_in_use(&server, 0).await;
+
+    setup_proxy_chain(&server, "A", proxy1, "10.0.0.1").await;
+    assert_eq!(dep_replica(&server, "B", "A", a).await, b2);
+
+    // healthy again: eligible for new chains, existing ones stay where they are
+    server
+        .orchestrator()
+        .handle_service_health(b1, &service_health("B", true), server.services())
+        .await;
+    let guard = server.services().read().await;
+    let ServiceInfo::Registered(reg) = &guard["B"] else {
+        panic!("B should be registered");
+    };
+    assert!(reg.replicas().iter().all(Replica::is_healthy));
+    assert_eq!(reg.client_count(), 1);
+}
+
+// ===========================================================================
+// dep_graph: A fans out to B and C, which share D; F→B→D shares B→D with A.
+// G's trigger reaches B, which fans out to C and D.
+// ===========================================================================
+
+const DEP_GRAPH: &str = "dep_graph";
+
+async fn dep_graph_setup() -> NullnetGrpcImpl {
+    let services = load_fixture(DEP_GRAPH).await;
+    let server = NullnetGrpcImpl::new_for_test(services);
+
+    let ip_map = HashMap::from([
+        ("A", ip(1, 1, 1, 1)),
+        ("B", ip(2, 2, 2, 2)),
+        ("C", ip(3, 3, 3, 3)),
+        ("D", ip(4, 4, 4, 4)),
+        ("F", ip(6, 6, 6, 6)),
+        ("G", ip(7, 7, 7, 7)),
+    ]);
+    register_services(&server, &ip_map, 8080).await;
+    server
+        .orchestrator()
+        .register_fake_client(ip(5, 5, 5, 5))
+        .await;
+    server
+        .orchestrator()
+        .register_fake_client(ip(8, 8, 8, 8))
+        .await;
+
+    server
+}
+
+/// Every edge of A's graph is brought up, the shared D once per path reaching it.
+/// B→D is shared with F's chain, so it outlives A's chain and goes away with F's.
+#[tokio::test]
+async fn dep_graph_shared_edge_outlives_one_chain() {
+    let server = dep_graph_setup().await;
+    let (proxy1, proxy2) = (ip(5, 5, 5, 5), ip(8, 8, 8, 8));
+
+    // proxy1→A, A→B, A→C, B→D, C→D
+    setup_proxy_chain(&server, "A", proxy1, "10.0.0.1").await;
+    assert_net_ids_in_use(&server, 5).await;
+    // proxy2→F, F→B (B→D reused)
+    setup_proxy_chain(&server, "F", proxy2, "10.0.0.2").await;
+    assert_net_ids_in_use(&server, 7).await;
+
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, DEP_GRAPH, "start.dot");
+    drop(guard);
+
+    server
+        .orchestrator()
+        .handle_node_disconnect(proxy1, server.services())
+        .await;
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, DEP_GRAPH, "after_disconnect_proxy1.dot");
+    drop(guard);
+    // proxy2→F, F→B, B→D survive
+    assert_net_ids_in_use(&server, 3).await;
+
+    server
+        .orchestrator()
+        .handle_node_disconnect(proxy2, server.services())
+        .await;
+    assert_net_ids_in_use(&server, 0).await;
+}
+
+/// A trigger graph fans out past its first dep, and comes down as a whole
+/// when one of the services it reaches goes away.
+#[tokio::test]
+async fn dep_graph_backend_trigger_fan_out() {
+    let server = dep_graph_setup().await;
+
+    // G→B, B→C, B→D
+    trigger_backend_chain(&server, "G", ip(7, 7, 7, 7), 5555).await;
+    assert_net_ids_in_use(&server, 3).await;
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, DEP_GRAPH, "backend_start.dot");
+    drop(guard);
+
+    server
+        .orchestrator()
+        .handle_node_disconnect(ip(3, 3, 3, 3), server.services())
+        .await;
+    assert_net_ids_in_use(&server, 0).await;
+}
+
+// ===========================================================================
+// scoped_tails: A→B→C, F→B→D (B leads to a different dep in each chain).
+// G's trigger at 5555 reaches G→B→C.
+// ===========================================================================
+
+const SCOPED_TAILS: &str = "scoped_tails";
+
+async fn scoped_tails_setup() -> NullnetGrpcImpl {
+    let services = load_fixture(SCOPED_TAILS).await;
+    let server = NullnetGrpcImpl::new_for_test(services);
+
+    let ip_map = HashMap::from([
+        ("A", ip(1, 1, 1, 1)),
+        ("B", ip(2, 2, 2, 2)),
+        ("C", ip(3, 3, 3, 3)),
+        ("D", ip(4, 4, 4, 4)),
+        ("F", ip(6, 6, 6, 6)),
+        ("G", ip(7, 7, 7, 7)),
+    ]);
+    register_services(&server, &ip_map, 8080).await;
+    server
+        .orchestrator()
+        .register_fake_client(ip(5, 5, 5, 5))
+        .await;
+
+    server
+}
+
+/// Each entry point brings up its own tail after B.
+#[tokio::test]
+async fn scoped_tails_each_chain_has_its_own_tail() {
+    let server = scoped_tails_setup().await;
+    let proxy = ip(5, 5, 5, 5);
+
+    // proxy→A, A→B, B→C
+    setup_proxy_chain(&server, "A", proxy, "10.0.0.1").await;
+    // proxy→F, F→B, B→D
+    setup_proxy_chain(&server, "F", proxy, "10.0.0.2").await;
+    assert_net_ids_in_use(&server, 6).await;
+
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, SCOPED_TAILS, "start.dot");
+    assert!(guard["B"].proxy_deps().services().is_empty());
+}
+
+/// Losing the replica of C used past B tears down A's whole chain, started
+/// upstream of B, while F's chain through B is untouched. A's next request
+/// rebuilds it on the replica of C left.
+#[tokio::test]
+async fn scoped_tails_tail_replica_removed() {
+    let server = scoped_tails_setup().await;
+    let proxy = ip(5, 5, 5, 5);
+
+    setup_proxy_chain(&server, "A", proxy, "10.0.0.1").await;
+    setup_proxy_chain(&server, "F", proxy, "10.0.0.2").await;
+
+    let c2 = ip(9, 9, 9, 9);
+    server
+        .services()
+        .write()
+        .await
+        .get_mut("C")
+        .unwrap()
+        .add_replica(c2, 8080, None);
+    server.orchestrator().register_fake_client(c2).await;
+
+    server
+        .orchestrator()
+        .handle_node_disconnect(ip(3, 3, 3, 3), server.services())
+        .await;
+    // proxy→F, F→B, B→D
+    assert_net_ids_in_use(&server, 3).await;
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, SCOPED_TAILS, "after_disconnect_C.dot");
+    drop(guard);
+
+    setup_proxy_chain(&server, "A", proxy, "10.0.0.1").await;
+    assert_net_ids_in_use(&server, 6).await;
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, SCOPED_TAILS, "after_rebuild_A.dot");
+}
+
+/// Same for a backend chain: the edge B→C knows it belongs to G's trigger at
+/// 5555, so G→B comes down with it and the next trigger rebuilds the chain.
+#[tokio::test]
+async fn scoped_tails_backend_tail_replica_removed() {
+    let server = scoped_tails_setup().await;
+    let g_ip = ip(7, 7, 7, 7);
+
+    // G→B, B→C
+    trigger_backend_chain(&server, "G", g_ip, 5555).await;
+    // proxy→F, F→B, B→D
+    setup_proxy_chain(&server, "F", ip(5, 5, 5, 5), "10.0.0.2").await;
+    assert_net_ids_in_use(&server, 5).await;
+
+    let c2 = ip(9, 9, 9, 9);
+    server
+        .services()
+        .write()
+        .await
+        .get_mut("C")
+        .unwrap()
+        .add_replica(c2, 8080, None);
+    server.orchestrator().register_fake_client(c2).await;
+
+    server
+        .orchestrator()
+        .handle_node_disconnect(ip(3, 3, 3, 3), server.services())
+        .await;
+    assert_net_ids_in_use(&server, 3).await;
+
+    trigger_backend_chain(&server, "G", g_ip, 5555).await;
+    assert_net_ids_in_use(&server, 5).await;
+}
+
+// ===========================================================================
+// config_preview: the dep_changed and triggers_changed topologies, previewing
+// a config before applying it.
+// ===========================================================================
+
+/// Previewing the drop of C from A's deps reports A's chains without touching
+/// them; applying the config then frees exactly the networks reported.
+#[tokio::test]
+async fn config_preview_drop_C_from_A() {
+    let server = dep_changed_setup().await;
+    let new_config = load_config(DEP_CHANGED, "drop_C_from_A.toml").await;
+
+    let mut guard = server.services().write().await;
+    let impact = preview_config_update(&guard, &new_config).await;
+    // proxy1→A, proxy2→A, A→B, B→C
+    assert_eq!(impact.net_ids.len(), 4);
+    assert_eq!(
+        impact.proxy_clients,
+        [
+            ProxyClientImpact {
+                service: "A".to_string(),
+                client: "10.0.0.1".to_string(),
+                proxy: ip(5, 5, 5, 5),
+            },
+            ProxyClientImpact {
+                service: "A".to_string(),
+                client: "10.0.0.2".to_string(),
+                proxy: ip(6, 6, 6, 6),
+            },
+        ]
+    );
+    assert!(impact.backend_chains.is_empty());
+
+    // nothing was torn down
+    assert_graphviz(&guard, DEP_CHANGED, "start.dot");
+    assert_net_ids_in_use(&server, 6).await;
+
+    apply_config_update(&mut guard, new_config, server.orchestrator()).await;
+    drop(guard);
+    assert_net_ids_in_use(&server, 2).await;
+}
+
+/// Removing A's trigger only affects its backend chain.
+#[tokio::test]
+async fn config_preview_remove_A_trigger() {
+    let server = triggers_changed_setup().await;
+    let new_config = load_config(TRIGGERS_CHANGED, "remove_A_trigger.toml").await;
+
+    let guard = server.services().read().await;
+    let impact = preview_config_update(&guard, &new_config).await;
+    assert_eq!(impact.net_ids.len(), 1);
+    assert!(impact.proxy_clients.is_empty());
+    assert_eq!(
+        impact.backend_chains,
+        [BackendChainImpact {
+            service: "A".to_string(),
+            ip: ip(1, 1, 1, 1),
+            docker_container: None,
+            port: 5555,
+        }]
+    );
+    assert_graphviz(&guard, TRIGGERS_CHANGED, "start.dot");
+    drop(guard);
+    assert_net_ids_in_use(&server, 4).await;
+}
+
+/// A config that changes nothing has no impact.
+#[tokio::test]
+async fn config_preview_unchanged() {
+    let server = dep_changed_setup().await;
+    let same_config = load_fixture(DEP_CHANGED).await;
+
+    let guard = server.services().read().await;
+    let impact = preview_config_update(&guard, &same_config).await;
+    assert!(impact.net_ids.is_empty());
+    assert!(impact.proxy_clients.is_empty());
+    assert!(impact.backend_chains.is_empty());
+}
+
+// ── config_store: writes through the HTTP API ──────────────────────────────
+
+/// A copy of the `dep_changed` config in a directory of its own, to be written.
+async fn config_store_setup(test: &str) -> ConfigStore {
+    let dir = std::env::temp_dir().join(format!("nullnet-{test}-{}", std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    let path = dir.join("services.toml");
+    tokio::fs::copy(fixture_path(DEP_CHANGED, "services.toml"), &path)
+        .await
+        .unwrap();
+    ConfigStore::new(path, Arc::default())
+}
+
+/// Replacing the config applies it and saves the previous one as version 1.
+#[tokio::test]
+async fn config_store_replace_drop_C_from_A() {
+    let server = dep_changed_setup().await;
+    let store = config_store_setup("replace").await;
+    let (original, etag) = store.read().await.unwrap();
+    let new_content = tokio::fs::read_to_string(fixture_path(DEP_CHANGED, "drop_C_from_A.toml"))
+        .await
+        .unwrap();
+
+    let write = store
+        .replace(
+            &etag,
+            new_content.clone(),
+            server.services(),
+            server.orchestrator(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(write.previous_version, 1);
+    assert_eq!(write.etag, config_store::etag(&new_content));
+    // the file watcher doesn't apply the write again
+    assert!(store.is_applied(&new_content));
+    assert!(!store.is_applied(&original));
+    assert_eq!(store.read().await.unwrap(), (new_content, write.etag));
+    assert_eq!(store.version(1).await.unwrap(), original);
+
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, DEP_CHANGED, "after_drop_C_from_A.dot");
+}
+
+/// Writes based on a stale ETag, or resulting in an invalid config, change nothing.
+#[tokio::test]
+async fn config_store_rejected_writes() {
+    let server = dep_changed_setup().await;
+    let store = config_store_setup("rejected").await;
+    let (original, etag) = store.read().await.unwrap();
+
+    let result = store
+        .replace(
+            "\"stale\"",
+            String::new(),
+            server.services(),
+            server.orchestrator(),
+        )
+        .await;
+    assert!(
+        matches!(result, Err(ConfigWriteError::VersionMismatch { current }) if current == etag)
+    );
+
+    let result = store
+        .replace(
+            &etag,
+            "[[services]]\nname = \"A\"\nproxy_dependencies = [\"A\"]\n".to_string(),
+            server.services(),
+            server.orchestrator(),
+        )
+        .await;
+    assert!(matches!(result, Err(ConfigWriteError::Invalid(issues)) if issues.len() == 1));
+
+    let result = store
+        .patch(
+            &etag,
+            "remove = [\"E\"]",
+            server.services(),
+            server.orchestrator(),
+        )
+        .await;
+    assert!(matches!(result, Err(ConfigWriteError::Invalid(issues))
+        if issues[0].message == "patch: cannot remove 'E', it's not declared"));
+
+    assert_eq!(store.read().await.unwrap(), (original, etag));
+    assert!(store.history().await.is_empty());
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, DEP_CHANGED, "start.dot");
+    drop(guard);
+    assert_net_ids_in_use(&server, 6).await;
+}
+
+/// A patch upserting A and removing D, then a rollback to the original config.
+#[tokio::test]
+async fn config_store_patch_and_rollback() {
+    let server = dep_changed_setup().await;
+    let store = config_store_setup("patch").await;
+    let (original, etag) = store.read().await.unwrap();
+
+    let patch = "remove = [\"D\"]\n\n[[services]]\nname = \"A\"\nproxy_dependencies = [\"B\"]\n";
+    let write = store
+        .patch(&etag, patch, server.services(), server.orchestrator())
+        .await
+        .unwrap();
+    let patched = ServicesToml::parse(&store.read().await.unwrap().0).unwrap();
+    let mut names: Vec<&String> = patched.keys().collect();
+    names.sort();
+    assert_eq!(names, ["A", "B"]);
+    let guard = server.services().read().await;
+    assert_eq!(guard["A"].proxy_deps().services(), ["B"]);
+    assert!(!guard.contains_key("D"));
+    drop(guard);
+
+    let rollback = store
+        .rollback(
+            &write.etag,
+            write.previous_version,
+            server.services(),
+            server.orchestrator(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(rollback.previous_version, 2);
+    assert_eq!(store.read().await.unwrap().0, original);
+    let versions: Vec<u32> = store.history().await.iter().map(|h| h.version).collect();
+    assert_eq!(versions, [1, 2]);
+    let guard = server.services().read().await;
+    assert_eq!(guard["A"].proxy_deps().services(), ["B", "C"]);
+    assert!(guard.contains_key("D"));
+    drop(guard);
+
+    let result = store
+        .rollback(&rollback.etag, 7, server.services(), server.orchestrator())
+        .await;
+    assert!(matches!(result, Err(ConfigWriteError::UnknownVersion(7))));
+}
+
+/// A service removed and declared again by the same patch is replaced.
+#[tokio::test]
+async fn config_store_patch_remove_and_redeclare() {
+    let server = dep_changed_setup().await;
+    let store = config_store_setup("redeclare").await;
+    let (_, etag) = store.read().await.unwrap();
+
+    let patch = "remove = [\"A\"]\n\n[[services]]\nname = \"A\"\nproxy_dependencies = [\"B\"]\n";
+    store
+        .patch(&etag, patch, server.services(), server.orchestrator())
+        .await
+        .unwrap();
+    let patched = ServicesToml::parse(&store.read().await.unwrap().0).unwrap();
+    assert_eq!(patched["A"].proxy_deps().services(), ["B"]);
+    let guard = server.services().read().await;
+    assert_eq!(guard["A"].proxy_deps().services(), ["B"]);
+}
+
+// ── events: topology events published to subscribers ───────────────────────
+
+fn drain_events(events: &mut tokio::sync::broadcast::Receiver<Event>) -> Vec<TopologyEvent> {
+    std::iter::from_fn(|| events.try_recv().ok())
+        .map(|event| event.kind)
+        .collect()
+}
+
+/// Dropping C from A tears down A's chains with the change as reason,
+/// and setting A's chain up again reports its edges.
+#[tokio::test]
+async fn events_drop_C_from_A() {
+    let server = dep_changed_setup().await;
+    let mut events = server.orchestrator().subscribe();
+    let new_config = load_config(DEP_CHANGED, "drop_C_from_A.toml").await;
+
+    let mut guard = server.services().write().await;
+    apply_config_update(&mut guard, new_config, server.orchestrator()).await;
+    drop(guard);
+
+    let events_after_update = drain_events(&mut events);
+    // proxy1→A, proxy2→A, A→B, B→C
+    let torn_down: Vec<_> = events_after_update
+        .iter()
+        .filter_map(|event| match event {
+            TopologyEvent::EdgeTornDown { edge, reason } => Some((edge.service.as_str(), *reason)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(torn_down.len(), 4);
+    assert!(
+        torn_down
+            .iter()
+            .all(|(_, reason)| *reason == "ProxyDepsChanged")
+    );
+    assert_eq!(
+        torn_down
+            .iter()
+            .filter(|(service, _)| *service == "A")
+            .count(),
+        2
+    );
+    assert_eq!(
+        events_after_update.last(),
+        Some(&TopologyEvent::ConfigReloaded {
+            services: vec![
+                "A".to_string(),
+                "B".to_string(),
+                "C".to_string(),
+                "D".to_string()
+            ],
+        })
+    );
+
+    setup_proxy_chain(&server, "A", ip(5, 5, 5, 5), "10.0.0.1").await;
+    let mut set_up: Vec<(String, String)> = drain_events(&mut events)
+        .into_iter()
+        .filter_map(|event| match event {
+            TopologyEvent::EdgeSetUp { edge, .. } => Some((edge.client, edge.service)),
+            _ => None,
+        })
+        .collect();
+    set_up.sort();
+    assert_eq!(
+        set_up,
+        [
+            ("10.0.0.1 (via 5.5.5.5)".to_string(), "A".to_string()),
+            ("A".to_string(), "B".to_string()),
+        ]
+    );
+}
+
+/// Re-registering a host without one of its replicas reports the replica and its edges as removed.
+#[tokio::test]
+async fn events_replica_removed() {
+    let server = dep_changed_setup().await;
+    let mut events = server.orchestrator().subscribe();
+
+    // C's host now only hosts C on another port: same replica, updated
+    server
+        .apply_services_list(ip(3, 3, 3, 3), &[("C".to_string(), 9090, None)])
+        .await
+        .unwrap();
+    assert!(drain_events(&mut events).is_empty());
+
+    // B's host stops hosting B
+    server
+        .apply_services_list(ip(2, 2, 2, 2), &[])
+        .await
+        .unwrap();
+    let removed = drain_events(&mut events);
+    assert!(removed.contains(&TopologyEvent::ReplicaRemoved {
+        service: "B".to_string(),
+        ip: ip(2, 2, 2, 2),
+        docker_container: None,
+        reason: "ReplicaRemoved",
+    }));
+    assert!(removed.iter().any(|event| matches!(event,
+        TopologyEvent::EdgeTornDown { edge, reason: "ReplicaRemoved" } if edge.service == "B")));
+
+    server
+        .apply_services_list(ip(2, 2, 2, 2), &[("B".to_string(), 8080, None)])
+        .await
+        .unwrap();
+    assert_eq!(
+        drain_events(&mut events),
+        [TopologyEvent::ReplicaAdded {
+            service: "B".to_string(),
+            ip: ip(2, 2, 2, 2),
+            port: 8080,
+            docker_container: None,
+        }]
+    );
+}
+
+/// The audit log records the applied changes and the edges they tore down,
+/// queryable by service and node.
+#[tokio::test]
+async fn audit_log_drop_C_from_A() {
+    let server = dep_changed_setup().await;
+    let dir = std::env::temp_dir().join(format!("nullnet-audit-log-{}", std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    let path = dir.join("audit.log");
+    let events = server.orchestrator().subscribe();
+    let recorder = tokio::spawn(AuditLog::new(&path).record(events));
+
+    let new_config = load_config(DEP_CHANGED, "drop_C_from_A.toml").await;
+    let mut guard = server.services().write().await;
+    apply_config_update(&mut guard, new_config, server.orchestrator()).await;
+    drop(guard);
+
+    let log = AuditLog::new(&path);
+    let mut entries = Vec::new();
+    for _ in 0..50 {
+        entries = log.query(&AuditQuery::default()).await.unwrap();
+        if entries
+            .last()
+            .is_some_and(|e| e["type"] == "config_reloaded")
+        {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    recorder.abort();
+    assert_eq!(entries[0]["type"], "change_applied");
+    assert_eq!(entries[0]["change"], "ProxyDepsChanged");
+    assert_eq!(entries[0]["service"], "A");
+    // proxy1→A, proxy2→A, A→B, B→C, then the reload
+    assert_eq!(entries.len(), 6);
+
+    let query = AuditQuery {
+        node: Some(ip(6, 6, 6, 6)),
+        ..AuditQuery::default()
+    };
+    let via_proxy2 = log.query(&query).await.unwrap();
+    assert_eq!(via_proxy2.len(), 1);
+    assert_eq!(via_proxy2[0]["type"], "edge_torn_down");
+    assert_eq!(via_proxy2[0]["reason"], "ProxyDepsChanged");
+    assert_eq!(via_proxy2[0]["client"], "10.0.0.2 (via 6.6.6.6)");
+
+    let query = AuditQuery {
+        service: Some("C".to_string()),
+        ..AuditQuery::default()
+    };
+    let to_C = log.query(&query).await.unwrap();
+    assert_eq!(to_C.len(), 1);
+    assert_eq!(to_C[0]["server_ip"], "3.3.3.3");
+}
+
+// ── admin: evictions, draining and cordons ──────────────────────────────────
+
+fn replica_of<'a>(services: &'a Services, name: &str, ip: IpAddr) -> &'a Replica {
+    let ServiceInfo::Registered(reg) = &services[name] else {
+        panic!("'{name}' is not registered");
+    };
+    reg.replicas().iter().find(|r| r.ip() == ip).unwrap()
+}
+
+/// Evicting proxy2's client of A only tears down its own edge:
+/// the deps of A are still used by proxy1's chain.
+#[tokio::test]
+async fn admin_evict_proxy_client() {
+    let server = dep_changed_setup().await;
+    let mut guard = server.services().write().await;
+
+    let unknown = Client::new("10.0.0.9".to_string(), Some(ip(6, 6, 6, 6)));
+    assert!(detect_client_eviction(&guard, "A", unknown).is_none());
+
+    let client = Client::new("10.0.0.2".to_string(), Some(ip(6, 6, 6, 6)));
+    let change = detect_client_eviction(&guard, "A", client.clone()).unwrap();
+    apply_changes(vec![change], &mut guard, server.orchestrator()).await;
+    let ServiceInfo::Registered(reg) = &guard["A"] else {
+        panic!("'A' is not registered");
+    };
+    assert!(reg.is_client_setup(&client).is_none());
+    drop(guard);
+    assert_net_ids_in_use(&server, 5).await;
+}
+
+/// Evicting the network of proxy2's client of A tears down the chain using it.
+#[tokio::test]
+async fn admin_evict_net() {
+    let server = dep_changed_setup().await;
+    let net_id = proxy_net_id(&server, "A", ip(6, 6, 6, 6)).await;
+    let mut guard = server.services().write().await;
+
+    assert!(detect_net_eviction(&guard, 9999).is_none());
+    let change = detect_net_eviction(&guard, net_id).unwrap();
+    apply_changes(vec![change], &mut guard, server.orchestrator()).await;
+    assert!(detect_net_eviction(&guard, net_id).is_none());
+    drop(guard);
+    assert_net_ids_in_use(&server, 5).await;
+}
+
+/// New chains avoid a drained replica, while its existing clients keep it.
+#[tokio::test]
+async fn admin_drain_replica() {
+    let server = NullnetGrpcImpl::new_for_test(load_fixture(DEP_CHANGED).await);
+    let ip_map = HashMap::from([
+        ("A", ip(1, 1, 1, 1)),
+        ("B", ip(2, 2, 2, 2)),
+        ("C", ip(3, 3, 3, 3)),
+        ("D", ip(4, 4, 4, 4)),
+    ]);
+    register_services(&server, &ip_map, 8080).await;
+    server
+        .apply_services_list(ip(7, 7, 7, 7), &[("B".to_string(), 8080, None)])
+        .await
+        .unwrap();
+    let proxy1 = ip(5, 5, 5, 5);
+    server.orchestrator().register_fake_client(proxy1).await;
+
+    let mut guard = server.services().write().await;
+    assert!(detect_replica_draining(&guard, "B", ip(8, 8, 8, 8), None, true).is_none());
+    let change = detect_replica_draining(&guard, "B", ip(7, 7, 7, 7), None, true).unwrap();
+    apply_changes(vec![change], &mut guard, server.orchestrator()).await;
+    assert!(replica_of(&guard, "B", ip(7, 7, 7, 7)).is_draining());
+    drop(guard);
+
+    setup_proxy_chain(&server, "A", proxy1, "10.0.0.1").await;
+    let mut guard = server.services().write().await;
+    assert_eq!(replica_of(&guard, "B", ip(2, 2, 2, 2)).clients().len(), 1);
+    assert!(replica_of(&guard, "B", ip(7, 7, 7, 7)).clients().is_empty());
+
+    // the chain stays on the drained replica
+    let change = detect_replica_draining(&guard, "B", ip(2, 2, 2, 2), None, true).unwrap();
+    apply_changes(vec![change], &mut guard, server.orchestrator()).await;
+    assert_eq!(replica_of(&guard, "B", ip(2, 2, 2, 2)).clients().len(), 1);
+    drop(guard);
+    assert_net_ids_in_use(&server, 3).await;
+}
+
+/// Cordoning a node drains its replicas, including the ones registering later.
+#[tokio::test]
+async fn admin_cordon_node() {
+    let server = dep_changed_setup().await;
+    let node = ip(3, 3, 3, 3);
+    let cordon = |cordoned| ServiceChange::NodeCordoned { ip: node, cordoned };
+
+    let mut guard = server.services().write().await;
+    apply_changes(vec![cordon(true)], &mut guard, server.orchestrator()).await;
+    assert!(replica_of(&guard, "C", node).is_draining());
+    assert!(!replica_of(&guard, "B", ip(2, 2, 2, 2)).is_draining());
+    drop(guard);
+    assert_net_ids_in_use(&server, 6).await;
+
+    server.apply_services_list(node, &[]).await.unwrap();
+    server
+        .apply_services_list(node, &[("C".to_string(), 8080, None)])
+        .await
+        .unwrap();
+    let mut guard = server.services().write().await;
+    assert!(replica_of(&guard, "C", node).is_draining());
+
+    apply_changes(vec![cordon(false)], &mut guard, server.orchestrator()).await;
+    assert!(!replica_of(&guard, "C", node).is_draining());
+    drop(guard);
+    assert!(!server.orchestrator().is_cordoned(node).await);
+}
+
+/// Concurrent proxy requests for the same client are served by a single setup:
+/// all of them get the same upstream, and each client gets exactly one network.
+#[tokio::test]
+async fn concurrent_proxy_requests() {
+    let server = Arc::new(NullnetGrpcImpl::new_for_test(
+        load_fixture(DEP_CHANGED).await,
+    ));
+    let ip_map = HashMap::from([
+        ("A", ip(1, 1, 1, 1)),
+        ("B", ip(2, 2, 2, 2)),
+        ("C", ip(3, 3, 3, 3)),
+        ("D", ip(4, 4, 4, 4)),
+    ]);
+    let proxy1 = ip(5, 5, 5, 5);
+    let proxy2 = ip(6, 6, 6, 6);
+    register_services(&server, &ip_map, 8080).await;
+    server.orchestrator().register_fake_client(proxy1).await;
+    server.orchestrator().register_fake_client(proxy2).await;
+
+    let requests = [
+        ("A", proxy1, "10.0.0.1"),
+        ("D", proxy1, "10.0.0.1"),
+        ("A", proxy2, "10.0.0.2"),
+    ];
+    let mut set = tokio::task::JoinSet::new();
+    for _ in 0..16 {
+        for (service, proxy_ip, client_ip) in requests {
+            let server = server.clone();
+            set.spawn(async move {
+                let upstream = server
+                    .handle_proxy_request(service, proxy_ip, client_ip)
+                    .await
+                    .expect("proxy request failed");
+                ((service, proxy_ip), upstream)
+            });
+        }
+    }
+    let mut upstreams: HashMap<_, HashSet<_>> = HashMap::new();
+    while let Some(result) = set.join_next().await {
+        let (request, upstream) = result.unwrap();
+        upstreams
+            .entry(request)
+            .or_default()
+            .insert((upstream.ip, upstream.port));
+    }
+
+    assert_eq!(upstreams.len(), requests.len());
+    for upstream in upstreams.values() {
+        assert_eq!(upstream.len(), 1, "different upstreams: {upstream:?}");
+        let (upstream_ip, _) = upstream.iter().next().unwrap();
+        assert_ne!(upstream_ip, &Ipv4Addr::UNSPECIFIED.to_string());
+    }
+
+    // same topology as when the requests are made one at a time
+    let guard = server.services().read().await;
+    assert_graphviz(&guard, DEP_CHANGED, "start.dot");
+    for (service, proxy_ip, client_ip) in requests {
+        let client = Client::new(client_ip.to_string(), Some(proxy_ip));
+        let Some(ServiceInfo::Registered(registered)) = guard.get(service) else {
+            panic!("'{service}' is not registered");
+        };
+        assert!(registered.is_client_setup(&client).is_some());
+    }
+}
+
+/// Proxy requests over thousands of services and replicas, from new clients and then
+/// from the same clients again (sticky sessions). Run with:
+/// `cargo test --release -p nullnet-server bench_proxy_requests -- --ignored --nocapture`
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "benchmark"]
+async fn bench_proxy_requests() {
+    const SERVICES: usize = 1000;
+    const REPLICAS_PER_SERVICE: usize = 8;
+    const HOT_REPLICAS: usize = 2000;
+    const HOT_CLIENTS: usize = 2000;
+    const NODES: u8 = 16;
+    const STICKY_ROUNDS: usize = 4;
+
+    let config: String = (0..SERVICES)
+        .map(|i| format!("[[services]]\nname = \"S{i}\"\n\n"))
+        .chain(["[[services]]\nname = \"A\"\n".to_string()])
+        .collect();
+    let server = Arc::new(NullnetGrpcImpl::new_for_test(
+        ServicesToml::parse(&config).unwrap(),
+    ));
+    let node = |n: usize| ip(1, 0, 0, u8::try_from(n % usize::from(NODES)).unwrap() + 1);
+    let proxies = [ip(2, 0, 0, 1), ip(2, 0, 0, 2)];
+    let mut guard = server.services().write().await;
+    for i in 0..SERVICES {
+        let si = guard.get_mut(&format!("S{i}")).unwrap();
+        for r in 0..REPLICAS_PER_SERVICE {
+            si.add_replica(node(i + r), 8080, Some(format!("s{i}-{r}")));
+        }
+    }
+    let hot = guard.get_mut("A").unwrap();
+    for r in 0..HOT_REPLICAS {
+        hot.add_replica(node(r), 8080, Some(format!("a-{r}")));
+    }
+    drop(guard);
+    for n in 0..usize::from(NODES) {
+        server.orchestrator().register_fake_client(node(n)).await;
+    }
+    for proxy in proxies {
+        server.orchestrator().register_fake_client(proxy).await;
+    }
+
+    let requests: Vec<(String, IpAddr, String)> = (0..HOT_CLIENTS)
+        .map(|c| {
+            (
+                "A".to_string(),
+                proxies[c % 2],
+                format!("10.1.{}.{}", c / 256, c % 256),
+            )
+        })
+        .chain((0..SERVICES).map(|i| {
+            (
+                format!("S{i}"),
+                proxies[i % 2],
+                format!("10.2.{}.{}", i / 256, i % 256),
+            )
+        }))
+        .collect();
+    let run = async |rounds: usize| {
+        let start = std::time::Instant::now();
+        let mut set = tokio::task::JoinSet::new();
+        for _ in 0..rounds {
+            for (service, proxy_ip, client_ip) in requests.clone() {
+                let server = server.clone();
+                set.spawn(async move {
+                    server
+                        .handle_proxy_request(&service, proxy_ip, &client_ip)
+                        .await
+                        .expect("proxy request failed");
+                });
+            }
+        }
+        while let Some(result) = set.join_next().await {
+            result.unwrap();
+        }
+        let elapsed = start.elapsed();
+        let count = requests.len() * rounds;
+        #[allow(clippy::cast_precision_loss)]
+        let rate = count as f64 / elapsed.as_secs_f64();
+        (count, elapsed, rate)
+    };
+
+    let (count, elapsed, rate) = run(1).await;
+    println!("new clients: {count} requests in {elapsed:?} ({rate:.0}/s)");
+    let (count, elapsed, rate) = run(STICKY_ROUNDS).await;
+    println!("sticky clients: {count} requests in {elapsed:?} ({rate:.0}/s)");
+    assert_net_ids_in_use(&server, u32::try_from(requests.len()).unwrap()).await;
+}
+
+/// Measure proxy requests for services unrelated to a slow node, while the chains
+/// through it are being torn down: they shouldn't wait for its teardowns. Run with:
+/// `cargo test --release -p nullnet-server bench_slow_node -- --ignored --nocapture`
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "benchmark"]
+async fn bench_slow_node() {
+    const SLOW_CLIENTS: usize = 200;
+    const FAST_SERVICES: usize = 50;
+    const FAST_CLIENTS: usize = 20;
+    const SLOW_DELAY: std::time::Duration = std::time::Duration::from_millis(5);
+
+    let config: String = (0..FAST_SERVICES)
+        .map(|i| format!("[[services]]\nname = \"S{i}\"\n\n"))
+        .chain(["[[services]]\nname = \"SLOW\"\n".to_string()])
+        .collect();
+    let server = Arc::new(NullnetGrpcImpl::new_for_test(
+        ServicesToml::parse(&config).unwrap(),
+    ));
+    let slow_node = ip(1, 0, 0, 1);
+    let slow_proxy = ip(2, 0, 0, 1);
+    let fast_node = ip(1, 0, 0, 2);
+    let fast_proxy = ip(2, 0, 0, 2);
+    let mut guard = server.services().write().await;
+    for i in 0..FAST_SERVICES {
+        let si = guard.get_mut(&format!("S{i}")).unwrap();
+        si.add_replica(fast_node, 8080, Some(format!("s{i}")));
+    }
+    let slow = guard.get_mut("SLOW").unwrap();
+    slow.add_replica(slow_node, 8080, Some("slow".to_string()));
+    drop(guard);
+    for node in [slow_node, slow_proxy, fast_node, fast_proxy] {
+        server.orchestrator().register_fake_client(node).await;
+    }
+
+    for c in 0..SLOW_CLIENTS {
+        server
+            .handle_proxy_request("SLOW", slow_proxy, &format!("10.1.{}.{}", c / 256, c % 256))
+            .await
+            .expect("proxy request failed");
+    }
+    // from now on, each teardown sent to the slow node takes SLOW_DELAY to go through
+    server
+        .orchestrator()
+        .register_fake_client_slow(slow_node, SLOW_DELAY)
+        .await;
+
+    let start = std::time::Instant::now();
+    let teardown = {
+        let server = server.clone();
+        tokio::spawn(async move {
+            server
+                .orchestrator()
+                .handle_node_disconnect(slow_proxy, server.services())
+                .await;
+            start.elapsed()
+        })
+    };
+    // let the teardown lock the slow service first
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let mut set = tokio::task::JoinSet::new();
+    for i in 0..FAST_SERVICES {
+        for c in 0..FAST_CLIENTS {
+            let server = server.clone();
+            set.spawn(async move {
+                server
+                    .handle_proxy_request(&format!("S{i}"), fast_proxy, &format!("10.2.{i}.{c}"))
+                    .await
+                    .expect("proxy request failed");
+            });
+        }
+    }
+    while let Some(result) = set.join_next().await {
+        result.unwrap();
+    }
+    let fast_elapsed = start.elapsed();
+    let slow_elapsed = teardown.await.unwrap();
+
+    let count = FAST_SERVICES * FAST_CLIENTS;
+    println!("unrelated services: {count} requests done after {fast_elapsed:?}");
+    println!("slow node: {SLOW_CLIENTS} chains torn down after {slow_elapsed:?}");
+    assert!(fast_elapsed < slow_elapsed);
+}
+
+// ===========================================================================
+// warm_networks: A→B with 2 warm networks per proxy.
+// ===========================================================================
+
+const WARM_NETWORKS: &str = "warm_networks";
+
+async fn warm_networks_setup() -> (NullnetGrpcImpl, IpAddr) {
+    let server = NullnetGrpcImpl::new_for_test(load_fixture(WARM_NETWORKS).await);
+    let ip_map = HashMap::from([("A", ip(1, 1, 1, 1)), ("B", ip(2, 2, 2, 2))]);
+    register_services(&server, &ip_map, 8080).await;
+    let proxy = ip(5, 5, 5, 5);
+    server.orchestrator().register_fake_client(proxy).await;
+    server.orchestrator().add_proxy_node(proxy).await;
+
+    server.replenish_warm_networks().await;
+    assert_eq!(warm_count(&server, proxy).await, 2);
+    // 2 × proxy→A, sharing A→B
+    assert_net_ids_in_use(&server, 3).await;
+
+    (server, proxy)
+}
+
+async fn warm_count(server: &NullnetGrpcImpl, proxy: IpAddr) -> usize {
+    let guard = server.services().read().await;
+    let Some(ServiceInfo::Registered(reg)) = guard.get("A") else {
+        return 0;
+    };
+    reg.warm_clients(proxy).len()
+}
+
+/// A new client takes over a warm network, which is replaced by the next replenishment.
+/// Warm networks don't time out, the ones handed out do.
+#[tokio::test]
+async fn warm_networks_handed_out() {
+    let (server, proxy) = warm_networks_setup().await;
+
+    let upstream = server
+        .handle_proxy_request("A", proxy, "10.0.0.1")
+        .await
+        .unwrap();
+    assert_ne!(upstream.ip, Ipv4Addr::UNSPECIFIED.to_string());
+    assert_eq!(upstream.port, 8080);
+    assert_eq!(warm_count(&server, proxy).await, 1);
+    assert_net_ids_in_use(&server, 3).await;
+    let sticky = server
+        .handle_proxy_request("A", proxy, "10.0.0.1")
+        .await
+        .unwrap();
+    assert_eq!(sticky, upstream);
+
+    server.replenish_warm_networks().await;
+    assert_eq!(warm_count(&server, proxy).await, 2);
+    assert_net_ids_in_use(&server, 4).await;
+
+    let client = Client::new("10.0.0.1".to_string(), Some(proxy));
+    let mut guard = server.services().write().await;
+    let Some(ServiceInfo::Registered(reg)) = guard.get("A") else {
+        panic!("'A' is not registered");
+    };
+    assert_eq!(
+        reg.expired_proxy_clients(std::time::Duration::ZERO),
+        std::slice::from_ref(&client)
+    );
+    let change = detect_client_eviction(&guard, "A", client).unwrap();
+    apply_changes(vec![change], &mut guard, server.orchestrator()).await;
+    drop(guard);
+    assert_eq!(warm_count(&server, proxy).await, 2);
+    assert_net_ids_in_use(&server, 3).await;
+}
+
+/// Lowering `warm_networks` releases the warm networks in excess.
+#[tokio::test]
+async fn warm_networks_released() {
+    let (server, proxy) = warm_networks_setup().await;
+
+    let mut guard = server.services().write().await;
+    let new_config = load_config(WARM_NETWORKS, "warm_1.toml").await;
+    apply_config_update(&mut guard, new_config, server.orchestrator()).await;
+    drop(guard);
+    assert_eq!(warm_count(&server, proxy).await, 2);
+
+    server.replenish_warm_networks().await;
+    assert_eq!(warm_count(&server, proxy).await, 1);
+    assert_net_ids_in_use(&server, 2).await;
+}
+
+/// Warm networks on a drained replica don't count towards `warm_networks`:
+/// they're released and replaced on the replicas taking new clients.
+#[tokio::test]
+async fn warm_networks_moved_off_drained_replica() {
+    let (server, proxy) = warm_networks_setup().await;
+    let a2 = ip(3, 3, 3, 3);
+    server.orchestrator().register_fake_client(a2).await;
+    server
+        .apply_services_list(a2, &[("A".to_string(), 8080, None)])
+        .await
+        .unwrap();
+
+    let mut guard = server.services().write().await;
+    let change = detect_replica_draining(&guard, "A", ip(1, 1, 1, 1), None, true).unwrap();
+    apply_changes(vec![change], &mut guard, server.orchestrator()).await;
+    drop(guard);
+    assert_eq!(warm_count(&server, proxy).await, 0);
+
+    server.replenish_warm_networks().await;
+    assert_eq!(warm_count(&server, proxy).await, 2);
+    let guard = server.services().read().await;
+    assert!(replica_of(&guard, "A", ip(1, 1, 1, 1)).clients().is_empty());
+    assert_eq!(replica_of(&guard, "A", a2).clients().len(), 2);
+    drop(guard);
+    // 2 × proxy→A on the new replica, sharing its A→B
+    assert_net_ids_in_use(&server, 3).await;
+
+    server
+        .handle_proxy_request("A", proxy, "10.0.0.1")
+        .await
+        .unwrap();
+    assert_eq!(warm_count(&server, proxy).await, 1);
+    assert_net_ids_in_use(&server, 3).await;
+}
+
+/// Warm networks failing to be set up on a proxy are retried there with a growing delay,
+/// and replenished as usual once their setup succeeds again.
+#[tokio::test(start_paused = true)]
+async fn warm_networks_backoff() {
+    let server = NullnetGrpcImpl::new_for_test(load_fixture(WARM_NETWORKS).await);
+    let ip_map = HashMap::from([("A", ip(1, 1, 1, 1)), ("B", ip(2, 2, 2, 2))]);
+    register_services(&server, &ip_map, 8080).await;
+    let proxy = ip(5, 5, 5, 5);
+    let rejected = server
+        .orchestrator()
+        .register_fake_client_nacking(proxy, NackCode::SetupFailed)
+        .await;
+    server.orchestrator().add_proxy_node(proxy).await;
+    let attempts = || rejected.load(std::sync::atomic::Ordering::Relaxed);
+
+    server.replenish_warm_networks().await;
+    let first = attempts();
+    assert!(first > 0);
+    assert_eq!(warm_count(&server, proxy).await, 0);
+
+    // on hold for 5 s after the first failure
+    server.replenish_warm_networks().await;
+    assert_eq!(attempts(), first);
+    tokio::time::advance(std::time::Duration::from_secs(5)).await;
+    server.replenish_warm_networks().await;
+    let second = attempts();
+    assert!(second > first);
+
+    // then for 10 s
+    tokio::time::advance(std::time::Duration::from_secs(5)).await;
+    server.replenish_warm_networks().await;
+    assert_eq!(attempts(), second);
+    tokio::time::advance(std::time::Duration::from_secs(5)).await;
+    server.replenish_warm_networks().await;
+    assert!(attempts() > second);
+
+    // the proxy recovers: it gets its warm networks once the delay is over
+    server.orchestrator().register_fake_client(proxy).await;
+    server.replenish_warm_networks().await;
+    assert_eq!(warm_count(&server, proxy).await, 0);
+    tokio::time::advance(std::time::Duration::from_secs(20)).await;
+    server.replenish_warm_networks().await;
+    assert_eq!(warm_count(&server, proxy).await, 2);
+    assert_net_ids_in_use(&server, 3).await;
+}
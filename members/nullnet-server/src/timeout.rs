@@ -2,85 +2,118 @@ use crate::env::TIMEOUT;
 use crate::orchestrator::Orchestrator;
 use crate::services::changes::{ServiceChange, apply_changes};
 use crate::services::service_info::ServiceInfo;
-use std::collections::HashMap;
+use crate::services::service_map::{ServiceMap, Services};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{Notify, RwLock};
+use tokio::sync::Notify;
 
 pub(crate) async fn check_timeouts(
-    services: Arc<RwLock<HashMap<String, ServiceInfo>>>,
+    services: Arc<ServiceMap>,
     orchestrator: Orchestrator,
     config_changed: Arc<Notify>,
 ) {
     loop {
-        let sleep_duration = nearest_timeout(&*services.read().await);
+        let mut sleep_duration = Duration::from_secs(*TIMEOUT);
+        services
+            .for_each(|_, si| sleep_duration = nearest_timeout(si, sleep_duration))
+            .await;
 
         tokio::select! {
             () = tokio::time::sleep(sleep_duration) => {}
             () = config_changed.notified() => {}
         }
 
-        let mut services_mut = services.write().await;
+        // only the services with something to tear down are locked (with the ones their chains go through)
+        let mut services_mut = services
+            .lock_chains_where(|name, si| {
+                !timed_out_clients(name, si).is_empty() || !idle_backend_chains(name, si).is_empty()
+            })
+            .await;
         apply_timeouts(&mut services_mut, &orchestrator).await;
     }
 }
 
-pub(crate) async fn apply_timeouts(
-    services: &mut HashMap<String, ServiceInfo>,
-    orchestrator: &Orchestrator,
-) {
-    let changes = collect_timed_out_clients(services);
+pub(crate) async fn apply_timeouts(services: &mut Services, orchestrator: &Orchestrator) {
+    let mut changes: Vec<ServiceChange> = services
+        .iter()
+        .flat_map(|(name, si)| timed_out_clients(name, si))
+        .collect();
+    changes.extend(
+        services
+            .iter()
+            .flat_map(|(name, si)| idle_backend_chains(name, si)),
+    );
     if !changes.is_empty() {
-        apply_changes(changes, services, None, orchestrator).await;
+        apply_changes(changes, services, orchestrator).await;
     }
 }
 
-fn collect_timed_out_clients(services: &HashMap<String, ServiceInfo>) -> Vec<ServiceChange> {
-    let mut changes = Vec::new();
+fn timed_out_clients(name: &str, si: &ServiceInfo) -> Vec<ServiceChange> {
+    let Some(timeout) = si.timeout() else {
+        return Vec::new();
+    };
+    if timeout == 0 {
+        return Vec::new();
+    }
+    let ServiceInfo::Registered(reg) = si else {
+        return Vec::new();
+    };
 
-    for (name, si) in services {
-        let Some(timeout) = si.timeout() else {
-            continue;
-        };
-        if timeout == 0 {
-            continue;
-        }
-        let ServiceInfo::Registered(reg) = si else {
-            continue;
-        };
+    reg.expired_proxy_clients(Duration::from_secs(timeout))
+        .into_iter()
+        .map(|client| ServiceChange::ProxyClientTimedOut {
+            name: name.to_string(),
+            client,
+        })
+        .collect()
+}
 
-        for client in reg.expired_proxy_clients(Duration::from_secs(timeout)) {
-            changes.push(ServiceChange::ProxyClientTimedOut {
-                name: name.clone(),
-                client,
-            });
-        }
-    }
+fn idle_backend_chains(name: &str, si: &ServiceInfo) -> Vec<ServiceChange> {
+    let ServiceInfo::Registered(reg) = si else {
+        return Vec::new();
+    };
 
-    changes
+    reg.idle_backend_chains()
+        .into_iter()
+        .map(
+            |(ip, docker_container, port)| ServiceChange::BackendChainIdle {
+                name: name.to_string(),
+                ip,
+                docker_container,
+                port,
+            },
+        )
+        .collect()
 }
 
-fn nearest_timeout(services: &HashMap<String, ServiceInfo>) -> Duration {
-    let mut nearest = Duration::from_secs(*TIMEOUT);
+/// The nearest of `nearest` and the next timeout on `si`.
+fn nearest_timeout(si: &ServiceInfo, mut nearest: Duration) -> Duration {
+    // cap by the idle timeouts so new backend chains are caught within one period
+    for idle_timeout in si.idle_timeouts().values().filter(|t| **t > 0) {
+        nearest = nearest.min(Duration::from_secs(*idle_timeout));
+    }
+    if let ServiceInfo::Registered(reg) = si
+        && let Some(expiry) = reg.nearest_backend_idle_expiry()
+    {
+        nearest = nearest.min(expiry);
+    }
 
-    for si in services.values() {
-        let Some(timeout) = si.timeout() else {
-            continue;
-        };
-        if timeout == 0 {
-            continue;
-        }
+    let Some(timeout) = si.timeout() else {
+        return nearest;
+    };
+    if timeout == 0 {
+        return nearest;
+    }
 
-        let timeout_duration = Duration::from_secs(timeout);
+    let timeout_duration = Duration::from_secs(timeout);
 
-        // cap by the configured timeout so new clients are caught within one period
-        nearest = nearest.min(timeout_duration);
+    // cap by the configured timeout so new clients are caught within one period
+    nearest = nearest.min(timeout_duration);
 
-        if let ServiceInfo::Registered(reg) = si
-            && let Some(expiry) = reg.nearest_proxy_expiry(timeout_duration)
-        {
-            nearest = nearest.min(expiry);
-        }
+    if let ServiceInfo::Registered(reg) = si
+        && let Some(expiry) = reg.nearest_proxy_expiry(timeout_duration)
+    {
+        nearest = nearest.min(expiry);
     }
 
     nearest
@@ -0,0 +1,111 @@
+//! Mutual TLS on the gRPC endpoint.
+//!
+//! With mTLS enabled, every node must present a certificate issued by `TLS_CA`,
+//! and is identified by the IP address in that certificate's SAN instead of the
+//! source address of its requests.
+
+use crate::env::{TLS_CA, TLS_CERT, TLS_KEY};
+use nullnet_liberror::{Error, ErrorHandler, Location, location};
+use std::net::IpAddr;
+use tonic::Request;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use x509_parser::extensions::GeneralName;
+
+/// TLS configuration requiring client certificates, or `None` if mTLS is not configured.
+pub(crate) fn server_tls_config() -> Result<Option<ServerTlsConfig>, Error> {
+    let (cert_path, key_path, ca_path) = match (&*TLS_CERT, &*TLS_KEY, &*TLS_CA) {
+        (Some(cert), Some(key), Some(ca)) => (cert, key, ca),
+        (None, None, None) => return Ok(None),
+        _ => Err("'TLS_CERT', 'TLS_KEY' and 'TLS_CA' must be set together")
+            .handle_err(location!())?,
+    };
+
+    let cert = std::fs::read(cert_path).handle_err(location!())?;
+    let key = std::fs::read(key_path).handle_err(location!())?;
+    let ca = std::fs::read(ca_path).handle_err(location!())?;
+    println!("Mutual TLS enabled (CA: '{ca_path}')");
+
+    Ok(Some(
+        ServerTlsConfig::new()
+            .identity(Identity::from_pem(cert, key))
+            .client_ca_root(Certificate::from_pem(ca)),
+    ))
+}
+
+/// Identity of the node that sent `request`: the IP address in its client
+/// certificate with mTLS enabled, the source address of the request otherwise.
+pub(crate) fn node_ip<T>(request: &Request<T>) -> Result<IpAddr, Error> {
+    if TLS_CA.is_none() {
+        return Ok(request
+            .remote_addr()
+            .ok_or("Could not get remote address of the request")
+            .handle_err(location!())?
+            .ip());
+    }
+
+    let certs = request
+        .peer_certs()
+        .ok_or("No client certificate presented")
+        .handle_err(location!())?;
+    let leaf = certs
+        .first()
+        .ok_or("Empty client certificate chain")
+        .handle_err(location!())?;
+    cert_ip(leaf)
+}
+
+/// The IP address in the SAN of the DER-encoded certificate `der`.
+fn cert_ip(der: &[u8]) -> Result<IpAddr, Error> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).handle_err(location!())?;
+    let san = cert
+        .subject_alternative_name()
+        .handle_err(location!())?
+        .ok_or("Client certificate has no subject alternative name")
+        .handle_err(location!())?;
+
+    san.value
+        .general_names
+        .iter()
+        .find_map(|name| match name {
+            GeneralName::IPAddress(bytes) => match bytes.len() {
+                4 => <[u8; 4]>::try_from(*bytes).ok().map(IpAddr::from),
+                16 => <[u8; 16]>::try_from(*bytes)
+                    .ok()
+                    .map(|ip| IpAddr::from(ip).to_canonical()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .ok_or("Client certificate has no IP address in its subject alternative name")
+        .handle_err(location!())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_der(file: &str) -> Vec<u8> {
+        let path = format!("{}/tests/fixtures/tls/{file}", env!("CARGO_MANIFEST_DIR"));
+        let pem = std::fs::read(path).unwrap();
+        let (_, pem) = x509_parser::pem::parse_x509_pem(&pem).unwrap();
+        pem.contents
+    }
+
+    #[test]
+    fn test_cert_ip_from_san() {
+        assert_eq!(
+            cert_ip(&fixture_der("node.pem")).unwrap(),
+            "192.168.1.10".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            cert_ip(&fixture_der("ipv6.pem")).unwrap(),
+            "fd00::10".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_cert_without_ip_is_refused() {
+        assert!(cert_ip(&fixture_der("nosan.pem")).is_err());
+        assert!(cert_ip(b"not a certificate").is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! Networks set up ahead of proxy requests (`warm_networks` in `services.toml`),
+//! so that new clients don't wait for their chain to be built.
+
+use crate::nullnet_grpc_impl::NullnetGrpcImpl;
+use crate::services::changes::ServiceChange;
+use crate::services::service_info::ServiceInfo;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// How often the warm networks are checked even if none was handed out,
+/// to catch up with new replicas, reconnected proxies and config changes.
+const WARM_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Delay before the warm networks failing to be set up on a proxy are retried there,
+/// doubled at each consecutive failure up to [`WARM_RETRY_MAX_DELAY`].
+const WARM_RETRY_DELAY: Duration = WARM_CHECK_INTERVAL;
+const WARM_RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// Keep the warm networks of every service topped up, releasing the ones in excess.
+pub(crate) async fn keep_networks_warm(server: NullnetGrpcImpl, needed: Arc<Notify>) {
+    loop {
+        server.replenish_warm_networks().await;
+
+        tokio::select! {
+            () = tokio::time::sleep(WARM_CHECK_INTERVAL) => {}
+            () = needed.notified() => {}
+        }
+    }
+}
+
+/// The warm networks of service `name` missing on `proxies`, as `(service, proxy)`
+/// (one entry per network), and the changes releasing the ones in excess.
+pub(crate) fn plan_warm_networks(
+    name: &str,
+    si: &ServiceInfo,
+    proxies: &[IpAddr],
+) -> (Vec<(String, IpAddr)>, Vec<ServiceChange>) {
+    let mut missing = Vec::new();
+    let mut excess = Vec::new();
+
+    let ServiceInfo::Registered(reg) = si else {
+        return (missing, excess);
+    };
+    let target = si.warm_networks().unwrap_or(0) as usize;
+    // warm networks count towards `max_networks`
+    let mut room = reg.max_networks().map_or(usize::MAX, |max| {
+        (max as usize).saturating_sub(reg.proxy_clients_count())
+    });
+
+    for &proxy_ip in proxies {
+        // the ones stranded on replicas not taking new clients can't be handed out: replace them
+        let stranded = reg.stranded_warm_clients(proxy_ip);
+        room = room.saturating_add(stranded.len());
+        excess.extend(
+            stranded
+                .into_iter()
+                .map(|client| ServiceChange::WarmNetworkReleased {
+                    name: name.to_string(),
+                    client,
+                }),
+        );
+
+        let warm = reg.warm_clients(proxy_ip);
+        if warm.len() < target {
+            let count = (target - warm.len()).min(room);
+            room -= count;
+            missing.extend(std::iter::repeat_n((name.to_string(), proxy_ip), count));
+        } else {
+            // the ones still being set up are released by the next check
+            let count = warm.len() - target;
+            excess.extend(
+                warm.into_iter()
+                    .filter(|(_, in_flight)| !in_flight)
+                    .take(count)
+                    .map(|(client, _)| ServiceChange::WarmNetworkReleased {
+                        name: name.to_string(),
+                        client,
+                    }),
+            );
+        }
+    }
+
+    (missing, excess)
+}
+
+/// The warm networks failing to be set up, by service and proxy, on hold until their next attempt.
+#[derive(Default)]
+pub(crate) struct WarmBackoff {
+    failing: HashMap<(String, IpAddr), Failing>,
+}
+
+struct Failing {
+    /// Consecutive rounds of failed setups.
+    failures: u32,
+    retry_at: Instant,
+}
+
+impl WarmBackoff {
+    /// Drop from `missing` the networks on hold, forgetting the failures
+    /// of the services and proxies not missing any network anymore.
+    pub(crate) fn hold(&mut self, missing: &mut Vec<(String, IpAddr)>) {
+        let still_missing: HashSet<&(String, IpAddr)> = missing.iter().collect();
+        self.failing.retain(|key, _| still_missing.contains(key));
+        let now = Instant::now();
+        missing.retain(|key| {
+            self.failing
+                .get(key)
+                .is_none_or(|failing| failing.retry_at <= now)
+        });
+    }
+
+    /// Record whether all the setups of warm networks for `service` on `proxy` succeeded,
+    /// returning the delay before they're retried if they didn't.
+    pub(crate) fn record(
+        &mut self,
+        service: String,
+        proxy: IpAddr,
+        succeeded: bool,
+    ) -> Option<Duration> {
+        let key = (service, proxy);
+        if succeeded {
+            self.failing.remove(&key);
+            return None;
+        }
+        let failing = self.failing.entry(key).or_insert(Failing {
+            failures: 0,
+            retry_at: Instant::now(),
+        });
+        failing.failures += 1;
+        let delay = WARM_RETRY_DELAY
+            .saturating_mul(2_u32.saturating_pow(failing.failures - 1))
+            .min(WARM_RETRY_MAX_DELAY);
+        failing.retry_at = Instant::now() + delay;
+        Some(delay)
+    }
+}